@@ -0,0 +1,209 @@
+// Status window for the bridge: connection state, signal strength, held notes and
+// message counters. Built with `cargo build --release --features gui`.
+use anyhow::{anyhow, Context, Result};
+use blip::ble::DeviceInfo;
+use blip::{BleMidiBridge, BridgeState, Config};
+use eframe::egui;
+use log::{error, info};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::TryRecvError};
+
+// Configuration file read at startup if it exists, as by the headless binary
+const CONFIG_FILE: &str = "blip.toml";
+
+const USAGE: &str = "usage: blip-gui [--config <file>] [--profile <name>]";
+
+// How often the device info (RSSI, battery) is refreshed
+const DEVICE_INFO_REFRESH: Duration = Duration::from_secs(2);
+
+// Range drawn by the note display (A0 to C8, a full piano)
+const LOWEST_NOTE: u8 = 21;
+const HIGHEST_NOTE: u8 = 108;
+
+struct StatusWindow {
+    bridge: Arc<BleMidiBridge>,
+    messages: broadcast::Receiver<blip::midi::MidiMessage>,
+    counters: BTreeMap<&'static str, u64>,
+    device: Arc<Mutex<Option<DeviceInfo>>>,
+}
+
+impl StatusWindow {
+    fn count_new_messages(&mut self) {
+        loop {
+            match self.messages.try_recv() {
+                Ok(message) => *self.counters.entry(message.message_type()).or_default() += 1,
+                Err(TryRecvError::Lagged(missed)) => *self.counters.entry("Missed by display").or_default() += missed,
+                Err(TryRecvError::Empty | TryRecvError::Closed) => break,
+            }
+        }
+    }
+
+    fn note_display(&self, ui: &mut egui::Ui) {
+        let held: Vec<u8> = self.bridge.held_notes().iter().map(|held| held.note).collect();
+        let keys = (HIGHEST_NOTE - LOWEST_NOTE + 1) as f32;
+        let (rect, _) = ui.allocate_exact_size(egui::vec2(ui.available_width(), 60.0), egui::Sense::hover());
+        let key_width = rect.width() / keys;
+        let painter = ui.painter_at(rect);
+
+        for note in LOWEST_NOTE..=HIGHEST_NOTE {
+            let black = matches!(note % 12, 1 | 3 | 6 | 8 | 10);
+            let left = rect.left() + (note - LOWEST_NOTE) as f32 * key_width;
+            let height = if black { rect.height() * 0.6 } else { rect.height() };
+            let key = egui::Rect::from_min_size(egui::pos2(left, rect.top()), egui::vec2(key_width, height));
+            let colour = if held.contains(&note) {
+                egui::Color32::from_rgb(80, 170, 255)
+            } else if black {
+                egui::Color32::from_gray(30)
+            } else {
+                egui::Color32::from_gray(220)
+            };
+            painter.rect_filled(key.shrink(0.5), 1.0, colour);
+        }
+    }
+}
+
+impl eframe::App for StatusWindow {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.count_new_messages();
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            let state = self.bridge.state();
+            let state_colour = match state {
+                BridgeState::Connected | BridgeState::Forwarding => egui::Color32::GREEN,
+                BridgeState::Error => egui::Color32::RED,
+                _ => egui::Color32::YELLOW,
+            };
+            ui.heading("BLIP");
+            ui.horizontal(|ui| {
+                ui.label("State:");
+                ui.colored_label(state_colour, format!("{:?}", state));
+            });
+
+            match &*self.device.lock().unwrap() {
+                Some(info) => {
+                    ui.label(format!("Device: {} ({})", info.name.as_deref().unwrap_or("unnamed"), info.address));
+                    ui.label(format!(
+                        "Signal: {}",
+                        info.rssi.map_or("unknown".to_string(), |rssi| format!("{} dBm", rssi))
+                    ));
+                    if let Some(battery) = info.battery_level {
+                        ui.label(format!("Battery: {}%", battery));
+                    }
+                }
+                None => {
+                    ui.label("No device connected");
+                }
+            }
+
+            ui.separator();
+            self.note_display(ui);
+
+            ui.separator();
+            egui::Grid::new("counters").striped(true).show(ui, |ui| {
+                for (message_type, count) in &self.counters {
+                    ui.label(*message_type);
+                    ui.label(count.to_string());
+                    ui.end_row();
+                }
+            });
+        });
+
+        ctx.request_repaint_after(Duration::from_millis(50));
+    }
+}
+
+// Shown instead of the status window when the bridge can't start, since this binary is
+// often run without a console to show the error
+struct ErrorWindow {
+    message: String,
+}
+
+impl eframe::App for ErrorWindow {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading("BLIP could not start");
+            ui.colored_label(egui::Color32::RED, &self.message);
+        });
+    }
+}
+
+// The command line options the headless binary takes to pick a configuration
+fn parse_args() -> Result<(Option<PathBuf>, Option<String>)> {
+    let (mut config_file, mut profile) = (None, None);
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--config" => config_file = Some(iter.next().ok_or_else(|| anyhow!("--config needs a file"))?.into()),
+            "--profile" => profile = Some(iter.next().ok_or_else(|| anyhow!("--profile needs a name"))?),
+            _ => return Err(anyhow!("Unknown argument '{}' ({})", arg, USAGE)),
+        }
+    }
+    Ok((config_file, profile))
+}
+
+// Load the configuration like the headless binary, then create and start the bridge
+fn start(runtime: &tokio::runtime::Runtime) -> Result<StatusWindow> {
+    let (config_file, profile) = parse_args()?;
+    let mut config = Config::default();
+    let loaded = config
+        .load(Path::new(CONFIG_FILE), config_file.as_deref(), profile.as_deref())
+        .context("Invalid configuration")?;
+    if let Some(path) = loaded {
+        info!("Loaded configuration from {}", path.display());
+    }
+    let bridge = Arc::new(runtime.block_on(BleMidiBridge::new(&config)).context("Failed to create bridge")?);
+
+    runtime.spawn({
+        let bridge = bridge.clone();
+        async move {
+            if let Err(e) = bridge.start().await {
+                error!("Bridge error: {}", e);
+            }
+        }
+    });
+
+    let device = Arc::new(Mutex::new(None));
+    runtime.spawn({
+        let bridge = bridge.clone();
+        let device = device.clone();
+        async move {
+            loop {
+                let info = bridge.device_info().await.ok();
+                *device.lock().unwrap() = info;
+                tokio::time::sleep(DEVICE_INFO_REFRESH).await;
+            }
+        }
+    });
+
+    Ok(StatusWindow {
+        messages: bridge.message_stream(),
+        bridge,
+        counters: BTreeMap::new(),
+        device,
+    })
+}
+
+fn main() -> Result<()> {
+    env_logger::Builder::new()
+        .filter_level(log::LevelFilter::Info)
+        .filter_module("btleplug", log::LevelFilter::Warn)
+        .init();
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    let window: Box<dyn eframe::App> = match start(&runtime) {
+        Ok(window) => Box::new(window),
+        Err(e) => {
+            error!("{:#}", e);
+            Box::new(ErrorWindow { message: format!("{:#}", e) })
+        }
+    };
+    eframe::run_native(
+        "BLIP",
+        eframe::NativeOptions::default(),
+        Box::new(|_| Ok(window)),
+    )
+    .map_err(|e| anyhow!("Could not open the status window: {}", e))
+}
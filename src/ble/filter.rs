@@ -0,0 +1,298 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use uuid::Uuid;
+
+use super::BLE_MIDI_SERVICE_UUID;
+
+// One way of recognising the device to connect to. `Config::device_filters` lists
+// them in priority order.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum DeviceFilter {
+    // Advertised name contains this text
+    Name(String),
+    // Exact Bluetooth address, e.g. "C4:2A:11:00:3B:9E" (case-insensitive)
+    Address(String),
+    // Advertises the BLE-MIDI service, i.e. any BLE-MIDI device
+    MidiService,
+}
+
+impl DeviceFilter {
+    pub fn matches(&self, device: &Advertisement) -> bool {
+        match self {
+            DeviceFilter::Name(text) => device.name.as_deref().is_some_and(|name| name.contains(text.as_str())),
+            DeviceFilter::Address(address) => device.address.eq_ignore_ascii_case(address),
+            DeviceFilter::MidiService => device.services.contains(&BLE_MIDI_SERVICE_UUID),
+        }
+    }
+}
+
+// The filters used when none are configured: the LPK25, then any AKAI device
+pub fn default_device_filters() -> Vec<DeviceFilter> {
+    vec![DeviceFilter::Name("LPK25".to_string()), DeviceFilter::Name("AKAI".to_string())]
+}
+
+// Which device to pick when several match equally well
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MultiMatch {
+    // The first one found; a top-priority match ends the scan straight away
+    #[default]
+    FirstFound,
+    // The one with the strongest signal, i.e. usually the closest. The scan runs for
+    // its full window unless a top-priority match is clearly right next to us.
+    StrongestRssi,
+}
+
+// A signal this strong (in dBm) only comes from a device next to the adapter, so
+// nothing stronger is worth waiting for
+const DOMINANT_RSSI: i16 = -45;
+
+// What a scan tells us about a device before connecting to it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Advertisement {
+    pub name: Option<String>,
+    pub address: String,
+    pub services: Vec<Uuid>,
+    pub rssi: Option<i16>,
+}
+
+// Priority tier (index into `filters`) of the first filter the device matches
+pub fn match_tier(filters: &[DeviceFilter], device: &Advertisement) -> Option<usize> {
+    filters.iter().position(|filter| filter.matches(device))
+}
+
+// The current pick of a `BestMatch`
+#[derive(Debug)]
+struct Candidate<T> {
+    tier: usize,
+    address: String,
+    rssi: Option<i16>,
+    item: T,
+}
+
+// Keeps the best match seen over a scan. Earlier tiers win, and within a tier the
+// device seen first or, with `MultiMatch::StrongestRssi`, the one with the strongest signal.
+#[derive(Debug)]
+pub struct BestMatch<T> {
+    multi_match: MultiMatch,
+    // Matches with a weaker (or unknown) signal are ignored
+    min_rssi: Option<i16>,
+    // Addresses of matches ignored for a weak signal, so each is only logged once
+    too_weak: BTreeSet<String>,
+    best: Option<Candidate<T>>,
+}
+
+impl<T> BestMatch<T> {
+    pub fn new() -> Self {
+        Self::with_multi_match(MultiMatch::FirstFound)
+    }
+
+    pub fn with_multi_match(multi_match: MultiMatch) -> Self {
+        BestMatch { multi_match, min_rssi: None, too_weak: BTreeSet::new(), best: None }
+    }
+
+    pub fn with_min_rssi(mut self, min_rssi: Option<i16>) -> Self {
+        self.min_rssi = min_rssi;
+        self
+    }
+
+    // Scans report the same device again and again, so a device that is already the
+    // pick only has its signal strength updated
+    pub fn offer(&mut self, filters: &[DeviceFilter], device: &Advertisement, item: T) {
+        let Some(tier) = match_tier(filters, device) else {
+            return;
+        };
+        if let Some(min_rssi) = self.min_rssi {
+            if device.rssi.is_none_or(|rssi| rssi < min_rssi) {
+                if self.too_weak.insert(device.address.clone()) {
+                    info!(
+                        "Ignoring {} ({}): signal {} is below {} dBm",
+                        device.name.as_deref().unwrap_or("unnamed device"),
+                        device.address,
+                        device.rssi.map_or("unknown".to_string(), |rssi| format!("{} dBm", rssi)),
+                        min_rssi
+                    );
+                }
+                return;
+            }
+        }
+        let better = match &mut self.best {
+            None => true,
+            Some(best) if best.address == device.address => {
+                best.rssi = device.rssi.or(best.rssi);
+                false
+            }
+            Some(best) => {
+                tier < best.tier
+                    || (tier == best.tier
+                        && self.multi_match == MultiMatch::StrongestRssi
+                        && device.rssi.unwrap_or(i16::MIN) > best.rssi.unwrap_or(i16::MIN))
+            }
+        };
+        if better {
+            info!(
+                "Found candidate {} ({}) matching {:?} (priority {}, RSSI {})",
+                device.name.as_deref().unwrap_or("unnamed device"),
+                device.address,
+                filters[tier],
+                tier + 1,
+                device.rssi.map_or("unknown".to_string(), |rssi| format!("{} dBm", rssi))
+            );
+            self.best = Some(Candidate { tier, address: device.address.clone(), rssi: device.rssi, item });
+        }
+    }
+
+    pub fn is_top_tier(&self) -> bool {
+        self.best.as_ref().is_some_and(|best| best.tier == 0)
+    }
+
+    // Whether nothing better than the current match can turn up, so the scan can stop
+    pub fn is_final(&self) -> bool {
+        match self.multi_match {
+            MultiMatch::FirstFound => self.is_top_tier(),
+            MultiMatch::StrongestRssi => {
+                self.is_top_tier() && self.best.as_ref().and_then(|best| best.rssi).is_some_and(|rssi| rssi >= DOMINANT_RSSI)
+            }
+        }
+    }
+
+    pub fn into_inner(self) -> Option<(usize, T)> {
+        self.best.map(|best| (best.tier, best.item))
+    }
+}
+
+impl<T> Default for BestMatch<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(name: &str, address: &str, midi: bool) -> Advertisement {
+        Advertisement {
+            name: (!name.is_empty()).then(|| name.to_string()),
+            address: address.to_string(),
+            services: if midi { vec![BLE_MIDI_SERVICE_UUID] } else { Vec::new() },
+            rssi: None,
+        }
+    }
+
+    fn with_rssi(address: &str, rssi: i16) -> Advertisement {
+        Advertisement { rssi: Some(rssi), ..device("AKAI LPK25", address, true) }
+    }
+
+    fn filters() -> Vec<DeviceFilter> {
+        vec![
+            DeviceFilter::Name("LPK25".to_string()),
+            DeviceFilter::Name("AKAI".to_string()),
+            DeviceFilter::MidiService,
+        ]
+    }
+
+    #[test]
+    fn test_filters_match() {
+        let lpk = device("AKAI LPK25 Wireless", "C4:2A:11:00:3B:9E", true);
+        assert!(DeviceFilter::Name("LPK25".to_string()).matches(&lpk));
+        assert!(DeviceFilter::Address("c4:2a:11:00:3b:9e".to_string()).matches(&lpk));
+        assert!(DeviceFilter::MidiService.matches(&lpk));
+
+        let unnamed = device("", "00:11:22:33:44:55", false);
+        assert!(!DeviceFilter::Name("LPK25".to_string()).matches(&unnamed));
+        assert!(!DeviceFilter::MidiService.matches(&unnamed));
+    }
+
+    #[test]
+    fn test_highest_priority_match_wins() {
+        let seen = [
+            device("Headphones", "00:00:00:00:00:01", false),
+            device("", "00:00:00:00:00:02", true),
+            device("AKAI MPK Mini", "00:00:00:00:00:03", true),
+            device("AKAI LPK25", "00:00:00:00:00:04", true),
+        ];
+        let mut best = BestMatch::new();
+        for (i, ad) in seen.iter().enumerate() {
+            best.offer(&filters(), ad, i);
+        }
+        assert!(best.is_top_tier());
+        assert_eq!(best.into_inner(), Some((0, 3)));
+    }
+
+    #[test]
+    fn test_falls_back_to_lower_tiers() {
+        let mut best = BestMatch::new();
+        best.offer(&filters(), &device("", "00:00:00:00:00:01", true), "generic");
+        best.offer(&filters(), &device("AKAI MPK Mini", "00:00:00:00:00:02", true), "akai");
+        best.offer(&filters(), &device("Other MIDI", "00:00:00:00:00:03", true), "other");
+        assert!(!best.is_top_tier());
+        assert_eq!(best.into_inner(), Some((1, "akai")));
+
+        let mut best = BestMatch::new();
+        best.offer(&filters(), &device("Headphones", "00:00:00:00:00:01", false), "headphones");
+        assert_eq!(best.into_inner(), None);
+    }
+
+    #[test]
+    fn test_strongest_signal_wins() {
+        let seen = [
+            with_rssi("00:00:00:00:00:01", -80),
+            with_rssi("00:00:00:00:00:02", -60),
+            with_rssi("00:00:00:00:00:03", -90),
+        ];
+
+        let mut best = BestMatch::with_multi_match(MultiMatch::StrongestRssi);
+        for (i, ad) in seen.iter().enumerate() {
+            best.offer(&filters(), ad, i);
+        }
+        // Worth scanning on in case something even closer turns up
+        assert!(!best.is_final());
+        assert_eq!(best.into_inner(), Some((0, 1)));
+
+        let mut best = BestMatch::new();
+        for (i, ad) in seen.iter().enumerate() {
+            best.offer(&filters(), ad, i);
+        }
+        assert!(best.is_final());
+        assert_eq!(best.into_inner(), Some((0, 0)));
+    }
+
+    #[test]
+    fn test_weak_signals_are_ignored() {
+        let mut best = BestMatch::new().with_min_rssi(Some(-70));
+        best.offer(&filters(), &with_rssi("00:00:00:00:00:01", -85), "far");
+        best.offer(&filters(), &device("AKAI LPK25", "00:00:00:00:00:02", true), "unknown");
+        assert!(!best.is_final());
+        best.offer(&filters(), &with_rssi("00:00:00:00:00:03", -60), "near");
+        best.offer(&filters(), &with_rssi("00:00:00:00:00:01", -90), "far");
+        assert_eq!(best.into_inner(), Some((0, "near")));
+
+        let mut best = BestMatch::new().with_min_rssi(Some(-70));
+        best.offer(&filters(), &with_rssi("00:00:00:00:00:01", -85), "far");
+        assert_eq!(best.into_inner(), None);
+    }
+
+    #[test]
+    fn test_priority_beats_signal_strength() {
+        let mut best = BestMatch::with_multi_match(MultiMatch::StrongestRssi);
+        best.offer(&filters(), &Advertisement { rssi: Some(-40), ..device("AKAI MPK Mini", "00:00:00:00:00:01", true) }, "mpk");
+        best.offer(&filters(), &with_rssi("00:00:00:00:00:02", -85), "lpk");
+        best.offer(&filters(), &Advertisement { rssi: Some(-30), ..device("", "00:00:00:00:00:03", true) }, "generic");
+        assert_eq!(best.into_inner(), Some((0, "lpk")));
+    }
+
+    #[test]
+    fn test_dominant_signal_ends_the_scan() {
+        let mut best = BestMatch::with_multi_match(MultiMatch::StrongestRssi);
+        best.offer(&filters(), &with_rssi("00:00:00:00:00:01", -70), 1);
+        assert!(!best.is_final());
+        // The same device seen again, now closer
+        best.offer(&filters(), &with_rssi("00:00:00:00:00:01", -40), 1);
+        assert!(best.is_final());
+        best.offer(&filters(), &with_rssi("00:00:00:00:00:02", -50), 2);
+        assert_eq!(best.into_inner(), Some((0, 1)));
+    }
+}
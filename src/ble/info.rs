@@ -0,0 +1,141 @@
+use btleplug::api::{CharPropFlags, Service};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use uuid::Uuid;
+
+use super::{BLE_MIDI_CHARACTERISTIC_UUID, BLE_MIDI_SERVICE_UUID};
+
+// Everything known about a connected device, gathered in one place for UIs and logs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeviceInfo {
+    pub name: Option<String>,
+    pub address: String,
+    pub rssi: Option<i16>,
+    pub has_midi_service: bool,
+    // Whether the MIDI characteristic accepts writes, i.e. the device can receive MIDI
+    pub supports_input: bool,
+    // Battery percentage from the standard Battery Service, if the device exposes it
+    pub battery_level: Option<u8>,
+    // From the standard Device Information Service, if the device exposes it
+    pub manufacturer_name: Option<String>,
+    pub model_number: Option<String>,
+    pub services: Vec<Uuid>,
+}
+
+impl DeviceInfo {
+    // Build the info from already-gathered pieces; the service list is inspected for
+    // the BLE-MIDI service and the write capability of its characteristic
+    pub fn from_parts(
+        name: Option<String>,
+        address: String,
+        rssi: Option<i16>,
+        services: &BTreeSet<Service>,
+        battery_level: Option<u8>,
+    ) -> Self {
+        let midi_service = services.iter().find(|s| s.uuid == BLE_MIDI_SERVICE_UUID);
+        let supports_input = midi_service
+            .into_iter()
+            .flat_map(|s| s.characteristics.iter())
+            .filter(|c| c.uuid == BLE_MIDI_CHARACTERISTIC_UUID)
+            .any(|c| c.properties.intersects(CharPropFlags::WRITE | CharPropFlags::WRITE_WITHOUT_RESPONSE));
+
+        DeviceInfo {
+            name,
+            address,
+            rssi,
+            has_midi_service: midi_service.is_some(),
+            supports_input,
+            battery_level,
+            manufacturer_name: None,
+            model_number: None,
+            services: services.iter().map(|s| s.uuid).collect(),
+        }
+    }
+
+    pub fn with_device_information(mut self, manufacturer_name: Option<String>, model_number: Option<String>) -> Self {
+        self.manufacturer_name = manufacturer_name;
+        self.model_number = model_number;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use btleplug::api::{bleuuid::uuid_from_u16, Characteristic};
+
+    fn service(uuid: Uuid, characteristics: Vec<(Uuid, CharPropFlags)>) -> Service {
+        Service {
+            uuid,
+            primary: true,
+            characteristics: characteristics
+                .into_iter()
+                .map(|(c, properties)| Characteristic {
+                    uuid: c,
+                    service_uuid: uuid,
+                    properties,
+                    descriptors: BTreeSet::new(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_info_for_midi_device() {
+        let services: BTreeSet<Service> = [
+            service(BLE_MIDI_SERVICE_UUID, vec![(
+                BLE_MIDI_CHARACTERISTIC_UUID,
+                CharPropFlags::READ | CharPropFlags::NOTIFY | CharPropFlags::WRITE_WITHOUT_RESPONSE,
+            )]),
+            service(uuid_from_u16(0x180F), vec![(uuid_from_u16(0x2A19), CharPropFlags::READ)]),
+        ].into_iter().collect();
+
+        let info = DeviceInfo::from_parts(
+            Some("LPK25 Wireless".to_string()),
+            "AA:BB:CC:DD:EE:FF".to_string(),
+            Some(-60),
+            &services,
+            Some(80),
+        );
+
+        assert!(info.has_midi_service);
+        assert!(info.supports_input);
+        assert_eq!(info.battery_level, Some(80));
+        assert_eq!(info.services.len(), 2);
+        assert!(info.services.contains(&BLE_MIDI_SERVICE_UUID));
+    }
+
+    #[test]
+    fn test_info_for_notify_only_and_non_midi_devices() {
+        let notify_only: BTreeSet<Service> = [
+            service(BLE_MIDI_SERVICE_UUID, vec![(BLE_MIDI_CHARACTERISTIC_UUID, CharPropFlags::NOTIFY)]),
+        ].into_iter().collect();
+        let info = DeviceInfo::from_parts(None, "00:00:00:00:00:00".to_string(), None, &notify_only, None);
+        assert!(info.has_midi_service);
+        assert!(!info.supports_input);
+
+        let info = DeviceInfo::from_parts(None, "00:00:00:00:00:00".to_string(), None, &BTreeSet::new(), None);
+        assert!(!info.has_midi_service);
+        assert!(!info.supports_input);
+        assert!(info.services.is_empty());
+    }
+
+    #[test]
+    fn test_info_serializes_to_json() {
+        let info = DeviceInfo::from_parts(
+            Some("LPK25".to_string()),
+            "AA:BB:CC:DD:EE:FF".to_string(),
+            Some(-42),
+            &BTreeSet::new(),
+            Some(55),
+        );
+        let json = serde_json::to_value(&info).unwrap();
+
+        assert_eq!(json["name"], "LPK25");
+        assert_eq!(json["address"], "AA:BB:CC:DD:EE:FF");
+        assert_eq!(json["rssi"], -42);
+        assert_eq!(json["has_midi_service"], false);
+        assert_eq!(json["battery_level"], 55);
+        assert!(json["services"].as_array().unwrap().is_empty());
+    }
+}
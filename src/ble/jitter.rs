@@ -0,0 +1,130 @@
+use std::fmt;
+use std::time::Duration;
+use tokio::time::Instant;
+
+// BLE-MIDI timestamps are 13 bits of milliseconds
+const TIMESTAMP_PERIOD: u32 = 8192;
+
+// Arrival gaps from which the wrapping timestamps can't be compared any more
+const MAX_COMPARABLE_GAP: Duration = Duration::from_millis(TIMESTAMP_PERIOD as u64 / 2);
+
+// Jitter over one reporting period: how far the time between consecutive messages'
+// arrivals differed from the time between their timestamps
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JitterStats {
+    pub samples: u32,
+    pub mean: Duration,
+    pub max: Duration,
+}
+
+impl fmt::Display for JitterStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "mean {:.1} ms, max {:.1} ms over {} messages",
+            self.mean.as_secs_f64() * 1000.0,
+            self.max.as_secs_f64() * 1000.0,
+            self.samples
+        )
+    }
+}
+
+// Compares the device's timestamps with when packets arrive, to show whether timing
+// hiccups of the link line up with dropped or late notes (`Config::jitter_analysis`).
+// Messages of one packet arrive together, so bunching by the device shows up as well.
+#[derive(Debug)]
+pub struct JitterAnalyzer {
+    period: Duration,
+    // Timestamp and arrival of the last message
+    last: Option<(u16, Instant)>,
+    period_start: Option<Instant>,
+    samples: u32,
+    total: Duration,
+    max: Duration,
+}
+
+impl JitterAnalyzer {
+    // Reporting statistics every `period`
+    pub fn new(period: Duration) -> Self {
+        JitterAnalyzer { period, last: None, period_start: None, samples: 0, total: Duration::ZERO, max: Duration::ZERO }
+    }
+
+    // The timestamps of a packet's messages, received at `arrival`. Returns the
+    // statistics of the period this completes, if any.
+    pub fn record(&mut self, timestamps: &[u16], arrival: Instant) -> Option<JitterStats> {
+        for &timestamp in timestamps {
+            if let Some((last_timestamp, last_arrival)) = self.last {
+                let arrived_after = arrival.duration_since(last_arrival);
+                if arrived_after < MAX_COMPARABLE_GAP {
+                    let sent_after = (timestamp as u32 + TIMESTAMP_PERIOD - last_timestamp as u32) % TIMESTAMP_PERIOD;
+                    let jitter = arrived_after.abs_diff(Duration::from_millis(sent_after as u64));
+                    self.samples += 1;
+                    self.total += jitter;
+                    self.max = self.max.max(jitter);
+                }
+            }
+            self.last = Some((timestamp, arrival));
+        }
+
+        let start = *self.period_start.get_or_insert(arrival);
+        if arrival.duration_since(start) < self.period {
+            return None;
+        }
+        self.period_start = Some(arrival);
+        self.take_stats()
+    }
+
+    // Statistics since the last report, starting a new period
+    pub fn take_stats(&mut self) -> Option<JitterStats> {
+        let stats = (self.samples > 0).then(|| JitterStats { samples: self.samples, mean: self.total / self.samples, max: self.max });
+        self.samples = 0;
+        self.total = Duration::ZERO;
+        self.max = Duration::ZERO;
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jitter_of_timestamped_packets() {
+        let mut analyzer = JitterAnalyzer::new(Duration::from_secs(10));
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        // Sent 20 ms apart: on time, 5 ms late, then 3 ms early and on time again
+        analyzer.record(&[100], at(0));
+        analyzer.record(&[120], at(20));
+        analyzer.record(&[140], at(45));
+        analyzer.record(&[160], at(62));
+        // Two messages 10 ms apart arriving in one packet
+        analyzer.record(&[200, 210], at(102));
+
+        let stats = analyzer.take_stats().unwrap();
+        assert_eq!(stats.samples, 5);
+        assert_eq!(stats.max, Duration::from_millis(10));
+        assert_eq!(stats.mean, Duration::from_micros(3600));
+        assert_eq!(stats.to_string(), "mean 3.6 ms, max 10.0 ms over 5 messages");
+        assert_eq!(analyzer.take_stats(), None);
+    }
+
+    #[test]
+    fn test_wrap_and_long_gaps() {
+        let mut analyzer = JitterAnalyzer::new(Duration::from_secs(10));
+        let start = Instant::now();
+        // Across the wrap of the 13-bit timestamp
+        analyzer.record(&[8190], start);
+        analyzer.record(&[4], start + Duration::from_millis(6));
+        // After a long pause the timestamps say nothing
+        analyzer.record(&[100], start + Duration::from_secs(5));
+        assert_eq!(analyzer.take_stats(), Some(JitterStats { samples: 1, mean: Duration::ZERO, max: Duration::ZERO }));
+
+        // Reported once a period is complete
+        assert_eq!(analyzer.record(&[120], start + Duration::from_secs(10)), None);
+        assert_eq!(analyzer.record(&[140], start + Duration::from_millis(10_020)), None);
+        let stats = analyzer.record(&[150], start + Duration::from_millis(20_030)).unwrap();
+        assert_eq!(stats.samples, 1);
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::{anyhow, Result};
+use btleplug::api::CharPropFlags;
+use futures::StreamExt;
+use log::{debug, warn};
+use std::fmt;
+use std::time::Duration;
+use tokio::time::{self, Instant};
+
+use super::packet::{self, TimedMessage};
+use super::{find_characteristic, BleLink, NotificationStream, BLE_MIDI_CHARACTERISTIC_UUID};
+
+// Manufacturer ID reserved for non-commercial use, so a probe can't be mistaken for
+// anything the device sends on its own
+const NON_COMMERCIAL_ID: u8 = 0x7D;
+
+// Round trip times of a latency test, and how many probes never came back
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LatencyReport {
+    pub round_trips: Vec<Duration>,
+    pub lost: u32,
+}
+
+impl LatencyReport {
+    pub fn min(&self) -> Option<Duration> {
+        self.round_trips.iter().min().copied()
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.round_trips.iter().max().copied()
+    }
+
+    pub fn average(&self) -> Option<Duration> {
+        let total: Duration = self.round_trips.iter().sum();
+        (!self.round_trips.is_empty()).then(|| total / self.round_trips.len() as u32)
+    }
+}
+
+impl fmt::Display for LatencyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sent = self.round_trips.len() as u32 + self.lost;
+        writeln!(f, "Round trips  {}/{}", self.round_trips.len(), sent)?;
+        for (label, value) in [("Min", self.min()), ("Avg", self.average()), ("Max", self.max())] {
+            match value {
+                Some(value) => writeln!(f, "{:<11}  {:.1} ms", label, value.as_secs_f64() * 1000.0)?,
+                None => writeln!(f, "{:<11}  -", label)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// SysEx probe carrying its sequence number
+fn probe(sequence: u16) -> Vec<u8> {
+    vec![0xF0, NON_COMMERCIAL_ID, (sequence >> 7) as u8 & 0x7F, sequence as u8 & 0x7F, 0xF7]
+}
+
+// Measure how long messages written to the device's MIDI characteristic take to come
+// back as notifications, for a device that echoes its MIDI input. Each of `iterations`
+// probes waits up to `timeout` for its echo; other MIDI from the device is ignored.
+pub async fn measure_round_trip(link: &dyn BleLink, iterations: u16, timeout: Duration) -> Result<LatencyReport> {
+    let characteristic = find_characteristic(&link.services(), BLE_MIDI_CHARACTERISTIC_UUID)?;
+    if !characteristic.properties.intersects(CharPropFlags::WRITE | CharPropFlags::WRITE_WITHOUT_RESPONSE) {
+        return Err(anyhow!("The device's MIDI characteristic doesn't accept writes"));
+    }
+    let mut notifications = link.notifications().await?;
+    link.subscribe(&characteristic).await?;
+
+    let mut report = LatencyReport::default();
+    for sequence in 0..iterations {
+        let probe = probe(sequence);
+        let sent = Instant::now();
+        link.write(&characteristic, &packet::encode(&[TimedMessage::new(packet::timestamp_now(), probe.clone())]))
+            .await?;
+        match time::timeout(timeout, wait_for_echo(&mut notifications, &probe)).await {
+            Ok(Ok(())) => report.round_trips.push(sent.elapsed()),
+            Ok(Err(e)) => return Err(e),
+            Err(_) => {
+                warn!("No echo of probe {} within {:?}", sequence, timeout);
+                report.lost += 1;
+            }
+        }
+    }
+    Ok(report)
+}
+
+// Wait for a notification carrying `probe`
+async fn wait_for_echo(notifications: &mut NotificationStream, probe: &[u8]) -> Result<()> {
+    while let Some(notification) = notifications.next().await {
+        match packet::decode(&notification.value) {
+            Ok(messages) if messages.iter().any(|message| message.bytes == probe) => return Ok(()),
+            Ok(_) => {}
+            Err(e) => debug!("Ignoring undecodable packet while waiting for an echo: {}", e),
+        }
+    }
+    Err(anyhow!("Notification stream ended"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ble::testing::FakeLink;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_round_trips_through_a_loopback() {
+        let link = FakeLink::new();
+        link.set_loopback(Some(Duration::from_millis(12)));
+        // Played notes and other SysEx are not echoes
+        link.send_packet(&[0x80, 0x80, 0x90, 60, 100]);
+        link.send_packet(&packet::encode(&[TimedMessage::new(0, probe(1))]));
+
+        let report = measure_round_trip(&link, 4, Duration::from_millis(100)).await.unwrap();
+        assert_eq!(report.round_trips, [Duration::from_millis(12); 4]);
+        assert_eq!(report.lost, 0);
+        assert_eq!(report.average(), Some(Duration::from_millis(12)));
+        assert_eq!(link.writes().len(), 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_lost_probes() {
+        let link = FakeLink::new();
+        // Echoed too late to count
+        link.set_loopback(Some(Duration::from_millis(150)));
+        let report = measure_round_trip(&link, 2, Duration::from_millis(100)).await.unwrap();
+        assert_eq!(report, LatencyReport { round_trips: Vec::new(), lost: 2 });
+        assert_eq!(report.to_string(), "Round trips  0/2\nMin          -\nAvg          -\nMax          -\n");
+    }
+
+    #[test]
+    fn test_report_table() {
+        let report = LatencyReport {
+            round_trips: [8, 10, 15].map(Duration::from_millis).to_vec(),
+            lost: 1,
+        };
+        assert_eq!(report.to_string(), "Round trips  3/4\nMin          8.0 ms\nAvg          11.0 ms\nMax          15.0 ms\n");
+    }
+}
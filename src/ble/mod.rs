@@ -1,23 +1,280 @@
 use anyhow::{anyhow, Result};
+use async_trait::async_trait;
 use btleplug::api::{
-    Central, Manager as _, Peripheral as _, ScanFilter,
+    bleuuid::uuid_from_u16, Central, Characteristic, Manager as _, Peripheral as _, ScanFilter,
+    Service, ValueNotification, WriteType,
 };
 use btleplug::platform::{Manager, Peripheral};
+use futures::Stream;
 use log::{info, warn, debug};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::pin::Pin;
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 use tokio::time;
 use uuid::Uuid;
 
+use crate::error::BlipError;
+
+mod filter;
+mod info;
+mod jitter;
+mod latency;
+pub mod packet;
+mod sysex;
+#[cfg(test)]
+pub(crate) mod testing;
+
+pub use filter::{default_device_filters, match_tier, Advertisement, BestMatch, DeviceFilter, MultiMatch};
+pub use info::DeviceInfo;
+pub use jitter::{JitterAnalyzer, JitterStats};
+pub use latency::{measure_round_trip, LatencyReport};
+pub use sysex::{send_sysex, send_sysex_file, split_sysex, SYSEX_WRITE_INTERVAL};
+
 // BLE-MIDI protocol UUIDs
 pub const BLE_MIDI_CHARACTERISTIC_UUID: Uuid = Uuid::from_u128(0x7772E5DB_3868_4112_A1A9_F2669D106BF3);
 pub const BLE_MIDI_SERVICE_UUID: Uuid = Uuid::from_u128(0x03B80E5A_EDE8_4B33_A751_6CE34EC4C700);
 
+// Standard Battery Service level characteristic
+pub const BATTERY_LEVEL_CHARACTERISTIC_UUID: Uuid = uuid_from_u16(0x2A19);
+
+// Standard Device Information Service and the characteristics we read from it
+pub const DEVICE_INFORMATION_SERVICE_UUID: Uuid = uuid_from_u16(0x180A);
+pub const MANUFACTURER_NAME_CHARACTERISTIC_UUID: Uuid = uuid_from_u16(0x2A29);
+pub const MODEL_NUMBER_CHARACTERISTIC_UUID: Uuid = uuid_from_u16(0x2A24);
+
+pub type NotificationStream = Pin<Box<dyn Stream<Item = ValueNotification> + Send>>;
+
+// The subset of a connected peripheral used by the notification loop.
+// Implemented for the btleplug peripheral and by mocks in tests.
+#[async_trait]
+pub trait NotificationSource: Send + Sync {
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()>;
+    async fn notifications(&self) -> Result<NotificationStream>;
+    async fn is_connected(&self) -> Result<bool>;
+}
+
+#[async_trait]
+impl NotificationSource for Peripheral {
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        Ok(btleplug::api::Peripheral::subscribe(self, characteristic).await.map_err(BlipError::from)?)
+    }
+
+    async fn notifications(&self) -> Result<NotificationStream> {
+        Ok(btleplug::api::Peripheral::notifications(self).await.map_err(BlipError::from)?)
+    }
+
+    async fn is_connected(&self) -> Result<bool> {
+        Ok(btleplug::api::Peripheral::is_connected(self).await.map_err(BlipError::from)?)
+    }
+}
+
+// A connected BLE-MIDI device, as used by the bridge once discovery is done.
+// `BleDevice` is the real implementation; tests substitute fakes.
+#[async_trait]
+pub trait BleLink: NotificationSource {
+    fn services(&self) -> BTreeSet<Service>;
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>>;
+    // Write without response, as BLE-MIDI packets are sent
+    async fn write(&self, characteristic: &Characteristic, data: &[u8]) -> Result<()>;
+    async fn info(&self) -> Result<DeviceInfo>;
+    // Current signal strength in dBm, if the stack reports it
+    async fn rssi(&self) -> Result<Option<i16>>;
+    // ATT MTU negotiated for the connection, if the stack reports it
+    async fn mtu(&self) -> Result<Option<u16>>;
+}
+
+// Finds and connects to a BLE-MIDI device. Used by the bridge for the initial
+// connection and again after the device is lost.
+#[async_trait]
+pub trait BleConnector: Send + Sync {
+    async fn connect(&self) -> Result<Arc<dyn BleLink>>;
+}
+
+// Connects to a device found by a BLE scan: one matching the earliest of `filters`
+// (by default the LPK25, then any AKAI device), ignoring those weaker than `min_rssi`,
+// with `multi_match` choosing between several matches of the same filter
+pub struct DeviceScanner {
+    pub scan_timeout: Duration,
+    pub filters: Vec<DeviceFilter>,
+    pub multi_match: MultiMatch,
+    pub min_rssi: Option<i16>,
+}
+
+#[async_trait]
+impl BleConnector for DeviceScanner {
+    async fn connect(&self) -> Result<Arc<dyn BleLink>> {
+        Ok(Arc::new(BleDevice::discover(self.scan_timeout, &self.filters, self.multi_match, self.min_rssi).await?))
+    }
+}
+
+pub fn find_characteristic(services: &BTreeSet<Service>, uuid: Uuid) -> Result<Characteristic> {
+    services
+        .iter()
+        .flat_map(|service| service.characteristics.iter())
+        .find(|characteristic| characteristic.uuid == uuid)
+        .cloned()
+        .ok_or_else(|| anyhow!("Characteristic not found: {}", uuid))
+}
+
+// Read a characteristic of a connected device once, e.g. to see what it returns
+pub async fn read_characteristic(link: &dyn BleLink, uuid: Uuid) -> Result<Vec<u8>> {
+    let characteristic = find_characteristic(&link.services(), uuid)?;
+    link.read(&characteristic).await
+}
+
+// Largest BLE-MIDI packet one write to the device may carry: `max_write_len` if set,
+// otherwise what the connection's MTU allows, or a conservative 20 bytes when the MTU
+// isn't known
+pub async fn max_write_len(link: &dyn BleLink, max_write_len: Option<usize>) -> usize {
+    if let Some(max) = max_write_len {
+        return max;
+    }
+    match link.mtu().await {
+        Ok(Some(mtu)) => packet::max_packet_len(mtu).max(packet::DEFAULT_MAX_PACKET_LEN),
+        Ok(None) => packet::DEFAULT_MAX_PACKET_LEN,
+        Err(e) => {
+            debug!("Could not get the MTU: {}", e);
+            packet::DEFAULT_MAX_PACKET_LEN
+        }
+    }
+}
+
+// A text characteristic such as the Device Information Service's manufacturer name.
+// None if the device doesn't have it, the read fails or it is empty, since this is
+// only ever informational.
+pub async fn read_string_characteristic(link: &dyn BleLink, uuid: Uuid) -> Option<String> {
+    let characteristic = find_characteristic(&link.services(), uuid).ok()?;
+    let value = match link.read(&characteristic).await {
+        Ok(value) => value,
+        Err(e) => {
+            debug!("Could not read {}: {}", uuid, e);
+            return None;
+        }
+    };
+    // Some devices pad the string with NULs
+    let text = String::from_utf8_lossy(&value).trim_end_matches('\0').trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+// Open the notification stream *before* enabling notifications on the device.
+// Some stacks start delivering packets as soon as the subscribe request completes,
+// and anything received before the stream exists is lost. Taking the stream first
+// means those early packets are buffered in it until the caller starts polling.
+pub async fn subscribe_notifications<S>(source: &S, characteristic: &Characteristic) -> Result<NotificationStream>
+where
+    S: NotificationSource + ?Sized,
+{
+    subscribe_notifications_with_retry(source, characteristic, 1, Duration::ZERO).await
+}
+
+// Like `subscribe_notifications`, but retries a failed subscribe up to `attempts` times
+// in total, waiting `delay` in between. Some stacks intermittently fail the first
+// subscribe after connecting and succeed on the next one.
+pub async fn subscribe_notifications_with_retry<S>(
+    source: &S,
+    characteristic: &Characteristic,
+    attempts: u32,
+    delay: Duration,
+) -> Result<NotificationStream>
+where
+    S: NotificationSource + ?Sized,
+{
+    let notifications = source.notifications().await?;
+    let attempts = attempts.max(1);
+    for attempt in 1.. {
+        match source.subscribe(characteristic).await {
+            Ok(()) => break,
+            Err(e) if attempt < attempts => {
+                warn!("Subscribe attempt {}/{} failed: {} - retrying in {:?}", attempt, attempts, e, delay);
+                time::sleep(delay).await;
+            }
+            Err(e) => return Err(e.context(format!("Subscribe failed after {} attempt(s)", attempts))),
+        }
+    }
+    Ok(notifications)
+}
+
+// Ticker for the keep-alive task. The first tick fires after `initial_delay` (immediately
+// when zero), then every `interval`. Some devices disconnect if they are read too soon
+// after subscribing, which the initial delay works around.
+pub fn keepalive_ticker(interval: Duration, initial_delay: Duration) -> time::Interval {
+    time::interval_at(time::Instant::now() + initial_delay, interval)
+}
+
+// How the keep-alive exercises the link
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeepaliveMode {
+    // Read the characteristic
+    #[default]
+    Read,
+    // Write a header-only BLE-MIDI packet to it. A write without response needs no
+    // answer back from the device, so it holds up better on a weak link.
+    Write,
+}
+
+// Periodically read or write the characteristic so the device doesn't drop an idle
+// connection. Non-empty values read are passed to `reads` when given. The task runs until
+// the returned handle is aborted. Fails if the device doesn't have the characteristic,
+// e.g. after a partial service discovery.
+pub fn start_keepalive(
+    link: Arc<dyn BleLink>,
+    characteristic_uuid: Uuid,
+    mode: KeepaliveMode,
+    interval: Duration,
+    initial_delay: Duration,
+    reads: Option<mpsc::Sender<Vec<u8>>>,
+) -> Result<JoinHandle<()>> {
+    let characteristic = find_characteristic(&link.services(), characteristic_uuid)?;
+
+    if !initial_delay.is_zero() {
+        info!("Delaying first keep-alive by {:?}", initial_delay);
+    }
+
+    Ok(tokio::spawn(async move {
+        let mut interval = keepalive_ticker(interval, initial_delay);
+        loop {
+            interval.tick().await;
+            if mode == KeepaliveMode::Write {
+                match link.write(&characteristic, &packet::header_only(packet::timestamp_now())).await {
+                    Ok(()) => debug!("Keep-alive write successful"),
+                    Err(e) => warn!("Keep-alive write failed: {}", e),
+                }
+                continue;
+            }
+            match link.read(&characteristic).await {
+                Ok(value) => {
+                    debug!("Keep-alive ping successful ({} bytes)", value.len());
+                    if let Some(reads) = &reads {
+                        if !value.is_empty() && reads.send(value).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+                Err(e) => warn!("Keep-alive read failed: {}", e),
+            }
+        }
+    }))
+}
+
 pub struct BleDevice {
     pub peripheral: Peripheral,
 }
 
 impl BleDevice {
-    pub async fn discover(scan_timeout: Duration) -> Result<Self> {
+    // Scan for the device matching the highest-priority filter. A match of the first
+    // filter is taken right away; otherwise the whole scan window is used to look for
+    // a better one than the best found so far.
+    pub async fn discover(
+        scan_timeout: Duration,
+        filters: &[DeviceFilter],
+        multi_match: MultiMatch,
+        min_rssi: Option<i16>,
+    ) -> Result<Self> {
         let manager = Manager::new().await?;
         let adapters = manager.adapters().await?;
         
@@ -34,24 +291,26 @@ impl BleDevice {
 
         let start_time = std::time::Instant::now();
 
-        // Poll for devices every second until we find our target or timeout
-        let mut found_peripheral = None;
+        // Poll for devices every second until nothing better can turn up or timeout
+        let mut best = BestMatch::with_multi_match(multi_match).with_min_rssi(min_rssi);
         while start_time.elapsed() < scan_timeout {
             let peripherals = central.peripherals().await?;
             for peripheral in peripherals {
                 if let Ok(Some(properties)) = peripheral.properties().await {
-                    if let Some(name) = properties.local_name {
-                        info!("Found device: {}", name);
-                        if name.contains("LPK25") || name.contains("AKAI") {
-                            info!("Found target device: {}", name);
-                            found_peripheral = Some(peripheral);
-                            break;
-                        }
+                    if let Some(name) = &properties.local_name {
+                        debug!("Found device: {}", name);
                     }
+                    let advertisement = Advertisement {
+                        name: properties.local_name,
+                        address: peripheral.address().to_string(),
+                        services: properties.services,
+                        rssi: properties.rssi,
+                    };
+                    best.offer(filters, &advertisement, peripheral);
                 }
             }
 
-            if found_peripheral.is_some() {
+            if best.is_final() {
                 break;
             }
 
@@ -62,8 +321,10 @@ impl BleDevice {
         // Stop scanning
         central.stop_scan().await?;
 
-        let peripheral = found_peripheral
-            .ok_or_else(|| anyhow!("Could not find LPK25 or AKAI device within {} seconds", scan_timeout.as_secs()))?;
+        let (tier, peripheral) = best.into_inner().ok_or_else(|| {
+            anyhow!("Could not find a device matching {:?} within {} seconds", filters, scan_timeout.as_secs())
+        })?;
+        info!("Selected device matching {:?} (priority {})", filters[tier], tier + 1);
 
         // Connect to device
         info!("Connecting to device...");
@@ -82,44 +343,110 @@ impl BleDevice {
             }
         }
 
-        Ok(BleDevice { peripheral })
+        let device = BleDevice { peripheral };
+        if let Some(manufacturer) = device.manufacturer_name().await {
+            info!("Manufacturer: {}", manufacturer);
+        }
+        if let Some(model) = device.model_number().await {
+            info!("Model: {}", model);
+        }
+        Ok(device)
+    }
+
+    // From the Device Information Service, if the device has one
+    pub async fn manufacturer_name(&self) -> Option<String> {
+        read_string_characteristic(self, MANUFACTURER_NAME_CHARACTERISTIC_UUID).await
+    }
+
+    pub async fn model_number(&self) -> Option<String> {
+        read_string_characteristic(self, MODEL_NUMBER_CHARACTERISTIC_UUID).await
     }
 
-    pub async fn start_keepalive(&self, characteristic_uuid: Uuid, interval: Duration) {
-        let peripheral_clone = self.peripheral.clone();
-        let characteristic = self.get_characteristic(characteristic_uuid).await
-            .expect("Characteristic should exist");
+    // Gather name, signal strength, services and battery level of the connected device
+    pub async fn info(&self) -> Result<DeviceInfo> {
+        let properties = self.peripheral.properties().await?.unwrap_or_default();
 
-        tokio::spawn(async move {
-            let mut interval = time::interval(interval);
-            loop {
-                interval.tick().await;
-                if let Err(e) = peripheral_clone.read(&characteristic).await {
-                    warn!("Keep-alive read failed: {}", e);
-                } else {
-                    debug!("Keep-alive ping successful");
+        // The battery level is optional and a failed read shouldn't hide everything else
+        let battery_level = match self.get_characteristic(BATTERY_LEVEL_CHARACTERISTIC_UUID).await {
+            Ok(characteristic) => match self.peripheral.read(&characteristic).await {
+                Ok(value) => value.first().copied(),
+                Err(e) => {
+                    debug!("Could not read battery level: {}", e);
+                    None
                 }
-            }
-        });
+            },
+            Err(_) => None,
+        };
+
+        Ok(DeviceInfo::from_parts(
+            properties.local_name,
+            self.peripheral.address().to_string(),
+            properties.rssi,
+            &self.peripheral.services(),
+            battery_level,
+        )
+        .with_device_information(self.manufacturer_name().await, self.model_number().await))
     }
 
     pub async fn get_characteristic(&self, uuid: Uuid) -> Result<btleplug::api::Characteristic> {
-        for service in self.peripheral.services() {
-            for characteristic in service.characteristics {
-                if characteristic.uuid == uuid {
-                    return Ok(characteristic);
-                }
-            }
-        }
-        Err(anyhow!("Characteristic not found: {}", uuid))
+        find_characteristic(&self.peripheral.services(), uuid)
+    }
+
+    pub async fn read_characteristic(&self, uuid: Uuid) -> Result<Vec<u8>> {
+        read_characteristic(self, uuid).await
+    }
+}
+
+#[async_trait]
+impl NotificationSource for BleDevice {
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        NotificationSource::subscribe(&self.peripheral, characteristic).await
+    }
+
+    async fn notifications(&self) -> Result<NotificationStream> {
+        NotificationSource::notifications(&self.peripheral).await
+    }
+
+    async fn is_connected(&self) -> Result<bool> {
+        NotificationSource::is_connected(&self.peripheral).await
+    }
+}
+
+#[async_trait]
+impl BleLink for BleDevice {
+    fn services(&self) -> BTreeSet<Service> {
+        self.peripheral.services()
+    }
+
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        Ok(self.peripheral.read(characteristic).await.map_err(BlipError::from)?)
+    }
+
+    async fn write(&self, characteristic: &Characteristic, data: &[u8]) -> Result<()> {
+        Ok(self.peripheral.write(characteristic, data, WriteType::WithoutResponse).await.map_err(BlipError::from)?)
+    }
+
+    async fn info(&self) -> Result<DeviceInfo> {
+        BleDevice::info(self).await
+    }
+
+    async fn rssi(&self) -> Result<Option<i16>> {
+        let properties = self.peripheral.properties().await.map_err(BlipError::from)?;
+        Ok(properties.and_then(|properties| properties.rssi))
+    }
+
+    async fn mtu(&self) -> Result<Option<u16>> {
+        // btleplug doesn't expose the negotiated MTU
+        Ok(None)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::sync::Arc;
-    use futures::stream;
+    use futures::channel::mpsc;
+    use testing::midi_characteristic;
+    use futures::StreamExt;
     use tokio::sync::Mutex;
 
     // Mock types for testing
@@ -153,10 +480,211 @@ mod tests {
         let mock_peripheral = MockPeripheral::new("AKAI LPK25");
         
         // Test connection
+        assert_eq!(mock_peripheral.name, "AKAI LPK25");
         mock_peripheral.mock_connect().await.unwrap();
         assert!(mock_peripheral.mock_is_connected().await.unwrap());
     }
 
+    // Notification source that, like some real stacks, starts sending packets
+    // the moment the subscribe call completes
+    struct MockNotificationSource {
+        calls: std::sync::Mutex<Vec<&'static str>>,
+        sender: mpsc::UnboundedSender<ValueNotification>,
+        receiver: std::sync::Mutex<Option<mpsc::UnboundedReceiver<ValueNotification>>>,
+    }
+
+    impl MockNotificationSource {
+        fn new() -> Self {
+            let (sender, receiver) = mpsc::unbounded();
+            Self {
+                calls: std::sync::Mutex::new(Vec::new()),
+                sender,
+                receiver: std::sync::Mutex::new(Some(receiver)),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl NotificationSource for MockNotificationSource {
+        async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+            self.calls.lock().unwrap().push("subscribe");
+            self.sender.unbounded_send(ValueNotification {
+                uuid: characteristic.uuid,
+                value: vec![0x80, 0x80, 0x90, 0x3C, 0x7F],
+            })?;
+            Ok(())
+        }
+
+        async fn notifications(&self) -> Result<NotificationStream> {
+            self.calls.lock().unwrap().push("notifications");
+            let receiver = self.receiver.lock().unwrap().take()
+                .ok_or_else(|| anyhow!("Notification stream already taken"))?;
+            Ok(Box::pin(receiver))
+        }
+
+        async fn is_connected(&self) -> Result<bool> {
+            Ok(true)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_acquired_before_subscribe() {
+        let source = MockNotificationSource::new();
+        let _notifications = subscribe_notifications(&source, &midi_characteristic()).await.unwrap();
+
+        assert_eq!(*source.calls.lock().unwrap(), vec!["notifications", "subscribe"]);
+    }
+
+    #[tokio::test]
+    async fn test_notification_during_subscribe_is_not_lost() {
+        let source = MockNotificationSource::new();
+        let mut notifications = subscribe_notifications(&source, &midi_characteristic()).await.unwrap();
+
+        let first = notifications.next().await.expect("early notification should be buffered");
+        assert_eq!(first.uuid, BLE_MIDI_CHARACTERISTIC_UUID);
+        assert_eq!(first.value, vec![0x80, 0x80, 0x90, 0x3C, 0x7F]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_subscribe_retries_transient_failures() {
+        let link = testing::FakeLink::new();
+        link.fail_next_subscribes(2);
+
+        let start = time::Instant::now();
+        let result = subscribe_notifications_with_retry(&link, &midi_characteristic(), 3, Duration::from_millis(100)).await;
+        assert!(result.is_ok());
+        assert_eq!(start.elapsed(), Duration::from_millis(200));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_subscribe_gives_up_after_attempts() {
+        let link = testing::FakeLink::new();
+        link.fail_next_subscribes(3);
+
+        let result = subscribe_notifications_with_retry(&link, &midi_characteristic(), 3, Duration::from_millis(100)).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_keepalive_first_tick_without_delay_is_immediate() {
+        let start = time::Instant::now();
+        let mut ticker = keepalive_ticker(Duration::from_secs(10), Duration::ZERO);
+
+        ticker.tick().await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        ticker.tick().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(10));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_keepalive_first_tick_is_deferred() {
+        let start = time::Instant::now();
+        let mut ticker = keepalive_ticker(Duration::from_secs(10), Duration::from_secs(3));
+
+        ticker.tick().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(3));
+        ticker.tick().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(13));
+    }
+
+    #[test]
+    fn test_find_characteristic() {
+        let services: BTreeSet<Service> = [testing::midi_service()].into_iter().collect();
+        assert_eq!(
+            find_characteristic(&services, BLE_MIDI_CHARACTERISTIC_UUID).unwrap(),
+            midi_characteristic()
+        );
+        assert!(find_characteristic(&services, BATTERY_LEVEL_CHARACTERISTIC_UUID).is_err());
+    }
+
+    #[test]
+    fn test_keepalive_without_characteristic_is_an_error() {
+        let link = Arc::new(testing::FakeLink::new());
+        let result =
+            start_keepalive(link, BATTERY_LEVEL_CHARACTERISTIC_UUID, KeepaliveMode::Read, Duration::from_secs(10), Duration::ZERO, None);
+        assert!(result.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_keepalive_passes_on_read_values() {
+        let link = Arc::new(testing::FakeLink::new());
+        link.set_read_value(&[0x80, 0x80, 0x90, 60, 100]);
+        let (sender, mut reads) = tokio::sync::mpsc::channel(4);
+        let keepalive = start_keepalive(
+            link.clone(),
+            BLE_MIDI_CHARACTERISTIC_UUID,
+            KeepaliveMode::Read,
+            Duration::from_secs(10),
+            Duration::ZERO,
+            Some(sender),
+        )
+        .unwrap();
+
+        assert_eq!(reads.recv().await.unwrap(), vec![0x80, 0x80, 0x90, 60, 100]);
+
+        // Empty reads (nothing buffered) aren't passed on
+        link.set_read_value(&[]);
+        assert!(time::timeout(Duration::from_secs(30), reads.recv()).await.is_err());
+        keepalive.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_write_keepalive_sends_header_only_packets() {
+        let link = Arc::new(testing::FakeLink::new());
+        let keepalive = start_keepalive(
+            link.clone(),
+            BLE_MIDI_CHARACTERISTIC_UUID,
+            KeepaliveMode::Write,
+            Duration::from_secs(10),
+            Duration::ZERO,
+            None,
+        )
+        .unwrap();
+
+        time::sleep(Duration::from_secs(25)).await;
+        let writes = link.writes();
+        assert_eq!(writes.len(), 3);
+        assert!(writes.iter().all(|write| packet::is_header_only(write)));
+        assert_eq!(link.read_count(), 0);
+        keepalive.abort();
+    }
+
+    #[tokio::test]
+    async fn test_read_characteristic() {
+        let link = testing::FakeLink::new();
+        link.set_read_value(&[0x80, 0x80]);
+
+        assert_eq!(read_characteristic(&link, BLE_MIDI_CHARACTERISTIC_UUID).await.unwrap(), vec![0x80, 0x80]);
+        assert!(read_characteristic(&link, BATTERY_LEVEL_CHARACTERISTIC_UUID).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_device_information() {
+        let link = testing::FakeLink::new().with_device_information("AKAI Professional\0", "LPK25 Wireless");
+        assert_eq!(read_string_characteristic(&link, MANUFACTURER_NAME_CHARACTERISTIC_UUID).await.as_deref(), Some("AKAI Professional"));
+        assert_eq!(read_string_characteristic(&link, MODEL_NUMBER_CHARACTERISTIC_UUID).await.as_deref(), Some("LPK25 Wireless"));
+
+        let info = link.info().await.unwrap();
+        assert_eq!(info.manufacturer_name.as_deref(), Some("AKAI Professional"));
+        assert_eq!(info.model_number.as_deref(), Some("LPK25 Wireless"));
+        assert!(info.services.contains(&DEVICE_INFORMATION_SERVICE_UUID));
+
+        // Devices without the service just don't say
+        let link = testing::FakeLink::new();
+        assert_eq!(read_string_characteristic(&link, MANUFACTURER_NAME_CHARACTERISTIC_UUID).await, None);
+        assert_eq!(link.info().await.unwrap().model_number, None);
+    }
+
+    #[tokio::test]
+    async fn test_max_write_len() {
+        let link = testing::FakeLink::new();
+        assert_eq!(max_write_len(&link, None).await, 20);
+        link.set_mtu(Some(185));
+        assert_eq!(max_write_len(&link, None).await, 182);
+        // The override wins over the MTU
+        assert_eq!(max_write_len(&link, Some(64)).await, 64);
+    }
+
     #[test]
     fn test_ble_uuids() {
         // Test that our UUIDs are correctly defined
@@ -0,0 +1,367 @@
+// BLE-MIDI packet codec, independent of the BLE stack and the MIDI backend so it can
+// be used on its own.
+//
+// Layout: [header] then repeated [timestamp-low] [status] [data...], where a message
+// may omit its status (running status) and then also its timestamp. Timestamp and
+// status bytes both have the high bit set and are told apart by position: at the start
+// of a message the first one is the timestamp. System Real-Time messages (with their
+// timestamp) may also appear in the middle of another message's data, and a SysEx
+// message has a timestamp before both its F0 and its F7.
+use anyhow::{anyhow, Result};
+use log::debug;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SYSEX_START: u8 = 0xF0;
+const SYSEX_END: u8 = 0xF7;
+const REAL_TIME_START: u8 = 0xF8;
+
+// Bytes of each ATT write taken by its own header rather than the value
+const ATT_WRITE_OVERHEAD: usize = 3;
+
+// Largest packet one write can carry with the default ATT MTU of 23 bytes
+pub const DEFAULT_MAX_PACKET_LEN: usize = 20;
+
+// Largest packet one write can carry on a connection with this ATT MTU
+pub fn max_packet_len(mtu: u16) -> usize {
+    (mtu as usize).saturating_sub(ATT_WRITE_OVERHEAD)
+}
+
+// A MIDI message with its BLE-MIDI timestamp
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimedMessage {
+    // Milliseconds, 13 bits wide, so it wraps every 8192 ms
+    pub timestamp: u16,
+    // Status and data bytes; SysEx includes the F0 and F7 (only F0 when a packet ends
+    // before the SysEx does)
+    pub bytes: Vec<u8>,
+}
+
+impl TimedMessage {
+    pub fn new(timestamp: u16, bytes: impl Into<Vec<u8>>) -> Self {
+        TimedMessage { timestamp, bytes: bytes.into() }
+    }
+}
+
+// The 6 high timestamp bits carried by a packet header, or None if it isn't a valid header
+pub fn header_timestamp(header: u8) -> Option<u8> {
+    (header & 0x80 != 0).then_some(header & 0x3F)
+}
+
+// A packet of just a header and a timestamp, carrying no MIDI. Some devices send these
+// periodically to keep the link up.
+pub fn is_header_only(packet: &[u8]) -> bool {
+    matches!(packet, [header, timestamp] if header_timestamp(*header).is_some() && timestamp & 0x80 != 0)
+}
+
+// A header-only packet for `timestamp`, as written by the write-based keep-alive
+pub fn header_only(timestamp: u16) -> [u8; 2] {
+    [0x80 | ((timestamp >> 7) as u8 & 0x3F), 0x80 | (timestamp as u8 & 0x7F)]
+}
+
+// Number of data bytes that follow a status byte (SysEx aside)
+fn data_len(status: u8) -> usize {
+    match status & 0xF0 {
+        0x80 | 0x90 | 0xA0 | 0xB0 | 0xE0 => 2,
+        0xC0 | 0xD0 => 1,
+        _ => match status {
+            0xF2 => 2,
+            0xF1 | 0xF3 => 1,
+            _ => 0,
+        },
+    }
+}
+
+// Running status as defined by the MIDI spec: channel voice statuses set it,
+// System Common messages (0xF0-0xF7) clear it and System Real-Time messages
+// (0xF8-0xFE) leave it untouched. A System Reset (0xFF) starts over, clearing it too.
+#[derive(Debug, Default)]
+struct RunningStatus(Option<u8>);
+
+impl RunningStatus {
+    fn update(&mut self, status: u8) {
+        match status {
+            0x80..=0xEF => self.0 = Some(status),
+            0xF0..=0xF7 | 0xFF => self.0 = None,
+            _ => {}
+        }
+    }
+}
+
+// Timestamp for a packet sent now: the milliseconds of the wall clock, 13 bits wide
+pub fn timestamp_now() -> u16 {
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    (millis & 0x1FFF) as u16
+}
+
+// Full timestamps from the header's high bits and each timestamp byte's low bits. A
+// low part smaller than the previous one means the low bits wrapped around.
+struct TimestampClock {
+    high: u8,
+    last_low: Option<u8>,
+}
+
+impl TimestampClock {
+    fn stamp(&mut self, byte: u8) -> u16 {
+        let low = byte & 0x7F;
+        if self.last_low.is_some_and(|last| low < last) {
+            self.high = (self.high + 1) & 0x3F;
+        }
+        self.last_low = Some(low);
+        (self.high as u16) << 7 | low as u16
+    }
+}
+
+// Decode every message in a packet, in packet order. Messages interrupted by System
+// Real-Time messages come out after them.
+pub fn decode(packet: &[u8]) -> Result<Vec<TimedMessage>> {
+    if packet.len() < 2 {
+        return Err(anyhow!("BLE-MIDI packet too short"));
+    }
+    let high = header_timestamp(packet[0])
+        .ok_or_else(|| anyhow!("Invalid BLE-MIDI header byte 0x{:02X}", packet[0]))?;
+
+    let mut clock = TimestampClock { high, last_low: None };
+    let mut timestamp = (high as u16) << 7;
+    let mut messages = Vec::new();
+    let mut running_status = RunningStatus::default();
+    let mut i = 1;
+
+    while i < packet.len() {
+        // Where a message starts, a byte with the high bit set is always its timestamp,
+        // even if it looks like a status byte; the status (if any) comes after it
+        if packet[i] & 0x80 != 0 {
+            timestamp = clock.stamp(packet[i]);
+            i += 1;
+            if i >= packet.len() {
+                break;
+            }
+        }
+
+        let status = if packet[i] & 0x80 != 0 {
+            let status = packet[i];
+            i += 1;
+            status
+        } else if let Some(status) = running_status.0 {
+            status
+        } else {
+            debug!("Skipping data byte 0x{:02X} without running status", packet[i]);
+            i += 1;
+            continue;
+        };
+        running_status.update(status);
+
+        let mut bytes = vec![status];
+        if status == SYSEX_START {
+            // Data up to the end marker, which has its own timestamp
+            while let Some(&byte) = packet.get(i) {
+                if byte & 0x80 == 0 {
+                    bytes.push(byte);
+                    i += 1;
+                    continue;
+                }
+                match packet.get(i + 1) {
+                    Some(&SYSEX_END) => {
+                        clock.stamp(byte);
+                        bytes.push(SYSEX_END);
+                        i += 2;
+                        break;
+                    }
+                    Some(&real_time) if real_time >= REAL_TIME_START => {
+                        messages.push(TimedMessage::new(clock.stamp(byte), [real_time]));
+                        i += 2;
+                    }
+                    _ => return Err(anyhow!("Unexpected byte 0x{:02X} in SysEx", byte)),
+                }
+            }
+        } else {
+            while bytes.len() <= data_len(status) {
+                match packet.get(i) {
+                    Some(&byte) if byte & 0x80 == 0 => {
+                        bytes.push(byte);
+                        i += 1;
+                    }
+                    // Inside a message, a high-bit byte can only be the timestamp of a
+                    // System Real-Time message interrupting it
+                    Some(&byte) if packet.get(i + 1).is_some_and(|&next| next >= REAL_TIME_START) => {
+                        messages.push(TimedMessage::new(clock.stamp(byte), [packet[i + 1]]));
+                        i += 2;
+                    }
+                    _ => return Err(anyhow!("Truncated MIDI message with status 0x{:02X}", status)),
+                }
+            }
+        }
+        messages.push(TimedMessage { timestamp, bytes });
+    }
+
+    Ok(messages)
+}
+
+// Encode messages into one packet, each with its timestamp and status byte (no running
+// status). Timestamps should not decrease and span less than 128 ms, so the receiver
+// can follow the low bits wrapping around.
+pub fn encode(messages: &[TimedMessage]) -> Vec<u8> {
+    let first = messages.first().map_or(0, |message| message.timestamp);
+    let mut packet = vec![0x80 | ((first >> 7) as u8 & 0x3F)];
+    if messages.is_empty() {
+        packet.push(0x80);
+    }
+    for message in messages {
+        let timestamp = 0x80 | (message.timestamp as u8 & 0x7F);
+        packet.push(timestamp);
+        match message.bytes.split_last() {
+            Some((&SYSEX_END, body)) if message.bytes[0] == SYSEX_START => {
+                packet.extend_from_slice(body);
+                packet.extend_from_slice(&[timestamp, SYSEX_END]);
+            }
+            _ => packet.extend_from_slice(&message.bytes),
+        }
+    }
+    packet
+}
+
+// Split one SysEx message (F0 through F7) across as many packets of at most
+// `max_packet_len` bytes as it takes. The first packet holds the timestamp and F0, the
+// following ones continue the data right after their header, and the last one ends with
+// a timestamp and F7. `max_packet_len` is at least 3, enough for a header, timestamp and
+// status byte.
+pub fn encode_sysex(sysex: &[u8], timestamp: u16, max_packet_len: usize) -> Vec<Vec<u8>> {
+    let max_packet_len = max_packet_len.max(3);
+    let header = 0x80 | ((timestamp >> 7) as u8 & 0x3F);
+    let timestamp = 0x80 | (timestamp as u8 & 0x7F);
+    let data = sysex.strip_prefix(&[SYSEX_START]).unwrap_or(sysex);
+    let data = data.strip_suffix(&[SYSEX_END]).unwrap_or(data);
+
+    let mut packets = Vec::new();
+    let mut packet = vec![header, timestamp, SYSEX_START];
+    for &byte in data {
+        if packet.len() == max_packet_len {
+            packets.push(std::mem::replace(&mut packet, vec![header]));
+        }
+        packet.push(byte);
+    }
+    if packet.len() + 2 > max_packet_len {
+        packets.push(std::mem::replace(&mut packet, vec![header]));
+    }
+    packet.extend_from_slice(&[timestamp, SYSEX_END]);
+    packets.push(packet);
+    packets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timed(timestamp: u16, bytes: &[u8]) -> TimedMessage {
+        TimedMessage::new(timestamp, bytes)
+    }
+
+    fn round_trip(messages: &[TimedMessage]) {
+        assert_eq!(decode(&encode(messages)).unwrap(), messages);
+    }
+
+    #[test]
+    fn test_round_trips() {
+        round_trip(&[timed(5, &[0x90, 60, 100])]);
+        round_trip(&[timed(5, &[0x90, 60, 100]), timed(6, &[0xB0, 1, 64]), timed(6, &[0xC0, 5]), timed(9, &[0xF8])]);
+        round_trip(&[timed(10, &[0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7]), timed(11, &[0x90, 60, 100])]);
+        // Low bits wrapping between messages, and a high part from the header
+        round_trip(&[timed(126, &[0x90, 60, 100]), timed(130, &[0x80, 60, 0])]);
+        round_trip(&[timed(0x1FFE, &[0xE0, 0, 64])]);
+        round_trip(&[]);
+    }
+
+    #[test]
+    fn test_decode_running_status() {
+        // The second message has no status, the third neither timestamp nor status
+        let packet = [0x81, 0x85, 0x90, 60, 100, 0x86, 62, 100, 64, 100];
+        assert_eq!(
+            decode(&packet).unwrap(),
+            [timed(133, &[0x90, 60, 100]), timed(134, &[0x90, 62, 100]), timed(134, &[0x90, 64, 100])]
+        );
+    }
+
+    #[test]
+    fn test_system_reset_clears_running_status() {
+        // The data after the Reset would only make sense with running status
+        let packet = [0x80, 0x80, 0x90, 60, 100, 0x81, 0xFF, 62, 100];
+        assert_eq!(decode(&packet).unwrap(), [timed(0, &[0x90, 60, 100]), timed(1, &[0xFF])]);
+    }
+
+    #[test]
+    fn test_decode_sysex() {
+        let packet = [0x80, 0x81, 0xF0, 0x7E, 0x7F, 0x83, 0xF8, 0x06, 0x84, 0xF7, 0x85, 0xC0, 5];
+        assert_eq!(
+            decode(&packet).unwrap(),
+            [timed(3, &[0xF8]), timed(1, &[0xF0, 0x7E, 0x7F, 0x06, 0xF7]), timed(5, &[0xC0, 5])]
+        );
+        // Continued in the next packet
+        assert_eq!(decode(&[0x80, 0x81, 0xF0, 0x01, 0x02]).unwrap(), [timed(1, &[0xF0, 0x01, 0x02])]);
+        assert!(decode(&[0x80, 0x81, 0xF0, 0x01, 0x82, 0x90]).is_err());
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert!(decode(&[0x80]).is_err());
+        assert!(decode(&[0x00, 0x80, 0x90, 60, 100]).is_err());
+        assert!(decode(&[0x80, 0x80, 0x90, 60]).is_err());
+    }
+
+    #[test]
+    fn test_encode_sysex_across_packets() {
+        let data: Vec<u8> = (0..40).collect();
+        let sysex: Vec<u8> = [&[SYSEX_START], &data[..], &[SYSEX_END]].concat();
+        let packets = encode_sysex(&sysex, 0x85, 20);
+
+        assert_eq!(packets.len(), 3);
+        assert!(packets.iter().all(|packet| packet.len() <= 20 && packet[0] == 0x81));
+        assert_eq!(packets[0][..3], [0x81, 0x85, SYSEX_START]);
+        assert_eq!(packets[0][3..], data[..17]);
+        // Continuations carry data straight after the header
+        assert_eq!(packets[1][1..], data[17..36]);
+        assert_eq!(packets[2][1..], [&data[36..], &[0x85, SYSEX_END]].concat());
+        // A short one fits in a single packet, which decodes back to the message
+        let short = [SYSEX_START, 0x7E, 0x7F, 0x06, 0x01, SYSEX_END];
+        let packets = encode_sysex(&short, 3, 20);
+        assert_eq!(packets.len(), 1);
+        assert_eq!(decode(&packets[0]).unwrap(), [timed(3, &short)]);
+    }
+
+    #[test]
+    fn test_encode_sysex_within_the_mtu() {
+        let sysex: Vec<u8> = [&[SYSEX_START][..], &[0x2A; 1000], &[SYSEX_END]].concat();
+        for mtu in [23, 24, 27, 64, 185, 247, 517] {
+            let limit = max_packet_len(mtu);
+            let packets = encode_sysex(&sysex, 0x1234, limit);
+            assert!(packets.iter().all(|packet| packet.len() <= limit), "MTU {}", mtu);
+            // Every packet but the last is full
+            assert!(packets[..packets.len() - 1].iter().all(|packet| packet.len() == limit), "MTU {}", mtu);
+            let data = packets.iter().flatten().filter(|&&byte| byte == 0x2A).count();
+            assert_eq!(data, 1000, "MTU {}", mtu);
+        }
+        assert_eq!(max_packet_len(23), DEFAULT_MAX_PACKET_LEN);
+    }
+
+    #[test]
+    fn test_encode_sysex_end_in_its_own_packet() {
+        // The data fills the first packet exactly, leaving no room for the end marker
+        let packets = encode_sysex(&[SYSEX_START, 1, 2, SYSEX_END], 0, 5);
+        assert_eq!(packets, [vec![0x80, 0x80, SYSEX_START, 1, 2], vec![0x80, 0x80, SYSEX_END]]);
+    }
+
+    #[test]
+    fn test_header_timestamp() {
+        assert_eq!(header_timestamp(0x80), Some(0));
+        assert_eq!(header_timestamp(0xBF), Some(0x3F));
+        assert_eq!(header_timestamp(0x3F), None);
+    }
+
+    #[test]
+    fn test_header_only() {
+        assert!(is_header_only(&[0x80, 0x80]));
+        assert!(is_header_only(&[0xBF, 0xFF]));
+        assert!(decode(&[0x80, 0x80]).unwrap().is_empty());
+        assert!(!is_header_only(&[0x80, 0x80, 0xF8]));
+        assert!(!is_header_only(&[0x00, 0x80]));
+        assert!(!is_header_only(&[0x80, 0x3C]));
+    }
+}
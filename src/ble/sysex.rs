@@ -0,0 +1,95 @@
+use anyhow::{anyhow, Result};
+use log::info;
+use std::path::Path;
+use std::time::Duration;
+use tokio::time;
+
+use super::{find_characteristic, packet, BleLink, BLE_MIDI_CHARACTERISTIC_UUID};
+
+// Pause between writes, so a device with small buffers keeps up with a long dump
+pub const SYSEX_WRITE_INTERVAL: Duration = Duration::from_millis(10);
+
+// Split the contents of a .syx file into its SysEx messages. The file must start with
+// F0 and end with F7, and hold nothing but complete messages.
+pub fn split_sysex(data: &[u8]) -> Result<Vec<&[u8]>> {
+    if data.first() != Some(&0xF0) || data.last() != Some(&0xF7) {
+        return Err(anyhow!("SysEx data must start with F0 and end with F7"));
+    }
+    let mut messages = Vec::new();
+    let mut start = 0;
+    for (i, &byte) in data.iter().enumerate() {
+        match byte {
+            0xF0 if i == start => {}
+            0xF7 if i > start => {
+                messages.push(&data[start..=i]);
+                start = i + 1;
+            }
+            _ if byte & 0x80 == 0 && i > start => {}
+            _ => return Err(anyhow!("Unexpected byte 0x{:02X} at offset {} of SysEx data", byte, i)),
+        }
+    }
+    Ok(messages)
+}
+
+// Write SysEx messages to the device's MIDI characteristic, each split into packets of
+// at most `max_packet_len` bytes
+pub async fn send_sysex(link: &dyn BleLink, data: &[u8], max_packet_len: usize) -> Result<()> {
+    let messages = split_sysex(data)?;
+    let characteristic = find_characteristic(&link.services(), BLE_MIDI_CHARACTERISTIC_UUID)?;
+    let mut first = true;
+    for message in &messages {
+        for chunk in packet::encode_sysex(message, packet::timestamp_now(), max_packet_len) {
+            if !first {
+                time::sleep(SYSEX_WRITE_INTERVAL).await;
+            }
+            first = false;
+            link.write(&characteristic, &chunk).await?;
+        }
+    }
+    info!("Sent {} SysEx message(s), {} bytes", messages.len(), data.len());
+    Ok(())
+}
+
+// Read a .syx file and send it with `send_sysex`
+pub async fn send_sysex_file(link: &dyn BleLink, path: &Path, max_packet_len: usize) -> Result<()> {
+    let data = std::fs::read(path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+    send_sysex(link, &data, max_packet_len)
+        .await
+        .map_err(|e| anyhow!("Failed to send {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ble::testing::FakeLink;
+
+    #[test]
+    fn test_split_sysex() {
+        let data = [0xF0, 0x41, 0x10, 0xF7, 0xF0, 0x7E, 0xF7];
+        assert_eq!(split_sysex(&data).unwrap(), [&data[..4], &data[4..]]);
+        assert!(split_sysex(&[]).is_err());
+        assert!(split_sysex(&[0x41, 0xF7]).is_err());
+        assert!(split_sysex(&[0xF0, 0x41]).is_err());
+        // A status byte inside a message, and data between messages
+        assert!(split_sysex(&[0xF0, 0x90, 0xF7]).is_err());
+        assert!(split_sysex(&[0xF0, 0xF7, 0x41, 0xF0, 0xF7]).is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_large_sysex_is_written_in_chunks() {
+        let link = FakeLink::new();
+        let data: Vec<u8> = [&[0xF0][..], &[0x55; 100], &[0xF7]].concat();
+        let start = time::Instant::now();
+        send_sysex(&link, &data, 20).await.unwrap();
+
+        let writes = link.writes();
+        // 17 data bytes in the first packet, 19 in each of the next four, and the last 7
+        assert_eq!(writes.len(), 6);
+        assert!(writes.iter().all(|write| write.len() <= 20));
+        let data_bytes: usize = writes.iter().map(|write| write.iter().filter(|&&byte| byte == 0x55).count()).sum();
+        assert_eq!(data_bytes, 100);
+        assert_eq!(writes[0][2], 0xF0);
+        assert_eq!(writes[5].last(), Some(&0xF7));
+        assert_eq!(start.elapsed(), SYSEX_WRITE_INTERVAL * 5);
+    }
+}
@@ -0,0 +1,235 @@
+// Test doubles shared by the unit tests of several modules
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use btleplug::api::{CharPropFlags, Characteristic, Service, ValueNotification};
+use futures::channel::mpsc;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::error::BlipError;
+
+use super::{
+    read_string_characteristic, BleConnector, BleLink, DeviceInfo, NotificationSource, NotificationStream,
+    BLE_MIDI_CHARACTERISTIC_UUID, BLE_MIDI_SERVICE_UUID, DEVICE_INFORMATION_SERVICE_UUID,
+    MANUFACTURER_NAME_CHARACTERISTIC_UUID, MODEL_NUMBER_CHARACTERISTIC_UUID,
+};
+
+// In-memory BLE-MIDI device. Packets pushed with `send_packet` come out of the
+// notification stream; the connection state is controlled with `set_connected`.
+pub struct FakeLink {
+    connected: AtomicBool,
+    subscribe_failures: AtomicU32,
+    read_value: Mutex<Vec<u8>>,
+    reads: AtomicU32,
+    writes: Mutex<Vec<Vec<u8>>>,
+    // Send every write back as a notification after this delay
+    loopback: Mutex<Option<Duration>>,
+    rssi: Mutex<Option<i16>>,
+    mtu: Mutex<Option<u16>>,
+    // Values of particular characteristics, overriding `read_value`
+    characteristic_values: HashMap<Uuid, Vec<u8>>,
+    sender: mpsc::UnboundedSender<ValueNotification>,
+    receiver: Mutex<Option<mpsc::UnboundedReceiver<ValueNotification>>>,
+    services: BTreeSet<Service>,
+}
+
+impl FakeLink {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::unbounded();
+        FakeLink {
+            connected: AtomicBool::new(true),
+            subscribe_failures: AtomicU32::new(0),
+            read_value: Mutex::new(Vec::new()),
+            reads: AtomicU32::new(0),
+            writes: Mutex::new(Vec::new()),
+            loopback: Mutex::new(None),
+            rssi: Mutex::new(Some(-50)),
+            mtu: Mutex::new(None),
+            characteristic_values: HashMap::new(),
+            sender,
+            receiver: Mutex::new(Some(receiver)),
+            services: [midi_service()].into_iter().collect(),
+        }
+    }
+
+    // Add a Device Information Service reporting this manufacturer and model
+    pub fn with_device_information(mut self, manufacturer: &str, model: &str) -> Self {
+        let characteristics = [(MANUFACTURER_NAME_CHARACTERISTIC_UUID, manufacturer), (MODEL_NUMBER_CHARACTERISTIC_UUID, model)];
+        self.services.insert(Service {
+            uuid: DEVICE_INFORMATION_SERVICE_UUID,
+            primary: true,
+            characteristics: characteristics
+                .iter()
+                .map(|(uuid, _)| Characteristic {
+                    uuid: *uuid,
+                    service_uuid: DEVICE_INFORMATION_SERVICE_UUID,
+                    properties: CharPropFlags::READ,
+                    descriptors: BTreeSet::new(),
+                })
+                .collect(),
+        });
+        for (uuid, value) in characteristics {
+            self.characteristic_values.insert(uuid, value.as_bytes().to_vec());
+        }
+        self
+    }
+
+    pub fn send_packet(&self, value: &[u8]) {
+        self.sender
+            .unbounded_send(ValueNotification { uuid: BLE_MIDI_CHARACTERISTIC_UUID, value: value.to_vec() })
+            .expect("notification stream dropped");
+    }
+
+    pub fn set_connected(&self, connected: bool) {
+        self.connected.store(connected, Ordering::SeqCst);
+    }
+
+    // Value returned by reads of any characteristic
+    pub fn set_read_value(&self, value: &[u8]) {
+        *self.read_value.lock().unwrap() = value.to_vec();
+    }
+
+    // Characteristic reads so far, e.g. by the keep-alive
+    pub fn read_count(&self) -> u32 {
+        self.reads.load(Ordering::SeqCst)
+    }
+
+    // Every value written so far, in order
+    pub fn writes(&self) -> Vec<Vec<u8>> {
+        self.writes.lock().unwrap().clone()
+    }
+
+    // Echo writes back like a device looping its MIDI input to its output
+    pub fn set_loopback(&self, delay: Option<Duration>) {
+        *self.loopback.lock().unwrap() = delay;
+    }
+
+    pub fn set_rssi(&self, rssi: Option<i16>) {
+        *self.rssi.lock().unwrap() = rssi;
+    }
+
+    pub fn set_mtu(&self, mtu: Option<u16>) {
+        *self.mtu.lock().unwrap() = mtu;
+    }
+
+    // Make the next `count` subscribe calls fail
+    pub fn fail_next_subscribes(&self, count: u32) {
+        self.subscribe_failures.store(count, Ordering::SeqCst);
+    }
+}
+
+pub fn midi_characteristic() -> Characteristic {
+    Characteristic {
+        uuid: BLE_MIDI_CHARACTERISTIC_UUID,
+        service_uuid: BLE_MIDI_SERVICE_UUID,
+        properties: CharPropFlags::READ | CharPropFlags::NOTIFY | CharPropFlags::WRITE_WITHOUT_RESPONSE,
+        descriptors: BTreeSet::new(),
+    }
+}
+
+pub fn midi_service() -> Service {
+    Service {
+        uuid: BLE_MIDI_SERVICE_UUID,
+        primary: true,
+        characteristics: [midi_characteristic()].into_iter().collect(),
+    }
+}
+
+#[async_trait]
+impl NotificationSource for FakeLink {
+    async fn subscribe(&self, _characteristic: &Characteristic) -> Result<()> {
+        let failing = self.subscribe_failures
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok();
+        if failing {
+            let error = btleplug::Error::RuntimeError("Fake GATT error".to_string());
+            return Err(BlipError::from(error).into());
+        }
+        Ok(())
+    }
+
+    async fn notifications(&self) -> Result<NotificationStream> {
+        let receiver = self.receiver.lock().unwrap().take()
+            .ok_or_else(|| anyhow!("Notification stream already taken"))?;
+        Ok(Box::pin(receiver))
+    }
+
+    async fn is_connected(&self) -> Result<bool> {
+        Ok(self.connected.load(Ordering::SeqCst))
+    }
+}
+
+#[async_trait]
+impl BleLink for FakeLink {
+    fn services(&self) -> BTreeSet<Service> {
+        self.services.clone()
+    }
+
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        self.reads.fetch_add(1, Ordering::SeqCst);
+        match self.characteristic_values.get(&characteristic.uuid) {
+            Some(value) => Ok(value.clone()),
+            None => Ok(self.read_value.lock().unwrap().clone()),
+        }
+    }
+
+    async fn write(&self, _characteristic: &Characteristic, data: &[u8]) -> Result<()> {
+        self.writes.lock().unwrap().push(data.to_vec());
+        if let Some(delay) = *self.loopback.lock().unwrap() {
+            let sender = self.sender.clone();
+            let value = data.to_vec();
+            tokio::spawn(async move {
+                tokio::time::sleep(delay).await;
+                let _ = sender.unbounded_send(ValueNotification { uuid: BLE_MIDI_CHARACTERISTIC_UUID, value });
+            });
+        }
+        Ok(())
+    }
+
+    async fn info(&self) -> Result<DeviceInfo> {
+        let rssi = *self.rssi.lock().unwrap();
+        Ok(DeviceInfo::from_parts(
+            Some("Fake LPK25".to_string()),
+            "00:00:00:00:00:00".to_string(),
+            rssi,
+            &self.services,
+            None,
+        )
+        .with_device_information(
+            read_string_characteristic(self, MANUFACTURER_NAME_CHARACTERISTIC_UUID).await,
+            read_string_characteristic(self, MODEL_NUMBER_CHARACTERISTIC_UUID).await,
+        ))
+    }
+
+    async fn rssi(&self) -> Result<Option<i16>> {
+        Ok(*self.rssi.lock().unwrap())
+    }
+
+    async fn mtu(&self) -> Result<Option<u16>> {
+        Ok(*self.mtu.lock().unwrap())
+    }
+}
+
+// Hands out the given links in order, one per connection; fails once they run out
+#[derive(Default)]
+pub struct FakeConnector {
+    links: Mutex<VecDeque<Arc<FakeLink>>>,
+}
+
+impl FakeConnector {
+    pub fn new(links: impl IntoIterator<Item = Arc<FakeLink>>) -> Self {
+        FakeConnector { links: Mutex::new(links.into_iter().collect()) }
+    }
+}
+
+#[async_trait]
+impl BleConnector for FakeConnector {
+    async fn connect(&self) -> Result<Arc<dyn BleLink>> {
+        let link = self.links.lock().unwrap().pop_front()
+            .ok_or_else(|| anyhow!("No fake device left to connect to"))?;
+        Ok(link)
+    }
+}
@@ -0,0 +1,240 @@
+// End-to-end tests: a whole bridge running against a fake device and a recording
+// output, fed a scripted timeline of BLE-MIDI packets. Time is tokio's paused clock,
+// which the bridge's `SystemClock` follows, so timings come out exact.
+use anyhow::Result;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::{self, Instant};
+
+use super::*;
+use crate::ble::testing::{FakeConnector, FakeLink};
+use crate::midi::testing::FlakyPort;
+use std::sync::atomic::Ordering;
+
+// Output recording each message with the time it arrived, in ms since the harness started
+#[derive(Clone)]
+struct TimedSink {
+    start: Instant,
+    received: Arc<Mutex<Vec<(u64, MidiMessage)>>>,
+}
+
+impl MidiSink for TimedSink {
+    fn send_message(&self, message: &MidiMessage) -> Result<()> {
+        let elapsed = self.start.elapsed().as_millis() as u64;
+        self.received.lock().unwrap().push((elapsed, *message));
+        Ok(())
+    }
+}
+
+struct Harness {
+    bridge: Arc<BleMidiBridge>,
+    link: Arc<FakeLink>,
+    output: TimedSink,
+    start: Instant,
+    task: JoinHandle<Result<()>>,
+}
+
+impl Harness {
+    // A running bridge, connected and subscribed at time 0
+    async fn start(config: Config) -> Self {
+        Self::with_links(config, vec![Arc::new(FakeLink::new())]).await
+    }
+
+    // Connecting to `links` in turn: the first one, then the next on every reconnect
+    async fn with_links(config: Config, links: Vec<Arc<FakeLink>>) -> Self {
+        Self::build(config, links, None).await
+    }
+
+    // With a second output, `port`
+    async fn build(config: Config, links: Vec<Arc<FakeLink>>, port: Option<Box<dyn MidiSink>>) -> Self {
+        let start = Instant::now();
+        let output = TimedSink { start, received: Arc::default() };
+        let mut sinks = SinkSet::new();
+        sinks.add("output", Box::new(output.clone()));
+        if let Some(port) = port {
+            sinks.add("port", port);
+        }
+        let link = links[0].clone();
+        let connector = FakeConnector::new(links);
+        let bridge = Arc::new(BleMidiBridge::from_parts(&config, Box::new(connector), sinks));
+        let task = tokio::spawn({
+            let bridge = bridge.clone();
+            async move { bridge.start().await }
+        });
+        while bridge.state() != BridgeState::Connected {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        Harness { bridge, link, output, start, task }
+    }
+
+    // Let time pass up to `ms` after the start
+    async fn advance_to(&self, ms: u64) {
+        time::sleep_until(self.start + Duration::from_millis(ms)).await;
+    }
+
+    // Deliver a packet at `ms`
+    async fn packet_at(&self, ms: u64, packet: &[u8]) {
+        self.advance_to(ms).await;
+        self.link.send_packet(packet);
+    }
+
+    fn received(&self) -> Vec<(u64, MidiMessage)> {
+        self.output.received.lock().unwrap().clone()
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn note_on(channel: u8, note: u8, velocity: u8) -> MidiMessage {
+    MidiMessage { status: 0x90 | channel, data1: note, data2: velocity }
+}
+
+fn note_off(channel: u8, note: u8) -> MidiMessage {
+    MidiMessage { status: 0x80 | channel, data1: note, data2: 0 }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_transposed_chord_in_one_packet() {
+    let harness = Harness::start(Config { octave_offset: 1, ..Config::default() }).await;
+
+    // Two Note Ons, the second using running status and no timestamp of its own
+    harness.packet_at(0, &[0x80, 0x80, 0x90, 60, 100, 64, 90]).await;
+    // Both Note Offs as Note Ons at velocity 0, with their own timestamps
+    harness.packet_at(500, &[0x80, 0x81, 0x90, 60, 0, 0x82, 64, 0]).await;
+    harness.packet_at(750, &[0x80, 0x80, 0xB0, 7, 80]).await;
+    harness.advance_to(1000).await;
+
+    assert_eq!(
+        harness.received(),
+        [
+            (0, note_on(0, 72, 100)),
+            (0, note_on(0, 76, 90)),
+            (500, MidiMessage { status: 0x90, data1: 72, data2: 0 }),
+            (500, MidiMessage { status: 0x90, data1: 76, data2: 0 }),
+            // Controllers aren't transposed
+            (750, MidiMessage::control_change(0, 7, 80)),
+        ]
+    );
+    assert!(harness.bridge.held_notes().is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_ghost_notes_are_debounced() {
+    let config = Config { min_note_duration: Some(Duration::from_millis(30)), ..Config::default() };
+    let harness = Harness::start(config).await;
+
+    // Released within 30 ms: never heard
+    harness.packet_at(0, &[0x80, 0x80, 0x90, 60, 100]).await;
+    harness.packet_at(10, &[0x80, 0x80, 0x80, 60, 0]).await;
+    // Held long enough: sent late by the debounce time
+    harness.packet_at(100, &[0x80, 0x80, 0x90, 62, 100]).await;
+    harness.packet_at(300, &[0x80, 0x80, 0x80, 62, 0]).await;
+    harness.advance_to(400).await;
+
+    assert_eq!(harness.received(), [(130, note_on(0, 62, 100)), (300, note_off(0, 62))]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_stuck_notes_are_flushed() {
+    let config = Config { mute_toggle_cc: Some(20), octave_offset: -1, ..Config::default() };
+    let harness = Harness::start(config).await;
+
+    // The Note Off of 60 is lost, leaving it stuck until the mute flushes everything
+    harness.packet_at(0, &[0x80, 0x80, 0x90, 60, 100, 0x82, 0x91, 67, 100]).await;
+    harness.packet_at(200, &[0x80, 0x80, 0x81, 67, 0]).await;
+    harness.advance_to(210).await;
+    assert_eq!(harness.bridge.held_notes().len(), 1);
+    harness.packet_at(300, &[0x80, 0x80, 0xB0, 20, 127]).await;
+    // Dropped while muted, then back after unmuting
+    harness.packet_at(400, &[0x80, 0x80, 0x90, 62, 100]).await;
+    harness.packet_at(500, &[0x80, 0x80, 0xB0, 20, 127]).await;
+    harness.packet_at(600, &[0x80, 0x80, 0x90, 64, 100]).await;
+    harness.advance_to(700).await;
+
+    let mut expected = vec![(0, note_on(0, 48, 100)), (0, note_on(1, 55, 100)), (200, note_off(1, 55))];
+    expected.extend(MidiMessage::all_notes_off().into_iter().map(|message| (300, message)));
+    expected.push((600, note_on(0, 52, 100)));
+    assert_eq!(harness.received(), expected);
+    assert_eq!(harness.bridge.held_notes(), [HeldNote { note: 52, velocity: 100 }]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_forwarding_resumes_after_a_disconnect() {
+    let config = Config { reconnect_delay: Duration::from_millis(500), reconnect_attempts: Some(3), ..Config::default() };
+    let second = Arc::new(FakeLink::new());
+    let harness = Harness::with_links(config, vec![Arc::new(FakeLink::new()), second.clone()]).await;
+
+    harness.packet_at(100, &[0x80, 0x80, 0x90, 60, 100]).await;
+    harness.link.set_connected(false);
+    // Noticed at the status check a second later, then reconnected after the delay
+    harness.advance_to(1650).await;
+    assert_eq!(harness.bridge.state(), BridgeState::Connected);
+    // The note held across the drop is released through the new link
+    second.send_packet(&[0x80, 0x80, 0x80, 60, 0]);
+    harness.advance_to(1700).await;
+
+    assert_eq!(harness.received(), [(100, note_on(0, 60, 100)), (1650, note_off(0, 60))]);
+    assert_eq!(harness.bridge.reconnect_count(), 1);
+}
+
+// A MIDI port that can be unplugged, reopened per `recovery` when lost
+fn reopening_port(port: &FlakyPort, recovery: PortRecovery) -> Box<dyn MidiSink> {
+    Box::new(ReopeningSink::new("port", Box::new(port.clone()), port.opener(), recovery))
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_losing_the_midi_port_leaves_the_link_up() {
+    let port = FlakyPort::default();
+    let recovery = PortRecovery { retry_interval: Duration::from_millis(500), buffer: 16 };
+    let harness = Harness::build(Config::default(), vec![Arc::new(FakeLink::new())], Some(reopening_port(&port, recovery))).await;
+
+    harness.packet_at(0, &[0x80, 0x80, 0x90, 60, 100]).await;
+    harness.advance_to(50).await;
+    port.set_unplugged(true);
+    // More packets than the bridge tolerates errors for; the last 16 are held
+    for value in 0..20 {
+        harness.packet_at(100 + value as u64 * 10, &[0x80, 0x80, 0xB0, 1, value]).await;
+    }
+    harness.advance_to(400).await;
+    assert_eq!(harness.bridge.state(), BridgeState::Forwarding);
+    // The other output didn't miss anything
+    assert_eq!(harness.received().len(), 21);
+
+    // Reopened on the first message after the retry interval
+    port.set_unplugged(false);
+    harness.packet_at(700, &[0x80, 0x80, 0x80, 60, 0]).await;
+    harness.advance_to(800).await;
+
+    let mut expected = vec![note_on(0, 60, 100)];
+    expected.extend(MidiMessage::all_notes_off());
+    expected.extend((4..20).map(|value| MidiMessage::control_change(0, 1, value)));
+    expected.push(note_off(0, 60));
+    assert_eq!(port.output.messages(), expected);
+    assert_eq!(harness.bridge.reconnect_count(), 0);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_losing_the_link_leaves_the_midi_port_open() {
+    let port = FlakyPort::default();
+    let config = Config { reconnect_delay: Duration::from_millis(500), reconnect_attempts: Some(3), ..Config::default() };
+    let second = Arc::new(FakeLink::new());
+    let links = vec![Arc::new(FakeLink::new()), second.clone()];
+    let harness = Harness::build(config, links, Some(reopening_port(&port, PortRecovery::default()))).await;
+
+    harness.packet_at(100, &[0x80, 0x80, 0x90, 60, 100]).await;
+    harness.link.set_connected(false);
+    harness.advance_to(1650).await;
+    assert_eq!(harness.bridge.reconnect_count(), 1);
+    second.send_packet(&[0x80, 0x80, 0x80, 60, 0]);
+    harness.advance_to(1700).await;
+
+    // The port was never closed, so never reopened
+    assert_eq!(port.output.messages(), [note_on(0, 60, 100), note_off(0, 60)]);
+    assert_eq!(port.opens.load(Ordering::SeqCst), 0);
+}
@@ -0,0 +1,150 @@
+use anyhow::{Context, Result};
+use std::str::FromStr;
+use std::time::Duration;
+
+use super::Config;
+
+impl Config {
+    // Defaults overlaid with any BLIP_* environment variables
+    pub fn from_env() -> Result<Self> {
+        let mut config = Config::default();
+        config.apply_env()?;
+        Ok(config)
+    }
+
+    // Overlay the BLIP_* environment variables that are set onto this configuration
+    pub fn apply_env(&mut self) -> Result<()> {
+        self.apply_vars(|name| std::env::var(name).ok())
+    }
+
+    // Overlay variables looked up by name. Durations are whole seconds. The quirk
+    // profile is applied first so the other variables override it.
+    //
+    //   BLIP_QUIRK_PROFILE        lpk25, generic_compliant or non_compliant_timestamps
+    //   BLIP_PORT_NAME            virtual MIDI port name
+    //   BLIP_SCAN_TIMEOUT         BLE scan timeout
+    //   BLIP_KEEPALIVE            keep-alive interval
+    //   BLIP_STATUS_CHECK         connection status check interval
+    //   BLIP_RECONNECT_DELAY      pause before each reconnect attempt
+    //   BLIP_RECONNECT_ATTEMPTS   reconnect attempts before giving up
+    //   BLIP_OCTAVE_OFFSET        octave transposition (-11 to 11)
+    //   BLIP_INJECTION_CHANNEL    channel of injected messages (0-15)
+    //   BLIP_TCP_MIDI_LISTEN      address of the TCP MIDI stream, e.g. 0.0.0.0:5004
+    //   BLIP_LOG_TIMESTAMP        log timestamps: none, seconds, millis or rfc3339
+    pub fn apply_vars(&mut self, lookup: impl Fn(&str) -> Option<String>) -> Result<()> {
+        if let Some(profile) = lookup("BLIP_QUIRK_PROFILE") {
+            self.apply_quirk_profile(profile.trim().parse()?);
+        }
+        if let Some(name) = lookup("BLIP_PORT_NAME") {
+            self.virtual_midi_port_name = name;
+        }
+        if let Some(timeout) = parse_var(&lookup, "BLIP_SCAN_TIMEOUT")? {
+            self.ble_scan_timeout = Duration::from_secs(timeout);
+        }
+        if let Some(interval) = parse_var(&lookup, "BLIP_KEEPALIVE")? {
+            self.ble_keepalive_interval = Duration::from_secs(interval);
+        }
+        if let Some(interval) = parse_var(&lookup, "BLIP_STATUS_CHECK")? {
+            self.ble_status_check_interval = Duration::from_secs(interval);
+        }
+        if let Some(delay) = parse_var(&lookup, "BLIP_RECONNECT_DELAY")? {
+            self.reconnect_delay = Duration::from_secs(delay);
+        }
+        if let Some(attempts) = parse_var(&lookup, "BLIP_RECONNECT_ATTEMPTS")? {
+            self.reconnect_attempts = Some(attempts);
+        }
+        if let Some(offset) = parse_var(&lookup, "BLIP_OCTAVE_OFFSET")? {
+            self.octave_offset = offset;
+        }
+        if let Some(channel) = parse_var(&lookup, "BLIP_INJECTION_CHANNEL")? {
+            self.injection_channel = channel;
+        }
+        if let Some(addr) = parse_var(&lookup, "BLIP_TCP_MIDI_LISTEN")? {
+            self.tcp_midi_listen = Some(addr);
+        }
+        if let Some(format) = lookup("BLIP_LOG_TIMESTAMP") {
+            self.log_timestamp = format.trim().parse()?;
+        }
+        self.validate()
+    }
+}
+
+fn parse_var<T>(lookup: &impl Fn(&str) -> Option<String>, name: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match lookup(name) {
+        Some(value) => value
+            .trim()
+            .parse()
+            .map(Some)
+            .with_context(|| format!("Invalid value '{}' for {}", value, name)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bridge::LogTimestamp;
+    use std::collections::HashMap;
+
+    fn apply(vars: &[(&str, &str)]) -> Result<Config> {
+        let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let mut config = Config::default();
+        config.apply_vars(|name| vars.get(name).cloned())?;
+        Ok(config)
+    }
+
+    #[test]
+    fn test_vars_overlay_defaults() {
+        let config = apply(&[
+            ("BLIP_PORT_NAME", "Studio"),
+            ("BLIP_SCAN_TIMEOUT", "5"),
+            ("BLIP_OCTAVE_OFFSET", "-2"),
+            ("BLIP_RECONNECT_ATTEMPTS", "3"),
+            ("BLIP_TCP_MIDI_LISTEN", "127.0.0.1:5004"),
+            ("BLIP_LOG_TIMESTAMP", "Millis"),
+        ])
+        .unwrap();
+
+        assert_eq!(config.virtual_midi_port_name, "Studio");
+        assert_eq!(config.ble_scan_timeout, Duration::from_secs(5));
+        assert_eq!(config.octave_offset, -2);
+        assert_eq!(config.reconnect_attempts, Some(3));
+        assert_eq!(config.tcp_midi_listen, Some("127.0.0.1:5004".parse().unwrap()));
+        assert_eq!(config.log_timestamp, LogTimestamp::Millis);
+        // Unset variables keep the defaults
+        assert_eq!(config.ble_keepalive_interval, Config::default().ble_keepalive_interval);
+    }
+
+    #[test]
+    fn test_vars_override_quirk_profile() {
+        let config = apply(&[("BLIP_QUIRK_PROFILE", "lpk25"), ("BLIP_KEEPALIVE", "4")]).unwrap();
+        assert!(config.process_keepalive_reads);
+        assert_eq!(config.ble_keepalive_interval, Duration::from_secs(4));
+    }
+
+    #[test]
+    fn test_invalid_vars_are_rejected() {
+        assert!(apply(&[("BLIP_SCAN_TIMEOUT", "soon")]).is_err());
+        assert!(apply(&[("BLIP_OCTAVE_OFFSET", "12")]).is_err());
+        assert!(apply(&[("BLIP_INJECTION_CHANNEL", "16")]).is_err());
+        assert!(apply(&[("BLIP_LOG_TIMESTAMP", "nanos")]).is_err());
+    }
+
+    #[test]
+    fn test_from_env() {
+        // The only test touching the real environment, so it can't race with others
+        std::env::set_var("BLIP_PORT_NAME", "From Env");
+        std::env::set_var("BLIP_KEEPALIVE", "3");
+        let config = Config::from_env();
+        std::env::remove_var("BLIP_PORT_NAME");
+        std::env::remove_var("BLIP_KEEPALIVE");
+
+        let config = config.unwrap();
+        assert_eq!(config.virtual_midi_port_name, "From Env");
+        assert_eq!(config.ble_keepalive_interval, Duration::from_secs(3));
+    }
+}
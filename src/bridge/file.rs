@@ -0,0 +1,495 @@
+use anyhow::{anyhow, Context, Result};
+use serde::de::{DeserializeOwned, Error as _, IntoDeserializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::{Config, LogTimestamp, QuirkProfile};
+use crate::pipeline::ControlAction;
+
+// Settings a configuration file can set. Unset fields leave the configuration alone.
+// Durations are whole seconds, like the BLIP_* environment variables.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct ConfigOverlay {
+    // Applied before the other fields, which override it
+    pub quirk_profile: Option<QuirkProfile>,
+    pub port_name: Option<String>,
+    pub midi_port_wait: Option<u64>,
+    pub scan_timeout: Option<u64>,
+    pub keepalive: Option<u64>,
+    pub status_check: Option<u64>,
+    pub reconnect_delay: Option<u64>,
+    pub reconnect_attempts: Option<u32>,
+    pub octave_offset: Option<i8>,
+    pub injection_channel: Option<u8>,
+    pub max_notes_per_sec: Option<u32>,
+    pub panic_on_start: Option<bool>,
+    pub log_timestamp: Option<LogTimestamp>,
+    pub tcp_midi_listen: Option<SocketAddr>,
+    pub websocket_listen: Option<SocketAddr>,
+    pub named_pipe: Option<String>,
+    pub http_dashboard: Option<SocketAddr>,
+    pub csv_log: Option<PathBuf>,
+    pub max_write_len: Option<usize>,
+    // 128 note numbers, one for each incoming note
+    #[serde(default, deserialize_with = "deserialize_note_map", serialize_with = "serialize_note_map")]
+    pub note_map: Option<[u8; 128]>,
+    // Controller remapping, by hardware control name (see `QuirkProfile::controls`) or
+    // CC number: `[controls]` then `sustain = "toggle_mute"` or `"1" = { cc = 11 }`
+    #[serde(default, deserialize_with = "deserialize_controls", serialize_with = "serialize_controls")]
+    pub controls: Option<BTreeMap<u8, ControlAction>>,
+}
+
+pub(super) fn serialize_note_map<S: Serializer>(map: &Option<[u8; 128]>, serializer: S) -> Result<S::Ok, S::Error> {
+    map.as_ref().map(|notes| &notes[..]).serialize(serializer)
+}
+
+pub(super) fn deserialize_note_map<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<[u8; 128]>, D::Error> {
+    let Some(notes) = Option::<Vec<u8>>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    let count = notes.len();
+    notes
+        .try_into()
+        .map(Some)
+        .map_err(|_| D::Error::custom(format!("note_map needs 128 notes, got {}", count)))
+}
+
+fn serialize_controls<S: Serializer>(map: &Option<BTreeMap<u8, ControlAction>>, serializer: S) -> Result<S::Ok, S::Error> {
+    map.as_ref()
+        .map(|map| map.iter().map(|(cc, action)| (cc.to_string(), *action)).collect::<BTreeMap<_, _>>())
+        .serialize(serializer)
+}
+
+fn deserialize_controls<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<BTreeMap<u8, ControlAction>>, D::Error> {
+    let Some(controls) = Option::<BTreeMap<String, ControlAction>>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    controls
+        .into_iter()
+        .map(|(name, action)| match QuirkProfile::control_cc(&name) {
+            Some(cc) => Ok((cc, action)),
+            None => Err(D::Error::custom(format!("'{}' is neither a known control nor a CC number", name))),
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+// Maps keyed by MIDI number (CC, channel) as tables, whose keys have to be strings
+pub(super) mod u8_keys {
+    use super::*;
+
+    pub fn serialize<S: Serializer, V: Serialize>(map: &BTreeMap<u8, V>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(map.iter().map(|(key, value)| (key.to_string(), value)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, V: Deserialize<'de>>(deserializer: D) -> Result<BTreeMap<u8, V>, D::Error> {
+        BTreeMap::<String, V>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(key, value)| match key.parse() {
+                Ok(key) => Ok((key, value)),
+                Err(_) => Err(D::Error::custom(format!("'{}' isn't a number from 0 to 255", key))),
+            })
+            .collect()
+    }
+}
+
+// Maps keyed by an enum, written by name like TOML has to, but not read back as one
+pub(super) fn deserialize_enum_keys<'de, D, K, V>(deserializer: D) -> Result<BTreeMap<K, V>, D::Error>
+where
+    D: Deserializer<'de>,
+    K: DeserializeOwned + Ord,
+    V: Deserialize<'de>,
+{
+    BTreeMap::<String, V>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(key, value)| {
+            let key = K::deserialize(key.into_deserializer()).map_err(|e: serde::de::value::Error| D::Error::custom(e))?;
+            Ok((key, value))
+        })
+        .collect()
+}
+
+// `Config::channel_routing` as `[channel_routing]` then `"0" = { channel = 0, port = "Bass" }`
+pub(super) mod channel_routing {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[derive(Deserialize, Serialize)]
+    struct Destination {
+        channel: u8,
+        port: String,
+    }
+
+    pub fn serialize<S: Serializer>(routing: &HashMap<u8, (u8, String)>, serializer: S) -> Result<S::Ok, S::Error> {
+        let sorted: BTreeMap<u8, Destination> = routing
+            .iter()
+            .map(|(&source, (channel, port))| (source, Destination { channel: *channel, port: port.clone() }))
+            .collect();
+        u8_keys::serialize(&sorted, serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashMap<u8, (u8, String)>, D::Error> {
+        let routing: BTreeMap<u8, Destination> = u8_keys::deserialize(deserializer)?;
+        Ok(routing.into_iter().map(|(source, to)| (source, (to.channel, to.port))).collect())
+    }
+}
+
+impl ConfigOverlay {
+    // This overlay with the fields set in `other` taking precedence
+    pub fn merge(&self, other: &ConfigOverlay) -> ConfigOverlay {
+        ConfigOverlay {
+            quirk_profile: other.quirk_profile.or(self.quirk_profile),
+            port_name: other.port_name.clone().or_else(|| self.port_name.clone()),
+            midi_port_wait: other.midi_port_wait.or(self.midi_port_wait),
+            scan_timeout: other.scan_timeout.or(self.scan_timeout),
+            keepalive: other.keepalive.or(self.keepalive),
+            status_check: other.status_check.or(self.status_check),
+            reconnect_delay: other.reconnect_delay.or(self.reconnect_delay),
+            reconnect_attempts: other.reconnect_attempts.or(self.reconnect_attempts),
+            octave_offset: other.octave_offset.or(self.octave_offset),
+            injection_channel: other.injection_channel.or(self.injection_channel),
+            max_notes_per_sec: other.max_notes_per_sec.or(self.max_notes_per_sec),
+            panic_on_start: other.panic_on_start.or(self.panic_on_start),
+            log_timestamp: other.log_timestamp.or(self.log_timestamp),
+            tcp_midi_listen: other.tcp_midi_listen.or(self.tcp_midi_listen),
+            websocket_listen: other.websocket_listen.or(self.websocket_listen),
+            named_pipe: other.named_pipe.clone().or_else(|| self.named_pipe.clone()),
+            http_dashboard: other.http_dashboard.or(self.http_dashboard),
+            csv_log: other.csv_log.clone().or_else(|| self.csv_log.clone()),
+            max_write_len: other.max_write_len.or(self.max_write_len),
+            note_map: other.note_map.or(self.note_map),
+            controls: other.controls.clone().or_else(|| self.controls.clone()),
+        }
+    }
+
+    pub fn apply_to(&self, config: &mut Config) {
+        if let Some(profile) = self.quirk_profile {
+            config.apply_quirk_profile(profile);
+        }
+        if let Some(name) = &self.port_name {
+            config.virtual_midi_port_name = name.clone();
+        }
+        if let Some(wait) = self.midi_port_wait {
+            config.midi_port_wait = Some(Duration::from_secs(wait));
+        }
+        if let Some(timeout) = self.scan_timeout {
+            config.ble_scan_timeout = Duration::from_secs(timeout);
+        }
+        if let Some(interval) = self.keepalive {
+            config.ble_keepalive_interval = Duration::from_secs(interval);
+        }
+        if let Some(interval) = self.status_check {
+            config.ble_status_check_interval = Duration::from_secs(interval);
+        }
+        if let Some(delay) = self.reconnect_delay {
+            config.reconnect_delay = Duration::from_secs(delay);
+        }
+        if let Some(attempts) = self.reconnect_attempts {
+            config.reconnect_attempts = Some(attempts);
+        }
+        if let Some(offset) = self.octave_offset {
+            config.octave_offset = offset;
+        }
+        if let Some(channel) = self.injection_channel {
+            config.injection_channel = channel;
+        }
+        if let Some(max) = self.max_notes_per_sec {
+            // Zero turns the limit off
+            config.max_notes_per_sec = (max > 0).then_some(max);
+        }
+        if let Some(panic) = self.panic_on_start {
+            config.panic_on_start = panic;
+        }
+        if let Some(format) = self.log_timestamp {
+            config.log_timestamp = format;
+        }
+        if let Some(addr) = self.tcp_midi_listen {
+            config.tcp_midi_listen = Some(addr);
+        }
+        if let Some(addr) = self.websocket_listen {
+            config.websocket_listen = Some(addr);
+        }
+        if let Some(name) = &self.named_pipe {
+            config.named_pipe = Some(name.clone());
+        }
+        if let Some(addr) = self.http_dashboard {
+            config.http_dashboard = Some(addr);
+        }
+        if let Some(path) = &self.csv_log {
+            config.csv_log = Some(path.clone());
+        }
+        if let Some(max) = self.max_write_len {
+            config.max_write_len = Some(max);
+        }
+        if let Some(map) = self.note_map {
+            config.note_map = Some(map);
+        }
+        // On top of the quirk profile's controls
+        if let Some(controls) = &self.controls {
+            config.control_map.extend(controls);
+        }
+    }
+}
+
+// A configuration file: top-level settings shared by every profile, plus named
+// profiles overriding some of them.
+//
+//   port_name = "AKAI_LPK25_IN_BLE"
+//   octave_offset = 0
+//
+//   [profiles.song_a]
+//   octave_offset = -1
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ConfigFile {
+    #[serde(flatten)]
+    pub defaults: ConfigOverlay,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, ConfigOverlay>,
+    // A complete configuration, as `Config::save_file` writes it, replacing the one the
+    // file is applied to before the settings above are laid over it
+    #[serde(default)]
+    pub base: Option<Box<Config>>,
+}
+
+// What `Config::save_file` writes
+#[derive(Serialize)]
+struct SavedFile<'a> {
+    base: &'a Config,
+}
+
+impl ConfigFile {
+    pub fn parse(text: &str) -> Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read configuration file {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("Invalid configuration file {}", path.display()))
+    }
+
+    pub fn profile_names(&self) -> Vec<&str> {
+        self.profiles.keys().map(String::as_str).collect()
+    }
+
+    // The file's defaults with the named profile, if any, laid over them
+    pub fn resolve(&self, profile: Option<&str>) -> Result<ConfigOverlay> {
+        let Some(name) = profile else {
+            return Ok(self.defaults.clone());
+        };
+        let overrides = self.profiles.get(name).ok_or_else(|| {
+            let available = match self.profile_names() {
+                names if names.is_empty() => "none".to_string(),
+                names => names.join(", "),
+            };
+            anyhow!("Unknown profile '{}' (available profiles: {})", name, available)
+        })?;
+        Ok(self.defaults.merge(overrides))
+    }
+}
+
+impl Config {
+    // Overlay the settings of a configuration file, with the named profile applied
+    pub fn apply_file(&mut self, path: &Path, profile: Option<&str>) -> Result<()> {
+        let file = ConfigFile::load(path)?;
+        if let Some(base) = &file.base {
+            *self = Config::clone(base);
+        }
+        file.resolve(profile)?.apply_to(self);
+        self.validate()
+    }
+
+    // Overlay the configuration file and the environment, as at startup: `file` if given,
+    // otherwise `default_file` if it exists. A file or profile asked for must exist.
+    // Returns the file read, if any.
+    pub fn load(&mut self, default_file: &Path, file: Option<&Path>, profile: Option<&str>) -> Result<Option<PathBuf>> {
+        let required = file.is_some() || profile.is_some();
+        let path = file.unwrap_or(default_file);
+        let loaded = if required || path.exists() {
+            self.apply_file(path, profile)?;
+            Some(path.to_path_buf())
+        } else {
+            None
+        };
+        self.apply_env()?;
+        Ok(loaded)
+    }
+
+    // Write the whole configuration to `path`, as a `base` table that `apply_file` reads
+    // back to exactly this configuration. Durations are written in seconds with their
+    // fractions. Settings a file can't hold are an error rather than left out.
+    pub fn save_file(&self, path: &Path) -> Result<()> {
+        // Through a `Value`, which writes plain keys before tables as TOML needs
+        let file = toml::Value::try_from(SavedFile { base: self }).context("Cannot represent the configuration as a file")?;
+        let text = toml::to_string(&file)?;
+        std::fs::write(path, text).with_context(|| format!("Cannot write configuration file {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    use crate::ble::DeviceFilter;
+    use crate::midi::{MidiMessage, PanicMode, PortRecovery, DIN_MIDI_MESSAGE_TIME};
+    use crate::pipeline::{
+        ArpConfig, ArpPattern, FreezeConfig, GateTrigger, MessageCategory, NormalizeConfig, NoteRepeatConfig, OctaveShiftTriggers, Scale,
+        ScaleConfig, Split, Trigger,
+    };
+    use super::super::FlapDetection;
+
+    const FILE: &str = r#"
+        port_name = "Studio"
+        octave_offset = 1
+        reconnect_attempts = 5
+
+        [profiles.song_a]
+        octave_offset = -2
+
+        [profiles.song_b]
+        port_name = "Live"
+        max_notes_per_sec = 0
+        log_timestamp = "millis"
+    "#;
+
+    #[test]
+    fn test_profile_overlays_file_defaults() {
+        let file = ConfigFile::parse(FILE).unwrap();
+        let mut config = Config::default();
+        file.resolve(Some("song_a")).unwrap().apply_to(&mut config);
+
+        assert_eq!(config.octave_offset, -2);
+        // Not set by the profile, so the file's default applies
+        assert_eq!(config.virtual_midi_port_name, "Studio");
+        assert_eq!(config.reconnect_attempts, Some(5));
+        // Set nowhere
+        assert_eq!(config.ble_scan_timeout, Config::default().ble_scan_timeout);
+    }
+
+    #[test]
+    fn test_without_profile_only_defaults_apply() {
+        let file = ConfigFile::parse(FILE).unwrap();
+        let mut config = Config::default();
+        file.resolve(None).unwrap().apply_to(&mut config);
+
+        assert_eq!(config.octave_offset, 1);
+        assert_eq!(config.max_notes_per_sec, Config::default().max_notes_per_sec);
+
+        let mut config = Config::default();
+        file.resolve(Some("song_b")).unwrap().apply_to(&mut config);
+        assert_eq!(config.virtual_midi_port_name, "Live");
+        assert_eq!(config.max_notes_per_sec, None);
+        assert_eq!(config.log_timestamp, LogTimestamp::Millis);
+    }
+
+    #[test]
+    fn test_unknown_profile_lists_available_ones() {
+        let file = ConfigFile::parse(FILE).unwrap();
+        let error = file.resolve(Some("song_c")).unwrap_err().to_string();
+        assert!(error.contains("song_c"));
+        assert!(error.contains("song_a, song_b"));
+    }
+
+    #[test]
+    fn test_saved_file_loads_back() {
+        let dir = std::env::temp_dir();
+        let id = std::process::id();
+        let (path, saved) = (dir.join(format!("blip-config-{}.toml", id)), dir.join(format!("blip-config-saved-{}.toml", id)));
+        let mut map = [0; 128];
+        map.iter_mut().enumerate().for_each(|(note, mapped)| *mapped = 127 - note as u8);
+        std::fs::write(&path, FILE).unwrap();
+        let mut config = Config {
+            note_map: Some(map),
+            csv_log: Some(dir.join("notes.csv")),
+            // Settings only the full configuration holds
+            device_filters: vec![DeviceFilter::Address("C4:2A:11:00:3B:9E".to_string()), DeviceFilter::MidiService],
+            keepalive_initial_delay: Duration::from_millis(250),
+            min_send_interval: Some(DIN_MIDI_MESSAGE_TIME),
+            flap_detection: Some(FlapDetection::default()),
+            octave_shift_triggers: Some(OctaveShiftTriggers { up: Trigger::ControlChange(21), down: Trigger::Note(0) }),
+            control_map: BTreeMap::from([(1, ControlAction::Cc(11)), (64, ControlAction::ToggleMute)]),
+            channel_banks: BTreeMap::from([(9, (1, 2))]),
+            gate: Some(GateTrigger::ControlChange { cc: 64, threshold: 64 }),
+            velocity_normalize: Some(NormalizeConfig { adaptation_rate: 0.05, reset_cc: Some(20) }),
+            choke_groups: vec![vec![42, 46], vec![49, 57]],
+            arpeggiator: Some(ArpConfig { rate: Duration::from_millis(125), pattern: ArpPattern::UpDown, gate: 0.5 }),
+            harmonize: Some(vec![4, 7, -12]),
+            scale_snap: Some(ScaleConfig { root: 9, scale: Scale::Minor }),
+            freeze: Some(FreezeConfig { channel: 15, sink: Some("Pad".to_string()), trigger_cc: Some(66) }),
+            note_repeat: Some(NoteRepeatConfig { rate: Duration::from_secs_f64(0.0625), notes: Some(vec![36, 38]) }),
+            pitch_bend_scale: 0.3,
+            splits: vec![Split { range: 0..=59, sink: "Bass".to_string() }, Split { range: 60..=127, sink: "Lead".to_string() }],
+            category_ports: BTreeMap::from([(MessageCategory::ControlChange, "Controls".to_string())]),
+            channel_routing: HashMap::from([(0, (0, "Bass".to_string())), (1, (0, "Lead".to_string()))]),
+            midi_port_recovery: Some(PortRecovery { retry_interval: Duration::from_millis(500), buffer: 16 }),
+            disconnect_cue: Some(vec![MidiMessage { status: 0x90, data1: 84, data2: 100 }]),
+            panic_mode: PanicMode::Both,
+            ..Config::default()
+        };
+        config.apply_file(&path, Some("song_b")).unwrap();
+
+        config.save_file(&saved).unwrap();
+        let mut reloaded = Config::default();
+        reloaded.apply_file(&saved, None).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&saved).unwrap();
+
+        assert_eq!(reloaded, config);
+        assert_eq!(reloaded.virtual_midi_port_name, "Live");
+        assert_eq!(reloaded.reconnect_attempts, Some(5));
+    }
+
+    #[test]
+    fn test_file_settings_apply_over_the_base() {
+        let base = toml::Value::try_from(SavedFile { base: &Config { octave_offset: 2, legato: true, ..Config::default() } }).unwrap();
+        let path = std::env::temp_dir().join(format!("blip-config-base-{}.toml", std::process::id()));
+        std::fs::write(&path, format!("octave_offset = -1\n\n{}", toml::to_string(&base).unwrap())).unwrap();
+        let mut config = Config { semitone_offset: 3, ..Config::default() };
+        config.apply_file(&path, None).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // The base replaces the configuration, then the top-level settings apply
+        assert!(config.legato);
+        assert_eq!(config.semitone_offset, 0);
+        assert_eq!(config.octave_offset, -1);
+    }
+
+    #[test]
+    fn test_controls_by_name_or_number() {
+        let file = ConfigFile::parse("quirk_profile = \"lpk25\"\n[controls]\nsustain = \"toggle_mute\"\n\"1\" = { cc = 11 }\n").unwrap();
+        let mut config = Config::default();
+        file.resolve(None).unwrap().apply_to(&mut config);
+        assert_eq!(config.control_map, BTreeMap::from([(1, ControlAction::Cc(11)), (64, ControlAction::ToggleMute)]));
+        assert!(ConfigFile::parse("[controls]\narp = \"ignore\"\n").is_err());
+    }
+
+    #[test]
+    fn test_merge_prefers_the_override() {
+        let base = ConfigOverlay { octave_offset: Some(1), keepalive: Some(5), ..ConfigOverlay::default() };
+        let overrides = ConfigOverlay { octave_offset: Some(-1), ..ConfigOverlay::default() };
+        let merged = base.merge(&overrides);
+        assert_eq!(merged.octave_offset, Some(-1));
+        assert_eq!(merged.keepalive, Some(5));
+    }
+
+    #[test]
+    fn test_note_map_is_a_list() {
+        let notes: Vec<String> = (0..128).rev().map(|note: u8| note.to_string()).collect();
+        let file = ConfigFile::parse(&format!("note_map = [{}]", notes.join(", "))).unwrap();
+        let mut config = Config::default();
+        file.resolve(None).unwrap().apply_to(&mut config);
+        assert_eq!(config.note_map.map(|map| (map[0], map[127])), Some((127, 0)));
+
+        let error = ConfigFile::parse("note_map = [1, 2, 3]").unwrap_err();
+        assert!(format!("{:#}", error).contains("128"), "{:#}", error);
+    }
+
+    #[test]
+    fn test_invalid_file_is_rejected() {
+        assert!(ConfigFile::parse("octave_offset = \"high\"").is_err());
+    }
+}
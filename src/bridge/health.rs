@@ -0,0 +1,210 @@
+use log::{info, warn};
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::time::Instant;
+
+// Debounces the periodic connection status check.
+//
+// A single failed `is_connected` poll can happen transiently (notably on Windows), so the
+// device is only treated as disconnected after `required` consecutive failed checks.
+// Any successful check resets the count.
+#[derive(Debug)]
+pub struct DisconnectDebouncer {
+    required: u32,
+    failures: u32,
+}
+
+impl DisconnectDebouncer {
+    pub fn new(required: u32) -> Self {
+        DisconnectDebouncer {
+            // Zero would mean "disconnected without any failed check"
+            required: required.max(1),
+            failures: 0,
+        }
+    }
+
+    // Record the result of one status check. Returns true once the disconnect is confirmed.
+    pub fn record(&mut self, connected: bool) -> bool {
+        if connected {
+            self.failures = 0;
+            return false;
+        }
+
+        self.failures += 1;
+        warn!(
+            "Connection status check failed ({}/{})",
+            self.failures, self.required
+        );
+        self.failures >= self.required
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+}
+
+// Detects a link flapping between connected and disconnected, e.g. at the edge of
+// range. Once more than `max_drops` drops happen within `window` the link counts as
+// unstable until the drops slow down again.
+#[derive(Debug)]
+pub struct FlapDetector {
+    max_drops: usize,
+    window: Duration,
+    drops: VecDeque<Instant>,
+    unstable: bool,
+}
+
+impl FlapDetector {
+    pub fn new(max_drops: u32, window: Duration) -> Self {
+        FlapDetector { max_drops: max_drops as usize, window, drops: VecDeque::new(), unstable: false }
+    }
+
+    // Record a drop of the link at `now`. Returns true while the link is unstable.
+    pub fn record(&mut self, now: Instant) -> bool {
+        while self.drops.front().is_some_and(|&t| now.duration_since(t) >= self.window) {
+            self.drops.pop_front();
+        }
+        self.drops.push_back(now);
+
+        let unstable = self.drops.len() > self.max_drops;
+        if unstable && !self.unstable {
+            warn!(
+                "Link unstable: {} disconnects within {:?} - backing off between reconnects",
+                self.drops.len(),
+                self.window
+            );
+        } else if !unstable && self.unstable {
+            info!("Link stable again");
+        }
+        self.unstable = unstable;
+        unstable
+    }
+
+    pub fn is_unstable(&self) -> bool {
+        self.unstable
+    }
+}
+
+// Signal strength as judged by `LinkAdapter`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkQuality {
+    Strong,
+    Weak,
+}
+
+// Judges the link from RSSI samples. It turns weak at or below `weak_rssi` and strong
+// again only at or above `strong_rssi`, so samples in between keep the current quality.
+#[derive(Debug)]
+pub struct LinkAdapter {
+    weak_rssi: i16,
+    strong_rssi: i16,
+    quality: LinkQuality,
+}
+
+impl LinkAdapter {
+    pub fn new(weak_rssi: i16, strong_rssi: i16) -> Self {
+        LinkAdapter { weak_rssi, strong_rssi, quality: LinkQuality::Strong }
+    }
+
+    // Record an RSSI sample (dBm). Returns the new quality when it changes.
+    pub fn sample(&mut self, rssi: i16) -> Option<LinkQuality> {
+        let quality = match self.quality {
+            LinkQuality::Strong if rssi <= self.weak_rssi => LinkQuality::Weak,
+            LinkQuality::Weak if rssi >= self.strong_rssi => LinkQuality::Strong,
+            quality => quality,
+        };
+        if quality == self.quality {
+            return None;
+        }
+        match quality {
+            LinkQuality::Weak => warn!("Weak signal (RSSI {} dBm)", rssi),
+            LinkQuality::Strong => info!("Signal strong again (RSSI {} dBm)", rssi),
+        }
+        self.quality = quality;
+        Some(quality)
+    }
+
+    pub fn quality(&self) -> LinkQuality {
+        self.quality
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_adapter_follows_scripted_rssi() {
+        let mut adapter = LinkAdapter::new(-80, -70);
+        let transitions: Vec<_> = [-60, -75, -81, -85, -75, -71, -70, -90]
+            .into_iter()
+            .map(|rssi| adapter.sample(rssi))
+            .collect();
+        assert_eq!(
+            transitions,
+            [
+                None,
+                // Between the thresholds nothing changes either way
+                None,
+                Some(LinkQuality::Weak),
+                None,
+                None,
+                None,
+                Some(LinkQuality::Strong),
+                Some(LinkQuality::Weak),
+            ]
+        );
+        assert_eq!(adapter.quality(), LinkQuality::Weak);
+    }
+
+    #[test]
+    fn test_single_confirmation_matches_previous_behavior() {
+        let mut debouncer = DisconnectDebouncer::new(1);
+        assert!(!debouncer.record(true));
+        assert!(debouncer.record(false));
+    }
+
+    #[test]
+    fn test_requires_consecutive_failures() {
+        let mut debouncer = DisconnectDebouncer::new(3);
+        assert!(!debouncer.record(false));
+        assert!(!debouncer.record(false));
+        assert_eq!(debouncer.failures(), 2);
+        assert!(debouncer.record(false));
+    }
+
+    #[test]
+    fn test_successful_check_resets_counter() {
+        let mut debouncer = DisconnectDebouncer::new(3);
+        // Spurious flaps separated by good polls never trip the disconnect
+        for _ in 0..5 {
+            assert!(!debouncer.record(false));
+            assert!(!debouncer.record(false));
+            assert!(!debouncer.record(true));
+            assert_eq!(debouncer.failures(), 0);
+        }
+    }
+
+    #[test]
+    fn test_zero_is_treated_as_one() {
+        let mut debouncer = DisconnectDebouncer::new(0);
+        assert!(!debouncer.record(true));
+        assert!(debouncer.record(false));
+    }
+
+    #[test]
+    fn test_rapid_drops_mark_the_link_unstable() {
+        let mut flaps = FlapDetector::new(3, Duration::from_secs(60));
+        let start = Instant::now();
+
+        for i in 0..3 {
+            assert!(!flaps.record(start + Duration::from_secs(i * 5)));
+        }
+        assert!(flaps.record(start + Duration::from_secs(15)));
+        assert!(flaps.is_unstable());
+
+        // Drops spread out again
+        assert!(!flaps.record(start + Duration::from_secs(120)));
+        assert!(!flaps.is_unstable());
+    }
+}
@@ -0,0 +1,170 @@
+use super::BridgeState;
+
+// Steps of the connection lifecycle `BleMidiBridge::start` drives. Transitions:
+//
+//   Scanning      -- Connected -->      Connecting { 0 }
+//   Scanning      -- ConnectFailed -->  Stopped
+//   Connecting    -- Subscribed -->     Running
+//   Connecting { n } -- LinkLost -->    Backoff { n + 1 }
+//   Running       -- LinkLost -->       Backoff { attempt: 1 }
+//   Backoff { n } -- Waited -->         Reconnecting { n }
+//   Reconnecting { n } -- Connected --> Connecting { n }
+//   Reconnecting { n } -- ConnectFailed --> Backoff { n + 1 }
+//   any           -- Fatal -->          Stopped
+//
+// Out of reconnect attempts, LinkLost and ConnectFailed lead to Stopped instead of
+// Backoff. Events that make no sense in a state leave it unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    // Looking for the device the first time
+    Scanning,
+    // Linked to the device, subscribing to its MIDI notifications. A reconnect only
+    // counts as successful once subscribed, so this carries on its attempt (0 for the
+    // first connection).
+    Connecting { attempt: u32 },
+    // Subscribed and forwarding MIDI until the link is lost
+    Running,
+    // Pausing before reconnect attempt `attempt` (counted from 1)
+    Backoff { attempt: u32 },
+    // Looking for the lost device again
+    Reconnecting { attempt: u32 },
+    // Given up; `start` returns
+    Stopped,
+}
+
+// What happened in the current state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    // The device was found and linked to
+    Connected,
+    // The device could not be found or linked to
+    ConnectFailed,
+    // MIDI notifications are coming in
+    Subscribed,
+    // The link dropped; reconnecting may bring it back
+    LinkLost,
+    // The backoff pause is over
+    Waited,
+    // Anything else going wrong, which reconnecting won't fix
+    Fatal,
+}
+
+impl ConnectionState {
+    // The state after `event`, given the reconnect attempts allowed (None: unlimited)
+    pub fn next(self, event: ConnectionEvent, max_attempts: Option<u32>) -> Self {
+        use ConnectionEvent::*;
+        use ConnectionState::*;
+
+        let backoff = |attempt: u32| match max_attempts {
+            Some(max) if attempt > max => Stopped,
+            _ => Backoff { attempt },
+        };
+        match (self, event) {
+            (_, Fatal) => Stopped,
+            (Scanning, Connected) => Connecting { attempt: 0 },
+            (Reconnecting { attempt }, Connected) => Connecting { attempt },
+            (Scanning, ConnectFailed) => Stopped,
+            (Connecting { .. }, Subscribed) => Running,
+            (Connecting { attempt }, LinkLost) => backoff(attempt + 1),
+            (Running, LinkLost) => backoff(1),
+            (Backoff { attempt }, Waited) => Reconnecting { attempt },
+            (Reconnecting { attempt }, ConnectFailed) => backoff(attempt + 1),
+            (state, _) => state,
+        }
+    }
+
+    // How the state is reported by `BleMidiBridge::state`
+    pub fn bridge_state(self) -> BridgeState {
+        match self {
+            ConnectionState::Scanning => BridgeState::Scanning,
+            ConnectionState::Connecting { .. } => BridgeState::Connecting,
+            ConnectionState::Running => BridgeState::Connected,
+            ConnectionState::Backoff { .. } | ConnectionState::Reconnecting { .. } => BridgeState::Reconnecting,
+            ConnectionState::Stopped => BridgeState::Error,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ConnectionEvent::*;
+    use ConnectionState::*;
+
+    fn walk(events: &[ConnectionEvent], max_attempts: Option<u32>) -> Vec<ConnectionState> {
+        let mut state = Scanning;
+        let mut states = vec![state];
+        for &event in events {
+            state = state.next(event, max_attempts);
+            states.push(state);
+        }
+        states
+    }
+
+    #[test]
+    fn test_connect_disconnect_reconnect_cycle() {
+        let states = walk(
+            &[Connected, Subscribed, LinkLost, Waited, ConnectFailed, Waited, Connected, Subscribed, LinkLost],
+            None,
+        );
+        assert_eq!(
+            states,
+            [
+                Scanning,
+                Connecting { attempt: 0 },
+                Running,
+                Backoff { attempt: 1 },
+                Reconnecting { attempt: 1 },
+                Backoff { attempt: 2 },
+                Reconnecting { attempt: 2 },
+                Connecting { attempt: 2 },
+                Running,
+                // A new drop starts counting attempts again
+                Backoff { attempt: 1 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_gives_up() {
+        // The first connection failing is not retried
+        assert_eq!(walk(&[ConnectFailed], None).last(), Some(&Stopped));
+        assert_eq!(walk(&[Connected, Subscribed, LinkLost], Some(0)).last(), Some(&Stopped));
+        let states = walk(&[Connected, Subscribed, LinkLost, Waited, ConnectFailed], Some(1));
+        assert_eq!(states.last(), Some(&Stopped));
+        assert_eq!(walk(&[Connected, Fatal], None).last(), Some(&Stopped));
+        // Failed subscriptions are retried like dropped links
+        assert_eq!(walk(&[Connected, LinkLost], None).last(), Some(&Backoff { attempt: 1 }));
+    }
+
+    #[test]
+    fn test_failed_subscriptions_use_up_attempts() {
+        // Linking to the device works every time but subscribing never does
+        let states = walk(&[Connected, LinkLost, Waited, Connected, LinkLost, Waited, Connected, LinkLost], Some(2));
+        assert_eq!(
+            &states[3..],
+            [
+                Reconnecting { attempt: 1 },
+                Connecting { attempt: 1 },
+                Backoff { attempt: 2 },
+                Reconnecting { attempt: 2 },
+                Connecting { attempt: 2 },
+                Stopped,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unexpected_events_are_ignored() {
+        assert_eq!(Running.next(Waited, None), Running);
+        assert_eq!(Backoff { attempt: 1 }.next(Subscribed, None), Backoff { attempt: 1 });
+        assert_eq!(Stopped.next(Connected, None), Stopped);
+    }
+
+    #[test]
+    fn test_bridge_state() {
+        assert_eq!(Running.bridge_state(), BridgeState::Connected);
+        assert_eq!(Backoff { attempt: 1 }.bridge_state(), BridgeState::Reconnecting);
+        assert_eq!(Stopped.bridge_state(), BridgeState::Error);
+    }
+}
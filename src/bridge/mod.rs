@@ -1,57 +1,1102 @@
 use anyhow::{anyhow, Result};
-use btleplug::api::{Peripheral as _};
 use futures::StreamExt;
-use log::{debug, error, info};
+use log::{debug, error, info, trace, warn};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
 use tokio::time;
+use std::collections::{BTreeMap, HashMap};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use crate::ble::{BleDevice, BLE_MIDI_CHARACTERISTIC_UUID, BLE_MIDI_SERVICE_UUID};
-use crate::midi::{MidiOutput, MidiMessage};
+use crate::ble::{
+    default_device_filters, start_keepalive, subscribe_notifications_with_retry, BleConnector, BleLink, DeviceFilter,
+    DeviceInfo, DeviceScanner, JitterAnalyzer, KeepaliveMode, max_write_len, MultiMatch, NotificationStream, packet, send_sysex_file, BLE_MIDI_CHARACTERISTIC_UUID, BLE_MIDI_SERVICE_UUID,
+};
+use crate::error::{is_ble_transport, BlipError};
+use crate::midi::{
+    create_virtual_port, header_timestamp, CsvNoteLog, NOTE_LOG_FLUSH_INTERVAL, parse_ble_midi_packet, virtual_port_unsupported, HeaderCheck, MidiOutput,
+    MidiMessage, MidiSink, PanicMode, PortOpener, PortRecovery, QueueConfig, QueueOverflow, QueuedSink, ReopeningSink, Route, SinkSet,
+};
+use crate::net::{NamedPipeSink, TcpMidiSink, WebSocketMidiSink};
+use crate::pipeline::{
+    ArpConfig, BendScaling, ControlAction, DropStats, FreezeConfig, GateTrigger, HeldNote, MessageCategory, NormalizeConfig, NoteRepeatConfig, OctaveShiftTriggers, Pipeline, RetriggerMode, ScaleConfig, Split, TransposeBounds, UnknownMessagePolicy,
+};
+use crate::util::{hexdump, Clock, SystemClock};
 
-#[derive(Clone)]
+#[cfg(test)]
+mod end_to_end;
+mod env;
+mod file;
+mod health;
+mod lifecycle;
+mod quirks;
+mod runtime;
+mod state;
+
+pub use file::{ConfigFile, ConfigOverlay};
+pub use health::{DisconnectDebouncer, FlapDetector, LinkAdapter, LinkQuality};
+use lifecycle::{ConnectionEvent, ConnectionState};
+pub use quirks::QuirkProfile;
+pub use runtime::{run, RuntimeBuilder};
+use quirks::QuirkSettings;
+pub use state::BridgeState;
+
+use state::StateCell;
+
+// How raw BLE-MIDI packets are written to the debug log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PacketLogStyle {
+    // Single line: [80, 80, 90, 3C, 7F]
+    Compact,
+    // Offset / hex / ASCII columns, easier to scan for long packets
+    HexDump,
+}
+
+// When more than `max_drops` disconnects happen within `window`, wait `backoff` instead
+// of `Config::reconnect_delay` before reconnecting, until the link settles down
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FlapDetection {
+    pub max_drops: u32,
+    #[serde(with = "crate::util::duration_secs")]
+    pub window: Duration,
+    #[serde(with = "crate::util::duration_secs")]
+    pub backoff: Duration,
+}
+
+impl Default for FlapDetection {
+    fn default() -> Self {
+        FlapDetection { max_drops: 5, window: Duration::from_secs(60), backoff: Duration::from_secs(30) }
+    }
+}
+
+// Adapting to the signal strength: while the RSSI is at or below `weak_rssi`, the
+// keep-alive runs in `weak_keepalive_mode` (by default writing rather than reading) every
+// `weak_keepalive_interval`, and up to `weak_error_threshold` consecutive bad packets are
+// tolerated. Once it is back at `strong_rssi` or above, the normal
+// settings return. The gap between the two keeps a borderline signal from flip-flopping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AdaptiveLink {
+    #[serde(with = "crate::util::duration_secs")]
+    pub sample_interval: Duration,
+    pub weak_rssi: i16,
+    pub strong_rssi: i16,
+    #[serde(with = "crate::util::duration_secs")]
+    pub weak_keepalive_interval: Duration,
+    pub weak_keepalive_mode: KeepaliveMode,
+    pub weak_error_threshold: u32,
+}
+
+impl Default for AdaptiveLink {
+    fn default() -> Self {
+        AdaptiveLink {
+            sample_interval: Duration::from_secs(5),
+            weak_rssi: -80,
+            strong_rssi: -70,
+            weak_keepalive_interval: Duration::from_secs(3),
+            weak_keepalive_mode: KeepaliveMode::Write,
+            weak_error_threshold: 30,
+        }
+    }
+}
+
+// Timestamp on each log line (always UTC). Only used by the binaries, which set up logging.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogTimestamp {
+    None,
+    // 2024-05-01T18:30:12Z
+    Seconds,
+    // 2024-05-01T18:30:12.345Z, for latency debugging
+    Millis,
+    // 2024-05-01T18:30:12.345678Z, for lining up with DAW recordings and other logs
+    Rfc3339,
+}
+
+impl FromStr for LogTimestamp {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(LogTimestamp::None),
+            "seconds" => Ok(LogTimestamp::Seconds),
+            "millis" => Ok(LogTimestamp::Millis),
+            "rfc3339" => Ok(LogTimestamp::Rfc3339),
+            _ => Err(anyhow!("Unknown log timestamp format '{}' (none, seconds, millis or rfc3339)", s)),
+        }
+    }
+}
+
+// Saved and loaded whole (see `Config::save_file`): loading needs every field except the
+// options, which are None when missing
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct Config {
+    // Quirk profile the device-specific settings below were set from, if any (see
+    // `Config::for_quirk_profile`)
+    pub quirk_profile: Option<QuirkProfile>,
     pub virtual_midi_port_name: String,
+    // Create the output port under that name instead of looking for an existing one.
+    // Needs the `midir` feature, and isn't possible on Windows.
+    pub create_virtual_port: bool,
+    // When the port doesn't exist yet, keep looking for it this long before giving up,
+    // so BLIP and loopMIDI can be started in any order
+    #[serde(default, with = "crate::util::duration_secs::option")]
+    pub midi_port_wait: Option<Duration>,
+    #[serde(with = "crate::util::duration_secs")]
     pub ble_scan_timeout: Duration,
+    // Devices to connect to, in priority order, e.g. the LPK25, then any AKAI device,
+    // then any BLE-MIDI device
+    pub device_filters: Vec<DeviceFilter>,
+    // Ignore `device_filters` and connect to the first device advertising the BLE-MIDI
+    // service, whatever it is
+    pub any_ble_midi: bool,
+    // Which device to connect to when several match the same filter
+    pub multi_match: MultiMatch,
+    // Ignore matching devices whose signal is weaker than this (in dBm), e.g. -70 to
+    // only connect to one in the same room
+    pub min_rssi: Option<i16>,
+    #[serde(with = "crate::util::duration_secs")]
     pub ble_keepalive_interval: Duration,
+    // Delay before the first keep-alive read after subscribing
+    #[serde(with = "crate::util::duration_secs")]
+    pub keepalive_initial_delay: Duration,
+    // Decode keep-alive reads of the MIDI characteristic as BLE-MIDI packets and forward
+    // them. Some devices return buffered MIDI data there, which is otherwise lost.
+    pub process_keepalive_reads: bool,
+    // Pause between service discovery and subscribing; some adapters fail the
+    // subscription with a GATT error when it comes too soon after connecting
+    #[serde(with = "crate::util::duration_secs")]
+    pub post_connect_delay: Duration,
+    // Subscribe attempts before giving up on a connection, and the pause between them
+    pub subscribe_attempts: u32,
+    #[serde(with = "crate::util::duration_secs")]
+    pub subscribe_retry_delay: Duration,
+    #[serde(with = "crate::util::duration_secs")]
     pub ble_status_check_interval: Duration,
+    // Pause before each attempt to reconnect to a lost device
+    #[serde(with = "crate::util::duration_secs")]
+    pub reconnect_delay: Duration,
+    // Back off when the link keeps dropping, e.g. at the edge of range (off by default;
+    // `FlapDetection::default()` is a good start)
+    pub flap_detection: Option<FlapDetection>,
+    // Adjust keep-alive and error tolerance to the signal strength; None disables it
+    pub adaptive_link: Option<AdaptiveLink>,
+    // Log link uptime and the reconnect count this often while connected; None disables it
+    #[serde(default, with = "crate::util::duration_secs::option")]
+    pub stats_log_interval: Option<Duration>,
+    // Failed reconnect attempts before giving up. The default of 0 ends `start` as soon as
+    // the device is lost; None keeps trying forever.
+    pub reconnect_attempts: Option<u32>,
+    // Consecutive failed status checks required before the device is considered disconnected
+    pub disconnect_confirmations: u32,
     pub octave_offset: i8,
+    // Semitones added on top of the octave offset, -11 to 11
+    pub semitone_offset: i8,
+    // Notes or CCs that shift the octave up/down while playing (e.g. the controller's
+    // octave buttons), on top of `octave_offset`. Keys held during a shift are released
+    // at the pitch they were played at.
+    pub octave_shift_triggers: Option<OctaveShiftTriggers>,
+    // CC (e.g. an expression pedal) whose value shifts the octave live, from two octaves
+    // down at the bottom of its range to two up at the top (see `pedal_octaves`). Added
+    // to the other offsets; held notes are released at the pitch they were played at.
+    pub transpose_cc: Option<u8>,
+    // MIDI channels (0-15) the transposition applies to; None transposes every channel
+    pub transpose_channels: Option<Vec<u8>>,
+    // Clamp transposed notes that leave the MIDI range, or wrap them back by octaves
+    pub transpose_bounds: TransposeBounds,
+    // CC number that toggles muting of all output (value >= 64 toggles)
+    pub mute_toggle_cc: Option<u8>,
+    // Remapping of the device's controllers by CC number, applied before any other CC
+    // handling. Quirk profiles list their device's hardware controls here (see
+    // `QuirkProfile::controls`); actions other than `Cc` fire on values of 64 and up.
+    #[serde(with = "file::u8_keys")]
+    pub control_map: BTreeMap<u8, ControlAction>,
+    // Bank (MSB, LSB) selected with CC 0 and CC 32 just before every Program Change,
+    // on its channel, for multitimbral synths that need the bank with each change.
+    // `channel_banks` sets it per channel (0-15), taking precedence.
+    pub program_change_bank: Option<(u8, u8)>,
+    #[serde(with = "file::u8_keys")]
+    pub channel_banks: BTreeMap<u8, (u8, u8)>,
+    // Momentary gate: forward only while this note or CC is held. Closing the gate
+    // releases the notes that are sounding.
+    pub gate: Option<GateTrigger>,
+    // Warn about Note Offs for keys that aren't held and Note Ons for keys already held
+    pub diagnose_note_pairing: bool,
+    // Force every Note On to this velocity (1-127), e.g. for organ patches. Note Offs,
+    // including Note Ons at velocity 0, are left alone.
+    pub fixed_velocity: Option<u8>,
+    // Vary each Note On's velocity randomly by up to this much either way, for a more
+    // natural feel. The result stays within 1-127.
+    pub velocity_humanize: Option<u8>,
+    // Seed for the velocity variation, to make it repeatable; None picks a new one each run
+    pub velocity_humanize_seed: Option<u32>,
+    // Stretch the range of velocities played over the session to the full 1-127, adapting
+    // as the playing changes. Ignored with `fixed_velocity`; applied before humanizing.
+    pub velocity_normalize: Option<NormalizeConfig>,
+    // Force the release velocity of every Note Off from the device to this value (0-127).
+    // A Note On at velocity 0 has no release velocity to set, so it is sent as a Note
+    // Off (0x80) instead.
+    pub note_off_velocity: Option<u8>,
+    // Swap Note On and Note Off: pressing a key stops its note and releasing starts it,
+    // e.g. to trigger drones. Held notes are tracked by what was sent, so a panic or mute
+    // still releases the sounding ones.
+    pub invert_note_onoff: bool,
+    // Notes (as the device sends them) that cut each other off, like an open and closed
+    // hi-hat on drum pads: playing one releases the others of its group held on that channel
+    pub choke_groups: Vec<Vec<u8>>,
+    // Play the held notes one after the other instead of together
+    pub arpeggiator: Option<ArpConfig>,
+    // Semitone intervals added to every note played, e.g. [4, 7] turns single notes
+    // into major triads. Releasing the key releases the whole chord.
+    pub harmonize: Option<Vec<i8>>,
+    // Replace each incoming note number with `note_map[note]`, before transposition, for
+    // custom or microtonal layouts. Values above 127 are clamped or wrapped back by
+    // octaves per `transpose_bounds`.
+    #[serde(default, deserialize_with = "file::deserialize_note_map", serialize_with = "file::serialize_note_map")]
+    pub note_map: Option<[u8; 128]>,
+    // Snap out-of-scale notes to the nearest note of this key, after transposition
+    pub scale_snap: Option<ScaleConfig>,
+    // Sending the held notes as sustained notes on a channel or port of their own
+    pub freeze: Option<FreezeConfig>,
+    // Retrigger held keys at a fixed rate, like a drum machine's note repeat
+    pub note_repeat: Option<NoteRepeatConfig>,
+    // Handling of a Note On for a key that is already held
+    pub retrigger_mode: RetriggerMode,
+    // Controller number whose CC messages are replaced by pitch bend on the same channel,
+    // for synths that only respond musically to pitch bend
+    pub cc_to_pitchbend: Option<u8>,
+    // Whether that CC bends both ways around its middle value or only upwards
+    pub pitchbend_scaling: BendScaling,
+    // Multiplies how far pitch bends move from centre, softening (below 1.0) or
+    // exaggerating (above 1.0) them without reconfiguring the synth's bend range
+    pub pitch_bend_scale: f32,
+    // Hold Note Ons back this long and drop them, together with their Note Off, if the
+    // key is released within it. Filters out ghost notes some controllers glitch out.
+    #[serde(default, with = "crate::util::duration_secs::option")]
+    pub min_note_duration: Option<Duration>,
+    // Mono-legato helper: Note Offs of keys released within `legato_window` of a note
+    // change on their channel are held until the channel's last key is released, so a
+    // mono synth glides between the notes instead of retriggering its envelope
+    pub legato: bool,
+    #[serde(with = "crate::util::duration_secs")]
+    pub legato_window: Duration,
+    // Suppress Note Ons while more than this many arrive per second (Note Offs still pass),
+    // protecting the synth from a glitching controller. None disables the limit.
+    pub max_notes_per_sec: Option<u32>,
+    // Keyboard splits routing note ranges to named outputs. Outputs that aren't already
+    // configured are opened as MIDI ports with that name. Empty sends notes everywhere.
+    pub splits: Vec<Split>,
+    // Output port for each message category, e.g. notes and CCs on separate ports so a
+    // DAW records them to separate tracks. Unlisted categories go to every output, and
+    // ports that aren't already configured are opened like split outputs.
+    #[serde(deserialize_with = "file::deserialize_enum_keys")]
+    pub category_ports: BTreeMap<MessageCategory, String>,
+    // Per source channel, the channel and port its messages go out on instead: e.g. two
+    // channels onto channel 1 of two ports, keeping them apart without a multitimbral
+    // synth. Takes precedence over splits, category ports and the freeze output, frozen
+    // notes being routed by the freeze channel; ports are opened like theirs.
+    #[serde(with = "file::channel_routing")]
+    pub channel_routing: HashMap<u8, (u8, String)>,
+    // Space messages sent to the MIDI ports at least this far apart, for ports feeding a
+    // serial MIDI interface that a burst would overflow (see `DIN_MIDI_MESSAGE_TIME`)
+    #[serde(default, with = "crate::util::duration_secs::option")]
+    pub min_send_interval: Option<Duration>,
+    // Hand messages to the MIDI ports through a queue of this many messages drained by a
+    // thread of their own, so a slow driver never holds up BLE processing. Pacing with
+    // `min_send_interval` uses the same thread, with an unbounded queue if this is None.
+    pub output_buffer_size: Option<usize>,
+    // What happens to a CC or pitch bend the full output queue has no room for. Notes and
+    // other messages are never dropped; they wait in the queue beyond its size.
+    pub output_overflow: QueueOverflow,
+    // Keep going when a MIDI output port goes away, reopening it and holding or dropping
+    // messages meanwhile, while the BLE side carries on. None makes a lost port fail
+    // sends, which counts towards the packet errors that end a session.
+    pub midi_port_recovery: Option<PortRecovery>,
+    pub packet_log_style: PacketLogStyle,
+    // Compare the device's BLE-MIDI timestamps with when packets arrive and log the
+    // jitter every JITTER_LOG_INTERVAL, to tell link hiccups from playing
+    pub jitter_analysis: bool,
+    pub log_timestamp: LogTimestamp,
+    // Larger notification values are ignored unparsed, guarding against a malformed or
+    // malicious peer
+    pub max_packet_bytes: usize,
+    // Largest BLE-MIDI packet written to the device (e.g. for SysEx), overriding what
+    // the connection's MTU allows
+    pub max_write_len: Option<usize>,
+    // Reject packets with an invalid header byte, or try to find the real header in them
+    pub packet_header_check: HeaderCheck,
+    // Drop system messages (clock, start/stop, active sensing, ...) and forward only
+    // channel voice messages, e.g. to keep a DAW recording clean
+    pub channel_voice_only: bool,
+    // Pass on System Reset (0xFF) messages. Either way a Reset releases the held notes
+    // and undoes octave shifts, mute and freeze, like the device resetting itself.
+    pub forward_system_reset: bool,
+    // Handling of messages with a status the parser doesn't know
+    pub unknown_message_policy: UnknownMessagePolicy,
+    // Also stream MIDI to TCP clients connecting to this address (length-prefixed raw MIDI)
+    pub tcp_midi_listen: Option<SocketAddr>,
+    // Also push MIDI as JSON to WebSocket clients (e.g. browser visualizers) on this address
+    pub websocket_listen: Option<SocketAddr>,
+    // Also write raw MIDI to this Windows named pipe, e.g. `\\.\pipe\blip`, for a
+    // companion app on the same machine
+    pub named_pipe: Option<String>,
+    // Serve a status page for browsers (e.g. on a phone) on this address; needs the
+    // `dashboard` feature
+    pub http_dashboard: Option<SocketAddr>,
+    // Append a CSV row per played note (start time, channel, note, velocity, duration)
+    // to this file, for analysis in a spreadsheet
+    pub csv_log: Option<PathBuf>,
+    // Messages sent to all outputs when the device disconnects, as an audible alert.
+    // A short pause follows each Note On so the notes actually sound.
+    pub disconnect_cue: Option<Vec<MidiMessage>>,
+    // Send a panic on every channel as soon as the outputs are open, to clear notes left
+    // hanging by a previous session
+    pub panic_on_start: bool,
+    // What a panic sends: at startup, on `send_panic` (e.g. at shutdown) and on muting
+    pub panic_mode: PanicMode,
+    // MIDI channel (0-15) of messages injected with `inject_message` and the self-test
+    pub injection_channel: u8,
+}
+
+// Octave and semitone offsets accepted, in the configuration and while running
+pub(crate) const OFFSET_RANGE: RangeInclusive<i8> = -11..=11;
+
+fn check_offset(what: &str, offset: i8) -> Result<()> {
+    if !OFFSET_RANGE.contains(&offset) {
+        return Err(anyhow!("{} must be {} to {}, got {}", what, OFFSET_RANGE.start(), OFFSET_RANGE.end(), offset));
+    }
+    Ok(())
+}
+
+impl Config {
+    // Filters the scan uses: `device_filters`, or any BLE-MIDI device with `any_ble_midi`
+    pub fn scan_filters(&self) -> Vec<DeviceFilter> {
+        if self.any_ble_midi {
+            vec![DeviceFilter::MidiService]
+        } else {
+            self.device_filters.clone()
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.scan_filters().is_empty() {
+            return Err(anyhow!("At least one device filter is required"));
+        }
+        check_offset("Octave offset", self.octave_offset)?;
+        check_offset("Semitone offset", self.semitone_offset)?;
+        if !self.pitch_bend_scale.is_finite() || self.pitch_bend_scale < 0.0 {
+            return Err(anyhow!("Pitch bend scale must be a non-negative number, got {}", self.pitch_bend_scale));
+        }
+        if let Some(scale) = self.scale_snap {
+            if scale.root > 11 {
+                return Err(anyhow!("Scale root must be a pitch class from 0 (C) to 11 (B), got {}", scale.root));
+            }
+        }
+        if let Some(adaptive) = self.adaptive_link {
+            // Equal thresholds would leave no gap to keep a borderline signal from flip-flopping
+            if adaptive.weak_rssi >= adaptive.strong_rssi {
+                return Err(anyhow!(
+                    "Adaptive link needs weak_rssi below strong_rssi, got {} and {} dBm",
+                    adaptive.weak_rssi,
+                    adaptive.strong_rssi
+                ));
+            }
+        }
+        if self.output_buffer_size == Some(0) {
+            return Err(anyhow!("Output buffer size must be at least 1"));
+        }
+        if self.create_virtual_port {
+            if let Some(reason) = virtual_port_unsupported() {
+                return Err(anyhow!(reason));
+            }
+        }
+        if self.http_dashboard.is_some() && !cfg!(feature = "dashboard") {
+            return Err(anyhow!("The HTTP dashboard needs BLIP built with the `dashboard` feature"));
+        }
+        if let Some(velocity) = self.fixed_velocity {
+            if !(1..=127).contains(&velocity) {
+                return Err(anyhow!("Fixed velocity must be 1-127, got {}", velocity));
+            }
+        }
+        if let Some(normalize) = self.velocity_normalize {
+            if !(0.0..=1.0).contains(&normalize.adaptation_rate) || normalize.reset_cc.is_some_and(|cc| cc > 127) {
+                return Err(anyhow!("Velocity normalization needs an adaptation rate of 0-1 and a reset CC of 0-127"));
+            }
+        }
+        if self.note_off_velocity.is_some_and(|velocity| velocity > 127) {
+            return Err(anyhow!("Note Off velocity must be 0-127"));
+        }
+        if let Some(arp) = &self.arpeggiator {
+            if arp.rate.is_zero() || !(arp.gate > 0.0 && arp.gate <= 1.0) {
+                return Err(anyhow!("Arpeggiator needs a non-zero rate and a gate above 0 and up to 1"));
+            }
+        }
+        if self.note_repeat.as_ref().is_some_and(|repeat| repeat.rate < Duration::from_millis(2)) {
+            return Err(anyhow!("Note repeat rate must be at least 2ms"));
+        }
+        if self.freeze.as_ref().is_some_and(|freeze| freeze.channel > 15) {
+            return Err(anyhow!("Freeze channel must be 0-15"));
+        }
+        if self.control_map.iter().any(|(&cc, action)| cc > 127 || matches!(action, ControlAction::Cc(to) if *to > 127)) {
+            return Err(anyhow!("Control map CCs must be 0-127"));
+        }
+        let bank_valid = |(msb, lsb): (u8, u8)| msb <= 127 && lsb <= 127;
+        if self.program_change_bank.is_some_and(|bank| !bank_valid(bank))
+            || self.channel_banks.iter().any(|(&channel, &bank)| channel > 15 || !bank_valid(bank))
+        {
+            return Err(anyhow!("Bank select values must be 0-127, on channels 0-15"));
+        }
+        if self.choke_groups.iter().flatten().any(|&note| note > 127) {
+            return Err(anyhow!("Choke group notes must be 0-127"));
+        }
+        if self.transpose_cc.is_some_and(|cc| cc > 127) {
+            return Err(anyhow!("Transpose CC must be 0-127"));
+        }
+        if self.max_write_len.is_some_and(|max| !(3..=512).contains(&max)) {
+            return Err(anyhow!("Max write length must be 3-512 bytes"));
+        }
+        if self.channel_routing.iter().any(|(&source, (channel, _))| source > 15 || *channel > 15) {
+            return Err(anyhow!("Channel routing channels must be 0-15"));
+        }
+        if self.injection_channel > 15 {
+            return Err(anyhow!("Injection channel must be 0-15, got {}", self.injection_channel));
+        }
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            quirk_profile: None,
+            virtual_midi_port_name: "AKAI_LPK25_IN_BLE".to_string(),
+            create_virtual_port: false,
+            midi_port_wait: None,
+            ble_scan_timeout: Duration::from_secs(30),
+            device_filters: default_device_filters(),
+            any_ble_midi: false,
+            multi_match: MultiMatch::FirstFound,
+            min_rssi: None,
+            ble_keepalive_interval: Duration::from_secs(10),
+            keepalive_initial_delay: Duration::ZERO,
+            process_keepalive_reads: false,
+            post_connect_delay: Duration::ZERO,
+            subscribe_attempts: 3,
+            subscribe_retry_delay: Duration::from_millis(500),
+            ble_status_check_interval: Duration::from_secs(1),
+            reconnect_delay: Duration::from_secs(2),
+            stats_log_interval: None,
+            flap_detection: None,
+            adaptive_link: None,
+            reconnect_attempts: Some(0),
+            disconnect_confirmations: 1,
+            octave_offset: 0,
+            semitone_offset: 0,
+            octave_shift_triggers: None,
+            transpose_cc: None,
+            transpose_channels: None,
+            transpose_bounds: TransposeBounds::Clamp,
+            mute_toggle_cc: None,
+            control_map: BTreeMap::new(),
+            program_change_bank: None,
+            channel_banks: BTreeMap::new(),
+            gate: None,
+            diagnose_note_pairing: false,
+            fixed_velocity: None,
+            velocity_humanize: None,
+            velocity_humanize_seed: None,
+            velocity_normalize: None,
+            note_off_velocity: None,
+            invert_note_onoff: false,
+            choke_groups: Vec::new(),
+            retrigger_mode: RetriggerMode::Passthrough,
+            arpeggiator: None,
+            note_repeat: None,
+            harmonize: None,
+            note_map: None,
+            scale_snap: None,
+            freeze: None,
+            cc_to_pitchbend: None,
+            pitchbend_scaling: BendScaling::Bipolar,
+            pitch_bend_scale: 1.0,
+            min_note_duration: None,
+            legato: false,
+            legato_window: Duration::from_millis(30),
+            max_notes_per_sec: Some(200),
+            splits: Vec::new(),
+            category_ports: BTreeMap::new(),
+            channel_routing: HashMap::new(),
+            min_send_interval: None,
+            output_buffer_size: None,
+            output_overflow: QueueOverflow::Drop,
+            midi_port_recovery: None,
+            packet_log_style: PacketLogStyle::Compact,
+            jitter_analysis: false,
+            log_timestamp: LogTimestamp::Seconds,
+            max_packet_bytes: 512,
+            max_write_len: None,
+            packet_header_check: HeaderCheck::Strict,
+            channel_voice_only: false,
+            forward_system_reset: true,
+            unknown_message_policy: UnknownMessagePolicy::Forward,
+            tcp_midi_listen: None,
+            websocket_listen: None,
+            named_pipe: None,
+            http_dashboard: None,
+            csv_log: None,
+            disconnect_cue: None,
+            panic_on_start: false,
+            panic_mode: PanicMode::AllNotesOff,
+            injection_channel: 0,
+        }
+    }
+}
+
+// How long each Note On of the disconnect cue sounds before the next message
+const DISCONNECT_CUE_STEP: Duration = Duration::from_millis(150);
+
+// Descending fifth (G5 -> C5) on the given channel, usable as `Config::disconnect_cue`
+pub fn disconnect_cue_motif(channel: u8) -> Vec<MidiMessage> {
+    [79, 72]
+        .into_iter()
+        .flat_map(|note| [
+            MidiMessage { status: 0x90, data1: note, data2: 100 }.with_channel(channel),
+            MidiMessage { status: 0x80, data1: note, data2: 0 }.with_channel(channel),
+        ])
+        .collect()
+}
+
+// Notes of the self-test scale (C major, C4 to C5) and how long each one sounds
+const SELF_TEST_SCALE: [u8; 8] = [60, 62, 64, 65, 67, 69, 71, 72];
+const SELF_TEST_STEP: Duration = Duration::from_millis(200);
+
+// Keep-alive reads waiting to be processed before the keep-alive task waits too
+const KEEPALIVE_READ_BUFFER: usize = 4;
+
+// Bad packets in a row after which a session is given up (see `AdaptiveLink` for weak links)
+const MAX_CONSECUTIVE_PACKET_ERRORS: u32 = 10;
+
+// Messages a `message_stream` receiver can fall behind by before it starts missing some
+const MESSAGE_STREAM_CAPACITY: usize = 256;
+
+// How often `Config::jitter_analysis` logs the jitter seen since the last report
+const JITTER_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+// Why a connected session with the device ended
+enum SessionEnd {
+    // The BLE link was lost; reconnecting may bring it back
+    Disconnected,
+    // Anything else, which reconnecting won't fix
+    Failed(anyhow::Error),
+}
+
+// A session's keep-alive task, restarted when its interval changes
+struct SessionKeepalive {
+    link: Arc<dyn BleLink>,
+    reads: Option<mpsc::Sender<Vec<u8>>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl SessionKeepalive {
+    fn start(&mut self, mode: KeepaliveMode, interval: Duration, initial_delay: Duration) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+        // The link may still work without it, so carry on if it can't start
+        self.task = match start_keepalive(self.link.clone(), BLE_MIDI_CHARACTERISTIC_UUID, mode, interval, initial_delay, self.reads.clone()) {
+            Ok(task) => Some(task),
+            Err(e) => {
+                warn!("Running without keep-alive: {}", e);
+                None
+            }
+        };
+    }
+}
+
+impl Drop for SessionKeepalive {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
 }
 
 pub struct BleMidiBridge {
-    ble_device: BleDevice,
-    midi_output: MidiOutput,
+    connector: Box<dyn BleConnector>,
+    // The device of the current session, if any
+    device: Mutex<Option<Arc<dyn BleLink>>>,
+    sinks: SinkSet,
+    pipeline: Mutex<Pipeline>,
     config: Config,
+    // The quirk settings of the current session, which `QuirkProfile::Auto` picks per device
+    quirks: Mutex<QuirkSettings>,
+    state: StateCell,
+    // Copy of every message sent to the outputs, for monitors and displays
+    messages: broadcast::Sender<MidiMessage>,
+    clock: Arc<dyn Clock>,
+    // When the current session's link came up; None while not connected
+    connected_at: Mutex<Option<time::Instant>>,
+    // Successful reconnects since the bridge started
+    reconnects: AtomicU32,
+    csv_log: Option<CsvNoteLog>,
+    jitter: Option<Mutex<JitterAnalyzer>>,
+}
+
+// Open the existing (loopMIDI) port the bridge sends to, explaining how to set it up if
+// it isn't there
+fn find_midi_port(name: &str) -> Result<Box<dyn MidiSink>> {
+    info!("Looking for MIDI port '{}'...", name);
+    match MidiOutput::new_with_device_name(name) {
+        Ok(output) => Ok(Box::new(output)),
+        // There is no loopMIDI to point to
+        Err(e) if cfg!(not(windows)) => Err(e),
+        Err(e) if matches!(e.downcast_ref::<BlipError>(), Some(BlipError::NoMidiDevices)) => {
+            error!("There are no MIDI output ports on this system at all. Install loopMIDI to create one:");
+            error!("Download it from: https://www.tobias-erichsen.de/software/loopmidi.html");
+            Err(e)
+        }
+        Err(_) => {
+            error!("Could not find MIDI port '{}'. Please create it in loopMIDI:", name);
+            error!("1. Download and install loopMIDI from: https://www.tobias-erichsen.de/software/loopmidi.html");
+            error!("2. Run loopMIDI");
+            error!("3. Click the '+' button to create a new virtual port");
+            error!("4. Double click the port name and rename it to: {}", name);
+            error!("5. Run this program again");
+            Err(anyhow!("MIDI port '{}' not found", name))
+        }
+    }
+}
+
+// How often `Config::midi_port_wait` looks for the port
+const MIDI_PORT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// Poll `list_devices` until an output named like `name` shows up or `wait` has passed.
+// Returns whether the port was found.
+async fn wait_for_midi_port(
+    name: &str,
+    wait: Duration,
+    list_devices: impl Fn() -> Result<Vec<(usize, String)>>,
+) -> bool {
+    let deadline = time::Instant::now() + wait;
+    loop {
+        match list_devices() {
+            Ok(devices) if devices.iter().any(|(_, device)| device.contains(name)) => return true,
+            Ok(_) => {}
+            Err(e) => warn!("Could not list MIDI ports: {}", e),
+        }
+        if time::Instant::now() >= deadline {
+            return false;
+        }
+        info!("MIDI port '{}' not there yet, looking again in {:?}", name, MIDI_PORT_POLL_INTERVAL);
+        time::sleep(MIDI_PORT_POLL_INTERVAL).await;
+    }
+}
+
+// Reopens the output port called `name`
+fn port_opener(name: &str) -> PortOpener {
+    let name = name.to_string();
+    Box::new(move || Ok(Box::new(MidiOutput::new_with_device_name(&name)?)))
+}
+
+// Apply `Config::midi_port_recovery`, `Config::min_send_interval` and
+// `Config::output_buffer_size` to an opened MIDI port
+fn output_port(name: &str, port: Box<dyn MidiSink>, opener: PortOpener, config: &Config) -> Box<dyn MidiSink> {
+    let port: Box<dyn MidiSink> = match config.midi_port_recovery {
+        Some(recovery) => Box::new(ReopeningSink::new(name, port, opener, recovery)),
+        None => port,
+    };
+    if config.min_send_interval.is_none() && config.output_buffer_size.is_none() {
+        return port;
+    }
+    let queue = QueueConfig {
+        capacity: config.output_buffer_size,
+        interval: config.min_send_interval,
+        overflow: config.output_overflow,
+    };
+    Box::new(QueuedSink::new(port, queue))
 }
 
 impl BleMidiBridge {
     pub async fn new(config: &Config) -> Result<Self> {
-        let ble_device = BleDevice::discover(config.ble_scan_timeout).await?;
-        
-        // Try to connect to loopMIDI virtual port
-        info!("Looking for MIDI port '{}'...", config.virtual_midi_port_name);
-        let midi_output = match MidiOutput::new_with_device_name(&config.virtual_midi_port_name) {
-            Ok(output) => output,
-            Err(_) => {
-                error!("Could not find MIDI port '{}'. Please create it in loopMIDI:", config.virtual_midi_port_name);
-                error!("1. Download and install loopMIDI from: https://www.tobias-erichsen.de/software/loopmidi.html");
-                error!("2. Run loopMIDI");
-                error!("3. Click the '+' button to create a new virtual port");
-                error!("4. Double click the port name and rename it to: {}", config.virtual_midi_port_name);
-                error!("5. Run this program again");
-                return Err(anyhow!("MIDI port '{}' not found", config.virtual_midi_port_name));
+        config.validate()?;
+
+        let name = &config.virtual_midi_port_name;
+        let (midi_output, opener): (Box<dyn MidiSink>, PortOpener) = if config.create_virtual_port {
+            let reopen_name = name.clone();
+            (create_virtual_port(name)?, Box::new(move || create_virtual_port(&reopen_name)))
+        } else {
+            if let Some(wait) = config.midi_port_wait {
+                wait_for_midi_port(name, wait, MidiOutput::list_devices).await;
+            }
+            (find_midi_port(name)?, port_opener(name))
+        };
+
+        let mut sinks = SinkSet::new();
+        sinks.add(name.clone(), output_port(name, midi_output, opener, config));
+        if let Some(addr) = config.tcp_midi_listen {
+            sinks.add("tcp", Box::new(TcpMidiSink::bind(addr).await?));
+        }
+        if let Some(addr) = config.websocket_listen {
+            sinks.add("websocket", Box::new(WebSocketMidiSink::bind(addr).await?));
+        }
+        if let Some(name) = &config.named_pipe {
+            sinks.add("named pipe", Box::new(NamedPipeSink::create(name)?));
+        }
+        for split in &config.splits {
+            if !sinks.contains(&split.sink) {
+                info!("Opening MIDI port '{}' for notes {:?}", split.sink, split.range);
+                let port = Box::new(MidiOutput::new_with_device_name(&split.sink)?);
+                sinks.add(split.sink.clone(), output_port(&split.sink, port, port_opener(&split.sink), config));
+            }
+        }
+        for (category, port) in &config.category_ports {
+            if !sinks.contains(port) {
+                info!("Opening MIDI port '{}' for {:?} messages", port, category);
+                let output = Box::new(MidiOutput::new_with_device_name(port)?);
+                sinks.add(port.clone(), output_port(port, output, port_opener(port), config));
             }
-        };        Ok(BleMidiBridge {
-            ble_device,
-            midi_output,
+        }
+        for (channel, (_, port)) in &config.channel_routing {
+            if !sinks.contains(port) {
+                info!("Opening MIDI port '{}' for channel {}", port, channel + 1);
+                let output = Box::new(MidiOutput::new_with_device_name(port)?);
+                sinks.add(port.clone(), output_port(port, output, port_opener(port), config));
+            }
+        }
+        if let Some(port) = config.freeze.as_ref().and_then(|freeze| freeze.sink.as_ref()) {
+            if !sinks.contains(port) {
+                info!("Opening MIDI port '{}' for frozen notes", port);
+                let output = Box::new(MidiOutput::new_with_device_name(port)?);
+                sinks.add(port.clone(), output_port(port, output, port_opener(port), config));
+            }
+        }
+
+        let scanner = DeviceScanner {
+            scan_timeout: config.ble_scan_timeout,
+            filters: config.scan_filters(),
+            multi_match: config.multi_match,
+            min_rssi: config.min_rssi,
+        };
+        let mut bridge = Self::from_parts(config, Box::new(scanner), sinks);
+        if let Some(path) = &config.csv_log {
+            bridge.csv_log = Some(CsvNoteLog::create(path)?);
+        }
+        Ok(bridge)
+    }
+
+    // Assemble a bridge from a way to reach the device and already opened outputs
+    pub fn from_parts(config: &Config, connector: Box<dyn BleConnector>, sinks: SinkSet) -> Self {
+        let bridge = BleMidiBridge {
+            connector,
+            device: Mutex::new(None),
+            sinks,
+            pipeline: Mutex::new(Pipeline::new(config)),
             config: config.clone(),
-        })
+            quirks: Mutex::new(QuirkSettings::from_config(config)),
+            state: StateCell::new(BridgeState::Scanning),
+            messages: broadcast::channel(MESSAGE_STREAM_CAPACITY).0,
+            clock: Arc::new(SystemClock),
+            connected_at: Mutex::new(None),
+            reconnects: AtomicU32::new(0),
+            csv_log: None,
+            jitter: config.jitter_analysis.then(|| Mutex::new(JitterAnalyzer::new(JITTER_LOG_INTERVAL))),
+        };
+
+        if config.panic_on_start {
+            info!("Clearing hanging notes on all outputs");
+            if let Err(e) = bridge.send_panic() {
+                warn!("Startup panic failed: {}", e);
+            }
+        }
+        bridge
+    }
+
+    // Read the time for time-dependent processing (e.g. the Note On throttle) from
+    // `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.pipeline.lock().unwrap().set_clock(clock.clone());
+        self.clock = clock;
+        self
+    }
+
+    // Keep the notes held right now sounding on the freeze output (`Config::freeze`)
+    // until `clear_freeze`
+    pub fn freeze_held_notes(&self) -> Result<()> {
+        if self.config.freeze.is_none() {
+            return Err(anyhow!("Freezing notes needs Config::freeze"));
+        }
+        let mut pipeline = self.pipeline.lock().unwrap();
+        for output in pipeline.freeze_held_notes() {
+            self.forward_message(&output.message, &output.route)?;
+        }
+        Ok(())
+    }
+
+    pub fn clear_freeze(&self) -> Result<()> {
+        let mut pipeline = self.pipeline.lock().unwrap();
+        for output in pipeline.clear_freeze() {
+            self.forward_message(&output.message, &output.route)?;
+        }
+        Ok(())
+    }
+
+    // Silence every channel of every output
+    pub fn send_panic(&self) -> Result<()> {
+        for message in &self.config.panic_mode.messages() {
+            self.sinks.send_all(message)?;
+        }
+        Ok(())
+    }
+
+    // Change the transposition while running, e.g. from a UI slider. Notes held at the
+    // time are still released at the pitch they were played at.
+    pub fn set_octave_offset(&self, offset: i8) -> Result<()> {
+        check_offset("Octave offset", offset)?;
+        self.pipeline.lock().unwrap().set_octave_offset(offset);
+        Ok(())
+    }
+
+    pub fn set_semitone_offset(&self, offset: i8) -> Result<()> {
+        check_offset("Semitone offset", offset)?;
+        self.pipeline.lock().unwrap().set_semitone_offset(offset);
+        Ok(())
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    // Current lifecycle state; cheap enough to poll from a UI timer
+    pub fn state(&self) -> BridgeState {
+        self.state.get()
+    }
+
+    // Every message forwarded to the outputs from now on. A receiver that lags
+    // too far behind gets `RecvError::Lagged` and skips ahead.
+    pub fn message_stream(&self) -> broadcast::Receiver<MidiMessage> {
+        self.messages.subscribe()
+    }
+
+    // Notes currently sounding downstream (after transposition)
+    pub fn held_notes(&self) -> Vec<HeldNote> {
+        self.pipeline.lock().unwrap().active_notes().iter().map(|(_, _, held)| *held).collect()
+    }
+
+    // How long the current link has been up; None while not connected
+    pub fn uptime(&self) -> Option<Duration> {
+        self.connected_at.lock().unwrap().map(|since| self.clock.now().duration_since(since))
+    }
+
+    // Times the link was lost and successfully re-established
+    pub fn reconnect_count(&self) -> u32 {
+        self.reconnects.load(Ordering::Relaxed)
+    }
+
+    // Messages each pipeline stage has dropped since the bridge started
+    pub fn drop_stats(&self) -> DropStats {
+        self.pipeline.lock().unwrap().drop_stats()
+    }
+
+    // Messages per second received from the device over the last second, per category,
+    // e.g. for activity meters. Categories with no recent messages are left out.
+    pub fn message_rates(&self) -> BTreeMap<MessageCategory, f64> {
+        self.pipeline.lock().unwrap().message_rates()
+    }
+
+    // Snapshot of the connected device's identity and capabilities
+    pub async fn device_info(&self) -> Result<DeviceInfo> {
+        let device = self.device.lock().unwrap().clone()
+            .ok_or_else(|| anyhow!("No device connected"))?;
+        device.info().await
+    }
+
+    // Send the SysEx messages of a .syx file (e.g. a patch dump) to the connected device
+    pub async fn send_sysex_file(&self, path: &Path) -> Result<()> {
+        let device = self.device.lock().unwrap().clone()
+            .ok_or_else(|| anyhow!("No device connected"))?;
+        let max_len = max_write_len(device.as_ref(), self.config.max_write_len).await;
+        send_sysex_file(device.as_ref(), path, max_len).await
     }
 
-    pub async fn start(&self, config: &Config) -> Result<()> {
+    // Connect to the device and forward its MIDI, reconnecting when it is lost.
+    // Only returns once the bridge gives up. The outputs stay open across reconnects,
+    // and with `Config::midi_port_recovery` a lost output doesn't end the session.
+    pub async fn start(&self) -> Result<()> {
+        let result = self.run().await;
+        *self.device.lock().unwrap() = None;
+        if result.is_err() {
+            self.state.set(BridgeState::Error);
+        }
+        result
+    }
+
+    // Drive the connection lifecycle (see `ConnectionState`) until it stops
+    async fn run(&self) -> Result<()> {
+        let mut flaps = self.config.flap_detection.map(|flap| FlapDetector::new(flap.max_drops, flap.window));
+        let mut state = ConnectionState::Scanning;
+        let mut device: Option<Arc<dyn BleLink>> = None;
+        let mut notifications = None;
+        // Pause before the next reconnect, and whether to keep its logging quiet
+        let mut backoff = (self.config.reconnect_delay, false);
+        let mut error = None;
+
+        loop {
+            self.state.set(state.bridge_state());
+            let event = match state {
+                ConnectionState::Scanning => match self.connector.connect().await {
+                    Ok(link) => {
+                        device = Some(link);
+                        ConnectionEvent::Connected
+                    }
+                    Err(e) => {
+                        error = Some(e);
+                        ConnectionEvent::ConnectFailed
+                    }
+                },
+                ConnectionState::Connecting { .. } => {
+                    let link = device.clone().expect("linked before connecting");
+                    *self.device.lock().unwrap() = Some(link.clone());
+                    match self.subscribe(&*link).await {
+                        Ok(stream) => {
+                            notifications = Some(stream);
+                            ConnectionEvent::Subscribed
+                        }
+                        // The link dropped while setting up; a fresh connection may work
+                        Err(e) if is_ble_transport(&e) => {
+                            warn!("{:#}", e);
+                            ConnectionEvent::LinkLost
+                        }
+                        Err(e) => {
+                            error = Some(e);
+                            ConnectionEvent::Fatal
+                        }
+                    }
+                }
+                ConnectionState::Running => {
+                    let link = device.clone().expect("linked while running");
+                    let stream = notifications.take().expect("subscribed while running");
+                    *self.connected_at.lock().unwrap() = Some(self.clock.now());
+                    let end = self.run_session(link, stream).await;
+                    *self.connected_at.lock().unwrap() = None;
+                    match end {
+                        SessionEnd::Disconnected => ConnectionEvent::LinkLost,
+                        SessionEnd::Failed(e) => {
+                            error = Some(e);
+                            ConnectionEvent::Fatal
+                        }
+                    }
+                }
+                ConnectionState::Backoff { .. } => {
+                    time::sleep(backoff.0).await;
+                    ConnectionEvent::Waited
+                }
+                ConnectionState::Reconnecting { attempt } => {
+                    if backoff.1 {
+                        debug!("Reconnecting to device (attempt {})...", attempt);
+                    } else {
+                        info!("Reconnecting to device (attempt {})...", attempt);
+                    }
+                    match self.connector.connect().await {
+                        Ok(link) => {
+                            let reconnects = self.reconnects.fetch_add(1, Ordering::Relaxed) + 1;
+                            info!("Reconnected ({} reconnect(s) so far)", reconnects);
+                            device = Some(link);
+                            ConnectionEvent::Connected
+                        }
+                        Err(e) => {
+                            warn!("Reconnect attempt {} failed: {}", attempt, e);
+                            ConnectionEvent::ConnectFailed
+                        }
+                    }
+                }
+                ConnectionState::Stopped => {
+                    return Err(error.unwrap_or_else(|| {
+                        anyhow!("BLE device disconnected unexpectedly - please check if the device is turned on and within range")
+                    }));
+                }
+            };
+
+            if event == ConnectionEvent::LinkLost {
+                // While the link flaps, the detector's single warning replaces the per-drop noise
+                let unstable = flaps.as_mut().is_some_and(|flaps| flaps.record(self.clock.now()));
+                if unstable {
+                    debug!("Device disconnected again");
+                } else {
+                    error!("Device disconnected unexpectedly");
+                }
+                self.send_disconnect_cue().await;
+                *self.device.lock().unwrap() = None;
+                backoff = match self.config.flap_detection {
+                    Some(flap) if unstable => (flap.backoff, true),
+                    _ => (self.config.reconnect_delay, false),
+                };
+            }
+
+            let next = state.next(event, self.config.reconnect_attempts);
+            debug!("Connection {:?} -> {:?} on {:?}", state, next, event);
+            state = next;
+        }
+    }
+
+    // Forward the MIDI of a subscribed device until the link is lost
+    async fn run_session(&self, device: Arc<dyn BleLink>, notifications: NotificationStream) -> SessionEnd {
+        let quirks = *self.quirks.lock().unwrap();
+
+        // Without `process_keepalive_reads` the sender is dropped and no reads arrive
+        let (reads_sender, keepalive_reads) = mpsc::channel(KEEPALIVE_READ_BUFFER);
+        let mut keepalive = SessionKeepalive {
+            link: device.clone(),
+            reads: quirks.process_keepalive_reads.then_some(reads_sender),
+            task: None,
+        };
+        keepalive.start(KeepaliveMode::Read, quirks.ble_keepalive_interval, self.config.keepalive_initial_delay);
+
+        self.forward_notifications(&*device, notifications, keepalive_reads, &mut keepalive).await
+    }
+
+    // Settings for `QuirkProfile::Auto`: those of the profile matching the device's
+    // identity, or the configured ones for an unknown device
+    async fn detect_quirks(&self, device: &dyn BleLink) -> QuirkSettings {
+        let info = match device.info().await {
+            Ok(info) => info,
+            Err(e) => {
+                warn!("Could not identify the device for the quirk profile: {}", e);
+                return QuirkSettings::from_config(&self.config);
+            }
+        };
+        let identity = format!(
+            "{} {} ('{}')",
+            info.manufacturer_name.as_deref().unwrap_or("unknown manufacturer"),
+            info.model_number.as_deref().unwrap_or("unknown model"),
+            info.name.as_deref().unwrap_or("unnamed"),
+        );
+        match QuirkProfile::detect(&info) {
+            Some(profile) => {
+                info!("Using the {:?} quirk profile for {}", profile, identity);
+                QuirkSettings::detected(&self.config, profile)
+            }
+            None => {
+                info!("No quirk profile known for {}, using the configured settings", identity);
+                QuirkSettings::from_config(&self.config)
+            }
+        }
+    }
+
+    async fn subscribe(&self, device: &dyn BleLink) -> Result<NotificationStream> {
+        if self.config.quirk_profile == Some(QuirkProfile::Auto) {
+            let quirks = self.detect_quirks(device).await;
+            *self.quirks.lock().unwrap() = quirks;
+        }
+        let quirks = *self.quirks.lock().unwrap();
+
         // Find the BLE-MIDI service and characteristic
-        let midi_service = self
-            .ble_device
-            .peripheral
+        let midi_service = device
             .services()
             .into_iter()
             .find(|s| s.uuid == BLE_MIDI_SERVICE_UUID)
@@ -66,118 +1111,325 @@ impl BleMidiBridge {
         info!("Found BLE-MIDI service: {}", midi_service.uuid);
         info!("Found BLE-MIDI characteristic: {}", characteristic.uuid);
 
-        // Subscribe to notifications
-        self.ble_device.peripheral.subscribe(&characteristic).await?;
-        info!("Subscribed to BLE-MIDI notifications");
+        if !quirks.post_connect_delay.is_zero() {
+            info!("Waiting {:?} before subscribing", quirks.post_connect_delay);
+            time::sleep(quirks.post_connect_delay).await;
+        }
 
-        // Start keep-alive
-        self.ble_device.start_keepalive(
-            BLE_MIDI_CHARACTERISTIC_UUID,
-            config.ble_keepalive_interval
-        ).await;
+        // Subscribe to notifications. The stream is acquired before subscribing so
+        // packets sent right after the subscription completes are not dropped.
+        let notifications = subscribe_notifications_with_retry(
+            device,
+            &characteristic,
+            quirks.subscribe_attempts,
+            self.config.subscribe_retry_delay,
+        )
+        .await?;
+        info!("Subscribed to BLE-MIDI notifications");
+        Ok(notifications)
+    }
 
+    async fn forward_notifications(
+        &self,
+        device: &dyn BleLink,
+        mut notifications: NotificationStream,
+        mut keepalive_reads: mpsc::Receiver<Vec<u8>>,
+        keepalive: &mut SessionKeepalive,
+    ) -> SessionEnd {
         // Main processing loop
-        let mut notifications = self.ble_device.peripheral.notifications().await?;
         let mut consecutive_errors = 0;
+        let mut max_consecutive_errors = MAX_CONSECUTIVE_PACKET_ERRORS;
+        let mut link_adapter = self.config.adaptive_link.map(|adaptive| LinkAdapter::new(adaptive.weak_rssi, adaptive.strong_rssi));
+        let mut rssi_ticker = self.config.adaptive_link.map(|adaptive| {
+            time::interval_at(time::Instant::now() + adaptive.sample_interval, adaptive.sample_interval)
+        });
+        let mut disconnect_debouncer = DisconnectDebouncer::new(self.config.disconnect_confirmations);
+        let mut stats_ticker = self.config.stats_log_interval.map(|interval| {
+            time::interval_at(time::Instant::now() + interval, interval)
+        });
+        let mut note_log_ticker = self.csv_log.as_ref().map(|_| {
+            time::interval_at(time::Instant::now() + NOTE_LOG_FLUSH_INTERVAL, NOTE_LOG_FLUSH_INTERVAL)
+        });
         
         loop {
+            let next_note_release = self.pipeline.lock().unwrap().next_note_release();
             tokio::select! {
-                Some(notification) = notifications.next() => {
+                notification = notifications.next() => {
+                    // The stack closes the stream when the link goes away
+                    let Some(notification) = notification else {
+                        warn!("BLE notification stream closed");
+                        return SessionEnd::Disconnected;
+                    };
                     if notification.uuid == BLE_MIDI_CHARACTERISTIC_UUID {
+                        // Nothing to forward, but proof that the link is alive
+                        if packet::is_header_only(&notification.value) {
+                            trace!("Header-only BLE-MIDI packet");
+                            disconnect_debouncer.record(true);
+                            continue;
+                        }
                         match self.process_ble_midi_packet(&notification.value).await {
                             Ok(_) => {
                                 // Reset error counter on successful processing
                                 consecutive_errors = 0;
+                                // Any MIDI shows the link is up, even if it was filtered
+                                // out, such as active sensing with `channel_voice_only`
+                                disconnect_debouncer.record(true);
+                                self.state.set(BridgeState::Forwarding);
                             }
                             Err(e) => {
                                 consecutive_errors += 1;
                                 error!("Error processing BLE-MIDI packet: {}", e);
                                 
                                 // If we get too many consecutive errors, propagate the error up
-                                if consecutive_errors > 10 {
-                                    return Err(anyhow!("Too many consecutive BLE-MIDI packet errors, last error: {}", e));
+                                if consecutive_errors > max_consecutive_errors {
+                                    return SessionEnd::Failed(anyhow!("Too many consecutive BLE-MIDI packet errors, last error: {}", e));
                                 }
                             }
                         }
                     }
                 }
-                _ = time::sleep(config.ble_status_check_interval) => {
-                    // Check connection status periodically
-                    if !self.ble_device.peripheral.is_connected().await? {
-                        error!("Device disconnected unexpectedly");
-                        return Err(anyhow!("BLE device disconnected unexpectedly - please check if the device is turned on and within range"));
+                Some(value) = keepalive_reads.recv() => {
+                    // Often not MIDI at all, so a bad packet is no reason to give up
+                    debug!("Processing keep-alive read");
+                    if let Err(e) = self.process_ble_midi_packet(&value).await {
+                        debug!("Ignoring keep-alive read: {}", e);
+                    }
+                }
+                _ = async {
+                    match next_note_release {
+                        Some(at) => time::sleep_until(at).await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    if let Err(e) = self.release_due_notes() {
+                        error!("Error sending timed notes: {}", e);
+                    }
+                }
+                _ = async {
+                    match &mut stats_ticker {
+                        Some(ticker) => ticker.tick().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    info!(
+                        "Link up for {:?}, {} reconnect(s) since start, {}",
+                        self.uptime().unwrap_or_default(),
+                        self.reconnect_count(),
+                        self.drop_stats()
+                    );
+                }
+                _ = async {
+                    match &mut note_log_ticker {
+                        Some(ticker) => ticker.tick().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    if let Some(Err(e)) = self.csv_log.as_ref().map(CsvNoteLog::flush) {
+                        warn!("Failed to write the note log: {}", e);
+                    }
+                }
+                _ = async {
+                    match &mut rssi_ticker {
+                        Some(ticker) => ticker.tick().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    let (Some(adaptive), Some(adapter)) = (self.config.adaptive_link, link_adapter.as_mut()) else {
+                        continue;
+                    };
+                    let quality = match device.rssi().await {
+                        Ok(Some(rssi)) => adapter.sample(rssi),
+                        Ok(None) => None,
+                        Err(e) => {
+                            debug!("Could not read RSSI: {}", e);
+                            None
+                        }
+                    };
+                    let (mode, interval, max_errors) = match quality {
+                        Some(LinkQuality::Weak) => {
+                            (adaptive.weak_keepalive_mode, adaptive.weak_keepalive_interval, adaptive.weak_error_threshold)
+                        }
+                        Some(LinkQuality::Strong) => {
+                            let interval = self.quirks.lock().unwrap().ble_keepalive_interval;
+                            (KeepaliveMode::Read, interval, MAX_CONSECUTIVE_PACKET_ERRORS)
+                        }
+                        None => continue,
+                    };
+                    info!("Keep-alive {:?} every {:?}, tolerating {} consecutive bad packets", mode, interval, max_errors);
+                    keepalive.start(mode, interval, interval);
+                    max_consecutive_errors = max_errors;
+                }
+                _ = time::sleep(self.config.ble_status_check_interval) => {
+                    // Check connection status periodically. A transport error counts as a
+                    // failed check rather than aborting immediately; anything else means
+                    // reconnecting won't help.
+                    let connected = match device.is_connected().await {
+                        Ok(connected) => connected,
+                        Err(e) if is_ble_transport(&e) => {
+                            warn!("Could not query connection status: {}", e);
+                            false
+                        }
+                        Err(e) => return SessionEnd::Failed(e),
+                    };
+                    if disconnect_debouncer.record(connected) {
+                        return SessionEnd::Disconnected;
                     }
                 }
             }
         }
-    }    async fn process_ble_midi_packet(&self, data: &[u8]) -> Result<()> {
+    }
+
+    // Play the configured disconnect cue on every output, while they are still open
+    async fn send_disconnect_cue(&self) {
+        let Some(cue) = &self.config.disconnect_cue else {
+            return;
+        };
+
+        info!("Sending disconnect cue");
+        for message in cue {
+            if let Err(e) = self.sinks.send_all(message) {
+                warn!("Failed to send disconnect cue: {}", e);
+            }
+            if message.message_type() == "Note On" {
+                time::sleep(DISCONNECT_CUE_STEP).await;
+            }
+        }
+    }
+
+    async fn process_ble_midi_packet(&self, data: &[u8]) -> Result<()> {
         if data.len() < 2 {
             return Err(anyhow!("BLE-MIDI packet too short"));
         }
+        // Not a parse error: a misbehaving peer shouldn't be able to take the bridge down
+        if data.len() > self.config.max_packet_bytes {
+            warn!(
+                "Ignoring oversized BLE-MIDI packet ({} bytes, limit {})",
+                data.len(),
+                self.config.max_packet_bytes
+            );
+            return Ok(());
+        }
 
-        debug!("Received BLE-MIDI packet: {:02X?}", data);
+        match self.config.packet_log_style {
+            PacketLogStyle::Compact => debug!("Received BLE-MIDI packet: {:02X?}", data),
+            PacketLogStyle::HexDump => debug!("Received BLE-MIDI packet:\n{}", hexdump(data)),
+        }
         debug!("Packet length: {}", data.len());
         
         // Debug header byte
-        debug!("Header byte: 0x{:02X}", data[0]);
+        debug!("Header byte: 0x{:02X} (timestamp high bits: {:?})", data[0], header_timestamp(data[0]));
         debug!("Timestamp byte: 0x{:02X}", data[1]);
 
-        // In BLE-MIDI, each packet has the format: [header, timestamp, status, data1, data2]
-        // The header and timestamp are BLE-specific, the actual MIDI message starts at index 2
-        if data.len() >= 5 {
-            let status = data[2];   // MIDI status byte
-            let mut data1 = data[3]; // First MIDI data byte (note number)
-            let data2 = data[4];    // Second MIDI data byte (velocity)
-
-            // Apply octave transposition for Note On/Off messages
-            let message_type = status & 0xF0;
-            if message_type == 0x90 || message_type == 0x80 {
-                let octave_shift = self.config.octave_offset * 12;
-                let original_note = data1;
-                let new_note = (data1 as i16 + octave_shift as i16).clamp(0, 127) as u8;
-                data1 = new_note;
-                  // Log transposition details only in debug mode
-                debug!(
-                    "Note transposition: {} ({}) -> {} ({}) [offset: {} octaves]",
-                    MidiMessage { status, data1: original_note, data2 }.note_name(),
-                    original_note,
-                    MidiMessage { status, data1: new_note, data2 }.note_name(),
-                    new_note,
-                    self.config.octave_offset
-                );
+        if let Some(jitter) = &self.jitter {
+            // Malformed packets are reported by the parser below
+            if let Ok(timed) = packet::decode(data) {
+                let timestamps: Vec<_> = timed.iter().map(|message| message.timestamp).collect();
+                if let Some(stats) = jitter.lock().unwrap().record(&timestamps, self.clock.now()) {
+                    info!("BLE-MIDI timing jitter: {}", stats);
+                }
             }
+        }
 
-            let message = MidiMessage { status, data1, data2 };
-            let msg = if message.message_type() == "Note On" {
-                format!(
-                    "Note On: {} (velocity: {}) [status: {:02X}, note: {:02X}, velocity: {:02X}]",
-                    message.note_name(),
-                    message.velocity(),
-                    message.status,
-                    message.data1,
-                    message.data2
-                )
-            } else if message.message_type() == "Note Off" {
-                format!(
-                    "Note Off: {} [status: {:02X}, note: {:02X}, velocity: {:02X}]",
-                    message.note_name(),
-                    message.status,
-                    message.data1,
-                    message.data2
-                )
-            } else {
-                format!(
-                    "MIDI Message: {} [status: {:02X}, data1: {:02X}, data2: {:02X}]",
-                    message.message_type(),
-                    message.status,
-                    message.data1,
-                    message.data2
-                )
-            };
-            debug!("{}", msg);
+        // A packet can carry several messages, possibly using running status. They go
+        // out in the order they were sent, e.g. a Note Off and Note On re-striking a key.
+        let messages = parse_ble_midi_packet(data, self.quirks.lock().unwrap().packet_header_check)?;
+        self.process_messages(messages)
+    }
+
+    // Handle a message as if the device had sent it, on `Config::injection_channel`
+    pub fn inject_message(&self, message: MidiMessage) -> Result<()> {
+        let message = message.with_channel(self.config.injection_channel);
+        debug!("Injecting {}", message.message_type());
+        self.process_message(message)
+    }
+
+    // Play a C major scale through the normal processing, to check the outputs work
+    pub async fn self_test(&self) -> Result<()> {
+        info!("Playing self-test scale on channel {}", self.config.injection_channel + 1);
+        for note in SELF_TEST_SCALE {
+            self.inject_message(MidiMessage { status: 0x90, data1: note, data2: 100 })?;
+            time::sleep(SELF_TEST_STEP).await;
+            self.inject_message(MidiMessage { status: 0x80, data1: note, data2: 0 })?;
+        }
+        Ok(())
+    }
 
-            // Send the MIDI message
-            self.midi_output.send_message(&message)?;
+    // Play one note on every output, e.g. for a "test output" button. Unlike injected
+    // messages it skips the pipeline, so muting or transposition can't hide it.
+    pub async fn play_test_note(&self, note: u8, velocity: u8, duration: Duration) -> Result<()> {
+        if note > 127 || velocity > 127 {
+            return Err(anyhow!("Invalid test note {} with velocity {}", note, velocity));
+        }
+        let status = self.config.injection_channel;
+        self.forward_message(&MidiMessage { status: 0x90 | status, data1: note, data2: velocity }, &Route::All)?;
+        time::sleep(duration).await;
+        self.forward_message(&MidiMessage { status: 0x80 | status, data1: note, data2: 0 }, &Route::All)
+    }
+
+    fn process_message(&self, message: MidiMessage) -> Result<()> {
+        self.process_messages([message])
+    }
+
+    // Run messages through the processing pipeline (transposition etc.) and send what
+    // comes out. The pipeline stays locked until everything is sent, so messages handled
+    // concurrently (e.g. injected ones) can't get in between and the outputs keep the
+    // order of the messages, minus any the pipeline drops.
+    fn process_messages(&self, messages: impl IntoIterator<Item = MidiMessage>) -> Result<()> {
+        let mut pipeline = self.pipeline.lock().unwrap();
+        for message in messages {
+            for output in pipeline.process(message) {
+                self.forward_message(&output.message, &output.route)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Send the notes the pipeline has timed: held-back Note Ons and arpeggiator steps
+    fn release_due_notes(&self) -> Result<()> {
+        let mut pipeline = self.pipeline.lock().unwrap();
+        for output in pipeline.release_due_notes() {
+            self.forward_message(&output.message, &output.route)?;
+        }
+        Ok(())
+    }
+
+    fn forward_message(&self, message: &MidiMessage, route: &Route) -> Result<()> {
+        let msg = if message.message_type() == "Note On" {
+            format!(
+                "Note On: {} (velocity: {}) [status: {:02X}, note: {:02X}, velocity: {:02X}]",
+                message.note_name(),
+                message.velocity(),
+                message.status,
+                message.data1,
+                message.data2
+            )
+        } else if message.message_type() == "Note Off" {
+            format!(
+                "Note Off: {} [status: {:02X}, note: {:02X}, velocity: {:02X}]",
+                message.note_name(),
+                message.status,
+                message.data1,
+                message.data2
+            )
+        } else {
+            format!(
+                "MIDI Message: {} [status: {:02X}, data1: {:02X}, data2: {:02X}]",
+                message.message_type(),
+                message.status,
+                message.data1,
+                message.data2
+            )
+        };
+        debug!("{}", msg);
+
+        // Send the MIDI message to its outputs
+        self.sinks.send(message, route)?;
+        // Nobody listening is fine
+        let _ = self.messages.send(*message);
+        if let Some(log) = &self.csv_log {
+            if let Err(e) = log.record(message, self.clock.now()) {
+                warn!("Failed to write the note log: {}", e);
+            }
         }
 
         Ok(())
@@ -187,6 +1439,11 @@ impl BleMidiBridge {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ble::testing::{FakeConnector, FakeLink};
+    use crate::ble::{Advertisement, BestMatch};
+    use crate::midi::testing::RecordingSink;
+    use crate::pipeline::transpose;
+    use crate::util::MockClock;
     use std::time::Duration;
 
     #[test]
@@ -197,6 +1454,7 @@ mod tests {
             ble_keepalive_interval: Duration::from_secs(10),
             ble_status_check_interval: Duration::from_secs(1),
             octave_offset: 1,
+            ..Config::default()
         };
 
         assert_eq!(config.virtual_midi_port_name, "TEST_PORT");
@@ -204,6 +1462,15 @@ mod tests {
         assert_eq!(config.ble_keepalive_interval, Duration::from_secs(10));
         assert_eq!(config.ble_status_check_interval, Duration::from_secs(1));
         assert_eq!(config.octave_offset, 1);
+        assert_eq!(config.packet_log_style, PacketLogStyle::Compact);
+        // Returning once the device is lost, unless reconnecting is asked for
+        assert_eq!(config.reconnect_attempts, Some(0));
+    }
+
+    #[test]
+    fn test_create_virtual_port_needs_support() {
+        let config = Config { create_virtual_port: true, ..Config::default() };
+        assert_eq!(config.validate().is_ok(), cfg!(all(unix, feature = "midir")));
     }
 
     // This test ensures the durations are positive and reasonable
@@ -215,6 +1482,7 @@ mod tests {
             ble_keepalive_interval: Duration::from_secs(10),
             ble_status_check_interval: Duration::from_secs(1),
             octave_offset: 0,
+            ..Config::default()
         };
 
         assert!(config.ble_scan_timeout > Duration::from_secs(0));
@@ -225,7 +1493,7 @@ mod tests {
         assert!(config.ble_keepalive_interval > config.ble_status_check_interval);
         
         // Check octave offset range
-        assert!(config.octave_offset >= -11 && config.octave_offset <= 11);
+        assert!(OFFSET_RANGE.contains(&config.octave_offset));
     }
 
     #[test]
@@ -245,23 +1513,701 @@ mod tests {
             let mut packet = vec![0x80, 0x80];  // Header and timestamp
             packet.extend_from_slice(&[0x90, original_note, 0x7F]); // Note On, note, velocity
             
-            let config = Config {
+            let _config = Config {
                 virtual_midi_port_name: "TEST_PORT".to_string(),
                 ble_scan_timeout: Duration::from_secs(30),
                 ble_keepalive_interval: Duration::from_secs(10),
                 ble_status_check_interval: Duration::from_secs(1),
                 octave_offset,
+                ..Config::default()
             };
 
-            let message = MidiMessage {
+            let _message = MidiMessage {
                 status: 0x90,
                 data1: original_note,
                 data2: 0x7F,
             };
 
-            let transposed_note = ((original_note as i16) + ((octave_offset * 12) as i16))
-                .clamp(0, 127) as u8;
+            let transposed_note = transpose(original_note, octave_offset as i16 * 12);
             assert_eq!(transposed_note, expected_note);
         }
     }
+
+    fn fake_bridge(config: &Config) -> (BleMidiBridge, Arc<FakeLink>, RecordingSink) {
+        let link = Arc::new(FakeLink::new());
+        let output = RecordingSink::new();
+        let mut sinks = SinkSet::new();
+        sinks.add("output", Box::new(output.clone()));
+        let connector = FakeConnector::new([link.clone()]);
+        (BleMidiBridge::from_parts(config, Box::new(connector), sinks), link, output)
+    }
+
+    // Give spawned bridge tasks a chance to run up to their next timer
+    async fn settle() {
+        time::sleep(Duration::from_millis(10)).await;
+    }
+
+    #[test]
+    fn test_disconnect_cue_motif() {
+        let cue = disconnect_cue_motif(2);
+        assert_eq!(cue.len(), 4);
+        assert!(cue.iter().all(|m| m.channel() == Some(2)));
+        assert_eq!(cue[0].message_type(), "Note On");
+        assert!(cue[0].data1 > cue[2].data1, "motif should descend");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_disconnect_cue_sent_on_disconnect() {
+        let config = Config {
+            disconnect_cue: Some(disconnect_cue_motif(0)),
+            reconnect_attempts: Some(0),
+            ..Config::default()
+        };
+        let (bridge, link, output) = fake_bridge(&config);
+
+        link.send_packet(&[0x80, 0x80, 0x90, 60, 100]);
+        link.set_connected(false);
+
+        assert!(bridge.start().await.is_err());
+
+        let mut expected = vec![MidiMessage { status: 0x90, data1: 60, data2: 100 }];
+        expected.extend(disconnect_cue_motif(0));
+        assert_eq!(output.messages(), expected);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_no_cue_by_default() {
+        let config = Config { reconnect_attempts: Some(0), ..Config::default() };
+        let (bridge, link, output) = fake_bridge(&config);
+        link.set_connected(false);
+
+        assert!(bridge.start().await.is_err());
+        assert!(output.messages().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_state_through_lifecycle() {
+        let config = Config { reconnect_attempts: Some(1), ..Config::default() };
+        let first = Arc::new(FakeLink::new());
+        let second = Arc::new(FakeLink::new());
+        let connector = FakeConnector::new([first.clone(), second.clone()]);
+        let bridge = Arc::new(BleMidiBridge::from_parts(&config, Box::new(connector), SinkSet::new()));
+        assert_eq!(bridge.state(), BridgeState::Scanning);
+
+        let task = tokio::spawn({
+            let bridge = bridge.clone();
+            async move { bridge.start().await }
+        });
+        settle().await;
+        assert_eq!(bridge.state(), BridgeState::Connected);
+        assert!(bridge.device_info().await.is_ok());
+
+        first.send_packet(&[0x80, 0x80, 0x90, 60, 100]);
+        settle().await;
+        assert_eq!(bridge.state(), BridgeState::Forwarding);
+
+        first.set_connected(false);
+        time::sleep(config.ble_status_check_interval).await;
+        assert_eq!(bridge.state(), BridgeState::Reconnecting);
+        assert!(bridge.device_info().await.is_err());
+
+        time::sleep(config.reconnect_delay).await;
+        assert_eq!(bridge.state(), BridgeState::Connected);
+
+        // The connector has no third device, so the single allowed attempt fails
+        second.set_connected(false);
+        assert!(task.await.unwrap().is_err());
+        assert_eq!(bridge.state(), BridgeState::Error);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_uptime_and_reconnect_count() {
+        let config = Config { reconnect_attempts: Some(1), ..Config::default() };
+        let links: Vec<_> = (0..3).map(|_| Arc::new(FakeLink::new())).collect();
+        let connector = FakeConnector::new(links.clone());
+        let bridge = Arc::new(BleMidiBridge::from_parts(&config, Box::new(connector), SinkSet::new()));
+        assert_eq!(bridge.uptime(), None);
+
+        let task = tokio::spawn({
+            let bridge = bridge.clone();
+            async move { bridge.start().await }
+        });
+        settle().await;
+        time::sleep(Duration::from_secs(5)).await;
+        assert!(bridge.uptime().unwrap() >= Duration::from_secs(5));
+        assert_eq!(bridge.reconnect_count(), 0);
+
+        // Two drops, each followed by a successful reconnect that restarts the uptime
+        for (i, link) in links[..2].iter().enumerate() {
+            link.set_connected(false);
+            time::sleep(config.ble_status_check_interval + config.reconnect_delay).await;
+            settle().await;
+            assert_eq!(bridge.reconnect_count(), i as u32 + 1);
+            assert!(bridge.uptime().unwrap() < Duration::from_secs(2));
+        }
+
+        // The last drop can't be recovered from
+        links[2].set_connected(false);
+        assert!(task.await.unwrap().is_err());
+        assert_eq!(bridge.uptime(), None);
+        assert_eq!(bridge.reconnect_count(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_wait_for_midi_port() {
+        let polls = std::cell::Cell::new(0);
+        let list_devices = || {
+            polls.set(polls.get() + 1);
+            let mut devices = vec![(0, "Microsoft GS Wavetable Synth".to_string())];
+            if polls.get() >= 3 {
+                devices.push((1, "AKAI_LPK25_IN_BLE".to_string()));
+            }
+            Ok(devices)
+        };
+
+        let started = time::Instant::now();
+        assert!(wait_for_midi_port("AKAI_LPK25_IN_BLE", Duration::from_secs(10), list_devices).await);
+        assert_eq!(polls.get(), 3);
+        assert_eq!(started.elapsed(), MIDI_PORT_POLL_INTERVAL * 2);
+
+        // Giving up after the wait, with a last look at the deadline
+        polls.set(-100);
+        let started = time::Instant::now();
+        assert!(!wait_for_midi_port("AKAI_LPK25_IN_BLE", Duration::from_secs(5), list_devices).await);
+        assert_eq!(started.elapsed(), Duration::from_secs(5));
+        assert_eq!(polls.get(), -94);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_connect_disconnect_reconnect_cycle() {
+        let config = Config { reconnect_attempts: Some(3), ..Config::default() };
+        let links: Vec<_> = (0..2).map(|_| Arc::new(FakeLink::new())).collect();
+        let connector = FakeConnector::new(links.clone());
+        let bridge = Arc::new(BleMidiBridge::from_parts(&config, Box::new(connector), SinkSet::new()));
+        assert_eq!(bridge.state(), BridgeState::Scanning);
+
+        let task = tokio::spawn({
+            let bridge = bridge.clone();
+            async move { bridge.start().await }
+        });
+        settle().await;
+        assert_eq!(bridge.state(), BridgeState::Connected);
+
+        // Lost at the next status check, then backing off before reconnecting
+        links[0].set_connected(false);
+        time::sleep(config.ble_status_check_interval).await;
+        settle().await;
+        assert_eq!(bridge.state(), BridgeState::Reconnecting);
+        assert_eq!(bridge.uptime(), None);
+
+        time::sleep(config.reconnect_delay).await;
+        assert_eq!(bridge.state(), BridgeState::Connected);
+        assert_eq!(bridge.reconnect_count(), 1);
+
+        links[1].send_packet(&[0x80, 0x80, 0x90, 60, 100]);
+        settle().await;
+        assert_eq!(bridge.state(), BridgeState::Forwarding);
+        task.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_flapping_link_backs_off() {
+        let flap = FlapDetection { max_drops: 2, window: Duration::from_secs(60), backoff: Duration::from_secs(30) };
+        for (flap_detection, expected) in [
+            // Every session lasts one status check, then the normal reconnect delay
+            (None, Duration::from_secs(4 * (1 + 2))),
+            // From the third drop on, the backoff replaces the reconnect delay
+            (Some(flap), Duration::from_secs(2 * (1 + 2) + 2 * (1 + 30))),
+        ] {
+            let config = Config { reconnect_attempts: Some(1), flap_detection, ..Config::default() };
+            let links: Vec<_> = (0..4).map(|_| Arc::new(FakeLink::new())).collect();
+            for link in &links {
+                link.set_connected(false);
+            }
+            let connector = FakeConnector::new(links);
+            let bridge = BleMidiBridge::from_parts(&config, Box::new(connector), SinkSet::new());
+
+            let start = time::Instant::now();
+            assert!(bridge.start().await.is_err());
+            assert_eq!(start.elapsed(), expected);
+            assert_eq!(bridge.reconnect_count(), 3);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_state_is_error_when_initial_connect_fails() {
+        let connector = FakeConnector::new([]);
+        let bridge = BleMidiBridge::from_parts(&Config::default(), Box::new(connector), SinkSet::new());
+
+        assert!(bridge.start().await.is_err());
+        assert_eq!(bridge.state(), BridgeState::Error);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_message_stream_and_held_notes() {
+        let config = Config { reconnect_attempts: Some(0), ..Config::default() };
+        let (bridge, link, _output) = fake_bridge(&config);
+        let mut stream = bridge.message_stream();
+
+        link.send_packet(&[0x80, 0x80, 0x90, 60, 100, 62, 100, 0x81, 0x80, 60, 0]);
+        link.set_connected(false);
+        assert!(bridge.start().await.is_err());
+
+        assert_eq!(stream.recv().await.unwrap(), MidiMessage { status: 0x90, data1: 60, data2: 100 });
+        assert_eq!(stream.recv().await.unwrap(), MidiMessage { status: 0x90, data1: 62, data2: 100 });
+        assert_eq!(stream.recv().await.unwrap(), MidiMessage { status: 0x80, data1: 60, data2: 0 });
+        assert_eq!(bridge.held_notes(), vec![HeldNote { note: 62, velocity: 100 }]);
+    }
+
+    #[test]
+    fn test_any_ble_midi_ignores_the_device_filters() {
+        let lpk25 = Advertisement { name: Some("LPK25".to_string()), address: "00:00:00:00:00:01".to_string(), ..Advertisement::default() };
+        let other = Advertisement {
+            name: Some("Other Keys".to_string()),
+            address: "00:00:00:00:00:02".to_string(),
+            services: vec![BLE_MIDI_SERVICE_UUID],
+            rssi: None,
+        };
+
+        for (any_ble_midi, expected) in [(false, "lpk25"), (true, "other")] {
+            let config = Config { any_ble_midi, ..Config::default() };
+            let mut best = BestMatch::new();
+            best.offer(&config.scan_filters(), &lpk25, "lpk25");
+            best.offer(&config.scan_filters(), &other, "other");
+            assert_eq!(best.into_inner().map(|(_, device)| device), Some(expected));
+        }
+    }
+
+    #[test]
+    fn test_message_rates_follow_the_clock() {
+        let clock = Arc::new(MockClock::new());
+        let (bridge, _link, _output) = fake_bridge(&Config::default());
+        let bridge = bridge.with_clock(clock.clone());
+
+        for note in 60..70 {
+            bridge.inject_message(MidiMessage { status: 0x90, data1: note, data2: 100 }).unwrap();
+            bridge.inject_message(MidiMessage { status: 0x80, data1: note, data2: 0 }).unwrap();
+            clock.advance(Duration::from_millis(50));
+        }
+        bridge.inject_message(MidiMessage { status: 0xE0, data1: 0, data2: 64 }).unwrap();
+        assert_eq!(bridge.message_rates(), BTreeMap::from([(MessageCategory::Notes, 20.0), (MessageCategory::PitchBend, 1.0)]));
+
+        clock.advance(Duration::from_secs(1));
+        assert!(bridge.message_rates().is_empty());
+    }
+
+    #[test]
+    fn test_injection_channel_validation() {
+        assert!(Config { injection_channel: 15, ..Config::default() }.validate().is_ok());
+        assert!(Config { injection_channel: 16, ..Config::default() }.validate().is_err());
+    }
+
+    #[test]
+    fn test_adaptive_link_validation() {
+        let adaptive = |weak_rssi, strong_rssi| Config {
+            adaptive_link: Some(AdaptiveLink { weak_rssi, strong_rssi, ..AdaptiveLink::default() }),
+            ..Config::default()
+        };
+        assert!(adaptive(-80, -70).validate().is_ok());
+        assert!(adaptive(-70, -80).validate().is_err());
+        assert!(adaptive(-75, -75).validate().is_err());
+    }
+
+    #[test]
+    fn test_injected_messages_use_injection_channel() {
+        let config = Config { injection_channel: 9, ..Config::default() };
+        let (bridge, _link, output) = fake_bridge(&config);
+
+        bridge.inject_message(MidiMessage { status: 0x90, data1: 60, data2: 100 }).unwrap();
+        bridge.inject_message(MidiMessage { status: 0xF8, data1: 0, data2: 0 }).unwrap();
+
+        assert_eq!(
+            output.messages(),
+            vec![
+                MidiMessage { status: 0x99, data1: 60, data2: 100 },
+                MidiMessage { status: 0xF8, data1: 0, data2: 0 },
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_self_test_scale() {
+        let config = Config { injection_channel: 3, ..Config::default() };
+        let (bridge, _link, output) = fake_bridge(&config);
+
+        bridge.self_test().await.unwrap();
+
+        let messages = output.messages();
+        assert_eq!(messages.len(), SELF_TEST_SCALE.len() * 2);
+        assert!(messages.iter().all(|m| m.channel() == Some(3)));
+        assert_eq!(messages[0], MidiMessage { status: 0x93, data1: 60, data2: 100 });
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_header_only_packets_feed_the_watchdog() {
+        let config = Config { disconnect_confirmations: 2, reconnect_attempts: Some(0), ..Config::default() };
+        let (bridge, link, output) = fake_bridge(&config);
+        let bridge = Arc::new(bridge);
+        let task = tokio::spawn({
+            let bridge = bridge.clone();
+            async move { bridge.start().await }
+        });
+        settle().await;
+
+        // One failed status check, then a header-only packet clears it
+        link.set_connected(false);
+        time::sleep(config.ble_status_check_interval).await;
+        link.send_packet(&[0x80, 0x80]);
+        settle().await;
+        time::sleep(config.ble_status_check_interval).await;
+        assert!(!task.is_finished());
+        assert!(output.messages().is_empty());
+
+        // Two in a row without one confirm the disconnect
+        time::sleep(config.ble_status_check_interval).await;
+        settle().await;
+        assert!(task.is_finished());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_filtered_active_sensing_feeds_the_watchdog() {
+        let config = Config { channel_voice_only: true, disconnect_confirmations: 2, reconnect_attempts: Some(0), ..Config::default() };
+        let (bridge, link, output) = fake_bridge(&config);
+        let bridge = Arc::new(bridge);
+        let task = tokio::spawn({
+            let bridge = bridge.clone();
+            async move { bridge.start().await }
+        });
+        settle().await;
+
+        link.set_connected(false);
+        time::sleep(config.ble_status_check_interval).await;
+        link.send_packet(&[0x80, 0x80, 0xFE]);
+        settle().await;
+        time::sleep(config.ble_status_check_interval).await;
+        assert!(!task.is_finished());
+        assert!(output.messages().is_empty());
+        assert_eq!(bridge.drop_stats().system, 1);
+        task.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_oversized_packets_are_ignored() {
+        let config = Config { max_packet_bytes: 8, reconnect_attempts: Some(0), ..Config::default() };
+        let (bridge, link, output) = fake_bridge(&config);
+
+        let mut oversized = vec![0x80];
+        for _ in 0..3 {
+            oversized.extend([0x80, 0x90, 60, 100]);
+        }
+        assert!(bridge.process_ble_midi_packet(&oversized).await.is_ok());
+        assert!(output.messages().is_empty());
+
+        // Plenty of them don't count as packet errors that would stop the bridge
+        for _ in 0..20 {
+            link.send_packet(&oversized);
+        }
+        link.send_packet(&[0x80, 0x80, 0x90, 62, 100]);
+        link.set_connected(false);
+
+        let error = bridge.start().await.unwrap_err();
+        assert!(error.to_string().contains("disconnected"), "{}", error);
+        assert_eq!(output.messages(), vec![MidiMessage { status: 0x90, data1: 62, data2: 100 }]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_held_back_note_is_sent_when_due() {
+        let config = Config { min_note_duration: Some(Duration::from_millis(20)), ..Config::default() };
+        let (bridge, link, output) = fake_bridge(&config);
+        let bridge = Arc::new(bridge);
+        let task = tokio::spawn({
+            let bridge = bridge.clone();
+            async move { bridge.start().await }
+        });
+        settle().await;
+
+        link.send_packet(&[0x80, 0x80, 0x90, 60, 100]);
+        time::sleep(Duration::from_millis(15)).await;
+        assert!(output.messages().is_empty());
+        time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(output.messages(), vec![MidiMessage { status: 0x90, data1: 60, data2: 100 }]);
+        task.abort();
+    }
+
+    #[test]
+    fn test_set_transposition_at_runtime() {
+        let (bridge, _link, output) = fake_bridge(&Config::default());
+        let note_on = |note| MidiMessage { status: 0x90, data1: note, data2: 100 };
+        let note_off = |note| MidiMessage { status: 0x80, data1: note, data2: 0 };
+
+        bridge.inject_message(note_on(60)).unwrap();
+        bridge.set_octave_offset(1).unwrap();
+        bridge.set_semitone_offset(-2).unwrap();
+        bridge.inject_message(note_on(64)).unwrap();
+        bridge.inject_message(note_off(60)).unwrap();
+        bridge.inject_message(note_off(64)).unwrap();
+        assert_eq!(output.messages(), [note_on(60), note_on(74), note_off(60), note_off(74)]);
+
+        // Out of range offsets leave the transposition alone
+        assert!(bridge.set_octave_offset(12).is_err());
+        assert!(bridge.set_semitone_offset(-12).is_err());
+        bridge.inject_message(note_on(60)).unwrap();
+        assert_eq!(output.messages().last(), Some(&note_on(70)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_play_test_note() {
+        // Transposition doesn't apply to the test note
+        let config = Config { injection_channel: 2, octave_offset: 1, ..Config::default() };
+        let (bridge, _link, output) = fake_bridge(&config);
+
+        let start = time::Instant::now();
+        bridge.play_test_note(64, 90, Duration::from_millis(300)).await.unwrap();
+
+        assert_eq!(start.elapsed(), Duration::from_millis(300));
+        assert_eq!(
+            output.messages(),
+            vec![
+                MidiMessage { status: 0x92, data1: 64, data2: 90 },
+                MidiMessage { status: 0x82, data1: 64, data2: 0 },
+            ]
+        );
+        assert!(bridge.play_test_note(128, 90, Duration::ZERO).await.is_err());
+    }
+
+    #[test]
+    fn test_injected_clock_drives_the_throttle() {
+        let config = Config { max_notes_per_sec: Some(2), ..Config::default() };
+        let (bridge, _link, output) = fake_bridge(&config);
+        let clock = Arc::new(MockClock::new());
+        let bridge = bridge.with_clock(clock.clone());
+        let note_on = MidiMessage { status: 0x90, data1: 60, data2: 100 };
+
+        for _ in 0..3 {
+            bridge.inject_message(note_on).unwrap();
+        }
+        assert_eq!(output.messages().len(), 2);
+
+        clock.advance(Duration::from_secs(1));
+        bridge.inject_message(note_on).unwrap();
+        assert_eq!(output.messages().len(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_keepalive_reads_are_processed_when_enabled() {
+        for enabled in [false, true] {
+            let config = Config { process_keepalive_reads: enabled, ..Config::default() };
+            let (bridge, link, output) = fake_bridge(&config);
+            link.set_read_value(&[0x80, 0x80, 0x90, 60, 100]);
+            let bridge = Arc::new(bridge);
+
+            let task = tokio::spawn({
+                let bridge = bridge.clone();
+                async move { bridge.start().await }
+            });
+            settle().await;
+            task.abort();
+
+            let expected = if enabled { vec![MidiMessage { status: 0x90, data1: 60, data2: 100 }] } else { vec![] };
+            assert_eq!(output.messages(), expected);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_post_connect_delay_defers_subscription() {
+        let config = Config { post_connect_delay: Duration::from_millis(500), ..Config::default() };
+        let (bridge, _link, _output) = fake_bridge(&config);
+        let bridge = Arc::new(bridge);
+
+        let task = tokio::spawn({
+            let bridge = bridge.clone();
+            async move { bridge.start().await }
+        });
+        settle().await;
+        assert_eq!(bridge.state(), BridgeState::Connecting);
+
+        time::sleep(config.post_connect_delay).await;
+        assert_eq!(bridge.state(), BridgeState::Connected);
+        task.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_auto_quirk_profile_follows_the_device() {
+        for (manufacturer, model, detected) in [("AKAI", "LPK25", true), ("Other", "X1", false)] {
+            let config = Config::for_quirk_profile(QuirkProfile::Auto);
+            let link = Arc::new(FakeLink::new().with_device_information(manufacturer, model));
+            let output = RecordingSink::new();
+            let mut sinks = SinkSet::new();
+            sinks.add("output", Box::new(output.clone()));
+            let connector = FakeConnector::new([link.clone()]);
+            let bridge = Arc::new(BleMidiBridge::from_parts(&config, Box::new(connector), sinks));
+            link.set_read_value(&[0x80, 0x80, 0x90, 60, 100]);
+
+            let task = tokio::spawn({
+                let bridge = bridge.clone();
+                async move { bridge.start().await }
+            });
+            settle().await;
+            // The LPK25 profile waits before subscribing and processes keep-alive reads
+            if detected {
+                assert_eq!(bridge.state(), BridgeState::Connecting);
+                time::sleep(Duration::from_millis(500)).await;
+            }
+            assert_eq!(bridge.state(), BridgeState::Connected);
+            settle().await;
+            task.abort();
+
+            let expected = if detected { vec![MidiMessage { status: 0x90, data1: 60, data2: 100 }] } else { vec![] };
+            assert_eq!(output.messages(), expected, "{} {}", manufacturer, model);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_weak_signal_switches_keepalive() {
+        let config = Config { adaptive_link: Some(AdaptiveLink::default()), ..Config::default() };
+        let (bridge, link, _output) = fake_bridge(&config);
+        let bridge = Arc::new(bridge);
+        let task = tokio::spawn({
+            let bridge = bridge.clone();
+            async move { bridge.start().await }
+        });
+        settle().await;
+
+        // Scripted RSSI samples; each holds for 35 s. In between the thresholds, the
+        // link keeps what it had.
+        let mut keepalives = Vec::new();
+        for rssi in [-60, -75, -90, -75, -60] {
+            link.set_rssi(Some(rssi));
+            // Noticed within a sample interval
+            time::sleep(Duration::from_secs(5)).await;
+            let (reads, writes) = (link.read_count(), link.writes().len());
+            time::sleep(Duration::from_secs(30)).await;
+            keepalives.push((link.read_count() - reads, link.writes().len() - writes));
+        }
+        // Strong: a read every 10 s. Weak: a header-only write every 3 s.
+        assert_eq!(keepalives, [(3, 0), (3, 0), (0, 10), (0, 10), (3, 0)]);
+        assert!(link.writes().iter().all(|write| packet::is_header_only(write)));
+        task.abort();
+    }
+
+    #[test]
+    fn test_panic_on_start() {
+        let config = Config { panic_on_start: true, ..Config::default() };
+        let (_bridge, _link, output) = fake_bridge(&config);
+        assert_eq!(output.messages(), MidiMessage::all_notes_off());
+
+        let config = Config { panic_mode: PanicMode::Both, ..config };
+        let (_bridge, _link, output) = fake_bridge(&config);
+        let mut expected = MidiMessage::all_notes_off();
+        expected.extend(MidiMessage::all_sound_off());
+        assert_eq!(output.messages(), expected);
+
+        let (_bridge, _link, output) = fake_bridge(&Config::default());
+        assert!(output.messages().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_subscribe_is_retried() {
+        let config = Config { reconnect_attempts: Some(0), ..Config::default() };
+        let (bridge, link, output) = fake_bridge(&config);
+        link.fail_next_subscribes(config.subscribe_attempts - 1);
+        link.send_packet(&[0x80, 0x80, 0x90, 60, 100]);
+        link.set_connected(false);
+
+        // Ends with the disconnect rather than a subscribe failure
+        assert!(bridge.start().await.is_err());
+        assert_eq!(output.messages(), vec![MidiMessage { status: 0x90, data1: 60, data2: 100 }]);
+    }
+
+    #[tokio::test]
+    async fn test_freeze_held_notes() {
+        let config = Config { freeze: Some(FreezeConfig { channel: 3, sink: None, trigger_cc: None }), ..Config::default() };
+        let (bridge, _link, output) = fake_bridge(&config);
+
+        bridge.inject_message(MidiMessage { status: 0x90, data1: 60, data2: 100 }).unwrap();
+        bridge.freeze_held_notes().unwrap();
+        bridge.inject_message(MidiMessage { status: 0x80, data1: 60, data2: 0 }).unwrap();
+        bridge.clear_freeze().unwrap();
+
+        assert_eq!(
+            output.messages(),
+            vec![
+                MidiMessage { status: 0x90, data1: 60, data2: 100 },
+                MidiMessage { status: 0x93, data1: 60, data2: 100 },
+                MidiMessage { status: 0x80, data1: 60, data2: 0 },
+                MidiMessage { status: 0x83, data1: 60, data2: 0 },
+            ]
+        );
+        assert!(fake_bridge(&Config::default()).0.freeze_held_notes().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_drop_stats() {
+        let config = Config { splits: vec![Split { range: 48..=72, sink: "keys".to_string() }], ..Config::default() };
+        let (bridge, _link, _output) = fake_bridge(&config);
+
+        bridge.process_ble_midi_packet(&[0x80, 0x80, 0x90, 30, 100, 31, 100]).await.unwrap();
+        assert_eq!(bridge.drop_stats(), DropStats { note_range: 2, ..DropStats::default() });
+    }
+
+    #[tokio::test]
+    async fn test_packet_order_is_kept() {
+        let config = Config { octave_offset: 1, mute_toggle_cc: Some(20), ..Config::default() };
+        let (bridge, _link, output) = fake_bridge(&config);
+
+        // Note Off then Note On re-striking C4, with a dropped CC between them and a
+        // running status Note On at the end
+        let packet = [0x80, 0x80, 0x80, 60, 0, 0x81, 0xB0, 20, 0, 0x82, 0x90, 60, 100, 64, 90];
+        bridge.process_ble_midi_packet(&packet).await.unwrap();
+
+        assert_eq!(
+            output.messages(),
+            vec![
+                MidiMessage { status: 0x80, data1: 72, data2: 0 },
+                MidiMessage { status: 0x90, data1: 72, data2: 100 },
+                MidiMessage { status: 0x90, data1: 76, data2: 90 },
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_transport_error_while_subscribing_reconnects() {
+        let config = Config { reconnect_attempts: Some(1), ..Config::default() };
+        let first = Arc::new(FakeLink::new());
+        let second = Arc::new(FakeLink::new());
+        first.fail_next_subscribes(config.subscribe_attempts);
+        second.send_packet(&[0x80, 0x80, 0x90, 60, 100]);
+        second.set_connected(false);
+
+        let output = RecordingSink::new();
+        let mut sinks = SinkSet::new();
+        sinks.add("output", Box::new(output.clone()));
+        let connector = FakeConnector::new([first, second]);
+        let bridge = BleMidiBridge::from_parts(&config, Box::new(connector), sinks);
+
+        assert!(bridge.start().await.is_err());
+        assert_eq!(output.messages(), vec![MidiMessage { status: 0x90, data1: 60, data2: 100 }]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_subscribe_that_keeps_failing_gives_up() {
+        let config = Config { reconnect_attempts: Some(2), ..Config::default() };
+        let failing: Vec<_> = (0..3).map(|_| Arc::new(FakeLink::new())).collect();
+        for link in &failing {
+            link.fail_next_subscribes(config.subscribe_attempts);
+        }
+        // Only reached if failed subscriptions didn't count as reconnect attempts
+        let working = Arc::new(FakeLink::new());
+        working.send_packet(&[0x80, 0x80, 0x90, 60, 100]);
+        working.set_connected(false);
+
+        let output = RecordingSink::new();
+        let mut sinks = SinkSet::new();
+        sinks.add("output", Box::new(output.clone()));
+        let connector = FakeConnector::new(failing.into_iter().chain([working]));
+        let bridge = BleMidiBridge::from_parts(&config, Box::new(connector), sinks);
+
+        assert!(bridge.start().await.is_err());
+        assert_eq!(output.messages(), vec![]);
+    }
 }
@@ -0,0 +1,236 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::time::Duration;
+
+use super::Config;
+use crate::ble::DeviceInfo;
+use crate::midi::HeaderCheck;
+use crate::pipeline::ControlAction;
+
+// Bundles of the lower-level settings working around the quirks of particular
+// controllers, so users don't need to know the individual workarounds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuirkProfile {
+    // AKAI LPK25 Wireless: drops idle links unless read regularly, buffers MIDI that
+    // keep-alive reads return, and fails a subscribe that comes right after connecting
+    Lpk25,
+    // Devices following the BLE-MIDI specification to the letter
+    GenericCompliant,
+    // Devices sending packets with a broken header byte
+    NonCompliantTimestamps,
+    // Pick one of the above from the connected device's identity (`QuirkProfile::detect`)
+    Auto,
+}
+
+// Devices with a known profile: manufacturer and model name fragments, matched
+// case-insensitively against the Device Information Service or, failing that, the
+// advertised name
+const KNOWN_DEVICES: &[(&str, &str, QuirkProfile)] = &[("akai", "lpk25", QuirkProfile::Lpk25)];
+
+// The LPK25's controls that send MIDI of their own: the sustain button, sending CC 64
+// with 127 when it latches and 0 when it lets go
+const LPK25_CONTROLS: &[(&str, u8)] = &[("sustain", 64)];
+
+impl QuirkProfile {
+    // Set the settings this profile covers, leaving the rest alone
+    pub fn apply(&self, config: &mut Config) {
+        match self {
+            QuirkProfile::Lpk25 => {
+                config.ble_keepalive_interval = Duration::from_secs(10);
+                config.process_keepalive_reads = true;
+                config.post_connect_delay = Duration::from_millis(500);
+                config.subscribe_attempts = 3;
+                config.packet_header_check = HeaderCheck::Strict;
+                // Passed on as they are, but listed so they can be remapped by name
+                for &(_, cc) in LPK25_CONTROLS {
+                    config.control_map.entry(cc).or_insert(ControlAction::Cc(cc));
+                }
+            }
+            QuirkProfile::GenericCompliant => {
+                config.process_keepalive_reads = false;
+                config.post_connect_delay = Duration::ZERO;
+                config.subscribe_attempts = 1;
+                config.packet_header_check = HeaderCheck::Strict;
+            }
+            QuirkProfile::NonCompliantTimestamps => {
+                config.packet_header_check = HeaderCheck::Resync;
+            }
+            // Resolved once a device is connected
+            QuirkProfile::Auto => {}
+        }
+    }
+
+    // Names and CC numbers of the device's hardware controls, for `Config::control_map`
+    pub fn controls(&self) -> &'static [(&'static str, u8)] {
+        match self {
+            QuirkProfile::Lpk25 => LPK25_CONTROLS,
+            _ => &[],
+        }
+    }
+
+    // CC number of a hardware control known to any profile, or a plain CC number
+    pub fn control_cc(name: &str) -> Option<u8> {
+        let known = LPK25_CONTROLS.iter().find(|(known, _)| known.eq_ignore_ascii_case(name));
+        known.map(|&(_, cc)| cc).or_else(|| name.parse().ok().filter(|cc| *cc <= 127))
+    }
+
+    // The profile for a known device, if this is one
+    pub fn detect(info: &DeviceInfo) -> Option<QuirkProfile> {
+        let contains = |value: &Option<String>, fragment: &str| {
+            value.as_ref().is_some_and(|value| value.to_ascii_lowercase().contains(fragment))
+        };
+        KNOWN_DEVICES.iter().find_map(|&(manufacturer, model, profile)| {
+            let by_dis = contains(&info.model_number, model)
+                && (info.manufacturer_name.is_none() || contains(&info.manufacturer_name, manufacturer));
+            let by_name = info.model_number.is_none() && contains(&info.name, model);
+            (by_dis || by_name).then_some(profile)
+        })
+    }
+}
+
+// The settings quirk profiles cover, as used for one connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct QuirkSettings {
+    pub ble_keepalive_interval: Duration,
+    pub process_keepalive_reads: bool,
+    pub post_connect_delay: Duration,
+    pub subscribe_attempts: u32,
+    pub packet_header_check: HeaderCheck,
+}
+
+impl QuirkSettings {
+    pub fn from_config(config: &Config) -> Self {
+        QuirkSettings {
+            ble_keepalive_interval: config.ble_keepalive_interval,
+            process_keepalive_reads: config.process_keepalive_reads,
+            post_connect_delay: config.post_connect_delay,
+            subscribe_attempts: config.subscribe_attempts,
+            packet_header_check: config.packet_header_check,
+        }
+    }
+
+    // The settings of `config` with a detected profile applied to those the user left at
+    // their defaults, so individually set values still win
+    pub fn detected(config: &Config, profile: QuirkProfile) -> Self {
+        let defaults = QuirkSettings::from_config(&Config::default());
+        let preset = QuirkSettings::from_config(&Config::for_quirk_profile(profile));
+        let configured = QuirkSettings::from_config(config);
+        fn pick<T: PartialEq>(configured: T, default: T, preset: T) -> T {
+            if configured == default { preset } else { configured }
+        }
+        QuirkSettings {
+            ble_keepalive_interval: pick(configured.ble_keepalive_interval, defaults.ble_keepalive_interval, preset.ble_keepalive_interval),
+            process_keepalive_reads: pick(configured.process_keepalive_reads, defaults.process_keepalive_reads, preset.process_keepalive_reads),
+            post_connect_delay: pick(configured.post_connect_delay, defaults.post_connect_delay, preset.post_connect_delay),
+            subscribe_attempts: pick(configured.subscribe_attempts, defaults.subscribe_attempts, preset.subscribe_attempts),
+            packet_header_check: pick(configured.packet_header_check, defaults.packet_header_check, preset.packet_header_check),
+        }
+    }
+}
+
+impl FromStr for QuirkProfile {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "lpk25" => Ok(QuirkProfile::Lpk25),
+            "generic_compliant" => Ok(QuirkProfile::GenericCompliant),
+            "non_compliant_timestamps" => Ok(QuirkProfile::NonCompliantTimestamps),
+            "auto" => Ok(QuirkProfile::Auto),
+            _ => Err(anyhow!(
+                "Unknown quirk profile '{}' (lpk25, generic_compliant, non_compliant_timestamps or auto)",
+                s
+            )),
+        }
+    }
+}
+
+impl Config {
+    // Defaults with a quirk profile applied. Individual settings can still be
+    // overridden: `Config { subscribe_attempts: 5, ..Config::for_quirk_profile(..) }`
+    pub fn for_quirk_profile(profile: QuirkProfile) -> Self {
+        let mut config = Config::default();
+        config.apply_quirk_profile(profile);
+        config
+    }
+
+    pub fn apply_quirk_profile(&mut self, profile: QuirkProfile) {
+        profile.apply(self);
+        self.quirk_profile = Some(profile);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profiles_set_their_flags() {
+        let config = Config::for_quirk_profile(QuirkProfile::Lpk25);
+        assert_eq!(config.quirk_profile, Some(QuirkProfile::Lpk25));
+        assert!(config.process_keepalive_reads);
+        assert_eq!(config.post_connect_delay, Duration::from_millis(500));
+        assert_eq!(config.subscribe_attempts, 3);
+        assert_eq!(config.control_map.get(&64), Some(&ControlAction::Cc(64)));
+
+        let config = Config::for_quirk_profile(QuirkProfile::GenericCompliant);
+        assert!(!config.process_keepalive_reads);
+        assert_eq!(config.subscribe_attempts, 1);
+        assert_eq!(config.packet_header_check, HeaderCheck::Strict);
+
+        let config = Config::for_quirk_profile(QuirkProfile::NonCompliantTimestamps);
+        assert_eq!(config.packet_header_check, HeaderCheck::Resync);
+        // Untouched settings keep their defaults
+        assert_eq!(config.subscribe_attempts, Config::default().subscribe_attempts);
+    }
+
+    #[test]
+    fn test_individual_settings_override_the_profile() {
+        let config = Config { subscribe_attempts: 5, ..Config::for_quirk_profile(QuirkProfile::Lpk25) };
+        assert_eq!(config.subscribe_attempts, 5);
+        assert!(config.process_keepalive_reads);
+    }
+
+    #[test]
+    fn test_control_names() {
+        assert_eq!(QuirkProfile::control_cc("Sustain"), Some(64));
+        assert_eq!(QuirkProfile::control_cc("1"), Some(1));
+        assert_eq!(QuirkProfile::control_cc("128"), None);
+        assert_eq!(QuirkProfile::control_cc("arp"), None);
+        assert!(QuirkProfile::GenericCompliant.controls().is_empty());
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("LPK25".parse::<QuirkProfile>().unwrap(), QuirkProfile::Lpk25);
+        assert_eq!("non_compliant_timestamps".parse::<QuirkProfile>().unwrap(), QuirkProfile::NonCompliantTimestamps);
+        assert!("lpk49".parse::<QuirkProfile>().is_err());
+        assert_eq!("auto".parse::<QuirkProfile>().unwrap(), QuirkProfile::Auto);
+    }
+
+    fn info(name: &str, manufacturer: Option<&str>, model: Option<&str>) -> DeviceInfo {
+        DeviceInfo::from_parts(Some(name.to_string()), String::new(), None, &Default::default(), None)
+            .with_device_information(manufacturer.map(str::to_string), model.map(str::to_string))
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(QuirkProfile::detect(&info("Keys", Some("AKAI"), Some("LPK25"))), Some(QuirkProfile::Lpk25));
+        assert_eq!(QuirkProfile::detect(&info("LPK25 Wireless", None, None)), Some(QuirkProfile::Lpk25));
+        // The Device Information Service outranks the name
+        assert_eq!(QuirkProfile::detect(&info("LPK25 Wireless", Some("Other"), Some("X1"))), None);
+        assert_eq!(QuirkProfile::detect(&info("Keys", Some("Other"), Some("LPK25"))), None);
+        assert_eq!(QuirkProfile::detect(&info("Keys", None, None)), None);
+    }
+
+    #[test]
+    fn test_detected_settings_keep_overrides() {
+        let config = Config { subscribe_attempts: 5, ..Config::for_quirk_profile(QuirkProfile::Auto) };
+        let settings = QuirkSettings::detected(&config, QuirkProfile::Lpk25);
+        assert!(settings.process_keepalive_reads);
+        assert_eq!(settings.post_connect_delay, Duration::from_millis(500));
+        assert_eq!(settings.subscribe_attempts, 5);
+    }
+}
@@ -0,0 +1,81 @@
+use anyhow::Result;
+use tokio::runtime::{Builder, Runtime};
+
+use super::{BleMidiBridge, Config};
+
+// Create a bridge for `config` and forward until it gives up, on the caller's runtime.
+// Nothing in the bridge needs a particular runtime flavour, so embedders can await
+// this (or `BleMidiBridge::start`) from a runtime of their own.
+pub async fn run(config: &Config) -> Result<()> {
+    BleMidiBridge::new(config).await?.start().await
+}
+
+// The tokio runtime to run a bridge on, for programs that don't want `#[tokio::main]`'s
+// multi-threaded default. A current-thread runtime is enough for one bridge and the
+// lightest option on small machines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RuntimeBuilder {
+    // Worker threads of a multi-threaded runtime; None runs everything on the thread
+    // calling `block_on`
+    worker_threads: Option<usize>,
+}
+
+impl RuntimeBuilder {
+    pub fn current_thread() -> Self {
+        RuntimeBuilder { worker_threads: None }
+    }
+
+    pub fn multi_thread(worker_threads: usize) -> Self {
+        RuntimeBuilder { worker_threads: Some(worker_threads.max(1)) }
+    }
+
+    pub fn build(&self) -> Result<Runtime> {
+        let mut builder = match self.worker_threads {
+            Some(threads) => {
+                let mut builder = Builder::new_multi_thread();
+                builder.worker_threads(threads);
+                builder
+            }
+            None => Builder::new_current_thread(),
+        };
+        Ok(builder.enable_all().thread_name("blip").build()?)
+    }
+
+    // Build the runtime and `run` a bridge on it, blocking until the bridge stops
+    pub fn run(&self, config: &Config) -> Result<()> {
+        self.build()?.block_on(run(config))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ble::testing::{FakeConnector, FakeLink};
+    use crate::midi::testing::RecordingSink;
+    use crate::midi::{MidiMessage, SinkSet};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_bridge_on_a_current_thread_runtime() {
+        let runtime = RuntimeBuilder::current_thread().build().unwrap();
+        let link = Arc::new(FakeLink::new());
+        let output = RecordingSink::new();
+        let mut sinks = SinkSet::new();
+        sinks.add("output", Box::new(output.clone()));
+        let bridge = Arc::new(BleMidiBridge::from_parts(&Config::default(), Box::new(FakeConnector::new([link.clone()])), sinks));
+
+        runtime.block_on(async {
+            let task = tokio::spawn({
+                let bridge = bridge.clone();
+                async move { bridge.start().await }
+            });
+            link.send_packet(&[0x80, 0x80, 0x90, 60, 100]);
+            while output.messages().is_empty() {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            task.abort();
+        });
+        assert_eq!(output.messages(), [MidiMessage { status: 0x90, data1: 60, data2: 100 }]);
+    }
+}
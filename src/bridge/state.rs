@@ -0,0 +1,74 @@
+use log::debug;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+// Where the bridge is in its connection lifecycle, as reported by `BleMidiBridge::state`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum BridgeState {
+    // Looking for the device (also the state before `start` is called)
+    Scanning,
+    // Device found, resolving the BLE-MIDI characteristic and subscribing
+    Connecting,
+    // Subscribed, no MIDI received yet
+    Connected,
+    // MIDI is flowing to the outputs
+    Forwarding,
+    // The device was lost and the bridge is trying to get it back
+    Reconnecting,
+    // `start` gave up and returned an error
+    Error,
+}
+
+impl BridgeState {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => BridgeState::Scanning,
+            1 => BridgeState::Connecting,
+            2 => BridgeState::Connected,
+            3 => BridgeState::Forwarding,
+            4 => BridgeState::Reconnecting,
+            _ => BridgeState::Error,
+        }
+    }
+}
+
+// Lock-free holder for the current state, so it can be polled while `start` runs
+pub struct StateCell(AtomicU8);
+
+impl StateCell {
+    pub fn new(state: BridgeState) -> Self {
+        StateCell(AtomicU8::new(state as u8))
+    }
+
+    pub fn get(&self) -> BridgeState {
+        BridgeState::from_u8(self.0.load(Ordering::Acquire))
+    }
+
+    pub fn set(&self, state: BridgeState) {
+        let previous = BridgeState::from_u8(self.0.swap(state as u8, Ordering::AcqRel));
+        if previous != state {
+            debug!("Bridge state: {:?} -> {:?}", previous, state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_round_trips() {
+        let cell = StateCell::new(BridgeState::Scanning);
+        for state in [
+            BridgeState::Scanning,
+            BridgeState::Connecting,
+            BridgeState::Connected,
+            BridgeState::Forwarding,
+            BridgeState::Reconnecting,
+            BridgeState::Error,
+        ] {
+            cell.set(state);
+            assert_eq!(cell.get(), state);
+        }
+    }
+}
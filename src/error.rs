@@ -0,0 +1,108 @@
+use std::fmt;
+
+// Errors the bridge needs to tell apart to decide how to recover. Everything else is
+// carried as a plain `anyhow::Error`.
+#[derive(Debug)]
+pub enum BlipError {
+    // The BLE link failed (device gone, timeout, OS stack hiccup). Reconnecting may help.
+    BleTransport(btleplug::Error),
+    // The BLE stack refused or can't do what we asked (permissions, unsupported
+    // operation, missing characteristic). Reconnecting won't help.
+    BleSetup(btleplug::Error),
+    // The system has no MIDI output ports at all, so there is nothing to look a port up in
+    NoMidiDevices,
+}
+
+impl BlipError {
+    pub fn is_transport(&self) -> bool {
+        matches!(self, BlipError::BleTransport(_))
+    }
+}
+
+impl From<btleplug::Error> for BlipError {
+    fn from(error: btleplug::Error) -> Self {
+        use btleplug::Error::*;
+        match error {
+            DeviceNotFound | NotConnected | TimedOut(_) | RuntimeError(_) | Other(_) => {
+                BlipError::BleTransport(error)
+            }
+            PermissionDenied
+            | UnexpectedCallback
+            | UnexpectedCharacteristic
+            | NoSuchCharacteristic
+            | NotSupported(_)
+            | Uuid(_)
+            | InvalidBDAddr(_) => BlipError::BleSetup(error),
+        }
+    }
+}
+
+impl fmt::Display for BlipError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlipError::BleTransport(e) => write!(f, "BLE transport error: {}", e),
+            BlipError::BleSetup(e) => write!(f, "BLE error: {}", e),
+            BlipError::NoMidiDevices => write!(
+                f,
+                "No MIDI output devices found on this system - install loopMIDI and create a port, or check the audio drivers"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BlipError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BlipError::BleTransport(e) | BlipError::BleSetup(e) => Some(e),
+            BlipError::NoMidiDevices => None,
+        }
+    }
+}
+
+// Whether an error is a BLE transport failure that a reconnect may fix, as opposed to a
+// setup problem or one of our own errors (e.g. a malformed packet)
+pub fn is_ble_transport(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause.downcast_ref::<BlipError>().is_some_and(BlipError::is_transport)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context};
+    use std::time::Duration;
+
+    #[test]
+    fn test_btleplug_errors_are_classified() {
+        for error in [
+            btleplug::Error::NotConnected,
+            btleplug::Error::DeviceNotFound,
+            btleplug::Error::TimedOut(Duration::from_secs(1)),
+            btleplug::Error::RuntimeError("GATT error".to_string()),
+        ] {
+            assert!(BlipError::from(error).is_transport());
+        }
+        for error in [
+            btleplug::Error::PermissionDenied,
+            btleplug::Error::NoSuchCharacteristic,
+            btleplug::Error::NotSupported("notify".to_string()),
+        ] {
+            assert!(!BlipError::from(error).is_transport());
+        }
+    }
+
+    #[test]
+    fn test_is_ble_transport() {
+        let transport = anyhow::Error::from(BlipError::from(btleplug::Error::NotConnected));
+        assert!(is_ble_transport(&transport));
+
+        // Context added on the way up doesn't hide the cause
+        let wrapped = Err::<(), _>(transport).context("Subscribe failed").unwrap_err();
+        assert!(is_ble_transport(&wrapped));
+
+        let setup = anyhow::Error::from(BlipError::from(btleplug::Error::PermissionDenied));
+        assert!(!is_ble_transport(&setup));
+        assert!(!is_ble_transport(&anyhow!("BLE-MIDI packet too short")));
+    }
+}
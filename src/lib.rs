@@ -1,6 +1,11 @@
 pub mod ble;
 pub mod midi;
 pub mod bridge;
+pub mod error;
+pub mod net;
+pub mod pipeline;
+pub mod util;
 
 // Re-export main types for convenience
-pub use bridge::{BleMidiBridge, Config};
+pub use bridge::{run, BleMidiBridge, BridgeState, Config, LogTimestamp, PacketLogStyle, RuntimeBuilder};
+pub use error::BlipError;
@@ -1,7 +1,13 @@
-use anyhow::Result;
-use log::{info, error};
+use anyhow::{anyhow, Context, Result};
+use log::{info, error, warn};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
-use blip::{BleMidiBridge, Config};
+use blip::ble::{max_write_len, measure_round_trip, send_sysex_file, BleDevice, BLE_MIDI_CHARACTERISTIC_UUID};
+use blip::midi::{verify_parser, MidiInput, MidiOutput};
+use blip::util::hexdump;
+use blip::{BleMidiBridge, Config, LogTimestamp, PacketLogStyle};
+use env_logger::TimestampPrecision;
 
 //-----------------------------------------------------------------------------
 // USER CONFIGURATION
@@ -24,6 +30,20 @@ const BLE_STATUS_CHECK_SECS: u64 = 1;
 // Octave offset for transposing MIDI notes (-11 to +11 octaves)
 const OCTAVE_OFFSET: i8 = 0;
 
+// Raw packet format in debug logs: Compact (one line) or HexDump (offset/hex/ascii)
+const PACKET_LOG_STYLE: PacketLogStyle = PacketLogStyle::Compact;
+
+// Log line timestamps (UTC): None, Seconds, Millis (for latency debugging) or
+// Rfc3339 (microseconds)
+const LOG_TIMESTAMP: LogTimestamp = LogTimestamp::Seconds;
+
+// Probes sent by --latency-test, and how long to wait for each one to come back
+const LATENCY_TEST_ITERATIONS: u16 = 20;
+const LATENCY_TEST_TIMEOUT_MS: u64 = 1000;
+
+// Configuration file read at startup if it exists (or another one given with --config)
+const CONFIG_FILE: &str = "blip.toml";
+
 //-----------------------------------------------------------------------------
 // MAIN FUNCTION
 // This is the entry point of the application
@@ -60,10 +80,163 @@ fn display_logo() {
     "#);
 }
 
+const USAGE: &str = "usage: blip [--config <file>] [--profile <name>] [--list-midi-ports] [--read-once] [--send-syx <file>] [--latency-test] [--verify-parser] [--dump-config <file>]";
+
+// Command line options
+struct Args {
+    config_file: Option<PathBuf>,
+    profile: Option<String>,
+    list_midi_ports: bool,
+    // Connect, read the MIDI characteristic once, dump it and exit
+    read_once: bool,
+    // Connect, send the SysEx messages of this file and exit
+    send_syx: Option<PathBuf>,
+    // Connect, time messages echoed back by the device and exit
+    latency_test: bool,
+    // Write the effective configuration to this file and exit
+    dump_config: Option<PathBuf>,
+    // Check the packet parser against the embedded test vectors and exit
+    verify_parser: bool,
+}
+
+fn parse_args() -> Result<Args> {
+    let mut args = Args { config_file: None, profile: None, list_midi_ports: false, read_once: false, send_syx: None, latency_test: false, dump_config: None, verify_parser: false };
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--config" => args.config_file = Some(iter.next().ok_or_else(|| anyhow!("--config needs a file"))?.into()),
+            "--profile" => args.profile = Some(iter.next().ok_or_else(|| anyhow!("--profile needs a name"))?),
+            "--list-midi-ports" => args.list_midi_ports = true,
+            "--read-once" => args.read_once = true,
+            "--latency-test" => args.latency_test = true,
+            "--verify-parser" => args.verify_parser = true,
+            "--dump-config" => args.dump_config = Some(iter.next().ok_or_else(|| anyhow!("--dump-config needs a file"))?.into()),
+            "--send-syx" => args.send_syx = Some(iter.next().ok_or_else(|| anyhow!("--send-syx needs a file"))?.into()),
+            _ => return Err(anyhow!("Unknown argument '{}' ({})", arg, USAGE)),
+        }
+    }
+    Ok(args)
+}
+
+// Print the MIDI devices the bridge could use, by id
+fn list_midi_ports() -> Result<()> {
+    for (title, devices) in [("Outputs", MidiOutput::list_devices()?), ("Inputs", MidiInput::list_devices()?)] {
+        println!("{}:", title);
+        if devices.is_empty() {
+            println!("  (none)");
+        }
+        for (id, name) in devices {
+            println!("  {}: {}", id, name);
+        }
+    }
+    Ok(())
+}
+
+// Run the parser over the embedded test vectors, to check this build decodes BLE-MIDI right
+fn verify_parser_vectors() -> Result<()> {
+    let count = verify_parser()?;
+    println!("All {} parser vectors passed", count);
+    Ok(())
+}
+
+// Connect to the device and show the raw bytes a read of the MIDI characteristic returns
+async fn read_once(config: &Config) -> Result<()> {
+    let device = BleDevice::discover(config.ble_scan_timeout, &config.scan_filters(), config.multi_match, config.min_rssi).await?;
+    let value = device.read_characteristic(BLE_MIDI_CHARACTERISTIC_UUID).await?;
+    println!("Read {} bytes from the BLE-MIDI characteristic:", value.len());
+    println!("{}", hexdump(&value));
+    Ok(())
+}
+
+// Connect to the device and send it the SysEx messages of a .syx file
+async fn send_syx(config: &Config, path: &Path) -> Result<()> {
+    let device = BleDevice::discover(config.ble_scan_timeout, &config.scan_filters(), config.multi_match, config.min_rssi).await?;
+    let max_len = max_write_len(&device, config.max_write_len).await;
+    send_sysex_file(&device, path, max_len).await
+}
+
+// Connect to a device that echoes its MIDI input and time the round trips
+async fn latency_test(config: &Config) -> Result<()> {
+    let device = BleDevice::discover(config.ble_scan_timeout, &config.scan_filters(), config.multi_match, config.min_rssi).await?;
+    info!("Sending {} probes", LATENCY_TEST_ITERATIONS);
+    let report = measure_round_trip(&device, LATENCY_TEST_ITERATIONS, Duration::from_millis(LATENCY_TEST_TIMEOUT_MS)).await?;
+    for line in report.to_string().lines() {
+        info!("{}", line);
+    }
+    Ok(())
+}
+
+// Waits for a request to stop, returning its name: Ctrl+C, or SIGTERM from a service
+// manager such as systemd
+#[cfg(unix)]
+async fn shutdown_signal() -> Result<&'static str> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    let signal = tokio::select! {
+        result = tokio::signal::ctrl_c() => result.map(|_| "Ctrl+C")?,
+        _ = terminate.recv() => "SIGTERM",
+    };
+    Ok(signal)
+}
+
+// Waits for a request to stop, returning its name: Ctrl+C or Ctrl+Break, the console
+// window closing, or the system shutting down
+#[cfg(windows)]
+async fn shutdown_signal() -> Result<&'static str> {
+    use tokio::signal::windows;
+
+    let mut ctrl_break = windows::ctrl_break()?;
+    let mut ctrl_close = windows::ctrl_close()?;
+    let mut ctrl_shutdown = windows::ctrl_shutdown()?;
+    let signal = tokio::select! {
+        result = tokio::signal::ctrl_c() => result.map(|_| "Ctrl+C")?,
+        _ = ctrl_break.recv() => "Ctrl+Break",
+        _ = ctrl_close.recv() => "console close",
+        _ = ctrl_shutdown.recv() => "system shutdown",
+    };
+    Ok(signal)
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
+    let args = parse_args()?;
+    if args.list_midi_ports {
+        return list_midi_ports();
+    }
+    if args.verify_parser {
+        return verify_parser_vectors();
+    }
+
+    // Create configuration. The configuration file (with the selected profile) overrides
+    // the values above, and BLIP_* environment variables override both. This happens
+    // before logging starts since it decides the log format; the outcome is logged below.
+    let mut config = Config {
+        virtual_midi_port_name: VIRTUAL_MIDI_PORT_NAME.to_string(),
+        ble_scan_timeout: Duration::from_secs(BLE_SCAN_TIMEOUT_SECS),
+        ble_keepalive_interval: Duration::from_secs(BLE_KEEPALIVE_SECS),
+        ble_status_check_interval: Duration::from_secs(BLE_STATUS_CHECK_SECS),
+        octave_offset: OCTAVE_OFFSET,
+        packet_log_style: PACKET_LOG_STYLE,
+        log_timestamp: LOG_TIMESTAMP,
+        ..Config::default()
+    };
+    let read_once_requested = args.read_once;
+    let send_syx_requested = args.send_syx.clone();
+    let latency_test_requested = args.latency_test;
+    let dump_config_requested = args.dump_config.clone();
+    let loaded = config
+        .load(Path::new(CONFIG_FILE), args.config_file.as_deref(), args.profile.as_deref())
+        .map(|path| path.map(|path| (path, args.profile)));
+
     // Set different default log levels for debug and release builds
     let mut builder = env_logger::Builder::new();
+    builder.format_timestamp(match config.log_timestamp {
+        LogTimestamp::None => None,
+        LogTimestamp::Seconds => Some(TimestampPrecision::Seconds),
+        LogTimestamp::Millis => Some(TimestampPrecision::Millis),
+        LogTimestamp::Rfc3339 => Some(TimestampPrecision::Micros),
+    });
     
     if cfg!(debug_assertions) {
         // Debug build: show all debug logs
@@ -84,14 +257,35 @@ async fn main() -> Result<()> {
     }
     info!("Press Ctrl+C to exit");
 
-    // Create configuration
-    let config = Config {
-        virtual_midi_port_name: VIRTUAL_MIDI_PORT_NAME.to_string(),
-        ble_scan_timeout: Duration::from_secs(BLE_SCAN_TIMEOUT_SECS),
-        ble_keepalive_interval: Duration::from_secs(BLE_KEEPALIVE_SECS),
-        ble_status_check_interval: Duration::from_secs(BLE_STATUS_CHECK_SECS),
-        octave_offset: OCTAVE_OFFSET,
-    };
+    match loaded {
+        Ok(Some((path, profile))) => {
+            info!("Loaded configuration from {}", path.display());
+            if let Some(profile) = profile {
+                info!("Using profile '{}'", profile);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => return Err(e.context("Invalid configuration")),
+    }
+
+    // The one-off modes fail with a non-zero exit status
+    if read_once_requested {
+        return read_once(&config).await.context("Read failed");
+    }
+
+    if let Some(path) = dump_config_requested {
+        config.save_file(&path).context("Could not save the configuration")?;
+        info!("Saved the effective configuration to {}", path.display());
+        return Ok(());
+    }
+
+    if latency_test_requested {
+        return latency_test(&config).await.context("Latency test failed");
+    }
+
+    if let Some(path) = send_syx_requested {
+        return send_syx(&config, &path).await.context("SysEx send failed");
+    }
 
     // Create bridge instance
     let bridge_result = BleMidiBridge::new(&config).await;
@@ -101,31 +295,40 @@ async fn main() -> Result<()> {
     }
     
     let bridge = match bridge_result {
-        Ok(b) => b,
+        Ok(b) => Arc::new(b),
         Err(_) => {
             // Wait for Ctrl+C before exiting on error
-            tokio::signal::ctrl_c().await?;
+            shutdown_signal().await?;
             return Ok(());
         }
     };
     
-    // Handle Ctrl+C gracefully
-    let ctrl_c = tokio::signal::ctrl_c();
-    
+    #[cfg(feature = "dashboard")]
+    let _dashboard = match config.http_dashboard {
+        Some(addr) => Some(blip::net::Dashboard::bind(addr, bridge.clone()).await?),
+        None => None,
+    };
+
+    // Handle Ctrl+C and service stops gracefully
+    let shutdown = shutdown_signal();
+
     tokio::select! {
-        result = bridge.start(&config) => {
+        result = bridge.start() => {
             match result {
                 Ok(_) => info!("Bridge stopped normally"),
                 Err(e) => {
                     error!("Bridge error: {}", e);
                     info!("Press Ctrl+C to exit...");
                     // Wait for Ctrl+C before exiting on bridge error
-                    tokio::signal::ctrl_c().await?;
+                    shutdown_signal().await?;
                 }
             }
         }
-        _ = ctrl_c => {
-            info!("Received Ctrl+C, shutting down...");
+        signal = shutdown => {
+            info!("Received {}, shutting down...", signal?);
+            if let Err(e) = bridge.send_panic() {
+                warn!("Shutdown panic failed: {}", e);
+            }
         }
     }
 
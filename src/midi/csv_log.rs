@@ -0,0 +1,191 @@
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use super::{MidiMessage, CC_ALL_NOTES_OFF, CC_ALL_SOUND_OFF};
+use crate::pipeline::ActiveNotes;
+
+const HEADER: &str = "time_s,channel,note,velocity,duration_s";
+// How often `CsvNoteLog::flush` should be called, so rows reach the file while playing
+pub const NOTE_LOG_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+// Duration column of notes still held when the log closes
+const OPEN_DURATION: &str = "open";
+
+// Spreadsheet-friendly log of played notes: one row per note once it ends, with its
+// start time (seconds since the first logged note), channel (1-16), note, velocity and
+// duration in seconds. Rows are appended to an existing file.
+pub struct CsvNoteLog {
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    writer: BufWriter<File>,
+    // Time of the first logged note, which row times count from
+    started: Option<Instant>,
+    held: ActiveNotes<Sounding>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sounding {
+    start: Instant,
+    velocity: u8,
+}
+
+impl CsvNoteLog {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| anyhow!("Failed to open note log {}: {}", path.display(), e))?;
+        let mut writer = BufWriter::new(file);
+        if writer.get_ref().metadata()?.len() == 0 {
+            writeln!(writer, "{}", HEADER)?;
+        }
+        info!("Logging notes to {}", path.display());
+        Ok(CsvNoteLog {
+            inner: Mutex::new(Inner { writer, started: None, held: ActiveNotes::new() }),
+        })
+    }
+
+    // Track a message sent downstream at `now`, writing a row for every note it ends
+    pub fn record(&self, message: &MidiMessage, now: Instant) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let channel = message.status & 0x0F;
+        match message.status & 0xF0 {
+            0x90 if message.data2 > 0 => {
+                inner.started.get_or_insert(now);
+                // Retriggered without a Note Off in between: the first one ends here
+                let sounding = Sounding { start: now, velocity: message.data2 };
+                if let Some(ended) = inner.held.note_on(channel, message.data1, sounding) {
+                    inner.write_row(channel, message.data1, ended, Some(now))?;
+                }
+            }
+            0x80 | 0x90 => {
+                if let Some(ended) = inner.held.note_off(channel, message.data1) {
+                    inner.write_row(channel, message.data1, ended, Some(now))?;
+                }
+            }
+            0xB0 if message.data1 == CC_ALL_SOUND_OFF || message.data1 == CC_ALL_NOTES_OFF => {
+                let ended: Vec<_> = inner.held.iter().filter(|&(held_channel, _, _)| held_channel == channel).map(|(_, note, _)| note).collect();
+                for note in ended {
+                    let sounding = inner.held.note_off(channel, note).unwrap();
+                    inner.write_row(channel, note, sounding, Some(now))?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // Write the buffered rows to the file
+    pub fn flush(&self) -> Result<()> {
+        self.inner.lock().unwrap().writer.flush()?;
+        Ok(())
+    }
+
+    // Write the notes still held with an open duration, and flush
+    pub fn finish(&self) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let held = std::mem::take(&mut inner.held);
+        for (channel, note, &sounding) in held.iter() {
+            inner.write_row(channel, note, sounding, None)?;
+        }
+        inner.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl Inner {
+    fn write_row(&mut self, channel: u8, note: u8, sounding: Sounding, end: Option<Instant>) -> Result<()> {
+        let Sounding { start, velocity } = sounding;
+        let time = self.started.map_or(Duration::ZERO, |started| start.duration_since(started));
+        let duration = match end {
+            Some(end) => format!("{:.3}", end.duration_since(start).as_secs_f64()),
+            None => OPEN_DURATION.to_string(),
+        };
+        writeln!(self.writer, "{:.3},{},{},{},{}", time.as_secs_f64(), channel + 1, note, velocity, duration)?;
+        Ok(())
+    }
+}
+
+impl Drop for CsvNoteLog {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            warn!("Failed to finish the note log: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(status: u8, note: u8, velocity: u8) -> MidiMessage {
+        MidiMessage { status, data1: note, data2: velocity }
+    }
+
+    #[test]
+    fn test_completed_and_open_notes() {
+        let path = std::env::temp_dir().join(format!("blip-note-log-{}.csv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        let log = CsvNoteLog::create(&path).unwrap();
+        log.record(&note(0x90, 60, 100), at(0)).unwrap();
+        log.record(&note(0x91, 64, 80), at(250)).unwrap();
+        // Note On with velocity 0 ends a note too
+        log.record(&note(0x90, 60, 0), at(500)).unwrap();
+        log.record(&note(0x81, 64, 0), at(1250)).unwrap();
+        log.record(&note(0x90, 67, 90), at(2000)).unwrap();
+        drop(log);
+
+        let rows = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            rows.lines().collect::<Vec<_>>(),
+            [HEADER, "0.000,1,60,100,0.500", "0.250,2,64,80,1.000", "2.000,1,67,90,open"]
+        );
+    }
+
+    #[test]
+    fn test_rows_reach_the_file_on_flush() {
+        let path = std::env::temp_dir().join(format!("blip-note-log-flush-{}.csv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let start = Instant::now();
+
+        let log = CsvNoteLog::create(&path).unwrap();
+        log.record(&note(0x90, 60, 100), start).unwrap();
+        log.record(&note(0x80, 60, 0), start + Duration::from_millis(100)).unwrap();
+        // Buffered until flushed, however long ago the last row was written
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+        log.flush().unwrap();
+        let rows = std::fs::read_to_string(&path).unwrap();
+        drop(log);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(rows.lines().collect::<Vec<_>>(), [HEADER, "0.000,1,60,100,0.100"]);
+    }
+
+    #[test]
+    fn test_all_notes_off_ends_the_channel() {
+        let path = std::env::temp_dir().join(format!("blip-note-log-panic-{}.csv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let start = Instant::now();
+
+        let log = CsvNoteLog::create(&path).unwrap();
+        log.record(&note(0x90, 60, 100), start).unwrap();
+        log.record(&note(0x91, 62, 100), start).unwrap();
+        log.record(&MidiMessage::control_change(0, CC_ALL_NOTES_OFF, 0), start + Duration::from_secs(1)).unwrap();
+        log.finish().unwrap();
+
+        let rows = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(rows.lines().collect::<Vec<_>>(), [HEADER, "0.000,1,60,100,1.000", "0.000,2,62,100,open"]);
+    }
+}
@@ -1,12 +1,65 @@
 use anyhow::{anyhow, Result};
-use std::ffi::CStr;
-use windows::Win32::Media::Audio::{
-    midiOutClose, midiOutGetDevCapsA, midiOutGetNumDevs, midiOutOpen, midiOutShortMsg, 
-    HMIDIOUT, MIDIOUTCAPSA, CALLBACK_NULL,
-};
-use log::{info, debug};
-
-#[derive(Debug)]
+use serde::{Deserialize, Serialize};
+
+use crate::error::BlipError;
+
+mod csv_log;
+mod parser;
+mod queued;
+mod reopen;
+#[cfg(test)]
+pub(crate) mod testing;
+#[cfg(not(windows))]
+mod unsupported_ports;
+mod vectors;
+mod virtual_port;
+#[cfg(windows)]
+mod winmm;
+
+pub use csv_log::{CsvNoteLog, NOTE_LOG_FLUSH_INTERVAL};
+pub use parser::{parse_ble_midi_packet, HeaderCheck};
+pub use queued::{QueueConfig, QueueOverflow, QueuedSink, DIN_MIDI_MESSAGE_TIME};
+pub use reopen::{PortOpener, PortRecovery, ReopeningSink};
+pub use vectors::{parser_vectors, verify_parser, ParserVector};
+pub use crate::ble::packet::header_timestamp;
+pub use virtual_port::{create_virtual_port, virtual_port_unsupported};
+#[cfg(not(windows))]
+pub use unsupported_ports::{MidiInput, MidiOutput};
+#[cfg(windows)]
+pub use winmm::{MidiInput, MidiOutput};
+
+// Channel Mode controller numbers
+pub const CC_ALL_SOUND_OFF: u8 = 120;
+pub const CC_ALL_NOTES_OFF: u8 = 123;
+
+// What a panic sends on every channel. All Notes Off releases the notes as if their keys
+// were let go, so they fade out with their release (and the sustain pedal keeps them
+// going); All Sound Off cuts everything immediately, release tails included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PanicMode {
+    #[default]
+    AllNotesOff,
+    AllSoundOff,
+    // All Notes Off, then All Sound Off
+    Both,
+}
+
+impl PanicMode {
+    pub fn messages(&self) -> Vec<MidiMessage> {
+        match self {
+            PanicMode::AllNotesOff => MidiMessage::all_notes_off(),
+            PanicMode::AllSoundOff => MidiMessage::all_sound_off(),
+            PanicMode::Both => {
+                let mut messages = MidiMessage::all_notes_off();
+                messages.extend(MidiMessage::all_sound_off());
+                messages
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
 pub struct MidiMessage {
     pub status: u8,
     pub data1: u8,
@@ -14,6 +67,53 @@ pub struct MidiMessage {
 }
 
 impl MidiMessage {
+    pub fn control_change(channel: u8, controller: u8, value: u8) -> Self {
+        MidiMessage { status: 0xB0 | (channel & 0x0F), data1: controller, data2: value }
+    }
+
+    // Channel (0-15) of a channel voice message; None for system messages
+    pub fn channel(&self) -> Option<u8> {
+        (self.status < 0xF0).then_some(self.status & 0x0F)
+    }
+
+    // The same message on another channel. System messages are returned unchanged.
+    pub fn with_channel(&self, channel: u8) -> MidiMessage {
+        match self.channel() {
+            Some(_) => MidiMessage { status: (self.status & 0xF0) | (channel & 0x0F), ..*self },
+            None => *self,
+        }
+    }
+
+    // All Notes Off (CC123) for every one of the 16 channels
+    pub fn all_notes_off() -> Vec<Self> {
+        (0..16).map(|channel| Self::control_change(channel, CC_ALL_NOTES_OFF, 0)).collect()
+    }
+
+    // All Sound Off (CC120) for every one of the 16 channels
+    pub fn all_sound_off() -> Vec<Self> {
+        (0..16).map(|channel| Self::control_change(channel, CC_ALL_SOUND_OFF, 0)).collect()
+    }
+
+    // Number of data bytes that follow the status byte for this message type
+    pub fn data_len(&self) -> usize {
+        match self.status & 0xF0 {
+            0x80 | 0x90 | 0xA0 | 0xB0 | 0xE0 => 2,
+            0xC0 | 0xD0 => 1,
+            _ => match self.status {
+                0xF2 => 2,
+                0xF1 | 0xF3 => 1,
+                _ => 0,
+            },
+        }
+    }
+
+    // Raw MIDI bytes as they would appear on the wire
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.status, self.data1, self.data2];
+        bytes.truncate(1 + self.data_len());
+        bytes
+    }
+
     pub fn to_midi_word(&self) -> u32 {
         (self.data2 as u32) << 16 | (self.data1 as u32) << 8 | (self.status as u32)
     }
@@ -27,7 +127,20 @@ impl MidiMessage {
             0xC0 => "Program Change",
             0xD0 => "Channel Pressure",
             0xE0 => "Pitch Bend",
-            _ => "Unknown",
+            _ => match self.status {
+                0xF1 => "MIDI Time Code Quarter Frame",
+                0xF2 => "Song Position Pointer",
+                0xF3 => "Song Select",
+                0xF6 => "Tune Request",
+                0xF8 => "Timing Clock",
+                0xFA => "Start",
+                0xFB => "Continue",
+                0xFC => "Stop",
+                0xFE => "Active Sensing",
+                0xFF => "System Reset",
+                // SysEx can't be carried by a MidiMessage; the rest are undefined
+                _ => "Unknown",
+            },
         }
     }
 
@@ -48,86 +161,119 @@ impl MidiMessage {
     }
 }
 
-pub struct MidiOutput {
-    handle: HMIDIOUT,
+// Destination for processed MIDI messages (the loopMIDI port, network clients, ...)
+pub trait MidiSink: Send + Sync {
+    fn send_message(&self, message: &MidiMessage) -> Result<()>;
 }
 
-impl MidiOutput {
-    pub fn list_devices() -> Result<Vec<(usize, String)>> {
-        let mut devices = Vec::new();
-        unsafe {
-            let num_devices = midiOutGetNumDevs();
-            for i in 0..num_devices {
-                let mut caps = MIDIOUTCAPSA::default();
-                let result = midiOutGetDevCapsA(i as usize, &mut caps, std::mem::size_of::<MIDIOUTCAPSA>() as u32);
-                if result == 0 {
-                    if let Ok(name) = CStr::from_ptr(caps.szPname.as_ptr() as *const i8).to_str() {
-                        devices.push((i as usize, name.to_string()));
-                    }
-                }
-            }
-        }
-        Ok(devices)
+// Which sinks a processed message is delivered to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Route {
+    // Every sink in the set
+    All,
+    // Only the sink with this name
+    Sink(String),
+}
+
+// Named set of sinks forwarded messages are fanned out to
+#[derive(Default)]
+pub struct SinkSet {
+    sinks: Vec<(String, Box<dyn MidiSink>)>,
+}
+
+impl SinkSet {
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    pub fn new_with_device_name(target_name: &str) -> Result<Self> {
-        unsafe {
-            let devices = Self::list_devices()?;
-            info!("Available MIDI output devices:");
-            for (idx, name) in &devices {
-                info!("  {}: {}", idx, name);
-            }
+    pub fn add(&mut self, name: impl Into<String>, sink: Box<dyn MidiSink>) {
+        self.sinks.push((name.into(), sink));
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.sinks.iter().any(|(sink_name, _)| sink_name == name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.sinks.iter().map(|(name, _)| name.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.sinks.len()
+    }
 
-            let device_id = devices.iter()
-                .find(|(_, name)| name.contains(target_name))
-                .map(|(idx, _)| *idx)
-                .ok_or_else(|| anyhow!("No MIDI output device found containing '{}'", target_name))?;
-
-            let mut handle = HMIDIOUT::default();
-            let result = midiOutOpen(
-                &mut handle,
-                device_id as u32,
-                0,
-                0,
-                CALLBACK_NULL,
-            );
-
-            if result == 0 {
-                info!("Successfully opened MIDI output device: {}", target_name);
-                Ok(MidiOutput { handle })
-            } else {
-                Err(anyhow!("Failed to open MIDI output device, error code: {}", result))
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    // Send to every sink. A failing sink doesn't prevent delivery to the others;
+    // the first error is returned once all sinks have been tried.
+    pub fn send_all(&self, message: &MidiMessage) -> Result<()> {
+        let mut first_error = None;
+        for (name, sink) in &self.sinks {
+            if let Err(e) = sink.send_message(message) {
+                if first_error.is_none() {
+                    first_error = Some(anyhow!("MIDI sink '{}' failed: {}", name, e));
+                }
             }
         }
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
     }
 
-    pub fn send_message(&self, message: &MidiMessage) -> Result<()> {
-        unsafe {
-            let midi_word = message.to_midi_word();
-            let result = midiOutShortMsg(self.handle, midi_word);
-            
-            if result == 0 {
-                debug!("Sent MIDI message: {:08X}", midi_word);
-                Ok(())
-            } else {
-                Err(anyhow!("Failed to send MIDI message, error code: {}", result))
-            }
+    pub fn send_to(&self, name: &str, message: &MidiMessage) -> Result<()> {
+        let (_, sink) = self.sinks.iter()
+            .find(|(sink_name, _)| sink_name == name)
+            .ok_or_else(|| anyhow!("No MIDI sink named '{}'", name))?;
+        sink.send_message(message)
+    }
+
+    pub fn send(&self, message: &MidiMessage, route: &Route) -> Result<()> {
+        match route {
+            Route::All => self.send_all(message),
+            Route::Sink(name) => self.send_to(name, message),
         }
     }
 }
 
-impl Drop for MidiOutput {
-    fn drop(&mut self) {
-        unsafe {
-            let _ = midiOutClose(self.handle);
-            info!("Closed MIDI output device");
-        }
+// Id of the first device whose name contains `target_name`. No devices at all is told
+// apart, since looking for the right name won't help there.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub(crate) fn find_device(devices: &[(usize, String)], target_name: &str) -> Result<usize> {
+    if devices.is_empty() {
+        return Err(BlipError::NoMidiDevices.into());
     }
+    devices
+        .iter()
+        .find(|(_, name)| name.contains(target_name))
+        .map(|(idx, _)| *idx)
+        .ok_or_else(|| anyhow!("No MIDI output device found containing '{}'", target_name))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use testing::RecordingSink;
+
+    #[test]
+    fn test_no_devices_at_all() {
+        let error = find_device(&[], "loopMIDI").unwrap_err();
+        assert!(matches!(error.downcast_ref::<BlipError>(), Some(BlipError::NoMidiDevices)));
+
+        let devices = [(3, "loopMIDI Port".to_string())];
+        assert_eq!(find_device(&devices, "loopMIDI").unwrap(), 3);
+        assert!(find_device(&devices, "Other").unwrap_err().downcast_ref::<BlipError>().is_none());
+    }
+
+    struct FailingSink;
+
+    impl MidiSink for FailingSink {
+        fn send_message(&self, _message: &MidiMessage) -> Result<()> {
+            Err(anyhow!("port gone"))
+        }
+    }
 
     #[test]
     fn test_midi_message_to_midi_word() {
@@ -151,6 +297,10 @@ mod tests {
             (MidiMessage { status: 0xD0, data1: 0, data2: 0 }, "Channel Pressure"),
             (MidiMessage { status: 0xE0, data1: 0, data2: 0 }, "Pitch Bend"),
             (MidiMessage { status: 0xF0, data1: 0, data2: 0 }, "Unknown"),
+            (MidiMessage { status: 0xF2, data1: 0, data2: 0 }, "Song Position Pointer"),
+            (MidiMessage { status: 0xF8, data1: 0, data2: 0 }, "Timing Clock"),
+            (MidiMessage { status: 0xF4, data1: 0, data2: 0 }, "Unknown"),
+            (MidiMessage { status: 0xFD, data1: 0, data2: 0 }, "Unknown"),
         ];
 
         for (msg, expected) in test_cases {
@@ -175,6 +325,48 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_all_notes_off_covers_every_channel() {
+        let messages = MidiMessage::all_notes_off();
+        assert_eq!(messages.len(), 16);
+        for (channel, message) in messages.iter().enumerate() {
+            assert_eq!(message.status, 0xB0 | channel as u8);
+            assert_eq!(message.data1, 123);
+            assert_eq!(message.data2, 0);
+        }
+    }
+
+    #[test]
+    fn test_panic_modes() {
+        let controllers = |mode: PanicMode| -> Vec<(u8, u8)> {
+            mode.messages().iter().map(|message| (message.status, message.data1)).collect()
+        };
+        let every_channel = |cc: u8| (0..16).map(move |channel| (0xB0 | channel, cc));
+
+        assert_eq!(controllers(PanicMode::AllNotesOff), every_channel(123).collect::<Vec<_>>());
+        assert_eq!(controllers(PanicMode::AllSoundOff), every_channel(120).collect::<Vec<_>>());
+        assert_eq!(controllers(PanicMode::Both), every_channel(123).chain(every_channel(120)).collect::<Vec<_>>());
+        assert!(PanicMode::Both.messages().iter().all(|message| message.data2 == 0));
+    }
+
+    #[test]
+    fn test_channel() {
+        assert_eq!(MidiMessage { status: 0x90, data1: 60, data2: 100 }.channel(), Some(0));
+        assert_eq!(MidiMessage { status: 0xBF, data1: 1, data2: 0 }.channel(), Some(15));
+        assert_eq!(MidiMessage { status: 0xF8, data1: 0, data2: 0 }.channel(), None);
+        assert_eq!(MidiMessage { status: 0xF0, data1: 0, data2: 0 }.channel(), None);
+    }
+
+    #[test]
+    fn test_with_channel() {
+        let note_on = MidiMessage { status: 0x93, data1: 60, data2: 100 };
+        assert_eq!(note_on.with_channel(9), MidiMessage { status: 0x99, data1: 60, data2: 100 });
+        assert_eq!(note_on.with_channel(0x1A).channel(), Some(0x0A));
+
+        let clock = MidiMessage { status: 0xF8, data1: 0, data2: 0 };
+        assert_eq!(clock.with_channel(5), clock);
+    }
+
     #[test]
     fn test_velocity() {
         let msg = MidiMessage {
@@ -184,4 +376,48 @@ mod tests {
         };
         assert_eq!(msg.velocity(), 100);
     }
+
+    #[test]
+    fn test_to_bytes_uses_message_length() {
+        assert_eq!(MidiMessage { status: 0x90, data1: 60, data2: 100 }.to_bytes(), vec![0x90, 60, 100]);
+        assert_eq!(MidiMessage { status: 0xC3, data1: 5, data2: 0 }.to_bytes(), vec![0xC3, 5]);
+        assert_eq!(MidiMessage { status: 0xF2, data1: 1, data2: 2 }.to_bytes(), vec![0xF2, 1, 2]);
+        assert_eq!(MidiMessage { status: 0xF8, data1: 0, data2: 0 }.to_bytes(), vec![0xF8]);
+    }
+
+    #[test]
+    fn test_sink_set_fans_out_past_failures() {
+        let first = RecordingSink::default();
+        let second = RecordingSink::default();
+        let mut sinks = SinkSet::new();
+        sinks.add("first", Box::new(first.clone()));
+        sinks.add("broken", Box::new(FailingSink));
+        sinks.add("second", Box::new(second.clone()));
+
+        let message = MidiMessage { status: 0x90, data1: 60, data2: 100 };
+        let result = sinks.send_all(&message);
+
+        assert!(result.unwrap_err().to_string().contains("broken"));
+        assert_eq!(first.messages(), vec![message]);
+        assert_eq!(second.messages(), vec![message]);
+        assert_eq!(sinks.names().collect::<Vec<_>>(), vec!["first", "broken", "second"]);
+    }
+
+    #[test]
+    fn test_sink_set_routes_to_named_sink() {
+        let low = RecordingSink::new();
+        let high = RecordingSink::new();
+        let mut sinks = SinkSet::new();
+        sinks.add("low", Box::new(low.clone()));
+        sinks.add("high", Box::new(high.clone()));
+
+        let message = MidiMessage { status: 0x90, data1: 40, data2: 100 };
+        sinks.send(&message, &Route::Sink("low".to_string())).unwrap();
+        assert_eq!(low.messages(), vec![message]);
+        assert!(high.messages().is_empty());
+
+        assert!(sinks.send(&message, &Route::Sink("missing".to_string())).is_err());
+        assert!(sinks.contains("high"));
+        assert!(!sinks.contains("missing"));
+    }
 }
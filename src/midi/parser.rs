@@ -0,0 +1,208 @@
+use anyhow::{anyhow, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use super::MidiMessage;
+use crate::ble::packet::{self, header_timestamp};
+
+const SYSEX_START: u8 = 0xF0;
+const SYSEX_END: u8 = 0xF7;
+
+// What to do with a packet whose header byte doesn't have the high bit set
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeaderCheck {
+    // Reject the packet
+    Strict,
+    // Skip leading bytes up to the first one that looks like a header and parse from there
+    Resync,
+}
+
+// Decode every MIDI message in a BLE-MIDI packet, in packet order (see
+// `ble::packet::decode`). SysEx is skipped since `MidiMessage` can't carry it, and so is
+// the end marker closing SysEx begun in an earlier packet, whose data bytes the decoder
+// already skips for lack of a status.
+pub fn parse_ble_midi_packet(data: &[u8], header_check: HeaderCheck) -> Result<Vec<MidiMessage>> {
+    if data.len() < 2 {
+        return Err(anyhow!("BLE-MIDI packet too short"));
+    }
+
+    let data = if header_timestamp(data[0]).is_some() {
+        data
+    } else {
+        match header_check {
+            HeaderCheck::Strict => {
+                return Err(anyhow!("Invalid BLE-MIDI header byte 0x{:02X}", data[0]));
+            }
+            HeaderCheck::Resync => {
+                let start = data
+                    .iter()
+                    .position(|&b| header_timestamp(b).is_some())
+                    .ok_or_else(|| anyhow!("No BLE-MIDI header found in packet"))?;
+                warn!("Invalid BLE-MIDI header byte 0x{:02X}, skipping {} byte(s)", data[0], start);
+                &data[start..]
+            }
+        }
+    };
+
+    Ok(packet::decode(data)?
+        .into_iter()
+        .filter(|message| !matches!(message.bytes[0], SYSEX_START | SYSEX_END))
+        .map(|message| MidiMessage {
+            status: message.bytes[0],
+            data1: message.bytes.get(1).copied().unwrap_or(0),
+            data2: message.bytes.get(2).copied().unwrap_or(0),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(status: u8, data1: u8, data2: u8) -> MidiMessage {
+        MidiMessage { status, data1, data2 }
+    }
+
+    #[test]
+    fn test_single_message() {
+        let messages = parse_ble_midi_packet(&[0x80, 0x80, 0x90, 60, 100], HeaderCheck::Strict).unwrap();
+        assert_eq!(messages, vec![msg(0x90, 60, 100)]);
+    }
+
+    #[test]
+    fn test_running_status_with_and_without_timestamps() {
+        let packet = [0x80, 0x80, 0x90, 60, 100, 62, 100, 0x81, 64, 100];
+        assert_eq!(
+            parse_ble_midi_packet(&packet, HeaderCheck::Strict).unwrap(),
+            vec![msg(0x90, 60, 100), msg(0x90, 62, 100), msg(0x90, 64, 100)]
+        );
+    }
+
+    #[test]
+    fn test_real_time_keeps_running_status() {
+        // Timing Clock between two running-status Note Ons
+        let packet = [0x80, 0x80, 0x90, 60, 100, 0x81, 0xF8, 0x82, 62, 100];
+        assert_eq!(
+            parse_ble_midi_packet(&packet, HeaderCheck::Strict).unwrap(),
+            vec![msg(0x90, 60, 100), msg(0xF8, 0, 0), msg(0x90, 62, 100)]
+        );
+    }
+
+    #[test]
+    fn test_system_common_clears_running_status() {
+        // Song Select, then data bytes that would only make sense with running status
+        let packet = [0x80, 0x80, 0x90, 60, 100, 0x81, 0xF3, 5, 0x82, 62, 100];
+        assert_eq!(
+            parse_ble_midi_packet(&packet, HeaderCheck::Strict).unwrap(),
+            vec![msg(0x90, 60, 100), msg(0xF3, 5, 0)]
+        );
+    }
+
+    #[test]
+    fn test_song_position_pointer_clears_running_status() {
+        let packet = [0x80, 0x80, 0xB0, 1, 64, 0x81, 0xF2, 0x10, 0x20, 2, 64, 0x82, 0xB0, 3, 64];
+        assert_eq!(
+            parse_ble_midi_packet(&packet, HeaderCheck::Strict).unwrap(),
+            vec![msg(0xB0, 1, 64), msg(0xF2, 0x10, 0x20), msg(0xB0, 3, 64)]
+        );
+    }
+
+    #[test]
+    fn test_sysex_is_skipped_and_clears_running_status() {
+        let packet = [0x80, 0x80, 0x90, 60, 100, 0x81, 0xF0, 0x7E, 0x7F, 0x82, 0xF7, 62, 100];
+        assert_eq!(parse_ble_midi_packet(&packet, HeaderCheck::Strict).unwrap(), vec![msg(0x90, 60, 100)]);
+    }
+
+    #[test]
+    fn test_sysex_end_fragment_is_skipped() {
+        // The last packet of SysEx split across several, followed by a note
+        let packet = [0x80, 0x41, 0x10, 0x81, 0xF7, 0x82, 0x90, 60, 100];
+        assert_eq!(parse_ble_midi_packet(&packet, HeaderCheck::Strict).unwrap(), vec![msg(0x90, 60, 100)]);
+    }
+
+    #[test]
+    fn test_header_only_packet_is_empty() {
+        assert!(parse_ble_midi_packet(&[0x80, 0x80], HeaderCheck::Strict).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_malformed_packets() {
+        assert!(parse_ble_midi_packet(&[0x80], HeaderCheck::Strict).is_err());
+        assert!(parse_ble_midi_packet(&[0x80, 0x80, 0x90, 60], HeaderCheck::Strict).is_err());
+    }
+
+    #[test]
+    fn test_header_timestamp() {
+        assert_eq!(header_timestamp(0x80), Some(0));
+        assert_eq!(header_timestamp(0xBF), Some(0x3F));
+        assert_eq!(header_timestamp(0x3F), None);
+    }
+
+    #[test]
+    fn test_corrupt_header_rejected_when_strict() {
+        let packet = [0x00, 0x80, 0x90, 60, 100];
+        assert!(parse_ble_midi_packet(&packet, HeaderCheck::Strict).is_err());
+    }
+
+    #[test]
+    fn test_corrupt_header_resyncs() {
+        // A stray byte before the real header
+        let packet = [0x12, 0x80, 0x80, 0x90, 60, 100];
+        assert_eq!(parse_ble_midi_packet(&packet, HeaderCheck::Resync).unwrap(), vec![msg(0x90, 60, 100)]);
+        assert!(parse_ble_midi_packet(&[0x12, 0x34, 0x56], HeaderCheck::Resync).is_err());
+    }
+
+    // Examples following the BLE-MIDI specification's packet layouts
+
+    #[test]
+    fn test_spec_full_messages_each_with_timestamp() {
+        // Two Note Ons with different statuses, each preceded by its timestamp
+        let packet = [0x80, 0x81, 0x90, 60, 100, 0x82, 0x91, 62, 90];
+        assert_eq!(
+            parse_ble_midi_packet(&packet, HeaderCheck::Strict).unwrap(),
+            vec![msg(0x90, 60, 100), msg(0x91, 62, 90)]
+        );
+    }
+
+    #[test]
+    fn test_timestamp_that_looks_like_a_status() {
+        // Timestamp-low 0x90 followed by Note On status 0x90
+        let packet = [0x80, 0x90, 0x90, 60, 100];
+        assert_eq!(parse_ble_midi_packet(&packet, HeaderCheck::Strict).unwrap(), vec![msg(0x90, 60, 100)]);
+
+        // After a complete message, 0xB0 is a timestamp and the data uses running status
+        let packet = [0x80, 0x80, 0x90, 60, 100, 0xB0, 62, 100];
+        assert_eq!(
+            parse_ble_midi_packet(&packet, HeaderCheck::Strict).unwrap(),
+            vec![msg(0x90, 60, 100), msg(0x90, 62, 100)]
+        );
+    }
+
+    #[test]
+    fn test_real_time_interrupting_a_message() {
+        // Timing Clock (with its timestamp) between the note number and the velocity
+        let packet = [0x80, 0x80, 0x90, 60, 0x81, 0xF8, 100];
+        assert_eq!(
+            parse_ble_midi_packet(&packet, HeaderCheck::Strict).unwrap(),
+            vec![msg(0xF8, 0, 0), msg(0x90, 60, 100)]
+        );
+    }
+
+    #[test]
+    fn test_status_byte_inside_a_message_is_an_error() {
+        // A new channel message can't start before the previous one is complete
+        let packet = [0x80, 0x80, 0x90, 60, 0x81, 0x80, 60, 0];
+        assert!(parse_ble_midi_packet(&packet, HeaderCheck::Strict).is_err());
+    }
+
+    #[test]
+    fn test_one_and_zero_data_byte_messages() {
+        // Program Change with running status, then Tune Request
+        let packet = [0x80, 0x80, 0xC0, 5, 6, 0x81, 0xF6];
+        assert_eq!(
+            parse_ble_midi_packet(&packet, HeaderCheck::Strict).unwrap(),
+            vec![msg(0xC0, 5, 0), msg(0xC0, 6, 0), msg(0xF6, 0, 0)]
+        );
+    }
+}
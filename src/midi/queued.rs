@@ -0,0 +1,207 @@
+use anyhow::{anyhow, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::{MidiMessage, MidiSink};
+
+// Time a three byte message takes on a serial (DIN) MIDI cable at 31250 baud, with a
+// start and stop bit per byte. A sensible `Config::min_send_interval` for a port that
+// feeds a hardware MIDI interface.
+pub const DIN_MIDI_MESSAGE_TIME: Duration = Duration::from_micros(960);
+
+// What a `QueuedSink` does with a CC or pitch bend its full queue has no room for. Sending
+// never waits, since that would hold up BLE processing; notes and every other message are
+// queued beyond the capacity rather than lost, as a lost Note Off leaves a note stuck.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueOverflow {
+    // Drop the message, with a warning
+    #[default]
+    Drop,
+    // Fail the send, which counts towards the packet errors that end a session
+    Fail,
+}
+
+// How a `QueuedSink` queues and paces its messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct QueueConfig {
+    // Messages the queue holds before CCs and pitch bends are refused; None never fills up
+    pub capacity: Option<usize>,
+    // Least time between two messages, for a slow downstream (e.g. a DIN-MIDI interface)
+    // that a burst would overflow
+    pub interval: Option<Duration>,
+    pub overflow: QueueOverflow,
+}
+
+// Sink that passes messages on to another from a thread of its own, in order, so a slow
+// driver call never holds up BLE processing. Dropping the sink sends whatever is still
+// queued.
+pub struct QueuedSink {
+    queue: Mutex<Option<mpsc::Sender<MidiMessage>>>,
+    // Messages sent but not yet taken by the thread
+    queued: Arc<AtomicUsize>,
+    capacity: Option<usize>,
+    overflow: QueueOverflow,
+    sender: Option<JoinHandle<()>>,
+}
+
+impl QueuedSink {
+    pub fn new(sink: Box<dyn MidiSink>, config: QueueConfig) -> Self {
+        let (queue, messages) = mpsc::channel();
+        let queued = Arc::new(AtomicUsize::new(0));
+        let taken = queued.clone();
+        let sender = thread::spawn(move || {
+            let mut last_sent: Option<Instant> = None;
+            for message in messages {
+                taken.fetch_sub(1, Ordering::SeqCst);
+                if let Some(wait) = config
+                    .interval
+                    .and_then(|interval| last_sent.and_then(|sent| (sent + interval).checked_duration_since(Instant::now())))
+                {
+                    thread::sleep(wait);
+                }
+                if let Err(e) = sink.send_message(&message) {
+                    warn!("Queued MIDI send failed: {}", e);
+                }
+                last_sent = Some(Instant::now());
+            }
+        });
+        QueuedSink {
+            queue: Mutex::new(Some(queue)),
+            queued,
+            capacity: config.capacity,
+            overflow: config.overflow,
+            sender: Some(sender),
+        }
+    }
+}
+
+impl MidiSink for QueuedSink {
+    fn send_message(&self, message: &MidiMessage) -> Result<()> {
+        let queue = self.queue.lock().unwrap();
+        let queue = queue.as_ref().ok_or_else(|| anyhow!("Queued MIDI sender has stopped"))?;
+        let droppable = matches!(message.status & 0xF0, 0xB0 | 0xE0);
+        let full = self.capacity.is_some_and(|capacity| self.queued.load(Ordering::SeqCst) >= capacity);
+        if droppable && full {
+            return match self.overflow {
+                QueueOverflow::Drop => {
+                    warn!("MIDI output queue full, dropping {}", message.message_type());
+                    Ok(())
+                }
+                QueueOverflow::Fail => Err(anyhow!("MIDI output queue full, {} not sent", message.message_type())),
+            };
+        }
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        queue.send(*message).map_err(|_| {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            anyhow!("Queued MIDI sender has stopped")
+        })
+    }
+}
+
+impl Drop for QueuedSink {
+    fn drop(&mut self) {
+        // Closing the queue lets the thread finish sending what is left, then stop
+        self.queue.lock().unwrap().take();
+        if let Some(sender) = self.sender.take() {
+            let _ = sender.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::testing::RecordingSink;
+
+    fn note_on(note: u8) -> MidiMessage {
+        MidiMessage { status: 0x90, data1: note, data2: 100 }
+    }
+
+    fn note_off(note: u8) -> MidiMessage {
+        MidiMessage { status: 0x80, data1: note, data2: 0 }
+    }
+
+    // Records when each message arrived
+    #[derive(Clone, Default)]
+    struct TimingSink {
+        received: Arc<Mutex<Vec<(MidiMessage, Instant)>>>,
+    }
+
+    impl MidiSink for TimingSink {
+        fn send_message(&self, message: &MidiMessage) -> Result<()> {
+            self.received.lock().unwrap().push((*message, Instant::now()));
+            Ok(())
+        }
+    }
+
+    // Holds every message until the test lets it through, announcing when one arrives
+    struct GatedSink {
+        output: RecordingSink,
+        arrived: Mutex<mpsc::Sender<()>>,
+        gate: Mutex<mpsc::Receiver<()>>,
+    }
+
+    impl MidiSink for GatedSink {
+        fn send_message(&self, message: &MidiMessage) -> Result<()> {
+            let _ = self.arrived.lock().unwrap().send(());
+            let _ = self.gate.lock().unwrap().recv();
+            self.output.send_message(message)
+        }
+    }
+
+    #[test]
+    fn test_messages_are_spaced_and_flushed() {
+        let interval = Duration::from_millis(5);
+        let timing = TimingSink::default();
+        let paced = QueuedSink::new(Box::new(timing.clone()), QueueConfig { interval: Some(interval), ..QueueConfig::default() });
+
+        let messages: Vec<_> = (60..66).map(note_on).collect();
+        for message in &messages {
+            paced.send_message(message).unwrap();
+        }
+        // Dropping waits for the queue to drain
+        drop(paced);
+
+        let received = timing.received.lock().unwrap();
+        assert_eq!(received.iter().map(|(message, _)| *message).collect::<Vec<_>>(), messages);
+        for pair in received.windows(2) {
+            assert!(pair[1].1 - pair[0].1 >= interval);
+        }
+    }
+
+    #[test]
+    fn test_full_queue_never_blocks() {
+        for overflow in [QueueOverflow::Drop, QueueOverflow::Fail] {
+            let output = RecordingSink::new();
+            let (arrived_sender, arrived) = mpsc::channel();
+            let (open, gate) = mpsc::channel();
+            let sink = GatedSink { output: output.clone(), arrived: Mutex::new(arrived_sender), gate: Mutex::new(gate) };
+            let queued = QueuedSink::new(Box::new(sink), QueueConfig { capacity: Some(1), overflow, ..QueueConfig::default() });
+
+            // The first note is held in the sink and the second fills the queue
+            queued.send_message(&note_on(60)).unwrap();
+            arrived.recv().unwrap();
+            queued.send_message(&note_on(61)).unwrap();
+            // No room for a CC or a bend
+            let cc = MidiMessage::control_change(0, 1, 64);
+            let bend = MidiMessage { status: 0xE0, data1: 0, data2: 0x50 };
+            for message in [cc, bend] {
+                assert_eq!(queued.send_message(&message).is_ok(), overflow == QueueOverflow::Drop);
+            }
+            // but a Note Off is queued all the same
+            queued.send_message(&note_off(60)).unwrap();
+
+            for _ in 0..3 {
+                open.send(()).unwrap();
+            }
+            drop(queued);
+            assert_eq!(output.messages(), [note_on(60), note_on(61), note_off(60)]);
+        }
+    }
+}
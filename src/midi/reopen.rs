@@ -0,0 +1,193 @@
+use anyhow::Result;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use super::{MidiMessage, MidiSink};
+
+// Opens (or reopens) an output port
+pub type PortOpener = Box<dyn Fn() -> Result<Box<dyn MidiSink>> + Send + Sync>;
+
+// What to do while an output port is lost
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PortRecovery {
+    // Least time between attempts to reopen the port
+    #[serde(with = "crate::util::duration_secs")]
+    pub retry_interval: Duration,
+    // Messages kept to send once the port is back, dropping the oldest beyond that. Zero
+    // drops everything sent while the port is away.
+    pub buffer: usize,
+}
+
+impl Default for PortRecovery {
+    fn default() -> Self {
+        PortRecovery { retry_interval: Duration::from_secs(1), buffer: 256 }
+    }
+}
+
+// Output that outlives its port: when a send fails, the port is closed and reopened on
+// a later send, at most once per `retry_interval`. Meanwhile sends succeed, the messages
+// being kept or dropped per `PortRecovery::buffer`, so whatever feeds the sink (the BLE
+// side of the bridge) carries on undisturbed. A note released while the port is away is
+// dropped whole rather than played late, and the reopened port gets All Notes Off first
+// for the notes whose Note Off was lost with the port.
+pub struct ReopeningSink {
+    name: String,
+    opener: PortOpener,
+    recovery: PortRecovery,
+    state: Mutex<PortState>,
+}
+
+struct PortState {
+    port: Option<Box<dyn MidiSink>>,
+    last_attempt: Option<Instant>,
+    pending: VecDeque<MidiMessage>,
+    dropped: u64,
+}
+
+impl ReopeningSink {
+    // Wrap an opened port, reopened with `opener` once lost
+    pub fn new(name: impl Into<String>, port: Box<dyn MidiSink>, opener: PortOpener, recovery: PortRecovery) -> Self {
+        ReopeningSink {
+            name: name.into(),
+            opener,
+            recovery,
+            state: Mutex::new(PortState { port: Some(port), last_attempt: None, pending: VecDeque::new(), dropped: 0 }),
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.state.lock().unwrap().port.is_some()
+    }
+
+    fn reopen(&self, state: &mut PortState) {
+        let now = Instant::now();
+        if state.last_attempt.is_some_and(|last| now.duration_since(last) < self.recovery.retry_interval) {
+            return;
+        }
+        state.last_attempt = Some(now);
+        match (self.opener)() {
+            Ok(port) => {
+                info!(
+                    "MIDI port '{}' reopened, sending {} held message(s) ({} dropped)",
+                    self.name,
+                    state.pending.len(),
+                    state.dropped
+                );
+                state.port = Some(port);
+                state.dropped = 0;
+                let mut replay: VecDeque<_> = MidiMessage::all_notes_off().into();
+                replay.append(&mut state.pending);
+                state.pending = replay;
+                while let Some(message) = state.pending.pop_front() {
+                    if !self.deliver(state, &message) {
+                        state.pending.push_front(message);
+                        break;
+                    }
+                }
+            }
+            Err(e) => debug!("MIDI port '{}' still unavailable: {}", self.name, e),
+        }
+    }
+
+    // Send through the open port, closing it if that fails. Returns whether it was sent.
+    fn deliver(&self, state: &mut PortState, message: &MidiMessage) -> bool {
+        let Some(port) = &state.port else {
+            return false;
+        };
+        match port.send_message(message) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("MIDI port '{}' lost: {} - reopening it every {:?}", self.name, e, self.recovery.retry_interval);
+                state.port = None;
+                state.last_attempt = Some(Instant::now());
+                false
+            }
+        }
+    }
+
+    // Keep a message for the reopened port
+    fn hold(&self, state: &mut PortState, message: &MidiMessage) {
+        if message.message_type() == "Note Off" {
+            let held = state.pending.iter().rposition(|pending| {
+                pending.message_type() == "Note On" && pending.channel() == message.channel() && pending.data1 == message.data1
+            });
+            if let Some(index) = held {
+                state.pending.remove(index);
+                state.dropped += 2;
+                return;
+            }
+        }
+        if self.recovery.buffer == 0 {
+            state.dropped += 1;
+            return;
+        }
+        while state.pending.len() >= self.recovery.buffer {
+            state.pending.pop_front();
+            state.dropped += 1;
+        }
+        state.pending.push_back(*message);
+    }
+}
+
+impl MidiSink for ReopeningSink {
+    fn send_message(&self, message: &MidiMessage) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.port.is_none() {
+            self.reopen(&mut state);
+        }
+        // Held messages go first, to keep the order
+        if state.pending.is_empty() && self.deliver(&mut state, message) {
+            return Ok(());
+        }
+        self.hold(&mut state, message);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::testing::FlakyPort;
+    use std::sync::atomic::Ordering;
+
+    fn note_on(note: u8) -> MidiMessage {
+        MidiMessage { status: 0x90, data1: note, data2: 100 }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_messages_are_held_until_the_port_is_back() {
+        let port = FlakyPort::default();
+        let recovery = PortRecovery { retry_interval: Duration::from_millis(100), buffer: 2 };
+        let sink = ReopeningSink::new("out", Box::new(port.clone()), port.opener(), recovery);
+
+        sink.send_message(&note_on(60)).unwrap();
+        port.set_unplugged(true);
+        // Not an error for the sender; beyond the buffer the oldest messages are dropped
+        for note in 61..=63 {
+            sink.send_message(&note_on(note)).unwrap();
+        }
+        assert!(!sink.is_open());
+        // A note played and released meanwhile is dropped whole
+        sink.send_message(&note_on(70)).unwrap();
+        sink.send_message(&MidiMessage { status: 0x80, data1: 70, data2: 0 }).unwrap();
+
+        // Back, but not retried before the interval is up
+        port.set_unplugged(false);
+        sink.send_message(&note_on(64)).unwrap();
+        assert_eq!(port.output.messages(), [note_on(60)]);
+        tokio::time::advance(Duration::from_millis(100)).await;
+        sink.send_message(&note_on(65)).unwrap();
+
+        assert!(sink.is_open());
+        assert_eq!(port.opens.load(Ordering::SeqCst), 1);
+        // Notes that were sounding when the port went away are released first
+        let mut expected = vec![note_on(60)];
+        expected.extend(MidiMessage::all_notes_off());
+        expected.extend([note_on(63), note_on(64), note_on(65)]);
+        assert_eq!(port.output.messages(), expected);
+    }
+}
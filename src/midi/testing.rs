@@ -0,0 +1,64 @@
+// Test doubles shared by the unit tests of several modules
+use anyhow::{anyhow, Result};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::{MidiMessage, MidiSink, PortOpener};
+
+// Sink recording everything it receives. Clones share the same record, so a clone can
+// be handed to a `SinkSet` while the test keeps the original to inspect.
+#[derive(Clone, Default)]
+pub struct RecordingSink {
+    received: Arc<Mutex<Vec<MidiMessage>>>,
+}
+
+impl RecordingSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn messages(&self) -> Vec<MidiMessage> {
+        self.received.lock().unwrap().clone()
+    }
+}
+
+impl MidiSink for RecordingSink {
+    fn send_message(&self, message: &MidiMessage) -> Result<()> {
+        self.received.lock().unwrap().push(*message);
+        Ok(())
+    }
+}
+
+// A port that can be unplugged and plugged back in, recording what reaches it
+#[derive(Clone, Default)]
+pub struct FlakyPort {
+    pub output: RecordingSink,
+    pub unplugged: Arc<AtomicBool>,
+    pub opens: Arc<AtomicU32>,
+}
+
+impl FlakyPort {
+    pub fn opener(&self) -> PortOpener {
+        let port = self.clone();
+        Box::new(move || {
+            if port.unplugged.load(Ordering::SeqCst) {
+                return Err(anyhow!("Port not found"));
+            }
+            port.opens.fetch_add(1, Ordering::SeqCst);
+            Ok(Box::new(port.clone()) as Box<dyn MidiSink>)
+        })
+    }
+
+    pub fn set_unplugged(&self, unplugged: bool) {
+        self.unplugged.store(unplugged, Ordering::SeqCst);
+    }
+}
+
+impl MidiSink for FlakyPort {
+    fn send_message(&self, message: &MidiMessage) -> Result<()> {
+        if self.unplugged.load(Ordering::SeqCst) {
+            return Err(anyhow!("Port gone"));
+        }
+        self.output.send_message(message)
+    }
+}
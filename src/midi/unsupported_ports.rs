@@ -0,0 +1,36 @@
+// Stand-ins for the winmm ports where there is no winmm. Existing ports can't be opened
+// by name there; `Config::create_virtual_port` creates the bridge's own port instead.
+use anyhow::{anyhow, Error, Result};
+
+use super::{MidiMessage, MidiSink};
+
+fn unsupported() -> Error {
+    anyhow!("Opening an existing MIDI port needs Windows; set create_virtual_port (with the `midir` feature) instead")
+}
+
+// Never constructed: opening always fails
+pub enum MidiOutput {}
+
+impl MidiOutput {
+    pub fn list_devices() -> Result<Vec<(usize, String)>> {
+        Err(unsupported())
+    }
+
+    pub fn new_with_device_name(_target_name: &str) -> Result<Self> {
+        Err(unsupported())
+    }
+}
+
+impl MidiSink for MidiOutput {
+    fn send_message(&self, _message: &MidiMessage) -> Result<()> {
+        match *self {}
+    }
+}
+
+pub struct MidiInput;
+
+impl MidiInput {
+    pub fn list_devices() -> Result<Vec<(usize, String)>> {
+        Err(unsupported())
+    }
+}
@@ -0,0 +1,104 @@
+use anyhow::{anyhow, Context, Result};
+
+use super::{parse_ble_midi_packet, HeaderCheck, MidiMessage};
+
+// Canonical packets from the BLE-MIDI specification's layouts plus LPK25-style traffic,
+// see the file for its format
+const PARSER_VECTORS: &str = include_str!("parser_vectors.txt");
+
+// A BLE-MIDI packet and what the parser must make of it: these messages, or an error (None)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParserVector {
+    pub name: String,
+    pub packet: Vec<u8>,
+    pub expected: Option<Vec<MidiMessage>>,
+}
+
+impl ParserVector {
+    // What the parser made of the packet, if that wasn't what was expected
+    pub fn check(&self) -> Option<String> {
+        match (parse_ble_midi_packet(&self.packet, HeaderCheck::Strict), &self.expected) {
+            (Ok(messages), Some(expected)) if messages == *expected => None,
+            (Err(_), None) => None,
+            (Ok(messages), _) => {
+                let messages: Vec<_> = messages.iter().map(|m| format!("{:02X} {:02X} {:02X}", m.status, m.data1, m.data2)).collect();
+                Some(format!("[{}]", messages.join("; ")))
+            }
+            (Err(e), _) => Some(format!("error ({})", e)),
+        }
+    }
+}
+
+// The vectors embedded in BLIP
+pub fn parser_vectors() -> Result<Vec<ParserVector>> {
+    PARSER_VECTORS
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| parse_vector(line).with_context(|| format!("Bad parser vector on line {}", i + 1)))
+        .collect()
+}
+
+// Run every embedded vector through `parse_ble_midi_packet`, returning how many there
+// are, or an error listing those the parser got wrong
+pub fn verify_parser() -> Result<usize> {
+    let vectors = parser_vectors()?;
+    let failures: Vec<_> = vectors
+        .iter()
+        .filter_map(|vector| vector.check().map(|actual| format!("{}: got {}", vector.name, actual)))
+        .collect();
+    if !failures.is_empty() {
+        return Err(anyhow!("{} of {} parser vectors failed:\n  {}", failures.len(), vectors.len(), failures.join("\n  ")));
+    }
+    Ok(vectors.len())
+}
+
+fn parse_vector(line: &str) -> Result<ParserVector> {
+    let (name, rest) = line.split_once(':').ok_or_else(|| anyhow!("Missing ':' after the name"))?;
+    let (packet, expected) = rest.split_once("=>").ok_or_else(|| anyhow!("Missing '=>'"))?;
+    let expected = match expected.trim() {
+        "error" => None,
+        "" => Some(Vec::new()),
+        messages => Some(messages.split(';').map(parse_message).collect::<Result<_>>()?),
+    };
+    Ok(ParserVector { name: name.trim().to_string(), packet: hex_bytes(packet)?, expected })
+}
+
+fn parse_message(text: &str) -> Result<MidiMessage> {
+    match hex_bytes(text)?[..] {
+        [status] => Ok(MidiMessage { status, data1: 0, data2: 0 }),
+        [status, data1] => Ok(MidiMessage { status, data1, data2: 0 }),
+        [status, data1, data2] => Ok(MidiMessage { status, data1, data2 }),
+        _ => Err(anyhow!("A message must have 1-3 bytes: '{}'", text.trim())),
+    }
+}
+
+fn hex_bytes(text: &str) -> Result<Vec<u8>> {
+    text.split_whitespace()
+        .map(|byte| u8::from_str_radix(byte, 16).map_err(|_| anyhow!("Bad hex byte '{}'", byte)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_embedded_vectors_pass() {
+        assert_eq!(verify_parser().unwrap(), parser_vectors().unwrap().len());
+    }
+
+    #[test]
+    fn test_vector_format() {
+        let vector = parse_vector("chord: 80 80 90 3C 64 40 64 => 90 3C 64; 90 40 64").unwrap();
+        assert_eq!(vector.packet, [0x80, 0x80, 0x90, 0x3C, 0x64, 0x40, 0x64]);
+        assert_eq!(vector.expected.as_ref().map(Vec::len), Some(2));
+        assert_eq!(parse_vector("bad: 00 => error").unwrap().expected, None);
+        assert!(parse_vector("no arrow: 80 80").is_err());
+        assert!(parse_vector("long: 80 80 => 90 3C 64 00").is_err());
+
+        // A wrong expectation is reported
+        let vector = ParserVector { expected: Some(Vec::new()), ..vector };
+        assert_eq!(vector.check(), Some("[90 3C 64; 90 40 64]".to_string()));
+    }
+}
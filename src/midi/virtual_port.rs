@@ -0,0 +1,88 @@
+// Output ports the bridge creates itself, so no loopMIDI-style driver is needed. ALSA
+// and CoreMIDI support this through midir; Windows has no API for it.
+use anyhow::Result;
+
+use super::MidiSink;
+
+// Why a virtual port can't be created in this build, if it can't
+pub fn virtual_port_unsupported() -> Option<&'static str> {
+    if cfg!(windows) {
+        Some("Windows can't create virtual MIDI ports; create the port in loopMIDI instead")
+    } else if !cfg!(unix) {
+        Some("Virtual MIDI ports aren't supported on this platform")
+    } else if !cfg!(feature = "midir") {
+        Some("Creating a virtual MIDI port needs BLIP built with the `midir` feature")
+    } else {
+        None
+    }
+}
+
+// Create an output port called `name` that other applications can connect to
+pub fn create_virtual_port(name: &str) -> Result<Box<dyn MidiSink>> {
+    imp::create(name)
+}
+
+#[cfg(all(unix, feature = "midir"))]
+mod imp {
+    use anyhow::{anyhow, Result};
+    use log::info;
+    use midir::os::unix::VirtualOutput;
+    use std::sync::Mutex;
+
+    use crate::midi::{MidiMessage, MidiSink};
+
+    pub struct VirtualPort {
+        connection: Mutex<midir::MidiOutputConnection>,
+    }
+
+    pub fn create(name: &str) -> Result<Box<dyn MidiSink>> {
+        let output = midir::MidiOutput::new("BLIP").map_err(|e| anyhow!("Failed to initialise MIDI: {}", e))?;
+        let connection = output
+            .create_virtual(name)
+            .map_err(|e| anyhow!("Failed to create virtual MIDI port '{}': {}", name, e))?;
+        info!("Created virtual MIDI port '{}'", name);
+        Ok(Box::new(VirtualPort { connection: Mutex::new(connection) }))
+    }
+
+    impl MidiSink for VirtualPort {
+        fn send_message(&self, message: &MidiMessage) -> Result<()> {
+            self.connection
+                .lock()
+                .unwrap()
+                .send(&message.to_bytes())
+                .map_err(|e| anyhow!("Failed to send MIDI message: {}", e))
+        }
+    }
+}
+
+#[cfg(not(all(unix, feature = "midir")))]
+mod imp {
+    use anyhow::{anyhow, Result};
+
+    use super::virtual_port_unsupported;
+    use crate::midi::MidiSink;
+
+    pub fn create(_name: &str) -> Result<Box<dyn MidiSink>> {
+        Err(anyhow!(virtual_port_unsupported().unwrap_or("Virtual MIDI ports aren't supported in this build")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(not(all(unix, feature = "midir")))]
+    #[test]
+    fn test_virtual_port_unsupported() {
+        use super::{create_virtual_port, virtual_port_unsupported};
+        let error = create_virtual_port("BLIP test").err().unwrap();
+        assert_eq!(Some(error.to_string().as_str()), virtual_port_unsupported());
+    }
+
+    #[cfg(all(unix, feature = "midir"))]
+    #[test]
+    #[ignore = "needs a running MIDI system (ALSA sequencer or CoreMIDI)"]
+    fn test_create_virtual_port() {
+        use super::create_virtual_port;
+        let port = create_virtual_port("BLIP test").unwrap();
+        port.send_message(&crate::midi::MidiMessage { status: 0x90, data1: 60, data2: 100 }).unwrap();
+    }
+}
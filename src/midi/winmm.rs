@@ -0,0 +1,206 @@
+// MIDI ports through the Windows multimedia API (winmm), which loopMIDI ports show up in
+use anyhow::{anyhow, Result};
+use log::{debug, info, warn};
+use std::ffi::CStr;
+use windows::Win32::Media::Audio::{
+    midiInGetDevCapsA, midiInGetNumDevs, midiOutClose, midiOutGetDevCapsA, midiOutGetNumDevs, midiOutOpen, midiOutReset, midiOutShortMsg,
+    HMIDIOUT, MIDIINCAPSA, MIDIOUTCAPSA, CALLBACK_NULL,
+};
+
+use super::{find_device, MidiMessage, MidiSink};
+
+pub struct MidiOutput {
+    handle: HMIDIOUT,
+}
+
+// The winmm calls made while opening a port, so tests can check their order
+trait PortApi {
+    fn open(&self, device_id: u32) -> std::result::Result<HMIDIOUT, u32>;
+    fn reset(&self, handle: HMIDIOUT) -> u32;
+}
+
+struct WinMm;
+
+impl PortApi for WinMm {
+    fn open(&self, device_id: u32) -> std::result::Result<HMIDIOUT, u32> {
+        let mut handle = HMIDIOUT::default();
+        match unsafe { midiOutOpen(&mut handle, device_id, 0, 0, CALLBACK_NULL) } {
+            0 => Ok(handle),
+            result => Err(result),
+        }
+    }
+
+    fn reset(&self, handle: HMIDIOUT) -> u32 {
+        unsafe { midiOutReset(handle) }
+    }
+}
+
+// Open the device and reset it straight away, clearing whatever a previous session
+// that crashed left pending or sounding
+fn open_port(api: &impl PortApi, device_id: u32) -> Result<HMIDIOUT> {
+    let handle = api
+        .open(device_id)
+        .map_err(|result| anyhow!("Failed to open MIDI output device, error code: {}", result))?;
+    // The port works regardless, so a failed reset is only worth a warning
+    let result = api.reset(handle);
+    if result != 0 {
+        warn!("Failed to reset MIDI output device, error code: {}", result);
+    }
+    Ok(handle)
+}
+
+// (device id, name) of each of `count` devices whose name can be read; ids of
+// devices that can't be queried are skipped rather than renumbered
+fn enumerate_devices(count: u32, name_of: impl Fn(u32) -> Option<String>) -> Vec<(usize, String)> {
+    (0..count).filter_map(|i| name_of(i).map(|name| (i as usize, name))).collect()
+}
+
+// Name from the fixed-size, NUL-terminated buffer of a capabilities struct
+fn device_name(buffer: &[u8]) -> Option<String> {
+    CStr::from_bytes_until_nul(buffer).ok()?.to_str().ok().map(str::to_string)
+}
+
+impl MidiOutput {
+    pub fn list_devices() -> Result<Vec<(usize, String)>> {
+        let count = unsafe { midiOutGetNumDevs() };
+        Ok(enumerate_devices(count, |i| {
+            let mut caps = MIDIOUTCAPSA::default();
+            let result = unsafe { midiOutGetDevCapsA(i as usize, &mut caps, std::mem::size_of::<MIDIOUTCAPSA>() as u32) };
+            (result == 0).then(|| device_name(&caps.szPname)).flatten()
+        }))
+    }
+
+    pub fn new_with_device_name(target_name: &str) -> Result<Self> {
+        let devices = Self::list_devices()?;
+        info!("Available MIDI output devices:");
+        for (idx, name) in &devices {
+            info!("  {}: {}", idx, name);
+        }
+        let device_id = find_device(&devices, target_name)?;
+
+        let handle = open_port(&WinMm, device_id as u32)?;
+        info!("Successfully opened MIDI output device: {}", target_name);
+        Ok(MidiOutput { handle })
+    }
+
+    // Driver-level reset (midiOutReset): drops pending output and turns off every note
+    // the driver knows to be sounding. Unlike sending All Notes Off, this doesn't go
+    // through the port as MIDI messages, so it also works when the receiving synth
+    // ignores Channel Mode messages.
+    pub fn reset(&self) -> Result<()> {
+        match WinMm.reset(self.handle) {
+            0 => Ok(()),
+            result => Err(anyhow!("Failed to reset MIDI output device, error code: {}", result)),
+        }
+    }
+
+    // All Sound Off (CC120) on every channel, silencing the port immediately
+    pub fn all_sound_off(&self) -> Result<()> {
+        for message in MidiMessage::all_sound_off() {
+            self.send_message(&message)?;
+        }
+        Ok(())
+    }
+
+    pub fn send_message(&self, message: &MidiMessage) -> Result<()> {
+        unsafe {
+            let midi_word = message.to_midi_word();
+            let result = midiOutShortMsg(self.handle, midi_word);
+            
+            if result == 0 {
+                debug!("Sent MIDI message: {:08X}", midi_word);
+                Ok(())
+            } else {
+                Err(anyhow!("Failed to send MIDI message, error code: {}", result))
+            }
+        }
+    }
+}
+
+// MIDI input devices, for choosing an input source
+pub struct MidiInput;
+
+impl MidiInput {
+    pub fn list_devices() -> Result<Vec<(usize, String)>> {
+        let count = unsafe { midiInGetNumDevs() };
+        Ok(enumerate_devices(count, |i| {
+            let mut caps = MIDIINCAPSA::default();
+            let result = unsafe { midiInGetDevCapsA(i as usize, &mut caps, std::mem::size_of::<MIDIINCAPSA>() as u32) };
+            (result == 0).then(|| device_name(&caps.szPname)).flatten()
+        }))
+    }
+}
+
+impl MidiSink for MidiOutput {
+    fn send_message(&self, message: &MidiMessage) -> Result<()> {
+        MidiOutput::send_message(self, message)
+    }
+}
+
+impl Drop for MidiOutput {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = midiOutClose(self.handle);
+            info!("Closed MIDI output device");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Records the winmm calls instead of making them
+    #[derive(Default)]
+    struct PortApiShim {
+        calls: std::cell::RefCell<Vec<&'static str>>,
+        open_result: u32,
+    }
+
+    impl PortApi for PortApiShim {
+        fn open(&self, _device_id: u32) -> std::result::Result<HMIDIOUT, u32> {
+            self.calls.borrow_mut().push("open");
+            match self.open_result {
+                0 => Ok(HMIDIOUT(1)),
+                result => Err(result),
+            }
+        }
+
+        fn reset(&self, _handle: HMIDIOUT) -> u32 {
+            self.calls.borrow_mut().push("reset");
+            0
+        }
+    }
+
+    #[test]
+    fn test_device_enumeration() {
+        let names = ["loopMIDI Port", "", "Microsoft GS Wavetable Synth"];
+        let devices = enumerate_devices(3, |i| Some(names[i as usize]).filter(|name| !name.is_empty()).map(str::to_string));
+        // Device 1 couldn't be queried; the others keep their ids
+        assert_eq!(
+            devices,
+            vec![(0, "loopMIDI Port".to_string()), (2, "Microsoft GS Wavetable Synth".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_device_name_from_caps_buffer() {
+        let mut buffer = [0u8; 32];
+        buffer[..4].copy_from_slice(b"LPK2");
+        assert_eq!(device_name(&buffer), Some("LPK2".to_string()));
+        // No terminator at all
+        assert_eq!(device_name(&[b'x'; 4]), None);
+    }
+
+    #[test]
+    fn test_port_is_reset_after_opening() {
+        let api = PortApiShim::default();
+        assert_eq!(open_port(&api, 0).unwrap(), HMIDIOUT(1));
+        assert_eq!(*api.calls.borrow(), vec!["open", "reset"]);
+
+        // Nothing to reset when opening fails
+        let api = PortApiShim { open_result: 2, ..PortApiShim::default() };
+        assert!(open_port(&api, 0).is_err());
+        assert_eq!(*api.calls.borrow(), vec!["open"]);
+    }
+}
@@ -0,0 +1,290 @@
+use anyhow::Result;
+use log::{debug, info, warn};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::ble::DeviceInfo;
+use crate::BleMidiBridge;
+
+// Age at which the device info (RSSI, battery) is read again for a request. Reading it
+// takes GATT reads, so it isn't done for every poll, nor while nobody is looking.
+const DEVICE_INFO_MAX_AGE: Duration = Duration::from_secs(2);
+
+// Largest request head accepted
+const MAX_REQUEST_BYTES: usize = 8192;
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>BLIP</title>
+<style>
+body { font-family: sans-serif; margin: 1em; background: #111; color: #eee; }
+td { padding: 0.2em 1em 0.2em 0; }
+#notes { font-size: 1.5em; min-height: 1.5em; color: #50aaff; }
+</style>
+</head>
+<body>
+<h1>BLIP</h1>
+<table id="status"></table>
+<h2>Held notes</h2>
+<div id="notes"></div>
+<h2>Messages</h2>
+<table id="counts"></table>
+<script>
+const NAMES = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+const noteName = (note) => NAMES[note % 12] + (Math.floor(note / 12) - 1);
+function rows(table, entries) {
+  document.getElementById(table).replaceChildren(...entries.map((entry) => {
+    const row = document.createElement("tr");
+    for (const value of entry) {
+      const cell = document.createElement("td");
+      cell.textContent = value;
+      row.append(cell);
+    }
+    return row;
+  }));
+}
+async function refresh() {
+  try {
+    const status = await (await fetch("/api/status")).json();
+    const device = status.device;
+    rows("status", [
+      ["State", status.state],
+      ["Device", device ? (device.name || "unnamed") + " (" + device.address + ")" : "none"],
+      ["Signal", device && device.rssi !== null ? device.rssi + " dBm" : "unknown"],
+      ["Battery", device && device.battery_level !== null ? device.battery_level + "%" : "unknown"],
+      ["Uptime", status.uptime_secs === null ? "-" : Math.round(status.uptime_secs) + " s"],
+      ["Reconnects", status.reconnects],
+      ["Dropped", status.dropped],
+    ]);
+    document.getElementById("notes").textContent = status.held_notes.map(noteName).join(" ");
+    rows("counts", Object.entries(status.message_counts));
+  } catch (e) {
+    rows("status", [["State", "bridge unreachable"]]);
+  }
+}
+refresh();
+setInterval(refresh, 500);
+</script>
+</body>
+</html>
+"#;
+
+// What `/api/status` returns
+#[derive(Debug, Clone, Serialize)]
+pub struct DashboardStatus {
+    pub state: String,
+    pub uptime_secs: Option<f64>,
+    pub reconnects: u32,
+    pub device: Option<DeviceInfo>,
+    // Note numbers sent for the keys held down
+    pub held_notes: Vec<u8>,
+    // Messages forwarded since the dashboard started, by type
+    pub message_counts: BTreeMap<&'static str, u64>,
+    // Messages per second over the last second, by category
+    pub message_rates: BTreeMap<String, f64>,
+    pub dropped: u64,
+}
+
+// Shared between the server and its background tasks
+struct Monitor {
+    bridge: Arc<BleMidiBridge>,
+    // The last device info read, and when; held while reading so requests share a read
+    device: tokio::sync::Mutex<Option<(Instant, Option<DeviceInfo>)>>,
+    counts: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl Monitor {
+    async fn device(&self) -> Option<DeviceInfo> {
+        let mut cached = self.device.lock().await;
+        match &*cached {
+            Some((read_at, info)) if read_at.elapsed() < DEVICE_INFO_MAX_AGE => info.clone(),
+            _ => {
+                let info = self.bridge.device_info().await.ok();
+                *cached = Some((Instant::now(), info.clone()));
+                info
+            }
+        }
+    }
+
+    async fn status(&self) -> DashboardStatus {
+        let device = self.device().await;
+        let bridge = &self.bridge;
+        DashboardStatus {
+            state: format!("{:?}", bridge.state()),
+            uptime_secs: bridge.uptime().map(|uptime| uptime.as_secs_f64()),
+            reconnects: bridge.reconnect_count(),
+            device,
+            held_notes: bridge.held_notes().iter().map(|held| held.note).collect(),
+            message_counts: self.counts.lock().unwrap().clone(),
+            message_rates: bridge.message_rates().into_iter().map(|(category, rate)| (format!("{:?}", category), rate)).collect(),
+            dropped: bridge.drop_stats().total(),
+        }
+    }
+}
+
+// Status page for a browser, e.g. on a phone: connection state, signal, battery,
+// held notes and message counters, polled from a JSON endpoint at `/api/status`.
+// Built with the `dashboard` feature.
+pub struct Dashboard {
+    local_addr: SocketAddr,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Dashboard {
+    pub async fn bind(addr: SocketAddr, bridge: Arc<BleMidiBridge>) -> Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        info!("Dashboard at http://{}", local_addr);
+
+        let mut messages = bridge.message_stream();
+        let monitor = Arc::new(Monitor { bridge, device: tokio::sync::Mutex::new(None), counts: Mutex::new(BTreeMap::new()) });
+
+        let counter = monitor.clone();
+        let count_task = tokio::spawn(async move {
+            loop {
+                match messages.recv().await {
+                    Ok(message) => *counter.counts.lock().unwrap().entry(message.message_type()).or_default() += 1,
+                    Err(RecvError::Lagged(missed)) => *counter.counts.lock().unwrap().entry("Missed by dashboard").or_default() += missed,
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+
+        let accept_task = tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, peer)) => {
+                        let monitor = monitor.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = serve_request(stream, &monitor).await {
+                                debug!("Dashboard request from {} failed: {}", peer, e);
+                            }
+                        });
+                    }
+                    Err(e) => warn!("Dashboard accept failed: {}", e),
+                }
+            }
+        });
+
+        Ok(Dashboard { local_addr, tasks: vec![count_task, accept_task] })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+// Answer one request, then close the connection
+async fn serve_request(mut stream: TcpStream, monitor: &Monitor) -> Result<()> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 || request.len() + read > MAX_REQUEST_BYTES {
+            return Ok(());
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+
+    let head = String::from_utf8_lossy(&request);
+    let mut request_line = head.lines().next().unwrap_or_default().split_whitespace();
+    let response = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some("/")) => response("200 OK", "text/html; charset=utf-8", PAGE),
+        (Some("GET"), Some("/api/status")) => {
+            response("200 OK", "application/json", &serde_json::to_string(&monitor.status().await)?)
+        }
+        (Some("GET"), _) => response("404 Not Found", "text/plain", "Not found"),
+        _ => response("405 Method Not Allowed", "text/plain", "Method not allowed"),
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+fn response(status: &str, content_type: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ble::testing::{FakeConnector, FakeLink};
+    use crate::midi::testing::RecordingSink;
+    use crate::midi::SinkSet;
+    use crate::Config;
+
+    // Send a GET request and return the status line and body
+    async fn get(addr: SocketAddr, path: &str) -> (String, String) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(format!("GET {} HTTP/1.1\r\nHost: blip\r\n\r\n", path).as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.lines().next().unwrap().to_string(), body.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_status_endpoint() {
+        let link = Arc::new(FakeLink::new());
+        let mut sinks = SinkSet::new();
+        sinks.add("output", Box::new(RecordingSink::new()));
+        let bridge = Arc::new(BleMidiBridge::from_parts(&Config::default(), Box::new(FakeConnector::new([link.clone()])), sinks));
+        let dashboard = Dashboard::bind("127.0.0.1:0".parse().unwrap(), bridge.clone()).await.unwrap();
+        tokio::spawn({
+            let bridge = bridge.clone();
+            async move { bridge.start().await }
+        });
+
+        link.send_packet(&[0x80, 0x80, 0x90, 60, 100]);
+        let status = loop {
+            let (status_line, body) = get(dashboard.local_addr(), "/api/status").await;
+            assert_eq!(status_line, "HTTP/1.1 200 OK");
+            let status: serde_json::Value = serde_json::from_str(&body).unwrap();
+            if status["held_notes"] == serde_json::json!([60]) && !status["device"].is_null() {
+                break status;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        assert_eq!(status["state"], "Forwarding");
+        assert_eq!(status["message_counts"]["Note On"], 1);
+        assert_eq!(status["device"]["address"], "00:00:00:00:00:00");
+        assert_eq!(status["device"]["rssi"], -50);
+        assert_eq!(status["reconnects"], 0);
+        // Polling again right away reuses the device info instead of reading it
+        link.set_rssi(Some(-70));
+        let (_, body) = get(dashboard.local_addr(), "/api/status").await;
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&body).unwrap()["device"]["rssi"], -50);
+
+        let (status_line, page) = get(dashboard.local_addr(), "/").await;
+        assert_eq!(status_line, "HTTP/1.1 200 OK");
+        assert!(page.contains("/api/status"));
+        // Values such as the advertised device name are never parsed as HTML
+        assert!(!page.contains("innerHTML"));
+        assert_eq!(get(dashboard.local_addr(), "/missing").await.0, "HTTP/1.1 404 Not Found");
+    }
+}
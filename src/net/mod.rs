@@ -0,0 +1,64 @@
+use log::warn;
+use std::net::SocketAddr;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+
+#[cfg(feature = "dashboard")]
+mod dashboard;
+mod pipe;
+mod tcp;
+mod websocket;
+
+#[cfg(feature = "dashboard")]
+pub use dashboard::{Dashboard, DashboardStatus};
+pub use pipe::NamedPipeSink;
+pub use tcp::{encode_frame, TcpMidiSink};
+pub use websocket::{encode_json, WebSocketMidiSink};
+
+// Items queued per client before it is considered too slow and disconnected
+const CLIENT_BUFFER_FRAMES: usize = 256;
+
+// A connected stream client, fed through a bounded queue drained by its writer task
+struct Client<T> {
+    peer: SocketAddr,
+    sender: mpsc::Sender<T>,
+}
+
+// Queue an item for every client, dropping the ones that are gone or can't keep up
+fn broadcast<T: Clone>(clients: &mut Vec<Client<T>>, item: T) {
+    clients.retain(|client| match client.sender.try_send(item.clone()) {
+        Ok(()) => true,
+        Err(TrySendError::Full(_)) => {
+            warn!("MIDI stream client {} is too slow, disconnecting it", client.peer);
+            false
+        }
+        Err(TrySendError::Closed(_)) => false,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_slow_client_is_dropped() {
+        // A client whose queue is never drained
+        let (sender, _receiver) = mpsc::channel(1);
+        let mut clients = vec![Client { peer: "127.0.0.1:1".parse().unwrap(), sender }];
+
+        broadcast(&mut clients, vec![0xF8]);
+        assert_eq!(clients.len(), 1);
+        broadcast(&mut clients, vec![0xF8]);
+        assert!(clients.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_disconnected_client_is_removed() {
+        let (sender, receiver) = mpsc::channel(8);
+        let mut clients = vec![Client { peer: "127.0.0.1:1".parse().unwrap(), sender }];
+        drop(receiver);
+
+        broadcast(&mut clients, vec![0xF8]);
+        assert!(clients.is_empty());
+    }
+}
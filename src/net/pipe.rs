@@ -0,0 +1,191 @@
+// Local IPC for companion apps on the same machine: every forwarded message is written
+// as raw MIDI bytes to a Windows named pipe such as `\\.\pipe\blip`. SysEx can't be
+// carried by a `MidiMessage`, so messages are never longer than three bytes.
+use anyhow::Result;
+
+use crate::midi::{MidiMessage, MidiSink};
+
+// Serves one pipe client at a time. A client that disconnects can connect again; while
+// none is connected, messages are dropped.
+pub struct NamedPipeSink {
+    inner: imp::Server,
+}
+
+impl NamedPipeSink {
+    pub fn create(name: &str) -> Result<Self> {
+        Ok(NamedPipeSink { inner: imp::Server::create(name)? })
+    }
+
+    pub fn is_client_connected(&self) -> bool {
+        self.inner.is_client_connected()
+    }
+}
+
+impl MidiSink for NamedPipeSink {
+    fn send_message(&self, message: &MidiMessage) -> Result<()> {
+        self.inner.send(message.to_bytes());
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use anyhow::{anyhow, Result};
+    use log::{info, warn};
+    use std::sync::{Arc, Mutex};
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::windows::named_pipe::ServerOptions;
+    use tokio::sync::mpsc;
+    use tokio::sync::mpsc::error::TrySendError;
+    use tokio::task::JoinHandle;
+
+    use crate::net::CLIENT_BUFFER_FRAMES;
+
+    pub struct Server {
+        // Queue of the connected client, drained by the server task
+        client: Arc<Mutex<Option<mpsc::Sender<Vec<u8>>>>>,
+        server_task: JoinHandle<()>,
+    }
+
+    impl Server {
+        pub fn create(name: &str) -> Result<Self> {
+            // Fails if another program already serves the pipe
+            let mut pipe = ServerOptions::new()
+                .first_pipe_instance(true)
+                .create(name)
+                .map_err(|e| anyhow!("Failed to create named pipe '{}': {}", name, e))?;
+            info!("Serving MIDI on named pipe {}", name);
+
+            let client = Arc::new(Mutex::new(None));
+            let task_client = client.clone();
+            let name = name.to_string();
+            let server_task = tokio::spawn(async move {
+                loop {
+                    if let Err(e) = pipe.connect().await {
+                        warn!("Named pipe {} connect failed: {}", name, e);
+                    } else {
+                        info!("Named pipe client connected to {}", name);
+                        let (sender, mut receiver) = mpsc::channel::<Vec<u8>>(CLIENT_BUFFER_FRAMES);
+                        *task_client.lock().unwrap() = Some(sender);
+                        while let Some(bytes) = receiver.recv().await {
+                            if let Err(e) = pipe.write_all(&bytes).await {
+                                info!("Named pipe client disconnected from {}: {}", name, e);
+                                break;
+                            }
+                        }
+                        *task_client.lock().unwrap() = None;
+                    }
+
+                    // A fresh instance for the next client
+                    pipe = match ServerOptions::new().create(&name) {
+                        Ok(pipe) => pipe,
+                        Err(e) => {
+                            warn!("Named pipe {} can't take new clients: {}", name, e);
+                            return;
+                        }
+                    };
+                }
+            });
+
+            Ok(Server { client, server_task })
+        }
+
+        pub fn is_client_connected(&self) -> bool {
+            self.client.lock().unwrap().is_some()
+        }
+
+        pub fn send(&self, bytes: Vec<u8>) {
+            let mut client = self.client.lock().unwrap();
+            let Some(sender) = client.as_ref() else {
+                return;
+            };
+            match sender.try_send(bytes) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    warn!("Named pipe client is too slow, disconnecting it");
+                    *client = None;
+                }
+                Err(TrySendError::Closed(_)) => *client = None,
+            }
+        }
+    }
+
+    impl Drop for Server {
+        fn drop(&mut self) {
+            self.server_task.abort();
+        }
+    }
+}
+
+#[cfg(not(windows))]
+mod imp {
+    use anyhow::{anyhow, Result};
+
+    pub struct Server;
+
+    impl Server {
+        pub fn create(_name: &str) -> Result<Self> {
+            Err(anyhow!("Named pipes are only available on Windows"))
+        }
+
+        pub fn is_client_connected(&self) -> bool {
+            false
+        }
+
+        pub fn send(&self, _bytes: Vec<u8>) {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(not(windows))]
+    #[test]
+    fn test_unsupported_outside_windows() {
+        assert!(NamedPipeSink::create(r"\\.\pipe\blip-test").is_err());
+    }
+
+    #[cfg(windows)]
+    #[tokio::test]
+    async fn test_client_receives_messages_and_can_reconnect() {
+        use std::time::Duration;
+        use tokio::io::AsyncReadExt;
+        use tokio::net::windows::named_pipe::ClientOptions;
+
+        const NAME: &str = r"\\.\pipe\blip-test";
+
+        async fn wait_for_client(sink: &NamedPipeSink, connected: bool) {
+            for _ in 0..100 {
+                if sink.is_client_connected() == connected {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            panic!("client connected should be {}", connected);
+        }
+
+        let sink = NamedPipeSink::create(NAME).unwrap();
+        for _ in 0..2 {
+            // The next pipe instance appears shortly after the previous client left
+            let mut client = loop {
+                match ClientOptions::new().open(NAME) {
+                    Ok(client) => break client,
+                    Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+                }
+            };
+            wait_for_client(&sink, true).await;
+
+            sink.send_message(&MidiMessage { status: 0x90, data1: 60, data2: 100 }).unwrap();
+            sink.send_message(&MidiMessage { status: 0xC0, data1: 7, data2: 0 }).unwrap();
+            let mut bytes = [0u8; 5];
+            client.read_exact(&mut bytes).await.unwrap();
+            assert_eq!(bytes, [0x90, 60, 100, 0xC0, 7]);
+
+            // The server notices the client leaving at its next write
+            drop(client);
+            sink.send_message(&MidiMessage { status: 0xF8, data1: 0, data2: 0 }).unwrap();
+            wait_for_client(&sink, false).await;
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use anyhow::Result;
+use log::{info, warn};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use super::{broadcast, Client, CLIENT_BUFFER_FRAMES};
+use crate::midi::{MidiMessage, MidiSink};
+
+// Each message is sent as a 2-byte big-endian length followed by the raw MIDI bytes
+pub fn encode_frame(bytes: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(2 + bytes.len());
+    frame.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    frame.extend_from_slice(bytes);
+    frame
+}
+
+// Streams every forwarded message to any number of TCP clients.
+//
+// Each client has its own bounded send queue drained by a writer task, so a slow or
+// stalled client never blocks the bridge: once its queue is full it is dropped.
+pub struct TcpMidiSink {
+    local_addr: SocketAddr,
+    clients: Arc<Mutex<Vec<Client<Vec<u8>>>>>,
+    accept_task: JoinHandle<()>,
+}
+
+impl TcpMidiSink {
+    pub async fn bind(addr: SocketAddr) -> Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        info!("TCP MIDI server listening on {}", local_addr);
+
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let accept_clients = clients.clone();
+        let accept_task = tokio::spawn(async move {
+            loop {
+                let (mut stream, peer) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!("TCP MIDI accept failed: {}", e);
+                        continue;
+                    }
+                };
+                info!("TCP MIDI client connected: {}", peer);
+                let _ = stream.set_nodelay(true);
+
+                let (sender, mut receiver) = mpsc::channel::<Vec<u8>>(CLIENT_BUFFER_FRAMES);
+                tokio::spawn(async move {
+                    while let Some(frame) = receiver.recv().await {
+                        if let Err(e) = stream.write_all(&frame).await {
+                            info!("TCP MIDI client {} disconnected: {}", peer, e);
+                            break;
+                        }
+                    }
+                });
+
+                accept_clients.lock().unwrap().push(Client { peer, sender });
+            }
+        });
+
+        Ok(TcpMidiSink { local_addr, clients, accept_task })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+}
+
+impl MidiSink for TcpMidiSink {
+    fn send_message(&self, message: &MidiMessage) -> Result<()> {
+        let frame = encode_frame(&message.to_bytes());
+        broadcast(&mut self.clients.lock().unwrap(), frame);
+        Ok(())
+    }
+}
+
+impl Drop for TcpMidiSink {
+    fn drop(&mut self) {
+        self.accept_task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpStream;
+
+    async fn wait_for_clients(sink: &TcpMidiSink, count: usize) {
+        for _ in 0..100 {
+            if sink.client_count() == count {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("expected {} clients, have {}", count, sink.client_count());
+    }
+
+    async fn read_frame(stream: &mut TcpStream) -> Vec<u8> {
+        let mut len = [0u8; 2];
+        stream.read_exact(&mut len).await.unwrap();
+        let mut bytes = vec![0u8; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut bytes).await.unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_encode_frame() {
+        assert_eq!(encode_frame(&[0x90, 0x3C, 0x7F]), vec![0x00, 0x03, 0x90, 0x3C, 0x7F]);
+        assert_eq!(encode_frame(&[0xF8]), vec![0x00, 0x01, 0xF8]);
+    }
+
+    #[tokio::test]
+    async fn test_clients_receive_messages() {
+        let sink = TcpMidiSink::bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let mut first = TcpStream::connect(sink.local_addr()).await.unwrap();
+        let mut second = TcpStream::connect(sink.local_addr()).await.unwrap();
+        wait_for_clients(&sink, 2).await;
+
+        sink.send_message(&MidiMessage { status: 0x90, data1: 60, data2: 100 }).unwrap();
+        sink.send_message(&MidiMessage { status: 0xC0, data1: 7, data2: 0 }).unwrap();
+
+        for stream in [&mut first, &mut second] {
+            assert_eq!(read_frame(stream).await, vec![0x90, 60, 100]);
+            assert_eq!(read_frame(stream).await, vec![0xC0, 7]);
+        }
+    }
+}
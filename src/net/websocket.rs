@@ -0,0 +1,161 @@
+use anyhow::Result;
+use futures::{SinkExt, StreamExt};
+use log::{info, warn};
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
+
+use super::{broadcast, Client, CLIENT_BUFFER_FRAMES};
+use crate::midi::{MidiMessage, MidiSink};
+
+// JSON form of a message sent to browser clients:
+// {"type":"Note On","channel":0,"bytes":[144,60,100]}
+#[derive(Serialize)]
+struct JsonMessage {
+    #[serde(rename = "type")]
+    message_type: &'static str,
+    channel: Option<u8>,
+    bytes: Vec<u8>,
+}
+
+pub fn encode_json(message: &MidiMessage) -> String {
+    let json = JsonMessage {
+        message_type: message.message_type(),
+        channel: message.channel(),
+        bytes: message.to_bytes(),
+    };
+    serde_json::to_string(&json).expect("MIDI message JSON is always serializable")
+}
+
+type Clients = Arc<Mutex<Vec<Client<String>>>>;
+
+// Pushes every forwarded message as a JSON text frame to WebSocket clients, e.g.
+// browser-based visualizers. Slow clients are dropped like in `TcpMidiSink`.
+pub struct WebSocketMidiSink {
+    local_addr: SocketAddr,
+    clients: Clients,
+    accept_task: JoinHandle<()>,
+}
+
+impl WebSocketMidiSink {
+    pub async fn bind(addr: SocketAddr) -> Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        info!("WebSocket MIDI server listening on ws://{}", local_addr);
+
+        let clients: Clients = Arc::new(Mutex::new(Vec::new()));
+        let accept_clients = clients.clone();
+        let accept_task = tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, peer)) => {
+                        tokio::spawn(serve_client(stream, peer, accept_clients.clone()));
+                    }
+                    Err(e) => warn!("WebSocket accept failed: {}", e),
+                }
+            }
+        });
+
+        Ok(WebSocketMidiSink { local_addr, clients, accept_task })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+}
+
+// Complete the handshake, register the client, then forward its queue until it goes away
+async fn serve_client(stream: TcpStream, peer: SocketAddr, clients: Clients) {
+    let websocket = match tokio_tungstenite::accept_async(stream).await {
+        Ok(websocket) => websocket,
+        Err(e) => {
+            warn!("WebSocket handshake with {} failed: {}", peer, e);
+            return;
+        }
+    };
+    info!("WebSocket client connected: {}", peer);
+
+    let (sender, mut receiver) = mpsc::channel::<String>(CLIENT_BUFFER_FRAMES);
+    clients.lock().unwrap().push(Client { peer, sender });
+
+    let (mut outgoing, mut incoming) = websocket.split();
+    loop {
+        tokio::select! {
+            json = receiver.recv() => {
+                // None: dropped from the client list for being too slow
+                let Some(json) = json else { break };
+                if outgoing.send(Message::text(json)).await.is_err() {
+                    break;
+                }
+            }
+            // Reading also answers pings; anything the client sends is ignored
+            frame = incoming.next() => {
+                if !matches!(frame, Some(Ok(_))) {
+                    break;
+                }
+            }
+        }
+    }
+    info!("WebSocket client disconnected: {}", peer);
+}
+
+impl MidiSink for WebSocketMidiSink {
+    fn send_message(&self, message: &MidiMessage) -> Result<()> {
+        broadcast(&mut self.clients.lock().unwrap(), encode_json(message));
+        Ok(())
+    }
+}
+
+impl Drop for WebSocketMidiSink {
+    fn drop(&mut self) {
+        self.accept_task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_encode_json() {
+        assert_eq!(
+            encode_json(&MidiMessage { status: 0x91, data1: 60, data2: 100 }),
+            r#"{"type":"Note On","channel":1,"bytes":[145,60,100]}"#
+        );
+        assert_eq!(
+            encode_json(&MidiMessage { status: 0xF8, data1: 0, data2: 0 }),
+            r#"{"type":"Timing Clock","channel":null,"bytes":[248]}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_receives_note_event() {
+        let sink = WebSocketMidiSink::bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let url = format!("ws://{}", sink.local_addr());
+        let (mut client, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+
+        for _ in 0..100 {
+            if sink.client_count() == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(sink.client_count(), 1);
+
+        sink.send_message(&MidiMessage { status: 0x90, data1: 64, data2: 90 }).unwrap();
+
+        let frame = client.next().await.unwrap().unwrap();
+        let json: serde_json::Value = serde_json::from_str(frame.to_text().unwrap()).unwrap();
+        assert_eq!(json["type"], "Note On");
+        assert_eq!(json["bytes"], serde_json::json!([0x90, 64, 90]));
+    }
+}
@@ -0,0 +1,194 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::midi::MidiMessage;
+use crate::util::XorShift32;
+
+// Order the arpeggiator plays the held notes in, lowest to highest pitch being "up"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArpPattern {
+    Up,
+    Down,
+    // Up then back down, without repeating the top and bottom notes
+    UpDown,
+    Random,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct ArpConfig {
+    // Time from one note to the next
+    #[serde(with = "crate::util::duration_secs")]
+    pub rate: Duration,
+    pub pattern: ArpPattern,
+    // Fraction of each step (0-1] the note sounds for; 1.0 plays legato
+    pub gate: f32,
+}
+
+// A held note the arpeggiator can play, at the pitch and velocity to send
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ArpNote {
+    pub note: u8,
+    pub channel: u8,
+    pub velocity: u8,
+}
+
+// Plays the held notes one at a time instead of together. It is driven like the ghost
+// note filter: `next_event` says when it next has something to send and `run` sends it.
+#[derive(Debug)]
+pub struct Arpeggiator {
+    config: ArpConfig,
+    // The note of the previous step; the patterns move on from it, so notes added or
+    // released mid-pattern fit in
+    last: Option<ArpNote>,
+    // Direction of an UpDown pattern
+    descending: bool,
+    // When the next note starts; None while stopped
+    next_step: Option<Instant>,
+    // Note Off for the note sounding now, and when it is due
+    sounding: Option<(MidiMessage, Instant)>,
+    random: XorShift32,
+}
+
+impl Arpeggiator {
+    pub fn new(config: ArpConfig) -> Self {
+        Arpeggiator { config, last: None, descending: false, next_step: None, sounding: None, random: XorShift32::new(0x2545_F491) }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.next_step.is_some()
+    }
+
+    // Begin with the first step at `now`, unless already running
+    pub fn start(&mut self, now: Instant) {
+        if self.next_step.is_none() {
+            self.last = None;
+            self.descending = false;
+            self.next_step = Some(now);
+        }
+    }
+
+    // Stop, returning the Note Off for the note still sounding, if any
+    pub fn stop(&mut self) -> Option<MidiMessage> {
+        self.next_step = None;
+        self.sounding.take().map(|(note_off, _)| note_off)
+    }
+
+    pub fn next_event(&self) -> Option<Instant> {
+        match (self.next_step, self.sounding.map(|(_, at)| at)) {
+            (Some(step), Some(off)) => Some(step.min(off)),
+            (step, off) => step.or(off),
+        }
+    }
+
+    // Messages due by `now`, playing from `pool` (sorted by pitch). Stops once the pool
+    // is empty at a step.
+    pub fn run(&mut self, now: Instant, pool: &[ArpNote]) -> Vec<MidiMessage> {
+        let mut messages = Vec::new();
+        if let Some((note_off, at)) = self.sounding {
+            if at <= now {
+                messages.push(note_off);
+                self.sounding = None;
+            }
+        }
+        let Some(step) = self.next_step.filter(|&step| step <= now) else {
+            return messages;
+        };
+        if pool.is_empty() {
+            messages.extend(self.stop());
+            return messages;
+        }
+
+        // With a gate of 1.0 the previous note ends exactly as this one starts
+        if let Some((note_off, _)) = self.sounding.take() {
+            messages.push(note_off);
+        }
+        let next = self.next_note(pool);
+        self.last = Some(next);
+        messages.push(MidiMessage { status: 0x90 | next.channel, data1: next.note, data2: next.velocity });
+        let note_off = MidiMessage { status: 0x80 | next.channel, data1: next.note, data2: 0 };
+        self.sounding = Some((note_off, step + self.config.rate.mul_f32(self.config.gate)));
+        // Keep to the grid, unless we've fallen behind it by more than a step
+        let following = step + self.config.rate;
+        self.next_step = Some(if following <= now { now + self.config.rate } else { following });
+        messages
+    }
+
+    // `pool` must not be empty
+    fn next_note(&mut self, pool: &[ArpNote]) -> ArpNote {
+        let above = |last: Option<ArpNote>| pool.iter().copied().find(|note| last.is_none_or(|last| *note > last));
+        let below = |last: Option<ArpNote>| pool.iter().rev().copied().find(|note| last.is_none_or(|last| *note < last));
+        match self.config.pattern {
+            ArpPattern::Up => above(self.last).unwrap_or(pool[0]),
+            ArpPattern::Down => below(self.last).unwrap_or(pool[pool.len() - 1]),
+            ArpPattern::UpDown => {
+                let next = if self.descending { below(self.last) } else { above(self.last) };
+                next.unwrap_or_else(|| {
+                    // Turn around at the end, without playing the end note twice
+                    self.descending = !self.descending;
+                    let turned = if self.descending { below(self.last) } else { above(self.last) };
+                    turned.unwrap_or(pool[0])
+                })
+            }
+            ArpPattern::Random => pool[self.random.next_u32() as usize % pool.len()],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(notes: &[u8]) -> Vec<ArpNote> {
+        notes.iter().map(|&note| ArpNote { note, channel: 0, velocity: 100 }).collect()
+    }
+
+    // Pitches of the Note Ons over `steps` steps
+    fn play(pattern: ArpPattern, notes: &[u8], steps: u32) -> Vec<u8> {
+        let rate = Duration::from_millis(100);
+        let mut arp = Arpeggiator::new(ArpConfig { rate, pattern, gate: 1.0 });
+        let start = Instant::now();
+        arp.start(start);
+        (0..steps)
+            .flat_map(|i| arp.run(start + rate * i, &pool(notes)))
+            .filter(|message| message.message_type() == "Note On")
+            .map(|message| message.data1)
+            .collect()
+    }
+
+    #[test]
+    fn test_patterns() {
+        assert_eq!(play(ArpPattern::Up, &[60, 64, 67], 5), vec![60, 64, 67, 60, 64]);
+        assert_eq!(play(ArpPattern::Down, &[60, 64, 67], 4), vec![67, 64, 60, 67]);
+        assert_eq!(play(ArpPattern::UpDown, &[60, 64, 67], 6), vec![60, 64, 67, 64, 60, 64]);
+        assert_eq!(play(ArpPattern::UpDown, &[60], 2), vec![60, 60]);
+        assert!(play(ArpPattern::Random, &[60, 64, 67], 20).iter().all(|note| [60, 64, 67].contains(note)));
+    }
+
+    #[test]
+    fn test_gate_ends_notes_early() {
+        let rate = Duration::from_millis(100);
+        let mut arp = Arpeggiator::new(ArpConfig { rate, pattern: ArpPattern::Up, gate: 0.25 });
+        let start = Instant::now();
+        arp.start(start);
+
+        assert_eq!(arp.run(start, &pool(&[60])), vec![MidiMessage { status: 0x90, data1: 60, data2: 100 }]);
+        assert_eq!(arp.next_event(), Some(start + Duration::from_millis(25)));
+        assert_eq!(arp.run(start + Duration::from_millis(25), &pool(&[60])), vec![MidiMessage { status: 0x80, data1: 60, data2: 0 }]);
+        assert_eq!(arp.next_event(), Some(start + rate));
+    }
+
+    #[test]
+    fn test_stops_when_nothing_is_held() {
+        let rate = Duration::from_millis(100);
+        let mut arp = Arpeggiator::new(ArpConfig { rate, pattern: ArpPattern::Up, gate: 1.0 });
+        let start = Instant::now();
+        arp.start(start);
+        arp.run(start, &pool(&[60]));
+
+        assert_eq!(arp.run(start + rate, &[]), vec![MidiMessage { status: 0x80, data1: 60, data2: 0 }]);
+        assert!(!arp.is_running());
+        assert_eq!(arp.next_event(), None);
+    }
+}
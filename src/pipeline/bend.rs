@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+
+use crate::midi::MidiMessage;
+
+const BEND_CENTER: u16 = 8192;
+const BEND_MAX: u16 = 16383;
+// Furthest a scaled bend may move from centre, either way
+const BEND_SCALED_RANGE: i32 = 8191;
+
+// How a 7-bit CC value maps onto the 14-bit pitch bend range
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BendScaling {
+    // 0 bends fully down, 64 is centre (no bend), 127 bends fully up. Suits a
+    // centre-detented knob.
+    Bipolar,
+    // 0 is centre (no bend) and 127 bends fully up, so the CC only ever bends
+    // upwards. Suits an expression pedal or a knob without a detent.
+    Unipolar,
+}
+
+// The 14-bit pitch bend value for a CC value. Both halves of the bipolar range are
+// scaled separately so that 0, 64 and 127 land exactly on the bottom, centre and top.
+pub fn cc_to_bend_value(value: u8, scaling: BendScaling) -> u16 {
+    let value = value.min(127) as u32;
+    let bend = match scaling {
+        BendScaling::Bipolar if value <= 64 => value * BEND_CENTER as u32 / 64,
+        BendScaling::Bipolar => BEND_CENTER as u32 + (value - 64) * (BEND_MAX - BEND_CENTER) as u32 / 63,
+        BendScaling::Unipolar => BEND_CENTER as u32 + value * (BEND_MAX - BEND_CENTER) as u32 / 127,
+    };
+    bend as u16
+}
+
+// Pitch bend message on the channel of `cc` replacing it
+pub fn cc_to_pitch_bend(cc: &MidiMessage, scaling: BendScaling) -> MidiMessage {
+    let bend = cc_to_bend_value(cc.data2, scaling);
+    MidiMessage { status: 0xE0 | (cc.status & 0x0F), data1: (bend & 0x7F) as u8, data2: (bend >> 7) as u8 }
+}
+
+// Pitch bend with its distance from centre multiplied by `scale`, rounded to the
+// nearest step and clamped to ±8191 so up and down reach equally far. Centre stays
+// exactly centre whatever the scale.
+pub fn scale_pitch_bend(bend: &MidiMessage, scale: f32) -> MidiMessage {
+    let value = ((bend.data2 as u16 & 0x7F) << 7) | (bend.data1 as u16 & 0x7F);
+    let offset = value as i32 - BEND_CENTER as i32;
+    let scaled = (offset as f32 * scale).round() as i32;
+    let value = (BEND_CENTER as i32 + scaled.clamp(-BEND_SCALED_RANGE, BEND_SCALED_RANGE)) as u16;
+    MidiMessage { data1: (value & 0x7F) as u8, data2: (value >> 7) as u8, ..*bend }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bipolar_scaling() {
+        assert_eq!(cc_to_bend_value(0, BendScaling::Bipolar), 0);
+        assert_eq!(cc_to_bend_value(64, BendScaling::Bipolar), 8192);
+        assert_eq!(cc_to_bend_value(127, BendScaling::Bipolar), 16383);
+    }
+
+    #[test]
+    fn test_unipolar_scaling() {
+        assert_eq!(cc_to_bend_value(0, BendScaling::Unipolar), 8192);
+        assert_eq!(cc_to_bend_value(64, BendScaling::Unipolar), 12319);
+        assert_eq!(cc_to_bend_value(127, BendScaling::Unipolar), 16383);
+    }
+
+    fn bend(value: u16) -> MidiMessage {
+        MidiMessage { status: 0xE3, data1: (value & 0x7F) as u8, data2: (value >> 7) as u8 }
+    }
+
+    #[test]
+    fn test_scale_pitch_bend_by_half() {
+        for (value, scaled) in [(0, 4096), (4096, 6144), (8191, 8191), (8192, 8192), (8193, 8193), (12288, 10240), (16383, 12288)] {
+            assert_eq!(scale_pitch_bend(&bend(value), 0.5), bend(scaled), "bend {}", value);
+        }
+    }
+
+    #[test]
+    fn test_scale_pitch_bend_doubled() {
+        for (value, scaled) in [(0, 1), (2048, 1), (4096, 1), (6144, 4096), (8192, 8192), (10240, 12288), (12287, 16382), (12288, 16383), (16383, 16383)] {
+            assert_eq!(scale_pitch_bend(&bend(value), 2.0), bend(scaled), "bend {}", value);
+        }
+    }
+
+    #[test]
+    fn test_pitch_bend_message() {
+        let cc = MidiMessage::control_change(5, 11, 127);
+        assert_eq!(
+            cc_to_pitch_bend(&cc, BendScaling::Bipolar),
+            MidiMessage { status: 0xE5, data1: 0x7F, data2: 0x7F }
+        );
+        let cc = MidiMessage::control_change(0, 11, 64);
+        assert_eq!(
+            cc_to_pitch_bend(&cc, BendScaling::Bipolar),
+            MidiMessage { status: 0xE0, data1: 0x00, data2: 0x40 }
+        );
+    }
+}
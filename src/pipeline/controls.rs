@@ -0,0 +1,75 @@
+use serde::de::{self, MapAccess, Visitor};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+// What a controller of the device does instead of being forwarded as is
+// (`Config::control_map`). In a configuration file: `"toggle_mute"`, or `{ cc = 66 }`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlAction {
+    // Send it as this CC, which then goes through the usual CC handling
+    Cc(u8),
+    // Mute or unmute, like `Config::mute_toggle_cc`
+    ToggleMute,
+    // Shift the octave, like `Config::octave_shift_triggers`
+    OctaveUp,
+    OctaveDown,
+    // Swallow it
+    Ignore,
+}
+
+const NAMED_ACTIONS: [(&str, ControlAction); 4] = [
+    ("toggle_mute", ControlAction::ToggleMute),
+    ("octave_up", ControlAction::OctaveUp),
+    ("octave_down", ControlAction::OctaveDown),
+    ("ignore", ControlAction::Ignore),
+];
+
+// (De)serialized by hand: TOML has no form for enum variants with data that its writer
+// produces and its reader takes back, so `Cc` is a table with a single `cc` key
+impl Serialize for ControlAction {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let name = match *self {
+            ControlAction::Cc(cc) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("cc", &cc)?;
+                return map.end();
+            }
+            ControlAction::ToggleMute => "toggle_mute",
+            ControlAction::OctaveUp => "octave_up",
+            ControlAction::OctaveDown => "octave_down",
+            ControlAction::Ignore => "ignore",
+        };
+        serializer.serialize_str(name)
+    }
+}
+
+impl<'de> Deserialize<'de> for ControlAction {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ControlActionVisitor)
+    }
+}
+
+struct ControlActionVisitor;
+
+impl<'de> Visitor<'de> for ControlActionVisitor {
+    type Value = ControlAction;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("\"toggle_mute\", \"octave_up\", \"octave_down\", \"ignore\" or { cc = <number> }")
+    }
+
+    fn visit_str<E: de::Error>(self, name: &str) -> Result<ControlAction, E> {
+        match NAMED_ACTIONS.iter().find(|(known, _)| *known == name) {
+            Some((_, action)) => Ok(*action),
+            None => Err(E::unknown_variant(name, &["toggle_mute", "octave_up", "octave_down", "ignore", "cc"])),
+        }
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<ControlAction, A::Error> {
+        match (map.next_entry::<String, u8>()?, map.next_key::<String>()?) {
+            (Some((key, cc)), None) if key == "cc" => Ok(ControlAction::Cc(cc)),
+            _ => Err(de::Error::custom("a controller action table holds just `cc`")),
+        }
+    }
+}
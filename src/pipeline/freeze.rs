@@ -0,0 +1,14 @@
+use serde::{Deserialize, Serialize};
+
+// Sustained copies of the held notes, e.g. to hold a pad while playing on top of it
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FreezeConfig {
+    // MIDI channel (0-15) the frozen notes are sent on
+    pub channel: u8,
+    // Output the frozen notes go to; None sends them to every output. Outputs that
+    // aren't already configured are opened as MIDI ports with that name.
+    pub sink: Option<String>,
+    // CC number that freezes the held notes, or clears the frozen ones if there are
+    // any (value >= 64 triggers)
+    pub trigger_cc: Option<u8>,
+}
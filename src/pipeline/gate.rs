@@ -0,0 +1,40 @@
+use serde::{Deserialize, Serialize};
+
+use crate::midi::MidiMessage;
+
+// Key or controller that opens the momentary forwarding gate while held
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum GateTrigger {
+    // Open while this note is held
+    Note(u8),
+    // Open while this CC is at or above `threshold`, e.g. a sustain pedal
+    ControlChange { cc: u8, threshold: u8 },
+}
+
+impl GateTrigger {
+    // None if the message isn't the trigger, otherwise whether the gate should be open
+    pub fn check(&self, message: &MidiMessage) -> Option<bool> {
+        match (*self, message.message_type()) {
+            (GateTrigger::Note(note), "Note On") if message.data1 == note => Some(true),
+            (GateTrigger::Note(note), "Note Off") if message.data1 == note => Some(false),
+            (GateTrigger::ControlChange { cc, threshold }, "Control Change") if message.data1 == cc => {
+                Some(message.data2 >= threshold)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cc_threshold() {
+        let gate = GateTrigger::ControlChange { cc: 64, threshold: 100 };
+        assert_eq!(gate.check(&MidiMessage::control_change(0, 64, 127)), Some(true));
+        assert_eq!(gate.check(&MidiMessage::control_change(0, 64, 99)), Some(false));
+        assert_eq!(gate.check(&MidiMessage::control_change(0, 1, 127)), None);
+    }
+}
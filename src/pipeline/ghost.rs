@@ -0,0 +1,85 @@
+use std::time::Duration;
+use tokio::time::Instant;
+
+use super::Output;
+
+// A Note On held back until it has proven not to be a ghost note
+#[derive(Debug)]
+struct PendingNote {
+    channel: u8,
+    key: u8,
+    output: Output,
+    release_at: Instant,
+}
+
+// Suppresses ghost notes: a Note On followed by its Note Off within `min_duration`,
+// which some controllers emit as glitches and which click on the synth. Note Ons are
+// held back for `min_duration`; if the Note Off arrives in that time both are dropped.
+#[derive(Debug)]
+pub struct GhostNoteFilter {
+    min_duration: Duration,
+    pending: Vec<PendingNote>,
+}
+
+impl GhostNoteFilter {
+    pub fn new(min_duration: Duration) -> Self {
+        GhostNoteFilter { min_duration, pending: Vec::new() }
+    }
+
+    // Hold back the output of a Note On for `key`, received at `now`
+    pub fn hold(&mut self, channel: u8, key: u8, output: Output, now: Instant) {
+        self.pending.push(PendingNote { channel, key, output, release_at: now + self.min_duration });
+    }
+
+    pub fn is_holding(&self, channel: u8, key: u8) -> bool {
+        self.pending.iter().any(|p| p.channel == channel && p.key == key)
+    }
+
+    // Drop the held-back Note Ons for `key` (several when harmonized), if there are
+    // any. Returns whether there were, in which case its Note Off must be dropped too.
+    pub fn cancel(&mut self, channel: u8, key: u8) -> bool {
+        let pending = self.pending.len();
+        self.pending.retain(|p| !(p.channel == channel && p.key == key));
+        self.pending.len() < pending
+    }
+
+    // Note Ons held long enough to be sent by `now`, in the order they arrived
+    pub fn release_due(&mut self, now: Instant) -> Vec<Output> {
+        let (due, pending) = std::mem::take(&mut self.pending).into_iter().partition(|p| p.release_at <= now);
+        self.pending = pending;
+        due.into_iter().map(|p: PendingNote| p.output).collect()
+    }
+
+    // Drop every held-back Note On, returning their (channel, key)
+    pub fn clear(&mut self) -> Vec<(u8, u8)> {
+        self.pending.drain(..).map(|p| (p.channel, p.key)).collect()
+    }
+
+    // When the next held-back Note On is due
+    pub fn next_release(&self) -> Option<Instant> {
+        self.pending.iter().map(|p| p.release_at).min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::MidiMessage;
+
+    #[test]
+    fn test_release_order_and_deadline() {
+        let mut filter = GhostNoteFilter::new(Duration::from_millis(10));
+        let start = Instant::now();
+        let note = |key| Output::all(MidiMessage { status: 0x90, data1: key, data2: 100 });
+
+        filter.hold(0, 60, note(60), start);
+        filter.hold(0, 64, note(64), start + Duration::from_millis(5));
+        assert_eq!(filter.next_release(), Some(start + Duration::from_millis(10)));
+        assert!(filter.release_due(start + Duration::from_millis(9)).is_empty());
+
+        assert!(filter.cancel(0, 64));
+        assert!(!filter.cancel(0, 64));
+        assert_eq!(filter.release_due(start + Duration::from_millis(20)), vec![note(60)]);
+        assert_eq!(filter.next_release(), None);
+    }
+}
@@ -0,0 +1,43 @@
+use crate::util::XorShift32;
+
+// Jitters Note On velocities by up to `max_deviation` either way, for a less mechanical
+// feel (e.g. together with `Config::fixed_velocity`)
+#[derive(Debug)]
+pub struct VelocityHumanizer {
+    max_deviation: u8,
+    random: XorShift32,
+}
+
+impl VelocityHumanizer {
+    pub fn new(max_deviation: u8, random: XorShift32) -> Self {
+        VelocityHumanizer { max_deviation, random }
+    }
+
+    // Never 0, which would turn the Note On into a Note Off
+    pub fn apply(&mut self, velocity: u8) -> u8 {
+        let range = 2 * self.max_deviation as u32 + 1;
+        let deviation = (self.random.next_u32() % range) as i16 - self.max_deviation as i16;
+        (velocity as i16 + deviation).clamp(1, 127) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jitter_is_reproducible_and_bounded() {
+        let jitter = |velocity, seed| {
+            let mut humanizer = VelocityHumanizer::new(10, XorShift32::new(seed));
+            (0..200).map(|_| humanizer.apply(velocity)).collect::<Vec<_>>()
+        };
+        let velocities = jitter(100, 42);
+        assert_eq!(velocities, jitter(100, 42));
+        assert!(velocities.iter().all(|velocity| (90..=110).contains(velocity)));
+        // Some spread, not one value repeated
+        assert!(velocities.iter().min() < velocities.iter().max());
+
+        assert!(jitter(3, 42).iter().all(|&velocity| velocity >= 1));
+        assert!(jitter(125, 42).iter().all(|&velocity| velocity <= 127));
+    }
+}
@@ -0,0 +1,121 @@
+use std::time::Duration;
+use tokio::time::Instant;
+
+use super::Output;
+
+// Note Offs of one channel that the legato filter is holding on to
+#[derive(Debug, Default)]
+struct Phrase {
+    last_note_on: Option<Instant>,
+    // Note Offs for a release that may yet be followed by a Note On, and when they are due
+    pending: Vec<Output>,
+    pending_until: Option<Instant>,
+    // Note Offs of notes the phrase moved on from, sent when the phrase ends
+    trailing: Vec<Output>,
+}
+
+impl Phrase {
+    fn is_empty(&self) -> bool {
+        self.pending.is_empty() && self.trailing.is_empty()
+    }
+
+    fn end(&mut self) -> Vec<Output> {
+        self.pending_until = None;
+        let mut outputs = std::mem::take(&mut self.trailing);
+        outputs.append(&mut self.pending);
+        outputs
+    }
+}
+
+// Mono-legato helper: a change of note on a channel shouldn't retrigger a mono synth's
+// envelope. When a key is released within `window` of another Note On on its channel,
+// or a Note On follows a release within `window`, the Note Off trails the phrase
+// instead and is only sent once the channel's last key is released. A Note Off with
+// nothing held on its channel is therefore sent `window` late.
+#[derive(Debug)]
+pub struct LegatoFilter {
+    window: Duration,
+    phrases: [Phrase; 16],
+}
+
+impl LegatoFilter {
+    pub fn new(window: Duration) -> Self {
+        LegatoFilter { window, phrases: Default::default() }
+    }
+
+    // A Note On on `channel` at `now`, continuing the phrase of a recent release
+    pub fn note_on(&mut self, channel: u8, now: Instant) {
+        let phrase = &mut self.phrases[channel as usize & 0x0F];
+        phrase.last_note_on = Some(now);
+        phrase.pending_until = None;
+        let mut pending = std::mem::take(&mut phrase.pending);
+        phrase.trailing.append(&mut pending);
+    }
+
+    // The outputs of a Note Off on `channel` at `now`, `others_held` telling whether
+    // other keys are still held there. Returns what to send now.
+    pub fn note_off(&mut self, channel: u8, outputs: Vec<Output>, others_held: bool, now: Instant) -> Vec<Output> {
+        let window = self.window;
+        let phrase = &mut self.phrases[channel as usize & 0x0F];
+        if !others_held {
+            phrase.pending.extend(outputs);
+            phrase.pending_until = Some(now + window);
+            return Vec::new();
+        }
+        if phrase.last_note_on.is_some_and(|last| now.duration_since(last) <= window) {
+            phrase.trailing.extend(outputs);
+            return Vec::new();
+        }
+        outputs
+    }
+
+    // Note Offs of the phrases that ended by `now`
+    pub fn release_due(&mut self, now: Instant) -> Vec<Output> {
+        self.phrases
+            .iter_mut()
+            .filter(|phrase| phrase.pending_until.is_some_and(|until| until <= now))
+            .flat_map(Phrase::end)
+            .collect()
+    }
+
+    // When the next phrase ends
+    pub fn next_release(&self) -> Option<Instant> {
+        self.phrases.iter().filter_map(|phrase| phrase.pending_until).min()
+    }
+
+    // Every Note Off held on to, ending all phrases
+    pub fn flush(&mut self) -> Vec<Output> {
+        self.phrases.iter_mut().filter(|phrase| !phrase.is_empty()).flat_map(Phrase::end).collect()
+    }
+
+    // Forget the held Note Offs, for when the outputs are silenced anyway
+    pub fn clear(&mut self) {
+        self.phrases = Default::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::MidiMessage;
+
+    fn note_off(key: u8) -> Vec<Output> {
+        vec![Output::all(MidiMessage { status: 0x80, data1: key, data2: 0 })]
+    }
+
+    #[test]
+    fn test_phrase_ends_after_the_window() {
+        let mut legato = LegatoFilter::new(Duration::from_millis(30));
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        legato.note_on(0, start);
+        assert!(legato.note_off(0, note_off(60), false, at(100)).is_empty());
+        assert_eq!(legato.next_release(), Some(at(130)));
+        // A Note On on another channel doesn't continue the phrase
+        legato.note_on(1, at(110));
+        assert!(legato.release_due(at(129)).is_empty());
+        assert_eq!(legato.release_due(at(130)), note_off(60));
+        assert_eq!(legato.next_release(), None);
+    }
+}
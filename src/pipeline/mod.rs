@@ -0,0 +1,2056 @@
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::bridge::{Config, OFFSET_RANGE};
+use crate::midi::{MidiMessage, Route};
+use crate::util::{Clock, SystemClock, XorShift32};
+
+mod arp;
+mod bend;
+mod controls;
+mod freeze;
+mod gate;
+mod ghost;
+mod humanize;
+mod legato;
+mod normalize;
+mod notes;
+mod octave;
+mod rates;
+mod repeat;
+mod routing;
+mod scale;
+mod throttle;
+
+pub use arp::{ArpConfig, ArpNote, ArpPattern, Arpeggiator};
+pub use bend::{cc_to_bend_value, cc_to_pitch_bend, scale_pitch_bend, BendScaling};
+pub use controls::ControlAction;
+pub use freeze::FreezeConfig;
+pub use gate::GateTrigger;
+pub use ghost::GhostNoteFilter;
+pub use humanize::VelocityHumanizer;
+pub use legato::LegatoFilter;
+pub use normalize::{NormalizeConfig, VelocityNormalizer};
+pub use notes::{ActiveNotes, HeldNote};
+pub use octave::{pedal_octaves, OctaveShiftTriggers, Trigger};
+pub use rates::MessageRates;
+pub use repeat::{NoteRepeat, NoteRepeatConfig};
+pub use routing::{route_category, route_note, MessageCategory, Output, Split};
+pub use scale::{Scale, ScaleConfig};
+pub use throttle::NoteThrottle;
+
+// Window `Pipeline::message_rates` averages over
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+const SYSTEM_RESET: u8 = 0xFF;
+
+// Velocity of a Note On made from a release whose press wasn't seen
+const DEFAULT_VELOCITY: u8 = 64;
+
+// What happens to a transposed note that falls outside 0-127
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransposeBounds {
+    // Pin it to the nearest end of the range
+    Clamp,
+    // Move it back into range by whole octaves, keeping the pitch class
+    Wrap,
+}
+
+// How the bounds affected a transposed note
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransposeOutcome {
+    // The shifted note was in range and sent as is
+    Unchanged,
+    Clamped,
+    Wrapped,
+}
+
+// Shift a note number by a number of semitones, clamping to the valid MIDI range
+pub fn transpose(note: u8, semitones: i16) -> u8 {
+    transpose_within(note, semitones, TransposeBounds::Clamp).0
+}
+
+// Shift a note number by a number of semitones, handling out-of-range results per `bounds`
+pub fn transpose_within(note: u8, semitones: i16, bounds: TransposeBounds) -> (u8, TransposeOutcome) {
+    let shifted = note as i16 + semitones;
+    if (0..=127).contains(&shifted) {
+        return (shifted as u8, TransposeOutcome::Unchanged);
+    }
+
+    match bounds {
+        TransposeBounds::Clamp => (shifted.clamp(0, 127) as u8, TransposeOutcome::Clamped),
+        TransposeBounds::Wrap => {
+            let mut wrapped = shifted;
+            while wrapped > 127 {
+                wrapped -= 12;
+            }
+            while wrapped < 0 {
+                wrapped += 12;
+            }
+            (wrapped as u8, TransposeOutcome::Wrapped)
+        }
+    }
+}
+
+// Counts of Note On / Note Off pairing problems seen while `diagnose_note_pairing` is on
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PairingStats {
+    // Note Off for a key that wasn't held (its Note On was probably lost)
+    pub orphan_note_offs: u64,
+    // Note On for a key that was already held (its Note Off was probably lost)
+    pub retriggers: u64,
+}
+
+// Messages each filtering stage has dropped, to tell whether a filter is too aggressive.
+// Messages that control the bridge (mute toggle, octave shift and gate triggers) are
+// consumed rather than dropped and aren't counted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DropStats {
+    // Unknown statuses dropped by `Config::unknown_message_policy`
+    pub unknown_status: u64,
+    // System messages dropped by `Config::channel_voice_only`
+    pub system: u64,
+    pub muted: u64,
+    pub gate_closed: u64,
+    // Notes outside every keyboard split
+    pub note_range: u64,
+    // Note Ons over `Config::max_notes_per_sec`
+    pub note_rate: u64,
+    // Note Ons and Note Offs of ghost notes (`Config::min_note_duration`)
+    pub ghost_notes: u64,
+}
+
+impl DropStats {
+    pub fn total(&self) -> u64 {
+        self.stages().iter().map(|(_, count)| count).sum()
+    }
+
+    fn stages(&self) -> [(&'static str, u64); 7] {
+        [
+            ("unknown status filter", self.unknown_status),
+            ("channel-voice filter", self.system),
+            ("mute", self.muted),
+            ("gate", self.gate_closed),
+            ("note-range filter", self.note_range),
+            ("note rate limit", self.note_rate),
+            ("ghost note filter", self.ghost_notes),
+        ]
+    }
+}
+
+// "note-range filter dropped 42, mute dropped 3", listing only stages that dropped something
+impl fmt::Display for DropStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut stages = self.stages().into_iter().filter(|(_, count)| *count > 0).peekable();
+        if stages.peek().is_none() {
+            return write!(f, "nothing dropped");
+        }
+        for (i, (stage, count)) in stages.enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{} dropped {}", stage, count)?;
+        }
+        Ok(())
+    }
+}
+
+// What to do with a Note On for a key that is already held
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetriggerMode {
+    // Send it as is
+    Passthrough,
+    // Release the sounding note first, for synths that won't re-articulate a note
+    // they consider still held
+    InsertNoteOff,
+}
+
+// What to do with messages whose status the parser doesn't know
+// (undefined System statuses such as 0xF4, 0xF5, 0xF9, 0xFD)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnknownMessagePolicy {
+    Forward,
+    Drop,
+    LogAndDrop,
+}
+
+// Processing applied to every MIDI message between the BLE parser and the outputs.
+// A single incoming message can produce zero, one or several outgoing messages.
+pub struct Pipeline {
+    config: Config,
+    active_notes: ActiveNotes,
+    muted: bool,
+    pairing_stats: PairingStats,
+    drop_stats: DropStats,
+    // Incoming messages per second, for activity meters
+    rates: MessageRates,
+    note_throttle: Option<NoteThrottle>,
+    clock: Arc<dyn Clock>,
+    // Octaves added to `Config::octave_offset` by the octave shift triggers
+    octave_shift: i8,
+    // Octaves added by the transpose pedal (`Config::transpose_cc`)
+    pedal_octaves: i8,
+    // Whether the momentary gate (`Config::gate`) is held open
+    gate_open: bool,
+    ghost_filter: Option<GhostNoteFilter>,
+    legato: Option<LegatoFilter>,
+    arpeggiator: Option<Arpeggiator>,
+    velocity_humanizer: Option<VelocityHumanizer>,
+    velocity_normalizer: Option<VelocityNormalizer>,
+    note_repeat: Option<NoteRepeat>,
+    // Note Ons sent by the last `freeze_held_notes`, not released yet
+    frozen: Vec<MidiMessage>,
+    // Velocities of the keys pressed down with `Config::invert_note_onoff`, by (channel,
+    // key), for the Note Ons their releases send
+    press_velocities: BTreeMap<(u8, u8), u8>,
+    // Keys still down whose notes a choke group ended, by (channel, key); their
+    // releases have nothing left to turn off
+    choked: BTreeSet<(u8, u8)>,
+}
+
+impl Pipeline {
+    pub fn new(config: &Config) -> Self {
+        Self::with_clock(config, Arc::new(SystemClock))
+    }
+
+    // A pipeline whose time-dependent stages read the time from `clock`
+    pub fn with_clock(config: &Config, clock: Arc<dyn Clock>) -> Self {
+        Pipeline {
+            config: config.clone(),
+            active_notes: ActiveNotes::new(),
+            muted: false,
+            pairing_stats: PairingStats::default(),
+            drop_stats: DropStats::default(),
+            rates: MessageRates::new(RATE_WINDOW),
+            note_throttle: config.max_notes_per_sec.map(NoteThrottle::new),
+            clock,
+            octave_shift: 0,
+            pedal_octaves: 0,
+            gate_open: false,
+            ghost_filter: config.min_note_duration.map(GhostNoteFilter::new),
+            legato: config.legato.then(|| LegatoFilter::new(config.legato_window)),
+            arpeggiator: config.arpeggiator.map(Arpeggiator::new),
+            velocity_humanizer: config.velocity_humanize.map(|max_deviation| {
+                let random = config.velocity_humanize_seed.map_or_else(XorShift32::from_entropy, XorShift32::new);
+                VelocityHumanizer::new(max_deviation, random)
+            }),
+            velocity_normalizer: config.velocity_normalize.map(VelocityNormalizer::new),
+            note_repeat: config.note_repeat.clone().map(NoteRepeat::new),
+            frozen: Vec::new(),
+            press_velocities: BTreeMap::new(),
+            choked: BTreeSet::new(),
+        }
+    }
+
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    pub fn pairing_stats(&self) -> PairingStats {
+        self.pairing_stats
+    }
+
+    pub fn drop_stats(&self) -> DropStats {
+        self.drop_stats
+    }
+
+    // Messages per second received of each category, over the last second
+    pub fn message_rates(&mut self) -> BTreeMap<MessageCategory, f64> {
+        let now = self.clock.now();
+        self.rates.rates(now)
+    }
+
+    // Notes sounding from the last freeze
+    pub fn frozen_notes(&self) -> &[MidiMessage] {
+        &self.frozen
+    }
+
+    // Re-send the held notes as Note Ons on the freeze channel and output
+    // (`Config::freeze`). They keep sounding until `clear_freeze`, whatever the keys
+    // do; a new freeze replaces the previous one. Like everything the pipeline sends,
+    // they then go through `Config::channel_routing`.
+    pub fn freeze_held_notes(&mut self) -> Vec<Output> {
+        let outputs = self.freeze();
+        self.route_channels(outputs)
+    }
+
+    // Release the frozen notes
+    pub fn clear_freeze(&mut self) -> Vec<Output> {
+        let outputs = self.release_frozen();
+        self.route_channels(outputs)
+    }
+
+    fn freeze(&mut self) -> Vec<Output> {
+        let Some(freeze) = self.config.freeze.clone() else {
+            return Vec::new();
+        };
+        let mut outputs = self.release_frozen();
+        let route = freeze.sink.map_or(Route::All, Route::Sink);
+        for (_, _, held) in self.active_notes.iter() {
+            let note_on = MidiMessage { status: 0x90 | freeze.channel, data1: held.note, data2: held.velocity };
+            if !self.frozen.iter().any(|frozen| frozen.data1 == held.note) {
+                self.frozen.push(note_on);
+                outputs.push(Output { message: note_on, route: route.clone() });
+            }
+        }
+        info!("Froze {} note(s)", self.frozen.len());
+        outputs
+    }
+
+    fn release_frozen(&mut self) -> Vec<Output> {
+        let route = self.config.freeze.as_ref().and_then(|freeze| freeze.sink.clone()).map_or(Route::All, Route::Sink);
+        self.frozen
+            .drain(..)
+            .map(|note_on| Output { message: MidiMessage { status: 0x80 | (note_on.status & 0x0F), data2: 0, ..note_on }, route: route.clone() })
+            .collect()
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    // Octave transposition currently applied: the configured offset plus live shifts
+    pub fn octave_offset(&self) -> i8 {
+        self.config.octave_offset + self.octave_shift + self.pedal_octaves
+    }
+
+    pub fn set_octave_offset(&mut self, offset: i8) {
+        self.config.octave_offset = offset;
+        info!("Octave offset now {:+}", self.octave_offset());
+    }
+
+    pub fn set_semitone_offset(&mut self, offset: i8) {
+        self.config.semitone_offset = offset;
+        info!("Semitone offset now {:+}", offset);
+    }
+
+    pub fn active_notes(&self) -> &ActiveNotes {
+        &self.active_notes
+    }
+
+    pub fn process(&mut self, message: MidiMessage) -> Vec<Output> {
+        self.rates.record(MessageCategory::of(&message), self.clock.now());
+        // Held-back Note Ons that are due go first, so the order of notes is kept
+        let mut outputs = self.release_due_notes();
+        let processed = self.process_message(message);
+        outputs.extend(self.route_channels(processed));
+        outputs
+    }
+
+    // Notes due to be sent by now: Note Ons held back by the ghost note filter
+    // (`Config::min_note_duration`) long enough, Note Offs ending a legato phrase, the
+    // arpeggiator's steps and note repeats
+    pub fn release_due_notes(&mut self) -> Vec<Output> {
+        let now = self.clock.now();
+        let mut outputs = match &mut self.ghost_filter {
+            Some(filter) => filter.release_due(now),
+            None => Vec::new(),
+        };
+        if let Some(legato) = &mut self.legato {
+            outputs.extend(legato.release_due(now));
+        }
+        outputs.extend(self.run_arpeggiator());
+        if let Some(repeat) = &mut self.note_repeat {
+            outputs.extend(repeat.run(now));
+        }
+        self.route_channels(outputs)
+    }
+
+    // Move messages from the channels in `Config::channel_routing` to their output
+    // channel and port. Notes are tracked by their source channel, so this comes last.
+    fn route_channels(&self, mut outputs: Vec<Output>) -> Vec<Output> {
+        if self.config.channel_routing.is_empty() {
+            return outputs;
+        }
+        for output in &mut outputs {
+            let Some(channel) = output.message.channel() else {
+                continue;
+            };
+            if let Some((to, port)) = self.config.channel_routing.get(&channel) {
+                output.message = output.message.with_channel(*to);
+                output.route = Route::Sink(port.clone());
+            }
+        }
+        outputs
+    }
+
+    // When `release_due_notes` will next have something to send
+    pub fn next_note_release(&self) -> Option<Instant> {
+        [
+            self.ghost_filter.as_ref().and_then(GhostNoteFilter::next_release),
+            self.legato.as_ref().and_then(LegatoFilter::next_release),
+            self.arpeggiator.as_ref().and_then(Arpeggiator::next_event),
+            self.note_repeat.as_ref().and_then(NoteRepeat::next_event),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+
+    // The arpeggiator's due steps, played from the held notes
+    fn run_arpeggiator(&mut self) -> Vec<Output> {
+        let Some(arp) = &mut self.arpeggiator else {
+            return Vec::new();
+        };
+        let mut pool: Vec<_> = self.active_notes
+            .iter()
+            .map(|(channel, _, held)| ArpNote { note: held.note, channel, velocity: held.velocity })
+            .collect();
+        pool.sort();
+        arp.run(self.clock.now(), &pool)
+            .into_iter()
+            .map(|message| Output { message, route: route_category(&self.config.category_ports, &message) })
+            .collect()
+    }
+
+    // Stop the arpeggiator, releasing the note it is playing
+    fn stop_arpeggiator(&mut self) -> Vec<Output> {
+        let note_off = self.arpeggiator.as_mut().and_then(Arpeggiator::stop);
+        note_off.map(|message| Output { message, route: route_category(&self.config.category_ports, &message) }).into_iter().collect()
+    }
+
+    fn process_message(&mut self, mut message: MidiMessage) -> Vec<Output> {
+        if message.message_type() == "Unknown" {
+            match self.config.unknown_message_policy {
+                UnknownMessagePolicy::Forward => {}
+                UnknownMessagePolicy::Drop => {
+                    self.drop_stats.unknown_status += 1;
+                    return Vec::new();
+                }
+                UnknownMessagePolicy::LogAndDrop => {
+                    warn!("Dropping message with unknown status 0x{:02X}", message.status);
+                    self.drop_stats.unknown_status += 1;
+                    return Vec::new();
+                }
+            }
+        }
+
+        if message.status == SYSTEM_RESET {
+            let mut outputs = self.system_reset();
+            if self.config.channel_voice_only {
+                self.drop_stats.system += 1;
+            } else if self.config.forward_system_reset {
+                outputs.push(Output { message, route: route_category(&self.config.category_ports, &message) });
+            }
+            return outputs;
+        }
+
+        if self.config.channel_voice_only && message.status >= 0xF0 {
+            self.drop_stats.system += 1;
+            return Vec::new();
+        }
+
+        // The device's own controls are remapped before anything else looks at them
+        if message.message_type() == "Control Change" {
+            match self.config.control_map.get(&message.data1) {
+                Some(ControlAction::Cc(cc)) => message.data1 = *cc,
+                Some(ControlAction::ToggleMute) => {
+                    return if message.data2 >= 64 { self.toggle_mute() } else { Vec::new() };
+                }
+                Some(ControlAction::OctaveUp) | Some(ControlAction::OctaveDown) if message.data2 < 64 => return Vec::new(),
+                Some(ControlAction::OctaveUp) => {
+                    self.shift_octave(1);
+                    return Vec::new();
+                }
+                Some(ControlAction::OctaveDown) => {
+                    self.shift_octave(-1);
+                    return Vec::new();
+                }
+                Some(ControlAction::Ignore) => return Vec::new(),
+                None => {}
+            }
+        }
+
+        // The mute toggle CC controls the bridge itself and is never forwarded
+        if let Some(cc) = self.config.mute_toggle_cc {
+            if message.message_type() == "Control Change" && message.data1 == cc {
+                return if message.data2 >= 64 { self.toggle_mute() } else { Vec::new() };
+            }
+        }
+
+        // And the freeze trigger
+        if let Some(cc) = self.config.freeze.as_ref().and_then(|freeze| freeze.trigger_cc) {
+            if message.message_type() == "Control Change" && message.data1 == cc {
+                return match message.data2 {
+                    0..=63 => Vec::new(),
+                    _ if self.frozen.is_empty() => self.freeze(),
+                    _ => self.release_frozen(),
+                };
+            }
+        }
+
+        // So are the octave shift triggers
+        if let Some(triggers) = self.config.octave_shift_triggers {
+            if let Some(octaves) = triggers.check(&message) {
+                self.shift_octave(octaves);
+                return Vec::new();
+            }
+        }
+
+        // And the transpose pedal
+        if let Some(cc) = self.config.transpose_cc {
+            if message.message_type() == "Control Change" && message.data1 == cc {
+                let octaves = pedal_octaves(message.data2);
+                if octaves != self.pedal_octaves {
+                    self.pedal_octaves = octaves;
+                    info!("Octave offset now {:+}", self.octave_offset());
+                }
+                return Vec::new();
+            }
+        }
+
+        // And the velocity normalizer's reset
+        if let Some(cc) = self.config.velocity_normalize.and_then(|normalize| normalize.reset_cc) {
+            if message.message_type() == "Control Change" && message.data1 == cc {
+                if message.data2 >= 64 {
+                    self.reset_velocity_range();
+                }
+                return Vec::new();
+            }
+        }
+
+        // The gate trigger too; without a gate everything passes
+        if let Some(gate) = self.config.gate {
+            if let Some(open) = gate.check(&message) {
+                return self.set_gate(open);
+            }
+        }
+
+        if self.muted {
+            self.drop_stats.muted += 1;
+            return Vec::new();
+        }
+        if self.config.gate.is_some() && !self.gate_open {
+            self.drop_stats.gate_closed += 1;
+            return Vec::new();
+        }
+
+        if self.config.invert_note_onoff {
+            message = self.invert_note(message);
+        }
+
+        match (message.message_type(), message.channel()) {
+            ("Note On", Some(channel)) => {
+                let Some(route) = self.route_note(&message) else {
+                    debug!("Dropping {} outside all keyboard splits", message.note_name());
+                    self.drop_stats.note_range += 1;
+                    return Vec::new();
+                };
+                if let Some(throttle) = &mut self.note_throttle {
+                    if !throttle.allow(self.clock.now()) {
+                        self.drop_stats.note_rate += 1;
+                        return Vec::new();
+                    }
+                }
+                let note = self.snap_note(self.transpose_note(channel, self.map_note(message.data1)));
+                let mut velocity = match (self.config.fixed_velocity, &mut self.velocity_normalizer) {
+                    (Some(velocity), _) => velocity,
+                    (None, Some(normalizer)) => normalizer.apply(message.data2),
+                    (None, None) => message.data2,
+                };
+                if let Some(humanizer) = &mut self.velocity_humanizer {
+                    velocity = humanizer.apply(velocity);
+                }
+                let choked = self.choke(channel, message.data1);
+                self.choked.remove(&(channel, message.data1));
+                let previous = self.active_notes.note_on(channel, message.data1, HeldNote { note, velocity });
+                if previous.is_some() && self.config.diagnose_note_pairing {
+                    self.pairing_stats.retriggers += 1;
+                    warn!(
+                        "Note On for {} on channel {} while it is already held - a Note Off may have been lost",
+                        message.note_name(),
+                        channel + 1
+                    );
+                }
+                // The arpeggiator plays the held notes itself
+                if let Some(arp) = &mut self.arpeggiator {
+                    arp.start(self.clock.now());
+                    return self.run_arpeggiator();
+                }
+                // A previous Note On still held back by the ghost filter never sounded,
+                // so there is nothing to release
+                let mut outputs = choked;
+                if let Some(previous) = previous {
+                    let sounding = !self.ghost_filter.as_ref().is_some_and(|filter| filter.is_holding(channel, message.data1));
+                    if self.config.retrigger_mode == RetriggerMode::InsertNoteOff && sounding {
+                        for sent in self.voiced(previous.note) {
+                            let note_off = MidiMessage { status: 0x80 | channel, data1: sent, data2: 0 };
+                            outputs.push(Output { message: note_off, route: route.clone() });
+                        }
+                    }
+                }
+                let voices: Vec<_> = self.voiced(note)
+                    .into_iter()
+                    .map(|sent| Output { message: MidiMessage { data1: sent, data2: velocity, ..message }, route: route.clone() })
+                    .collect();
+                if let Some(repeat) = &mut self.note_repeat {
+                    repeat.hold(channel, message.data1, &voices, self.clock.now());
+                }
+                if let Some(legato) = &mut self.legato {
+                    legato.note_on(channel, self.clock.now());
+                }
+                match &mut self.ghost_filter {
+                    Some(filter) => {
+                        for voice in voices {
+                            filter.hold(channel, message.data1, voice, self.clock.now());
+                        }
+                    }
+                    None => outputs.extend(voices),
+                }
+                outputs
+            }
+            ("Note Off", Some(channel)) => {
+                if self.choked.remove(&(channel, message.data1)) {
+                    return Vec::new();
+                }
+                let Some(route) = self.route_note(&message) else {
+                    self.drop_stats.note_range += 1;
+                    return Vec::new();
+                };
+                if self.arpeggiator.is_some() {
+                    self.active_notes.note_off(channel, message.data1);
+                    return if self.active_notes.is_empty() { self.stop_arpeggiator() } else { Vec::new() };
+                }
+                // A repeating note released between triggers has nothing left to turn off
+                let repeat_silent = self.note_repeat.as_mut().and_then(|repeat| repeat.release(channel, message.data1)) == Some(false);
+                if self.ghost_filter.as_mut().is_some_and(|filter| filter.cancel(channel, message.data1)) {
+                    debug!("Dropping ghost note {} on channel {}", message.note_name(), channel + 1);
+                    // Its Note On was dropped too
+                    self.drop_stats.ghost_notes += 2;
+                    self.active_notes.note_off(channel, message.data1);
+                    return Vec::new();
+                }
+                // Release the pitch that was actually sent for this key, so a Note Off
+                // always matches its Note On
+                let note = match self.active_notes.note_off(channel, message.data1) {
+                    Some(held) => held.note,
+                    None => {
+                        if self.config.diagnose_note_pairing {
+                            self.pairing_stats.orphan_note_offs += 1;
+                            warn!(
+                                "Note Off for {} on channel {} which is not held - a Note On may have been lost",
+                                message.note_name(),
+                                channel + 1
+                            );
+                        }
+                        self.snap_note(self.transpose_note(channel, self.map_note(message.data1)))
+                    }
+                };
+                if repeat_silent {
+                    return Vec::new();
+                }
+                let message = match self.config.note_off_velocity {
+                    Some(velocity) => MidiMessage { status: 0x80 | channel, data2: velocity, ..message },
+                    None => message,
+                };
+                let outputs = self.voiced(note)
+                    .into_iter()
+                    .map(|sent| Output { message: MidiMessage { data1: sent, ..message }, route: route.clone() })
+                    .collect();
+                match &mut self.legato {
+                    Some(legato) => {
+                        let others_held = self.active_notes.iter().any(|(held_channel, _, _)| held_channel == channel);
+                        legato.note_off(channel, outputs, others_held, self.clock.now())
+                    }
+                    None => outputs,
+                }
+            }
+            ("Control Change", Some(_)) if self.config.cc_to_pitchbend == Some(message.data1) => {
+                let bend = self.scale_bend(cc_to_pitch_bend(&message, self.config.pitchbend_scaling));
+                vec![Output { message: bend, route: route_category(&self.config.category_ports, &bend) }]
+            }
+            ("Program Change", Some(channel)) => {
+                let route = route_category(&self.config.category_ports, &message);
+                let bank = self.config.channel_banks.get(&channel).copied().or(self.config.program_change_bank);
+                let mut outputs: Vec<_> = bank
+                    .into_iter()
+                    .flat_map(|(msb, lsb)| [MidiMessage::control_change(channel, 0, msb), MidiMessage::control_change(channel, 32, lsb)])
+                    .map(|bank_select| Output { message: bank_select, route: route.clone() })
+                    .collect();
+                outputs.push(Output { message, route });
+                outputs
+            }
+            ("Pitch Bend", Some(_)) => {
+                let bend = self.scale_bend(message);
+                vec![Output { message: bend, route: route_category(&self.config.category_ports, &bend) }]
+            }
+            _ => vec![Output { message, route: route_category(&self.config.category_ports, &message) }],
+        }
+    }
+
+    // Swap a key's press and release, for `Config::invert_note_onoff`: the press becomes a
+    // Note Off and the release a Note On with the press's velocity. The rest of the
+    // pipeline sees the swapped messages, so the sounding notes are tracked as usual.
+    fn invert_note(&mut self, message: MidiMessage) -> MidiMessage {
+        let Some(channel) = message.channel() else {
+            return message;
+        };
+        let key = (channel, message.data1);
+        match message.message_type() {
+            "Note On" => {
+                self.press_velocities.insert(key, message.data2);
+                MidiMessage { status: 0x80 | channel, data1: message.data1, data2: 0 }
+            }
+            "Note Off" => {
+                let velocity = self.press_velocities.remove(&key).unwrap_or(DEFAULT_VELOCITY);
+                MidiMessage { status: 0x90 | channel, data1: message.data1, data2: velocity }
+            }
+            _ => message,
+        }
+    }
+
+    // Apply `Config::note_map`, clamping or wrapping the result per `transpose_bounds`
+    fn map_note(&self, note: u8) -> u8 {
+        match self.config.note_map.as_ref().and_then(|map| map.get(note as usize)) {
+            Some(&mapped) => transpose_within(0, mapped as i16, self.config.transpose_bounds).0,
+            None => note,
+        }
+    }
+
+    // Move a note onto `Config::scale_snap`'s scale. The held note remembers the result,
+    // so the Note Off releases the same pitch.
+    fn snap_note(&self, note: u8) -> u8 {
+        match self.config.scale_snap {
+            Some(scale) => scale.snap(note),
+            None => note,
+        }
+    }
+
+    fn scale_bend(&self, bend: MidiMessage) -> MidiMessage {
+        if self.config.pitch_bend_scale == 1.0 {
+            bend
+        } else {
+            scale_pitch_bend(&bend, self.config.pitch_bend_scale)
+        }
+    }
+
+    // Keyboard splits take precedence over the port configured for notes
+    fn route_note(&self, message: &MidiMessage) -> Option<Route> {
+        match route_note(&self.config.splits, message.data1)? {
+            Route::All => Some(route_category(&self.config.category_ports, message)),
+            route => Some(route),
+        }
+    }
+
+    // Flip the muted state. Muting silences anything still sounding downstream, since
+    // the Note Offs for currently held keys will be swallowed while muted.
+    fn toggle_mute(&mut self) -> Vec<Output> {
+        self.muted = !self.muted;
+        if self.muted {
+            info!("Output muted");
+            self.drop_held_back_notes();
+            if let Some(legato) = &mut self.legato {
+                legato.clear();
+            }
+            self.stop_arpeggiator();
+            if let Some(repeat) = &mut self.note_repeat {
+                repeat.clear();
+            }
+            self.active_notes.clear();
+            self.choked.clear();
+            self.config.panic_mode.messages().into_iter().map(Output::all).collect()
+        } else {
+            info!("Output unmuted");
+            Vec::new()
+        }
+    }
+
+    // Closing the gate releases every held note, so nothing keeps sounding while it is
+    // closed. Keys still held when it reopens need to be struck again.
+    fn set_gate(&mut self, open: bool) -> Vec<Output> {
+        if open == self.gate_open {
+            return Vec::new();
+        }
+        self.gate_open = open;
+        debug!("Gate {}", if open { "open" } else { "closed" });
+        if open {
+            return Vec::new();
+        }
+        self.release_held_notes()
+    }
+
+    // A System Reset from the device: release everything sounding, including frozen
+    // notes, and undo the octave shifts and mute its messages set
+    fn system_reset(&mut self) -> Vec<Output> {
+        info!("System Reset received, releasing held notes and resetting state");
+        let mut outputs = self.release_held_notes();
+        outputs.extend(self.release_frozen());
+        self.octave_shift = 0;
+        self.pedal_octaves = 0;
+        self.muted = false;
+        self.reset_velocity_range();
+        outputs
+    }
+
+    fn reset_velocity_range(&mut self) {
+        if let Some(normalizer) = &mut self.velocity_normalizer {
+            info!("Velocity range reset");
+            normalizer.reset();
+        }
+    }
+
+    // Note Offs for every held note, forgetting them. Those the legato filter is holding
+    // on to go first.
+    fn release_held_notes(&mut self) -> Vec<Output> {
+        self.drop_held_back_notes();
+        if let Some(repeat) = &mut self.note_repeat {
+            repeat.clear();
+        }
+        self.choked.clear();
+        // The arpeggiator's held notes never sounded themselves
+        if self.arpeggiator.is_some() {
+            self.active_notes.clear();
+            return self.stop_arpeggiator();
+        }
+        let mut outputs = self.legato.as_mut().map(LegatoFilter::flush).unwrap_or_default();
+        let held: Vec<_> = self.active_notes.iter().map(|(channel, note, held)| (channel, note, *held)).collect();
+        self.active_notes.clear();
+        let released = held.into_iter()
+            .filter_map(|(channel, note, held)| {
+                let key = MidiMessage { status: 0x80 | channel, data1: note, data2: 0 };
+                let route = self.route_note(&key)?;
+                Some(self.voiced(held.note).into_iter().map(move |sent| Output {
+                    message: MidiMessage { data1: sent, ..key },
+                    route: route.clone(),
+                }))
+            })
+            .flatten();
+        outputs.extend(released);
+        outputs
+    }
+
+    // Release the notes held on `channel` that share a `Config::choke_groups` group with
+    // `key`, before it sounds. Like any Note Off, this stops a repeating note, and notes
+    // the ghost filter or arpeggiator hold haven't sounded themselves. With legato the
+    // choking note continues the phrase, so the Note Offs wait for its end.
+    fn choke(&mut self, channel: u8, key: u8) -> Vec<Output> {
+        let groups: Vec<_> = self.config.choke_groups.iter().filter(|group| group.contains(&key)).collect();
+        if groups.is_empty() {
+            return Vec::new();
+        }
+        let choked: Vec<_> = self.active_notes
+            .iter()
+            .filter(|&(held_channel, held_key, _)| {
+                held_channel == channel && held_key != key && groups.iter().any(|group| group.contains(&held_key))
+            })
+            .map(|(_, held_key, held)| (held_key, held.note))
+            .collect();
+
+        let mut outputs = Vec::new();
+        for (held_key, note) in choked {
+            self.active_notes.note_off(channel, held_key);
+            self.choked.insert((channel, held_key));
+            let repeat_silent = self.note_repeat.as_mut().and_then(|repeat| repeat.release(channel, held_key)) == Some(false);
+            let held_back = self.ghost_filter.as_mut().is_some_and(|filter| filter.cancel(channel, held_key));
+            if repeat_silent || held_back || self.arpeggiator.is_some() {
+                continue;
+            }
+            let key = MidiMessage { status: 0x80 | channel, data1: held_key, data2: 0 };
+            let Some(route) = self.route_note(&key) else { continue };
+            debug!("Choking {} on channel {}", key.note_name(), channel + 1);
+            outputs.extend(self.voiced(note).into_iter().map(|sent| Output {
+                message: MidiMessage { data1: sent, ..key },
+                route: route.clone(),
+            }));
+        }
+        match &mut self.legato {
+            Some(legato) if !outputs.is_empty() => {
+                let now = self.clock.now();
+                legato.note_on(channel, now);
+                legato.note_off(channel, outputs, true, now)
+            }
+            _ => outputs,
+        }
+    }
+
+    // Notes sent for a key sounding `note`: the note itself, then the `Config::harmonize`
+    // intervals from it, kept within range and without duplicates
+    fn voiced(&self, note: u8) -> Vec<u8> {
+        let mut notes = vec![note];
+        for &interval in self.config.harmonize.iter().flatten() {
+            let extra = (note as i16 + interval as i16).clamp(0, 127) as u8;
+            if !notes.contains(&extra) {
+                notes.push(extra);
+            }
+        }
+        notes
+    }
+
+    // Forget Note Ons the ghost note filter hasn't sent yet, so they are neither sent
+    // later nor released
+    fn drop_held_back_notes(&mut self) {
+        if let Some(filter) = &mut self.ghost_filter {
+            for (channel, key) in filter.clear() {
+                self.active_notes.note_off(channel, key);
+            }
+        }
+    }
+
+    // Held notes keep the pitch they were sent with, so they are released correctly
+    fn shift_octave(&mut self, octaves: i8) {
+        if octaves == 0 {
+            return;
+        }
+        let offset = self.octave_offset() + octaves;
+        if !OFFSET_RANGE.contains(&offset) {
+            debug!("Ignoring octave shift beyond {} octaves", offset.signum() * OFFSET_RANGE.end());
+            return;
+        }
+        self.octave_shift += octaves;
+        info!("Octave offset now {:+}", offset);
+    }
+
+    fn transposes_channel(&self, channel: u8) -> bool {
+        match &self.config.transpose_channels {
+            Some(channels) => channels.contains(&channel),
+            None => true,
+        }
+    }
+
+    fn transpose_note(&self, channel: u8, note: u8) -> u8 {
+        if !self.transposes_channel(channel) {
+            return note;
+        }
+
+        let semitones = self.octave_offset() as i16 * 12 + self.config.semitone_offset as i16;
+        let (new_note, outcome) = transpose_within(note, semitones, self.config.transpose_bounds);
+        debug!(
+            "Note transposition: {} ({}) -> {} ({}) [offset: {} octaves {:+} semitones, channel: {}, {:?}]",
+            MidiMessage { status: 0x90, data1: note, data2: 0 }.note_name(),
+            note,
+            MidiMessage { status: 0x90, data1: new_note, data2: 0 }.note_name(),
+            new_note,
+            self.octave_offset(),
+            self.config.semitone_offset,
+            channel,
+            outcome
+        );
+        new_note
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::testing::RecordingSink;
+    use crate::midi::SinkSet;
+    use crate::util::MockClock;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    // Process a message and keep only the resulting messages, ignoring routes
+    fn run(pipeline: &mut Pipeline, message: MidiMessage) -> Vec<MidiMessage> {
+        pipeline.process(message).into_iter().map(|output| output.message).collect()
+    }
+
+    fn note_on(channel: u8, note: u8, velocity: u8) -> MidiMessage {
+        MidiMessage { status: 0x90 | channel, data1: note, data2: velocity }
+    }
+
+    fn note_off(channel: u8, note: u8) -> MidiMessage {
+        MidiMessage { status: 0x80 | channel, data1: note, data2: 0 }
+    }
+
+    fn cc(channel: u8, controller: u8, value: u8) -> MidiMessage {
+        MidiMessage::control_change(channel, controller, value)
+    }
+
+    #[test]
+    fn test_transpose_clamps() {
+        assert_eq!(transpose(60, 12), 72);
+        assert_eq!(transpose(60, -12), 48);
+        assert_eq!(transpose(120, 12), 127);
+        assert_eq!(transpose(5, -12), 0);
+    }
+
+    #[test]
+    fn test_transpose_outcomes() {
+        assert_eq!(transpose_within(60, 12, TransposeBounds::Clamp), (72, TransposeOutcome::Unchanged));
+        assert_eq!(transpose_within(120, 12, TransposeBounds::Clamp), (127, TransposeOutcome::Clamped));
+        assert_eq!(transpose_within(5, -12, TransposeBounds::Clamp), (0, TransposeOutcome::Clamped));
+        // C9 up an octave wraps back to C9, F-1 down an octave to F-1
+        assert_eq!(transpose_within(120, 12, TransposeBounds::Wrap), (120, TransposeOutcome::Wrapped));
+        assert_eq!(transpose_within(5, -12, TransposeBounds::Wrap), (5, TransposeOutcome::Wrapped));
+        assert_eq!(transpose_within(125, 24, TransposeBounds::Wrap), (125, TransposeOutcome::Wrapped));
+    }
+
+    #[test]
+    fn test_note_map() {
+        let identity: [u8; 128] = std::array::from_fn(|note| note as u8);
+        let reversed: [u8; 128] = std::array::from_fn(|note| 127 - note as u8);
+
+        let mut pipeline = Pipeline::new(&Config { note_map: Some(identity), ..Config::default() });
+        assert_eq!(run(&mut pipeline, note_on(0, 60, 100)), vec![note_on(0, 60, 100)]);
+
+        let config = Config { note_map: Some(reversed), octave_offset: 1, ..Config::default() };
+        let mut pipeline = Pipeline::new(&config);
+        // Mapped first, then transposed
+        assert_eq!(run(&mut pipeline, note_on(0, 60, 100)), vec![note_on(0, 79, 100)]);
+        assert_eq!(run(&mut pipeline, note_on(0, 0, 100)), vec![note_on(0, 127, 100)]);
+        // A map change can't leave the Note Off behind
+        pipeline.config.note_map = Some(identity);
+        assert_eq!(run(&mut pipeline, note_off(0, 60)), vec![note_off(0, 79)]);
+
+        let mut map = identity;
+        map[60] = 200;
+        for (bounds, expected) in [(TransposeBounds::Clamp, 127), (TransposeBounds::Wrap, 116)] {
+            let mut pipeline = Pipeline::new(&Config { note_map: Some(map), transpose_bounds: bounds, ..Config::default() });
+            assert_eq!(run(&mut pipeline, note_on(0, 60, 100)), vec![note_on(0, expected, 100)]);
+        }
+    }
+
+    #[test]
+    fn test_wrapping_transposition_in_pipeline() {
+        let config = Config { octave_offset: 1, transpose_bounds: TransposeBounds::Wrap, ..Config::default() };
+        let mut pipeline = Pipeline::new(&config);
+
+        assert_eq!(run(&mut pipeline, note_on(0, 120, 100)), vec![note_on(0, 120, 100)]);
+        assert_eq!(run(&mut pipeline, note_off(0, 120)), vec![note_off(0, 120)]);
+    }
+
+    #[test]
+    fn test_transposes_all_channels_by_default() {
+        let config = Config { octave_offset: 1, ..Config::default() };
+        let mut pipeline = Pipeline::new(&config);
+
+        assert_eq!(run(&mut pipeline, note_on(0, 60, 100)), vec![note_on(0, 72, 100)]);
+        assert_eq!(run(&mut pipeline, note_on(5, 60, 100)), vec![note_on(5, 72, 100)]);
+    }
+
+    #[test]
+    fn test_transpose_only_configured_channels() {
+        let config = Config {
+            octave_offset: -1,
+            transpose_channels: Some(vec![1]),
+            ..Config::default()
+        };
+        let mut pipeline = Pipeline::new(&config);
+
+        // Interleaved stream: channel 1 drives the bass synth, channel 0 stays at concert pitch
+        let input = vec![
+            note_on(1, 48, 100),
+            note_on(0, 60, 90),
+            MidiMessage { status: 0xB1, data1: 1, data2: 64 },
+            note_off(0, 60),
+            note_off(1, 48),
+        ];
+        let output: Vec<MidiMessage> = input.into_iter().flat_map(|m| run(&mut pipeline, m)).collect();
+
+        assert_eq!(output, vec![
+            note_on(1, 36, 100),
+            note_on(0, 60, 90),
+            MidiMessage { status: 0xB1, data1: 1, data2: 64 },
+            note_off(0, 60),
+            note_off(1, 36),
+        ]);
+        assert!(pipeline.active_notes().is_empty());
+    }
+
+    #[test]
+    fn test_active_notes_tracked_per_channel() {
+        let config = Config {
+            octave_offset: 1,
+            transpose_channels: Some(vec![2]),
+            ..Config::default()
+        };
+        let mut pipeline = Pipeline::new(&config);
+
+        // Same key held on a transposed and an untransposed channel
+        run(&mut pipeline, note_on(2, 60, 100));
+        run(&mut pipeline, note_on(3, 60, 100));
+
+        assert_eq!(pipeline.active_notes().get(2, 60).map(|h| h.note), Some(72));
+        assert_eq!(pipeline.active_notes().get(3, 60).map(|h| h.note), Some(60));
+
+        // Note On with velocity 0 releases like a Note Off
+        assert_eq!(run(&mut pipeline, note_on(3, 60, 0)), vec![note_on(3, 60, 0)]);
+        assert_eq!(run(&mut pipeline, note_off(2, 60)), vec![note_off(2, 72)]);
+        assert!(pipeline.active_notes().is_empty());
+    }
+
+    #[test]
+    fn test_mute_toggle_flushes_and_drops() {
+        let config = Config { mute_toggle_cc: Some(20), ..Config::default() };
+        let mut pipeline = Pipeline::new(&config);
+
+        run(&mut pipeline, note_on(0, 60, 100));
+
+        // Muting emits All Notes Off on every channel and nothing else
+        let flushed = run(&mut pipeline, cc(0, 20, 127));
+        assert!(pipeline.is_muted());
+        assert_eq!(flushed, MidiMessage::all_notes_off());
+        assert!(pipeline.active_notes().is_empty());
+
+        // Everything is dropped while muted
+        assert!(run(&mut pipeline, note_off(0, 60)).is_empty());
+        assert!(run(&mut pipeline, note_on(0, 62, 100)).is_empty());
+        assert!(run(&mut pipeline, cc(0, 1, 64)).is_empty());
+    }
+
+    #[test]
+    fn test_unmute_resumes_forwarding() {
+        let config = Config { mute_toggle_cc: Some(20), ..Config::default() };
+        let mut pipeline = Pipeline::new(&config);
+
+        run(&mut pipeline, cc(0, 20, 127));
+        // Button release (value < 64) doesn't toggle and isn't forwarded
+        assert!(run(&mut pipeline, cc(0, 20, 0)).is_empty());
+        assert!(pipeline.is_muted());
+
+        // Unmuting emits nothing itself
+        assert!(run(&mut pipeline, cc(0, 20, 127)).is_empty());
+        assert!(!pipeline.is_muted());
+        assert_eq!(run(&mut pipeline, note_on(0, 64, 90)), vec![note_on(0, 64, 90)]);
+    }
+
+    #[test]
+    fn test_other_ccs_unaffected_without_mute_cc() {
+        let mut pipeline = Pipeline::new(&Config::default());
+        assert_eq!(run(&mut pipeline, cc(0, 20, 127)), vec![cc(0, 20, 127)]);
+        assert!(!pipeline.is_muted());
+    }
+
+    #[test]
+    fn test_pairing_diagnostics_detect_both_conditions() {
+        let config = Config { diagnose_note_pairing: true, ..Config::default() };
+        let mut pipeline = Pipeline::new(&config);
+
+        // Note Off without a Note On
+        assert_eq!(run(&mut pipeline, note_off(0, 60)), vec![note_off(0, 60)]);
+        assert_eq!(pipeline.pairing_stats(), PairingStats { orphan_note_offs: 1, retriggers: 0 });
+
+        // Note On twice without a release in between
+        run(&mut pipeline, note_on(0, 62, 100));
+        run(&mut pipeline, note_on(0, 62, 100));
+        assert_eq!(pipeline.pairing_stats(), PairingStats { orphan_note_offs: 1, retriggers: 1 });
+
+        // A correctly paired note raises nothing
+        run(&mut pipeline, note_off(0, 62));
+        run(&mut pipeline, note_on(1, 64, 100));
+        run(&mut pipeline, note_off(1, 64));
+        assert_eq!(pipeline.pairing_stats(), PairingStats { orphan_note_offs: 1, retriggers: 1 });
+    }
+
+    #[test]
+    fn test_pairing_diagnostics_disabled_by_default() {
+        let mut pipeline = Pipeline::new(&Config::default());
+        run(&mut pipeline, note_off(0, 60));
+        run(&mut pipeline, note_on(0, 62, 100));
+        run(&mut pipeline, note_on(0, 62, 100));
+        assert_eq!(pipeline.pairing_stats(), PairingStats::default());
+    }
+
+    #[test]
+    fn test_splits_route_notes_to_different_sinks() {
+        let config = Config {
+            splits: vec![
+                Split { range: 0..=59, sink: "bass".to_string() },
+                Split { range: 60..=127, sink: "lead".to_string() },
+            ],
+            ..Config::default()
+        };
+        let mut pipeline = Pipeline::new(&config);
+
+        let bass = RecordingSink::new();
+        let lead = RecordingSink::new();
+        let mut sinks = SinkSet::new();
+        sinks.add("bass", Box::new(bass.clone()));
+        sinks.add("lead", Box::new(lead.clone()));
+
+        for message in [note_on(0, 40, 100), note_on(0, 72, 100), cc(0, 1, 64), note_off(0, 40)] {
+            for output in pipeline.process(message) {
+                sinks.send(&output.message, &output.route).unwrap();
+            }
+        }
+
+        // Non-note messages go to every sink
+        assert_eq!(bass.messages(), vec![note_on(0, 40, 100), cc(0, 1, 64), note_off(0, 40)]);
+        assert_eq!(lead.messages(), vec![note_on(0, 72, 100), cc(0, 1, 64)]);
+    }
+
+    #[test]
+    fn test_channel_routing() {
+        let config = Config {
+            channel_routing: HashMap::from([(0, (0, "left".to_string())), (1, (0, "right".to_string()))]),
+            octave_offset: 1,
+            ..Config::default()
+        };
+        let mut pipeline = Pipeline::new(&config);
+
+        let left = RecordingSink::new();
+        let right = RecordingSink::new();
+        let other = RecordingSink::new();
+        let mut sinks = SinkSet::new();
+        sinks.add("left", Box::new(left.clone()));
+        sinks.add("right", Box::new(right.clone()));
+        sinks.add("other", Box::new(other.clone()));
+
+        for message in [note_on(0, 60, 100), note_on(1, 60, 90), cc(1, 1, 64), note_off(0, 60), note_off(1, 60), cc(2, 7, 100)] {
+            for output in pipeline.process(message) {
+                sinks.send(&output.message, &output.route).unwrap();
+            }
+        }
+
+        // Both on channel 1, each on its own port
+        assert_eq!(left.messages(), vec![note_on(0, 72, 100), note_off(0, 72), cc(2, 7, 100)]);
+        assert_eq!(right.messages(), vec![note_on(0, 72, 90), cc(0, 1, 64), note_off(0, 72), cc(2, 7, 100)]);
+        // Unmapped channels still go everywhere
+        assert_eq!(other.messages(), vec![cc(2, 7, 100)]);
+    }
+
+    #[test]
+    fn test_splits_drop_notes_outside_all_ranges() {
+        let config = Config {
+            splits: vec![Split { range: 48..=72, sink: "keys".to_string() }],
+            ..Config::default()
+        };
+        let mut pipeline = Pipeline::new(&config);
+
+        assert!(pipeline.process(note_on(0, 30, 100)).is_empty());
+        assert!(pipeline.process(note_off(0, 30)).is_empty());
+        assert!(pipeline.active_notes().is_empty());
+        assert_eq!(pipeline.drop_stats().note_range, 2);
+
+        // Splits match the key played, so a transposition doesn't move a note across them
+        let config = Config { octave_offset: 2, ..config };
+        let mut pipeline = Pipeline::new(&config);
+        let outputs = pipeline.process(note_on(0, 60, 100));
+        assert_eq!(outputs, vec![Output {
+            message: note_on(0, 84, 100),
+            route: Route::Sink("keys".to_string()),
+        }]);
+    }
+
+    #[test]
+    fn test_category_ports_route_notes_and_ccs_apart() {
+        let config = Config {
+            category_ports: [
+                (MessageCategory::Notes, "notes".to_string()),
+                (MessageCategory::ControlChange, "automation".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+            ..Config::default()
+        };
+        let mut pipeline = Pipeline::new(&config);
+
+        let notes = RecordingSink::new();
+        let automation = RecordingSink::new();
+        let mut sinks = SinkSet::new();
+        sinks.add("notes", Box::new(notes.clone()));
+        sinks.add("automation", Box::new(automation.clone()));
+
+        let bend = MidiMessage { status: 0xE0, data1: 0, data2: 64 };
+        for message in [note_on(0, 60, 100), cc(0, 1, 64), bend, note_off(0, 60)] {
+            for output in pipeline.process(message) {
+                sinks.send(&output.message, &output.route).unwrap();
+            }
+        }
+
+        // Categories without a port still go everywhere
+        assert_eq!(notes.messages(), vec![note_on(0, 60, 100), bend, note_off(0, 60)]);
+        assert_eq!(automation.messages(), vec![cc(0, 1, 64), bend]);
+    }
+
+    #[test]
+    fn test_note_on_flood_is_throttled() {
+        let config = Config { max_notes_per_sec: Some(20), ..Config::default() };
+        let mut pipeline = Pipeline::new(&config);
+
+        let forwarded = (0..100).filter(|_| !run(&mut pipeline, note_on(0, 60, 100)).is_empty()).count();
+        assert_eq!(forwarded, 20);
+        assert_eq!(pipeline.drop_stats().note_rate, 80);
+        // Note Offs still get through
+        assert_eq!(run(&mut pipeline, note_off(0, 60)), vec![note_off(0, 60)]);
+    }
+
+    #[test]
+    fn test_throttle_follows_the_clock() {
+        let config = Config { max_notes_per_sec: Some(5), ..Config::default() };
+        let clock = Arc::new(MockClock::new());
+        let mut pipeline = Pipeline::with_clock(&config, clock.clone());
+
+        // However long processing takes, these all arrive at the same instant
+        let forwarded = (0..10).filter(|_| !run(&mut pipeline, note_on(0, 60, 100)).is_empty()).count();
+        assert_eq!(forwarded, 5);
+
+        // Still within the second of the flood
+        clock.advance(Duration::from_millis(999));
+        assert!(run(&mut pipeline, note_on(0, 60, 100)).is_empty());
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(run(&mut pipeline, note_on(0, 60, 100)), vec![note_on(0, 60, 100)]);
+    }
+
+    #[test]
+    fn test_octave_shift_mid_phrase() {
+        let config = Config {
+            octave_shift_triggers: Some(OctaveShiftTriggers { up: Trigger::ControlChange(21), down: Trigger::Note(0) }),
+            ..Config::default()
+        };
+        let mut pipeline = Pipeline::new(&config);
+
+        assert_eq!(run(&mut pipeline, note_on(0, 60, 100)), vec![note_on(0, 60, 100)]);
+        // Shift up while C4 is still held; the trigger itself isn't forwarded
+        assert!(run(&mut pipeline, cc(0, 21, 127)).is_empty());
+        assert!(run(&mut pipeline, cc(0, 21, 0)).is_empty());
+        assert_eq!(pipeline.octave_offset(), 1);
+
+        assert_eq!(run(&mut pipeline, note_on(0, 62, 100)), vec![note_on(0, 74, 100)]);
+        // The key held across the shift is released at its original pitch
+        assert_eq!(run(&mut pipeline, note_off(0, 60)), vec![note_off(0, 60)]);
+        assert_eq!(run(&mut pipeline, note_off(0, 62)), vec![note_off(0, 74)]);
+
+        // Two octaves down by note trigger
+        for _ in 0..2 {
+            assert!(run(&mut pipeline, note_on(0, 0, 100)).is_empty());
+            assert!(run(&mut pipeline, note_off(0, 0)).is_empty());
+        }
+        assert_eq!(run(&mut pipeline, note_on(0, 60, 100)), vec![note_on(0, 48, 100)]);
+    }
+
+    #[test]
+    fn test_octave_shift_is_limited() {
+        let config = Config {
+            octave_offset: 10,
+            octave_shift_triggers: Some(OctaveShiftTriggers { up: Trigger::ControlChange(21), down: Trigger::ControlChange(20) }),
+            ..Config::default()
+        };
+        let mut pipeline = Pipeline::new(&config);
+
+        for _ in 0..3 {
+            run(&mut pipeline, cc(0, 21, 127));
+        }
+        assert_eq!(pipeline.octave_offset(), 11);
+        run(&mut pipeline, cc(0, 20, 127));
+        assert_eq!(pipeline.octave_offset(), 10);
+    }
+
+    #[test]
+    fn test_transpose_pedal_mid_phrase() {
+        let config = Config { transpose_cc: Some(4), ..Config::default() };
+        let mut pipeline = Pipeline::new(&config);
+
+        assert_eq!(run(&mut pipeline, note_on(0, 60, 100)), vec![note_on(0, 60, 100)]);
+        // Pedal down to the first zone: two octaves lower, and the CC isn't forwarded
+        assert!(run(&mut pipeline, cc(0, 4, 10)).is_empty());
+        assert_eq!(pipeline.octave_offset(), -2);
+        assert_eq!(run(&mut pipeline, note_on(0, 64, 100)), vec![note_on(0, 40, 100)]);
+        // The note held before the change is released where it was played
+        assert_eq!(run(&mut pipeline, note_off(0, 60)), vec![note_off(0, 60)]);
+
+        // Up to the fourth zone while 64 is held
+        run(&mut pipeline, cc(0, 4, 90));
+        assert_eq!(run(&mut pipeline, note_on(0, 67, 100)), vec![note_on(0, 79, 100)]);
+        assert_eq!(run(&mut pipeline, note_off(0, 64)), vec![note_off(0, 40)]);
+        assert_eq!(run(&mut pipeline, note_off(0, 67)), vec![note_off(0, 79)]);
+
+        // Back to the middle
+        run(&mut pipeline, cc(0, 4, 64));
+        assert_eq!(run(&mut pipeline, note_on(0, 60, 100)), vec![note_on(0, 60, 100)]);
+    }
+
+    #[test]
+    fn test_cc_becomes_pitch_bend() {
+        let config = Config { cc_to_pitchbend: Some(11), ..Config::default() };
+        let mut pipeline = Pipeline::new(&config);
+
+        for (value, bend) in [(0, 0u16), (64, 8192), (127, 16383)] {
+            let expected = MidiMessage { status: 0xE2, data1: (bend & 0x7F) as u8, data2: (bend >> 7) as u8 };
+            assert_eq!(run(&mut pipeline, cc(2, 11, value)), vec![expected]);
+        }
+        // Other controllers pass unchanged
+        assert_eq!(run(&mut pipeline, cc(2, 1, 64)), vec![cc(2, 1, 64)]);
+    }
+
+    #[test]
+    fn test_pitch_bend_is_scaled() {
+        let config = Config { pitch_bend_scale: 0.5, ..Config::default() };
+        let mut pipeline = Pipeline::new(&config);
+
+        let bend = |value: u16| MidiMessage { status: 0xE1, data1: (value & 0x7F) as u8, data2: (value >> 7) as u8 };
+        assert_eq!(run(&mut pipeline, bend(16383)), vec![bend(12288)]);
+        assert_eq!(run(&mut pipeline, bend(8192)), vec![bend(8192)]);
+        assert_eq!(run(&mut pipeline, bend(0)), vec![bend(4096)]);
+
+        // Unscaled bends pass untouched, including the very bottom of the range
+        let mut pipeline = Pipeline::new(&Config::default());
+        assert_eq!(run(&mut pipeline, bend(0)), vec![bend(0)]);
+    }
+
+    #[test]
+    fn test_gate_open_and_close() {
+        let config = Config { gate: Some(GateTrigger::ControlChange { cc: 64, threshold: 64 }), octave_offset: 1, ..Config::default() };
+        let mut pipeline = Pipeline::new(&config);
+
+        // Closed until the trigger is held
+        assert!(run(&mut pipeline, note_on(0, 60, 100)).is_empty());
+        assert!(run(&mut pipeline, cc(0, 64, 127)).is_empty());
+        assert_eq!(run(&mut pipeline, note_on(0, 62, 100)), vec![note_on(0, 74, 100)]);
+        assert_eq!(run(&mut pipeline, cc(0, 1, 30)), vec![cc(0, 1, 30)]);
+
+        // Closing releases the held note at the pitch it was sent with
+        assert_eq!(run(&mut pipeline, cc(0, 64, 0)), vec![note_off(0, 74)]);
+        assert!(pipeline.active_notes().is_empty());
+        assert!(run(&mut pipeline, note_off(0, 62)).is_empty());
+        assert!(run(&mut pipeline, cc(0, 1, 40)).is_empty());
+
+        // Reopened, the key can be struck again
+        assert!(run(&mut pipeline, cc(0, 64, 100)).is_empty());
+        assert_eq!(run(&mut pipeline, note_on(0, 62, 90)), vec![note_on(0, 74, 90)]);
+    }
+
+    #[test]
+    fn test_ghost_note_is_dropped() {
+        let config = Config { min_note_duration: Some(Duration::from_millis(5)), ..Config::default() };
+        let clock = Arc::new(MockClock::new());
+        let mut pipeline = Pipeline::with_clock(&config, clock.clone());
+
+        assert!(run(&mut pipeline, note_on(0, 60, 100)).is_empty());
+        clock.advance(Duration::from_millis(1));
+        assert!(run(&mut pipeline, note_off(0, 60)).is_empty());
+
+        clock.advance(Duration::from_millis(10));
+        assert!(pipeline.release_due_notes().is_empty());
+        assert!(pipeline.active_notes().is_empty());
+        assert_eq!(pipeline.drop_stats().ghost_notes, 2);
+    }
+
+    #[test]
+    fn test_real_note_passes_after_the_window() {
+        let config = Config { min_note_duration: Some(Duration::from_millis(5)), ..Config::default() };
+        let clock = Arc::new(MockClock::new());
+        let mut pipeline = Pipeline::with_clock(&config, clock.clone());
+
+        assert!(run(&mut pipeline, note_on(0, 60, 100)).is_empty());
+        assert_eq!(pipeline.next_note_release(), Some(clock.now() + Duration::from_millis(5)));
+        clock.advance(Duration::from_millis(5));
+        let released: Vec<_> = pipeline.release_due_notes().into_iter().map(|output| output.message).collect();
+        assert_eq!(released, vec![note_on(0, 60, 100)]);
+
+        clock.advance(Duration::from_millis(200));
+        assert_eq!(run(&mut pipeline, note_off(0, 60)), vec![note_off(0, 60)]);
+    }
+
+    #[test]
+    fn test_legato_overlapping_notes() {
+        let config = Config { legato: true, ..Config::default() };
+        let clock = Arc::new(MockClock::new());
+        let mut pipeline = Pipeline::with_clock(&config, clock.clone());
+        let ms = Duration::from_millis;
+
+        run(&mut pipeline, note_on(0, 60, 100));
+        clock.advance(ms(200));
+        assert_eq!(run(&mut pipeline, note_on(0, 62, 100)), vec![note_on(0, 62, 100)]);
+        // Released just after the next note started: held until the phrase ends
+        clock.advance(ms(10));
+        assert!(run(&mut pipeline, note_off(0, 60)).is_empty());
+        // Other channels aren't affected
+        run(&mut pipeline, note_on(1, 60, 100));
+        clock.advance(ms(200));
+        assert_eq!(run(&mut pipeline, note_off(1, 60)), Vec::new());
+        // Released long after: sent right away, after channel 2's ended phrase
+        run(&mut pipeline, note_on(0, 64, 100));
+        clock.advance(ms(100));
+        assert_eq!(run(&mut pipeline, note_off(0, 62)), vec![note_off(1, 60), note_off(0, 62)]);
+
+        // The last key up ends the phrase once nothing follows within the window
+        assert!(run(&mut pipeline, note_off(0, 64)).is_empty());
+        assert_eq!(pipeline.next_note_release(), Some(clock.now() + ms(30)));
+        clock.advance(ms(30));
+        let released: Vec<_> = pipeline.release_due_notes().into_iter().map(|output| output.message).collect();
+        assert_eq!(released, vec![note_off(0, 60), note_off(0, 64)]);
+        assert_eq!(pipeline.next_note_release(), None);
+    }
+
+    #[test]
+    fn test_legato_gap_between_notes() {
+        let config = Config { legato: true, ..Config::default() };
+        let clock = Arc::new(MockClock::new());
+        let mut pipeline = Pipeline::with_clock(&config, clock.clone());
+        let ms = Duration::from_millis;
+
+        // Released just before the next note: the Note Off comes after its Note On
+        run(&mut pipeline, note_on(0, 60, 100));
+        clock.advance(ms(200));
+        assert!(run(&mut pipeline, note_off(0, 60)).is_empty());
+        clock.advance(ms(20));
+        assert_eq!(run(&mut pipeline, note_on(0, 62, 100)), vec![note_on(0, 62, 100)]);
+        clock.advance(ms(200));
+        assert!(run(&mut pipeline, note_off(0, 62)).is_empty());
+
+        // Too long a gap: separate notes, released on time
+        clock.advance(ms(30));
+        assert_eq!(run(&mut pipeline, note_on(0, 64, 100)), vec![note_off(0, 60), note_off(0, 62), note_on(0, 64, 100)]);
+
+        // Closing the gate doesn't leave the held Note Offs behind
+        let config = Config { legato: true, gate: Some(GateTrigger::ControlChange { cc: 64, threshold: 64 }), ..Config::default() };
+        let mut pipeline = Pipeline::with_clock(&config, clock.clone());
+        run(&mut pipeline, cc(0, 64, 127));
+        run(&mut pipeline, note_on(0, 60, 100));
+        run(&mut pipeline, note_on(0, 62, 100));
+        run(&mut pipeline, note_off(0, 60));
+        assert_eq!(run(&mut pipeline, cc(0, 64, 0)), vec![note_off(0, 60), note_off(0, 62)]);
+        assert_eq!(pipeline.next_note_release(), None);
+    }
+
+    #[test]
+    fn test_due_note_goes_before_the_next_message() {
+        let config = Config { min_note_duration: Some(Duration::from_millis(5)), ..Config::default() };
+        let clock = Arc::new(MockClock::new());
+        let mut pipeline = Pipeline::with_clock(&config, clock.clone());
+
+        run(&mut pipeline, note_on(0, 60, 100));
+        clock.advance(Duration::from_millis(10));
+        assert_eq!(run(&mut pipeline, cc(0, 1, 64)), vec![note_on(0, 60, 100), cc(0, 1, 64)]);
+    }
+
+    #[test]
+    fn test_retrigger_inserts_note_off() {
+        let config = Config { retrigger_mode: RetriggerMode::InsertNoteOff, octave_offset: 1, ..Config::default() };
+        let mut pipeline = Pipeline::new(&config);
+
+        assert_eq!(run(&mut pipeline, note_on(2, 60, 100)), vec![note_on(2, 72, 100)]);
+        assert_eq!(run(&mut pipeline, note_on(2, 60, 80)), vec![note_off(2, 72), note_on(2, 72, 80)]);
+        assert_eq!(run(&mut pipeline, note_off(2, 60)), vec![note_off(2, 72)]);
+        // A fresh strike after the release needs nothing inserted
+        assert_eq!(run(&mut pipeline, note_on(2, 60, 90)), vec![note_on(2, 72, 90)]);
+    }
+
+    #[test]
+    fn test_retrigger_releases_the_pitch_that_sounded() {
+        let config = Config {
+            retrigger_mode: RetriggerMode::InsertNoteOff,
+            octave_shift_triggers: Some(OctaveShiftTriggers { up: Trigger::ControlChange(21), down: Trigger::ControlChange(20) }),
+            ..Config::default()
+        };
+        let mut pipeline = Pipeline::new(&config);
+
+        run(&mut pipeline, note_on(0, 60, 100));
+        run(&mut pipeline, cc(0, 21, 127));
+        assert_eq!(run(&mut pipeline, note_on(0, 60, 100)), vec![note_off(0, 60), note_on(0, 72, 100)]);
+    }
+
+    #[test]
+    fn test_velocity_humanize() {
+        let config = Config { fixed_velocity: Some(100), velocity_humanize: Some(5), velocity_humanize_seed: Some(7), ..Config::default() };
+        let play = || {
+            let mut pipeline = Pipeline::new(&config);
+            (0..20).map(|i| run(&mut pipeline, note_on(0, 40 + i, 64))[0].data2).collect::<Vec<_>>()
+        };
+        let velocities = play();
+        assert_eq!(velocities, play());
+        assert!(velocities.iter().all(|velocity| (95..=105).contains(velocity)));
+        assert!(velocities.iter().any(|&velocity| velocity != 100));
+
+        // Note Offs are left alone
+        let mut pipeline = Pipeline::new(&config);
+        run(&mut pipeline, note_on(0, 60, 64));
+        assert_eq!(run(&mut pipeline, note_off(0, 60)), vec![note_off(0, 60)]);
+    }
+
+    #[test]
+    fn test_fixed_velocity() {
+        let config = Config { fixed_velocity: Some(100), ..Config::default() };
+        let mut pipeline = Pipeline::new(&config);
+
+        assert_eq!(run(&mut pipeline, note_on(0, 60, 30)), vec![note_on(0, 60, 100)]);
+        assert_eq!(run(&mut pipeline, note_on(0, 62, 127)), vec![note_on(0, 62, 100)]);
+        // A Note On at velocity 0 is a Note Off and stays one
+        let release = MidiMessage { status: 0x90, data1: 60, data2: 0 };
+        assert_eq!(run(&mut pipeline, release), vec![release]);
+        assert_eq!(run(&mut pipeline, note_off(0, 62)), vec![note_off(0, 62)]);
+    }
+
+    #[test]
+    fn test_note_off_velocity() {
+        let config = Config { note_off_velocity: Some(64), octave_offset: 1, ..Config::default() };
+        let mut pipeline = Pipeline::new(&config);
+
+        run(&mut pipeline, note_on(0, 60, 100));
+        run(&mut pipeline, note_on(2, 62, 100));
+        let release = MidiMessage { status: 0x80, data1: 60, data2: 12 };
+        assert_eq!(run(&mut pipeline, release), vec![MidiMessage { status: 0x80, data1: 72, data2: 64 }]);
+        // A Note On at velocity 0 becomes a real Note Off to carry the velocity
+        let release = MidiMessage { status: 0x92, data1: 62, data2: 0 };
+        assert_eq!(run(&mut pipeline, release), vec![MidiMessage { status: 0x82, data1: 74, data2: 64 }]);
+        // Note Ons keep theirs
+        assert_eq!(run(&mut pipeline, note_on(0, 64, 90)), vec![note_on(0, 76, 90)]);
+    }
+
+    #[test]
+    fn test_scale_snap_pairs_note_offs() {
+        let config = Config { scale_snap: Some(ScaleConfig { root: 0, scale: Scale::Major }), ..Config::default() };
+        let mut pipeline = Pipeline::new(&config);
+
+        // C# sounds as C, and its Note Off releases C
+        assert_eq!(run(&mut pipeline, note_on(0, 61, 100)), vec![note_on(0, 60, 100)]);
+        assert_eq!(run(&mut pipeline, note_on(0, 62, 100)), vec![note_on(0, 62, 100)]);
+        assert_eq!(run(&mut pipeline, note_off(0, 61)), vec![note_off(0, 60)]);
+        assert_eq!(run(&mut pipeline, note_off(0, 62)), vec![note_off(0, 62)]);
+
+        // Snapping happens after transposition
+        let config = Config { octave_offset: 1, ..config };
+        let mut pipeline = Pipeline::new(&config);
+        assert_eq!(run(&mut pipeline, note_on(0, 66, 100)), vec![note_on(0, 77, 100)]);
+        assert_eq!(run(&mut pipeline, note_off(0, 66)), vec![note_off(0, 77)]);
+    }
+
+    #[test]
+    fn test_arpeggiator_plays_held_notes_up() {
+        let rate = Duration::from_millis(100);
+        let config = Config {
+            arpeggiator: Some(ArpConfig { rate, pattern: ArpPattern::Up, gate: 0.5 }),
+            ..Config::default()
+        };
+        let clock = Arc::new(MockClock::new());
+        let mut pipeline = Pipeline::with_clock(&config, clock.clone());
+
+        // The first key starts it straight away; the chord isn't passed through
+        assert_eq!(run(&mut pipeline, note_on(0, 64, 100)), vec![note_on(0, 64, 100)]);
+        assert!(run(&mut pipeline, note_on(0, 60, 90)).is_empty());
+
+        let mut played = Vec::new();
+        for _ in 0..8 {
+            clock.advance(rate / 2);
+            played.extend(pipeline.release_due_notes().into_iter().map(|output| output.message));
+        }
+        assert_eq!(
+            played,
+            vec![note_off(0, 64), note_on(0, 60, 90), note_off(0, 60), note_on(0, 64, 100), note_off(0, 64), note_on(0, 60, 90), note_off(0, 60), note_on(0, 64, 100)]
+        );
+    }
+
+    #[test]
+    fn test_releasing_all_keys_stops_the_arpeggiator() {
+        let rate = Duration::from_millis(100);
+        let config = Config {
+            arpeggiator: Some(ArpConfig { rate, pattern: ArpPattern::Up, gate: 1.0 }),
+            ..Config::default()
+        };
+        let clock = Arc::new(MockClock::new());
+        let mut pipeline = Pipeline::with_clock(&config, clock.clone());
+
+        run(&mut pipeline, note_on(0, 60, 100));
+        run(&mut pipeline, note_on(0, 64, 100));
+        clock.advance(rate);
+        assert_eq!(pipeline.release_due_notes().len(), 2);
+
+        // 64 is sounding; letting go of 60 keeps it going, letting go of both stops it
+        assert!(run(&mut pipeline, note_off(0, 60)).is_empty());
+        assert_eq!(run(&mut pipeline, note_off(0, 64)), vec![note_off(0, 64)]);
+        assert_eq!(pipeline.next_note_release(), None);
+        clock.advance(rate * 4);
+        assert!(pipeline.release_due_notes().is_empty());
+    }
+
+    #[test]
+    fn test_held_note_repeats() {
+        let rate = Duration::from_millis(100);
+        let config = Config { note_repeat: Some(NoteRepeatConfig { rate, notes: None }), ..Config::default() };
+        let clock = Arc::new(MockClock::new());
+        let mut pipeline = Pipeline::with_clock(&config, clock.clone());
+
+        assert_eq!(run(&mut pipeline, note_on(9, 36, 110)), vec![note_on(9, 36, 110)]);
+        let mut played = Vec::new();
+        for _ in 0..5 {
+            clock.advance(rate / 2);
+            played.extend(pipeline.release_due_notes().into_iter().map(|output| output.message));
+        }
+        assert_eq!(played, vec![note_off(9, 36), note_on(9, 36, 110), note_off(9, 36), note_on(9, 36, 110), note_off(9, 36)]);
+
+        // Released between triggers: the last one already ended
+        assert!(run(&mut pipeline, note_off(9, 36)).is_empty());
+        assert_eq!(pipeline.next_note_release(), None);
+    }
+
+    #[test]
+    fn test_note_repeat_stops_on_release() {
+        let rate = Duration::from_millis(100);
+        let config = Config { note_repeat: Some(NoteRepeatConfig { rate, notes: Some(vec![36]) }), ..Config::default() };
+        let clock = Arc::new(MockClock::new());
+        let mut pipeline = Pipeline::with_clock(&config, clock.clone());
+
+        run(&mut pipeline, note_on(9, 36, 110));
+        run(&mut pipeline, note_on(9, 38, 110));
+        clock.advance(rate);
+        assert_eq!(
+            pipeline.release_due_notes().into_iter().map(|output| output.message).collect::<Vec<_>>(),
+            vec![note_off(9, 36), note_on(9, 36, 110)]
+        );
+
+        // Released while sounding, so the Note Off goes through; unlisted notes never repeat
+        assert_eq!(run(&mut pipeline, note_off(9, 36)), vec![note_off(9, 36)]);
+        assert_eq!(run(&mut pipeline, note_off(9, 38)), vec![note_off(9, 38)]);
+        clock.advance(rate * 3);
+        assert!(pipeline.release_due_notes().is_empty());
+    }
+
+    #[test]
+    fn test_harmonize_plays_chords() {
+        let config = Config { harmonize: Some(vec![4, 7]), ..Config::default() };
+        let mut pipeline = Pipeline::new(&config);
+
+        assert_eq!(run(&mut pipeline, note_on(0, 60, 100)), vec![note_on(0, 60, 100), note_on(0, 64, 100), note_on(0, 67, 100)]);
+        assert_eq!(run(&mut pipeline, cc(0, 1, 64)), vec![cc(0, 1, 64)]);
+        assert_eq!(run(&mut pipeline, note_off(0, 60)), vec![note_off(0, 60), note_off(0, 64), note_off(0, 67)]);
+
+        // Intervals leaving the range are clamped, and not sent twice
+        assert_eq!(run(&mut pipeline, note_on(0, 124, 100)), vec![note_on(0, 124, 100), note_on(0, 127, 100)]);
+        assert_eq!(run(&mut pipeline, note_off(0, 124)), vec![note_off(0, 124), note_off(0, 127)]);
+    }
+
+    #[test]
+    fn test_harmonized_note_repeats_whole() {
+        let rate = Duration::from_millis(100);
+        let config = Config {
+            harmonize: Some(vec![4, 7]),
+            note_repeat: Some(NoteRepeatConfig { rate, notes: None }),
+            ..Config::default()
+        };
+        let clock = Arc::new(MockClock::new());
+        let mut pipeline = Pipeline::with_clock(&config, clock.clone());
+
+        let chord_on = vec![note_on(0, 60, 100), note_on(0, 64, 100), note_on(0, 67, 100)];
+        let chord_off = vec![note_off(0, 60), note_off(0, 64), note_off(0, 67)];
+        assert_eq!(run(&mut pipeline, note_on(0, 60, 100)), chord_on);
+        clock.advance(rate / 2);
+        let played: Vec<_> = pipeline.release_due_notes().into_iter().map(|output| output.message).collect();
+        assert_eq!(played, chord_off);
+        clock.advance(rate / 2);
+        let played: Vec<_> = pipeline.release_due_notes().into_iter().map(|output| output.message).collect();
+        assert_eq!(played, chord_on);
+        clock.advance(rate / 2);
+        let played: Vec<_> = pipeline.release_due_notes().into_iter().map(|output| output.message).collect();
+        assert_eq!(played, chord_off);
+
+        // Released between triggers: the whole chord already ended, and nothing is left
+        assert!(run(&mut pipeline, note_off(0, 60)).is_empty());
+        assert_eq!(pipeline.next_note_release(), None);
+    }
+
+    #[test]
+    fn test_harmonized_ghost_note_is_dropped_whole() {
+        let config = Config { harmonize: Some(vec![7]), min_note_duration: Some(Duration::from_millis(5)), ..Config::default() };
+        let clock = Arc::new(MockClock::new());
+        let mut pipeline = Pipeline::with_clock(&config, clock.clone());
+
+        assert!(run(&mut pipeline, note_on(0, 60, 100)).is_empty());
+        assert!(run(&mut pipeline, note_off(0, 60)).is_empty());
+        clock.advance(Duration::from_millis(10));
+        assert!(pipeline.release_due_notes().is_empty());
+    }
+
+    fn freeze_config() -> Config {
+        Config {
+            freeze: Some(FreezeConfig { channel: 5, sink: Some("pads".to_string()), trigger_cc: Some(30) }),
+            octave_offset: 1,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_freeze_and_clear() {
+        let mut pipeline = Pipeline::new(&freeze_config());
+        run(&mut pipeline, note_on(0, 60, 100));
+        run(&mut pipeline, note_on(0, 64, 80));
+
+        let outputs = pipeline.freeze_held_notes();
+        assert_eq!(outputs, vec![
+            Output { message: note_on(5, 72, 100), route: Route::Sink("pads".to_string()) },
+            Output { message: note_on(5, 76, 80), route: Route::Sink("pads".to_string()) },
+        ]);
+
+        // Releasing the keys doesn't touch the frozen notes
+        assert_eq!(run(&mut pipeline, note_off(0, 60)), vec![note_off(0, 72)]);
+        assert_eq!(run(&mut pipeline, note_off(0, 64)), vec![note_off(0, 76)]);
+        assert_eq!(pipeline.frozen_notes(), &[note_on(5, 72, 100), note_on(5, 76, 80)]);
+
+        let released: Vec<_> = pipeline.clear_freeze().into_iter().map(|output| output.message).collect();
+        assert_eq!(released, vec![note_off(5, 72), note_off(5, 76)]);
+        assert!(pipeline.frozen_notes().is_empty());
+        assert!(pipeline.clear_freeze().is_empty());
+    }
+
+    #[test]
+    fn test_freeze_trigger_toggles() {
+        let mut pipeline = Pipeline::new(&freeze_config());
+        run(&mut pipeline, note_on(0, 60, 100));
+
+        assert_eq!(run(&mut pipeline, cc(0, 30, 127)), vec![note_on(5, 72, 100)]);
+        assert!(run(&mut pipeline, cc(0, 30, 0)).is_empty());
+        // A new freeze replaces the old one
+        run(&mut pipeline, note_on(0, 62, 90));
+        assert_eq!(pipeline.freeze_held_notes().len(), 3);
+        assert_eq!(run(&mut pipeline, cc(0, 30, 127)), vec![note_off(5, 72), note_off(5, 74)]);
+    }
+
+    #[test]
+    fn test_freeze_is_channel_routed_either_way() {
+        let config = Config { channel_routing: HashMap::from([(5, (1, "synth".to_string()))]), ..freeze_config() };
+        let routed = |message| Output { message, route: Route::Sink("synth".to_string()) };
+        let mut pipeline = Pipeline::new(&config);
+        run(&mut pipeline, note_on(0, 60, 100));
+
+        // Frozen with the trigger or through the API, the notes end up in the same place
+        assert_eq!(pipeline.process(cc(0, 30, 127)), vec![routed(note_on(1, 72, 100))]);
+        assert_eq!(pipeline.clear_freeze(), vec![routed(note_off(1, 72))]);
+        assert_eq!(pipeline.freeze_held_notes(), vec![routed(note_on(1, 72, 100))]);
+        assert_eq!(pipeline.process(cc(0, 30, 127)), vec![routed(note_off(1, 72))]);
+    }
+
+    #[test]
+    fn test_retrigger_passthrough() {
+        let mut pipeline = Pipeline::new(&Config::default());
+
+        run(&mut pipeline, note_on(0, 60, 100));
+        assert_eq!(run(&mut pipeline, note_on(0, 60, 80)), vec![note_on(0, 60, 80)]);
+    }
+
+    #[test]
+    fn test_drops_are_counted_per_stage() {
+        let config = Config {
+            mute_toggle_cc: Some(20),
+            splits: vec![Split { range: 48..=72, sink: "keys".to_string() }],
+            unknown_message_policy: UnknownMessagePolicy::Drop,
+            ..Config::default()
+        };
+        let mut pipeline = Pipeline::new(&config);
+
+        run(&mut pipeline, note_on(0, 30, 100));
+        run(&mut pipeline, MidiMessage { status: 0xF5, data1: 0, data2: 0 });
+        run(&mut pipeline, note_on(0, 60, 100));
+        assert_eq!(pipeline.drop_stats(), DropStats { note_range: 1, unknown_status: 1, ..DropStats::default() });
+
+        // The toggle itself is consumed, not dropped
+        run(&mut pipeline, cc(0, 20, 127));
+        run(&mut pipeline, note_on(0, 62, 100));
+        run(&mut pipeline, cc(0, 1, 64));
+        let stats = pipeline.drop_stats();
+        assert_eq!(stats, DropStats { note_range: 1, unknown_status: 1, muted: 2, ..DropStats::default() });
+        assert_eq!(stats.total(), 4);
+        assert_eq!(stats.to_string(), "unknown status filter dropped 1, mute dropped 2, note-range filter dropped 1");
+        assert_eq!(DropStats::default().to_string(), "nothing dropped");
+    }
+
+    #[test]
+    fn test_gate_drops_are_counted() {
+        let config = Config { gate: Some(GateTrigger::Note(36)), ..Config::default() };
+        let mut pipeline = Pipeline::new(&config);
+
+        run(&mut pipeline, note_on(0, 60, 100));
+        run(&mut pipeline, note_off(0, 60));
+        assert_eq!(pipeline.drop_stats(), DropStats { gate_closed: 2, ..DropStats::default() });
+    }
+
+    #[test]
+    fn test_channel_voice_only() {
+        let config = Config { channel_voice_only: true, ..Config::default() };
+        let mut pipeline = Pipeline::new(&config);
+        let system = |status| MidiMessage { status, data1: 0, data2: 0 };
+
+        for status in [0xF8, 0xFA, 0xFE, 0xF2] {
+            assert!(run(&mut pipeline, system(status)).is_empty());
+        }
+        assert_eq!(run(&mut pipeline, note_on(3, 60, 100)), vec![note_on(3, 60, 100)]);
+        assert_eq!(run(&mut pipeline, cc(3, 1, 64)), vec![cc(3, 1, 64)]);
+        assert_eq!(pipeline.drop_stats().system, 4);
+
+        let mut pipeline = Pipeline::new(&Config::default());
+        assert_eq!(run(&mut pipeline, system(0xF8)), vec![system(0xF8)]);
+    }
+
+    #[test]
+    fn test_velocity_normalization() {
+        let config = Config { velocity_normalize: Some(NormalizeConfig { adaptation_rate: 0.0, reset_cc: Some(30) }), ..Config::default() };
+        let mut pipeline = Pipeline::new(&config);
+
+        run(&mut pipeline, note_on(0, 60, 40));
+        assert_eq!(run(&mut pipeline, note_on(0, 62, 70)), vec![note_on(0, 62, 127)]);
+        assert_eq!(run(&mut pipeline, note_on(0, 64, 40)), vec![note_on(0, 64, 1)]);
+        assert_eq!(run(&mut pipeline, note_on(0, 65, 55)), vec![note_on(0, 65, 64)]);
+        // Note Offs keep velocity 0
+        assert_eq!(run(&mut pipeline, note_on(0, 60, 0)), vec![note_on(0, 60, 0)]);
+
+        // The reset CC isn't forwarded, and the range starts over
+        assert!(run(&mut pipeline, cc(0, 30, 127)).is_empty());
+        assert_eq!(run(&mut pipeline, note_on(0, 67, 70)), vec![note_on(0, 67, 70)]);
+    }
+
+    #[test]
+    fn test_bank_select_before_program_change() {
+        let config = Config {
+            program_change_bank: Some((1, 2)),
+            channel_banks: BTreeMap::from([(9, (120, 0))]),
+            category_ports: BTreeMap::from([(MessageCategory::ProgramChange, "synth".to_string())]),
+            ..Config::default()
+        };
+        let mut pipeline = Pipeline::new(&config);
+        let program_change = |channel: u8| MidiMessage { status: 0xC0 | channel, data1: 5, data2: 0 };
+
+        let outputs = pipeline.process(program_change(3));
+        assert_eq!(
+            outputs.iter().map(|output| output.message).collect::<Vec<_>>(),
+            vec![cc(3, 0, 1), cc(3, 32, 2), program_change(3)]
+        );
+        // The bank select goes wherever the Program Change goes
+        assert!(outputs.iter().all(|output| output.route == Route::Sink("synth".to_string())));
+        assert_eq!(run(&mut pipeline, program_change(9)), vec![cc(9, 0, 120), cc(9, 32, 0), program_change(9)]);
+
+        let mut pipeline = Pipeline::new(&Config::default());
+        assert_eq!(run(&mut pipeline, program_change(3)), vec![program_change(3)]);
+    }
+
+    #[test]
+    fn test_inverted_note_on_off() {
+        let config = Config { invert_note_onoff: true, octave_offset: 1, ..Config::default() };
+        let mut pipeline = Pipeline::new(&config);
+
+        // Pressing stops the note, releasing starts it at the press's velocity
+        assert_eq!(run(&mut pipeline, note_on(0, 60, 90)), vec![note_off(0, 72)]);
+        assert_eq!(run(&mut pipeline, note_off(0, 60)), vec![note_on(0, 72, 90)]);
+        assert_eq!(pipeline.active_notes().get(0, 60).map(|held| held.note), Some(72));
+        // A Note On at velocity 0 is a release too
+        run(&mut pipeline, note_on(1, 64, 70));
+        assert_eq!(run(&mut pipeline, note_on(1, 64, 0)), vec![note_on(1, 76, 70)]);
+        assert_eq!(pipeline.active_notes().len(), 2);
+
+        // Striking the key again stops its drone
+        assert_eq!(run(&mut pipeline, note_on(0, 60, 100)), vec![note_off(0, 72)]);
+        assert_eq!(pipeline.active_notes().len(), 1);
+        // A release without a press seen still starts a note
+        assert_eq!(run(&mut pipeline, note_off(0, 62)), vec![note_on(0, 74, 64)]);
+    }
+
+    #[test]
+    fn test_choke_groups() {
+        // Closed, pedal and open hi-hat
+        let config = Config { choke_groups: vec![vec![42, 44, 46]], ..Config::default() };
+        let mut pipeline = Pipeline::new(&config);
+
+        run(&mut pipeline, note_on(9, 46, 100));
+        run(&mut pipeline, note_on(9, 38, 100));
+        run(&mut pipeline, note_on(3, 46, 100));
+        // The closed hi-hat cuts off the open one on its channel, leaving the rest
+        assert_eq!(run(&mut pipeline, note_on(9, 42, 90)), vec![note_off(9, 46), note_on(9, 42, 90)]);
+        assert!(!pipeline.active_notes().is_held(9, 46));
+        assert_eq!(pipeline.active_notes().len(), 3);
+        // Notes outside the group don't choke it
+        assert_eq!(run(&mut pipeline, note_on(9, 36, 100)), vec![note_on(9, 36, 100)]);
+        assert_eq!(run(&mut pipeline, note_on(9, 44, 80)), vec![note_off(9, 42), note_on(9, 44, 80)]);
+    }
+
+    #[test]
+    fn test_choked_key_release_is_quiet() {
+        let config = Config { choke_groups: vec![vec![42, 46]], diagnose_note_pairing: true, ..Config::default() };
+        let mut pipeline = Pipeline::new(&config);
+
+        run(&mut pipeline, note_on(9, 46, 100));
+        assert_eq!(run(&mut pipeline, note_on(9, 42, 90)), vec![note_off(9, 46), note_on(9, 42, 90)]);
+        // The open hi-hat's key comes up after its note was choked: no second Note Off
+        assert!(run(&mut pipeline, note_off(9, 46)).is_empty());
+        assert_eq!(pipeline.pairing_stats(), PairingStats::default());
+        // Struck again, it sounds and releases as usual
+        run(&mut pipeline, note_on(9, 46, 100));
+        assert_eq!(run(&mut pipeline, note_off(9, 46)), vec![note_off(9, 46)]);
+
+        // With legato, the choked note's Note Off waits for the end of the phrase
+        let config = Config { choke_groups: vec![vec![42, 46]], legato: true, ..Config::default() };
+        let clock = Arc::new(MockClock::new());
+        let mut pipeline = Pipeline::with_clock(&config, clock.clone());
+        run(&mut pipeline, note_on(9, 46, 100));
+        clock.advance(Duration::from_millis(200));
+        assert_eq!(run(&mut pipeline, note_on(9, 42, 90)), vec![note_on(9, 42, 90)]);
+        assert!(run(&mut pipeline, note_off(9, 46)).is_empty());
+        assert!(run(&mut pipeline, note_off(9, 42)).is_empty());
+        clock.advance(config.legato_window);
+        let released: Vec<_> = pipeline.release_due_notes().into_iter().map(|output| output.message).collect();
+        assert_eq!(released, vec![note_off(9, 46), note_off(9, 42)]);
+    }
+
+    #[test]
+    fn test_control_map() {
+        let mut config = Config::for_quirk_profile(crate::bridge::QuirkProfile::Lpk25);
+        // The LPK25's sustain button mutes instead
+        config.control_map.insert(64, ControlAction::ToggleMute);
+        config.control_map.insert(1, ControlAction::Cc(11));
+        config.control_map.insert(20, ControlAction::OctaveUp);
+        config.control_map.insert(2, ControlAction::Ignore);
+        let mut pipeline = Pipeline::new(&config);
+
+        run(&mut pipeline, note_on(0, 60, 100));
+        assert_eq!(run(&mut pipeline, cc(0, 64, 127)), MidiMessage::all_notes_off());
+        assert!(pipeline.is_muted());
+        assert!(run(&mut pipeline, note_on(0, 62, 100)).is_empty());
+        // Letting go of the button does nothing; the next latch unmutes
+        assert!(run(&mut pipeline, cc(0, 64, 0)).is_empty());
+        assert!(pipeline.is_muted());
+        run(&mut pipeline, cc(0, 64, 127));
+        assert!(!pipeline.is_muted());
+
+        assert_eq!(run(&mut pipeline, cc(0, 1, 80)), vec![cc(0, 11, 80)]);
+        assert!(run(&mut pipeline, cc(0, 2, 80)).is_empty());
+        assert!(run(&mut pipeline, cc(0, 20, 127)).is_empty());
+        assert_eq!(pipeline.octave_offset(), 1);
+    }
+
+    #[test]
+    fn test_system_reset_flushes_held_notes() {
+        let config = Config {
+            octave_shift_triggers: Some(OctaveShiftTriggers { up: Trigger::ControlChange(21), down: Trigger::ControlChange(20) }),
+            mute_toggle_cc: Some(22),
+            ..Config::default()
+        };
+        let reset = MidiMessage { status: 0xFF, data1: 0, data2: 0 };
+        let mut pipeline = Pipeline::new(&config);
+
+        run(&mut pipeline, cc(0, 21, 127));
+        run(&mut pipeline, note_on(0, 60, 100));
+        run(&mut pipeline, note_on(1, 64, 100));
+        // Note Offs for what sounds, then the Reset itself
+        assert_eq!(run(&mut pipeline, reset), vec![note_off(0, 72), note_off(1, 76), reset]);
+        assert!(pipeline.active_notes().is_empty());
+        // Untransposed again, and a Note Off arriving late is passed on as is
+        assert_eq!(pipeline.octave_offset(), 0);
+        assert_eq!(run(&mut pipeline, note_off(0, 60)), vec![note_off(0, 60)]);
+        // A Reset unmutes too
+        run(&mut pipeline, cc(0, 22, 127));
+        assert!(pipeline.is_muted());
+        run(&mut pipeline, reset);
+        assert!(!pipeline.is_muted());
+
+        // Not forwarded, but still acted on
+        let config = Config { forward_system_reset: false, ..Config::default() };
+        let mut pipeline = Pipeline::new(&config);
+        run(&mut pipeline, note_on(0, 60, 100));
+        assert_eq!(run(&mut pipeline, reset), vec![note_off(0, 60)]);
+    }
+
+    #[test]
+    fn test_unknown_message_policies() {
+        let unknown = MidiMessage { status: 0xF5, data1: 0, data2: 0 };
+        let clock = MidiMessage { status: 0xF8, data1: 0, data2: 0 };
+
+        for (policy, forwarded) in [
+            (UnknownMessagePolicy::Forward, true),
+            (UnknownMessagePolicy::Drop, false),
+            (UnknownMessagePolicy::LogAndDrop, false),
+        ] {
+            let config = Config { unknown_message_policy: policy, ..Config::default() };
+            let mut pipeline = Pipeline::new(&config);
+
+            let expected = if forwarded { vec![unknown] } else { Vec::new() };
+            assert_eq!(run(&mut pipeline, unknown), expected, "{:?}", policy);
+            // Known system messages are unaffected
+            assert_eq!(run(&mut pipeline, clock), vec![clock], "{:?}", policy);
+        }
+    }
+}
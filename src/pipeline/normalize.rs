@@ -0,0 +1,105 @@
+use serde::{Deserialize, Serialize};
+
+// Settings of the velocity normalizer (`Config::velocity_normalize`)
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct NormalizeConfig {
+    // How far each Note On pulls the observed range's ends towards its velocity (0-1),
+    // so extremes that aren't played again are forgotten. 0 only ever widens the range.
+    pub adaptation_rate: f32,
+    // CC whose values of 64 and up start over with an empty range
+    pub reset_cc: Option<u8>,
+}
+
+impl Default for NormalizeConfig {
+    fn default() -> Self {
+        NormalizeConfig { adaptation_rate: 0.01, reset_cc: None }
+    }
+}
+
+// Velocities this close together don't make a range worth stretching
+const MIN_SPAN: f32 = 4.0;
+
+// Stretches the range of velocities actually played over the session to the full 1-127:
+// the softest velocity seen so far comes out as 1 and the hardest as 127. Unlike a
+// fixed curve it follows the player, widening at once and narrowing at
+// `adaptation_rate`.
+#[derive(Debug)]
+pub struct VelocityNormalizer {
+    adaptation_rate: f32,
+    // Softest and hardest velocity seen, None until the first Note On
+    range: Option<(f32, f32)>,
+}
+
+impl VelocityNormalizer {
+    pub fn new(config: NormalizeConfig) -> Self {
+        VelocityNormalizer { adaptation_rate: config.adaptation_rate.clamp(0.0, 1.0), range: None }
+    }
+
+    // Never 0, which would turn the Note On into a Note Off
+    pub fn apply(&mut self, velocity: u8) -> u8 {
+        let value = velocity as f32;
+        let (min, max) = match self.range {
+            None => (value, value),
+            Some((min, max)) => (
+                if value < min { value } else { min + (value - min) * self.adaptation_rate },
+                if value > max { value } else { max - (max - value) * self.adaptation_rate },
+            ),
+        };
+        self.range = Some((min, max));
+        // Until a range shows up, velocities pass unchanged
+        if max - min < MIN_SPAN {
+            return velocity;
+        }
+        (1.0 + (value - min) * 126.0 / (max - min)).round().clamp(1.0, 127.0) as u8
+    }
+
+    // Forget the velocities seen so far
+    pub fn reset(&mut self) {
+        self.range = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_narrow_range_is_expanded() {
+        let mut normalizer = VelocityNormalizer::new(NormalizeConfig::default());
+        // Nothing to go on yet, then too little of a range
+        assert_eq!(normalizer.apply(50), 50);
+        assert_eq!(normalizer.apply(52), 52);
+
+        for velocity in [40, 60, 45, 55] {
+            normalizer.apply(velocity);
+        }
+        let low = normalizer.apply(40);
+        let middle = normalizer.apply(50);
+        let high = normalizer.apply(60);
+        assert!(low <= 5, "softest played velocity {} should come out near 1", low);
+        assert!((60..=68).contains(&middle), "middle velocity came out as {}", middle);
+        assert!(high >= 122, "hardest played velocity {} should come out near 127", high);
+
+        // A new extreme widens the range at once
+        assert_eq!(normalizer.apply(80), 127);
+        assert!(normalizer.apply(60) < high);
+
+        normalizer.reset();
+        assert_eq!(normalizer.apply(60), 60);
+    }
+
+    #[test]
+    fn test_range_narrows_at_the_adaptation_rate() {
+        let mut normalizer = VelocityNormalizer::new(NormalizeConfig { adaptation_rate: 0.2, reset_cc: None });
+        normalizer.apply(10);
+        normalizer.apply(120);
+        // Played softly from then on, the top of the range comes down to meet it
+        let first = normalizer.apply(60);
+        for _ in 0..50 {
+            normalizer.apply(50);
+            normalizer.apply(60);
+        }
+        assert!(normalizer.apply(60) > first);
+        assert_eq!(normalizer.apply(60), 127);
+    }
+}
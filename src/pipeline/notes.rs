@@ -0,0 +1,86 @@
+use std::collections::BTreeMap;
+
+// A note currently held on the controller, and what was actually sent for it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeldNote {
+    // Note number sent downstream (after transposition)
+    pub note: u8,
+    pub velocity: u8,
+}
+
+// Table of held notes keyed by (channel, incoming note number).
+//
+// Transforms that change the pitch of a Note On record the outgoing pitch here so the
+// matching Note Off can be sent to the same pitch, even if the transform settings differ
+// by the time the key is released. Without this, notes can get stuck downstream.
+// Other trackers of sounding notes keep what they need per note instead of a `HeldNote`.
+#[derive(Debug)]
+pub struct ActiveNotes<T = HeldNote> {
+    held: BTreeMap<(u8, u8), T>,
+}
+
+impl<T> Default for ActiveNotes<T> {
+    fn default() -> Self {
+        ActiveNotes { held: BTreeMap::new() }
+    }
+}
+
+impl<T> ActiveNotes<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn note_on(&mut self, channel: u8, note: u8, held: T) -> Option<T> {
+        self.held.insert((channel, note), held)
+    }
+
+    pub fn note_off(&mut self, channel: u8, note: u8) -> Option<T> {
+        self.held.remove(&(channel, note))
+    }
+
+    pub fn get(&self, channel: u8, note: u8) -> Option<&T> {
+        self.held.get(&(channel, note))
+    }
+
+    pub fn is_held(&self, channel: u8, note: u8) -> bool {
+        self.held.contains_key(&(channel, note))
+    }
+
+    pub fn len(&self) -> usize {
+        self.held.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.held.is_empty()
+    }
+
+    // Held notes as (channel, incoming note, held note), ordered by channel then note
+    pub fn iter(&self) -> impl Iterator<Item = (u8, u8, &T)> {
+        self.held.iter().map(|(&(channel, note), held)| (channel, note, held))
+    }
+
+    pub fn clear(&mut self) {
+        self.held.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_note_on_off_per_channel() {
+        let mut notes = ActiveNotes::new();
+        notes.note_on(0, 60, HeldNote { note: 72, velocity: 100 });
+        notes.note_on(1, 60, HeldNote { note: 60, velocity: 90 });
+
+        assert_eq!(notes.len(), 2);
+        assert!(notes.is_held(0, 60));
+        assert!(notes.is_held(1, 60));
+
+        assert_eq!(notes.note_off(0, 60), Some(HeldNote { note: 72, velocity: 100 }));
+        assert!(!notes.is_held(0, 60));
+        assert_eq!(notes.get(1, 60).map(|h| h.note), Some(60));
+        assert_eq!(notes.note_off(0, 60), None);
+    }
+}
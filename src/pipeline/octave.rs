@@ -0,0 +1,75 @@
+use serde::{Deserialize, Serialize};
+
+use crate::midi::MidiMessage;
+
+// A key or controller the bridge reacts to instead of forwarding it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum Trigger {
+    // Note On of this note fires; its Note Off is swallowed
+    Note(u8),
+    // This CC with a value of 64 or more fires; lower values are swallowed
+    ControlChange(u8),
+}
+
+impl Trigger {
+    // None if the message isn't this trigger, otherwise whether it fires
+    pub fn check(&self, message: &MidiMessage) -> Option<bool> {
+        match (*self, message.message_type()) {
+            (Trigger::Note(note), "Note On") if message.data1 == note => Some(true),
+            (Trigger::Note(note), "Note Off") if message.data1 == note => Some(false),
+            (Trigger::ControlChange(cc), "Control Change") if message.data1 == cc => Some(message.data2 >= 64),
+            _ => None,
+        }
+    }
+}
+
+// Messages that shift the octave up or down while playing, on top of `Config::octave_offset`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct OctaveShiftTriggers {
+    pub up: Trigger,
+    pub down: Trigger,
+}
+
+impl OctaveShiftTriggers {
+    // None if the message isn't a trigger, otherwise the octaves to shift by (0 when
+    // it is a trigger that doesn't fire, such as a button release)
+    pub fn check(&self, message: &MidiMessage) -> Option<i8> {
+        if let Some(fired) = self.up.check(message) {
+            return Some(if fired { 1 } else { 0 });
+        }
+        self.down.check(message).map(|fired| if fired { -1 } else { 0 })
+    }
+}
+
+// Octave offset selected by a pedal's CC value (`Config::transpose_cc`): the range is cut
+// into five equal zones, from two octaves down to two up, with no shift in the middle
+pub fn pedal_octaves(value: u8) -> i8 {
+    (value.min(127) as i16 * 5 / 128 - 2) as i8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_triggers() {
+        let triggers = OctaveShiftTriggers { up: Trigger::ControlChange(21), down: Trigger::Note(0) };
+
+        assert_eq!(triggers.check(&MidiMessage::control_change(3, 21, 127)), Some(1));
+        assert_eq!(triggers.check(&MidiMessage::control_change(3, 21, 0)), Some(0));
+        assert_eq!(triggers.check(&MidiMessage { status: 0x90, data1: 0, data2: 100 }), Some(-1));
+        assert_eq!(triggers.check(&MidiMessage { status: 0x80, data1: 0, data2: 0 }), Some(0));
+        // Note On with velocity 0 is a release too
+        assert_eq!(triggers.check(&MidiMessage { status: 0x90, data1: 0, data2: 0 }), Some(0));
+
+        assert_eq!(triggers.check(&MidiMessage::control_change(0, 22, 127)), None);
+        assert_eq!(triggers.check(&MidiMessage { status: 0x90, data1: 60, data2: 100 }), None);
+    }
+
+    #[test]
+    fn test_pedal_zones() {
+        let zones: Vec<_> = [0, 25, 26, 51, 52, 64, 76, 77, 102, 103, 127].map(pedal_octaves).into();
+        assert_eq!(zones, [-2, -2, -1, -1, 0, 0, 0, 1, 1, 2, 2]);
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use super::MessageCategory;
+
+// Messages per second of each category over a sliding window, for activity meters.
+// Unlike `DropStats` these only reflect the last `window`.
+#[derive(Debug)]
+pub struct MessageRates {
+    window: Duration,
+    // When each message in the window arrived, oldest first
+    seen: BTreeMap<MessageCategory, VecDeque<Instant>>,
+}
+
+impl MessageRates {
+    pub fn new(window: Duration) -> Self {
+        MessageRates { window, seen: BTreeMap::new() }
+    }
+
+    pub fn record(&mut self, category: MessageCategory, now: Instant) {
+        let times = self.seen.entry(category).or_default();
+        times.push_back(now);
+        Self::expire(times, now, self.window);
+    }
+
+    // Current rate of every category seen within the window
+    pub fn rates(&mut self, now: Instant) -> BTreeMap<MessageCategory, f64> {
+        let window = self.window;
+        self.seen.retain(|_, times| {
+            Self::expire(times, now, window);
+            !times.is_empty()
+        });
+        self.seen.iter().map(|(&category, times)| (category, times.len() as f64 / window.as_secs_f64())).collect()
+    }
+
+    fn expire(times: &mut VecDeque<Instant>, now: Instant, window: Duration) {
+        while times.front().is_some_and(|&time| now.duration_since(time) >= window) {
+            times.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burst_rate_and_decay() {
+        let start = Instant::now();
+        let mut rates = MessageRates::new(Duration::from_secs(1));
+
+        // 20 notes and 5 CCs within half a second
+        for i in 0..20 {
+            rates.record(MessageCategory::Notes, start + Duration::from_millis(i * 25));
+        }
+        for i in 0..5 {
+            rates.record(MessageCategory::ControlChange, start + Duration::from_millis(i * 100));
+        }
+        let now = start + Duration::from_millis(500);
+        assert_eq!(rates.rates(now), BTreeMap::from([(MessageCategory::Notes, 20.0), (MessageCategory::ControlChange, 5.0)]));
+
+        // Only those after 250 ms are still in the window
+        let now = start + Duration::from_millis(1250);
+        assert_eq!(rates.rates(now), BTreeMap::from([(MessageCategory::Notes, 9.0), (MessageCategory::ControlChange, 2.0)]));
+
+        assert!(rates.rates(start + Duration::from_secs(3)).is_empty());
+    }
+}
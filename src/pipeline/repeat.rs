@@ -0,0 +1,124 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use super::Output;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct NoteRepeatConfig {
+    // Time from one trigger to the next
+    #[serde(with = "crate::util::duration_secs")]
+    pub rate: Duration,
+    // Keys (as played, before transposition) that repeat; None repeats every key
+    pub notes: Option<Vec<u8>>,
+}
+
+// A held key being retriggered
+#[derive(Debug)]
+struct Repeating {
+    channel: u8,
+    key: u8,
+    // Note Ons sent for the key (its `Config::harmonize` chord), replayed on every trigger
+    note_ons: Vec<Output>,
+    sounding: bool,
+    // When the note next turns off or on
+    next_at: Instant,
+}
+
+// Drum machine style note repeat: while a key is held, its note is retriggered at a
+// fixed rate, each trigger sounding for half the interval. Driven like the ghost note
+// filter: `next_event` says when it next has something to send and `run` sends it.
+#[derive(Debug)]
+pub struct NoteRepeat {
+    config: NoteRepeatConfig,
+    held: Vec<Repeating>,
+}
+
+impl NoteRepeat {
+    pub fn new(config: NoteRepeatConfig) -> Self {
+        NoteRepeat { config, held: Vec::new() }
+    }
+
+    pub fn repeats(&self, key: u8) -> bool {
+        self.config.notes.as_ref().is_none_or(|notes| notes.contains(&key))
+    }
+
+    // Start repeating `key` if it qualifies. `note_ons` are its first trigger, sent at `now`.
+    pub fn hold(&mut self, channel: u8, key: u8, note_ons: &[Output], now: Instant) {
+        if !self.repeats(key) {
+            return;
+        }
+        self.held.retain(|r| (r.channel, r.key) != (channel, key));
+        self.held.push(Repeating { channel, key, note_ons: note_ons.to_vec(), sounding: true, next_at: now + self.half_step() });
+    }
+
+    // Stop repeating `key`. None if it wasn't repeating, otherwise whether its notes are
+    // sounding and so still needs its Note Off.
+    pub fn release(&mut self, channel: u8, key: u8) -> Option<bool> {
+        let index = self.held.iter().position(|r| (r.channel, r.key) == (channel, key))?;
+        Some(self.held.remove(index).sounding)
+    }
+
+    pub fn clear(&mut self) {
+        self.held.clear();
+    }
+
+    pub fn next_event(&self) -> Option<Instant> {
+        self.held.iter().map(|r| r.next_at).min()
+    }
+
+    // Note Offs and retriggering Note Ons due by `now`
+    pub fn run(&mut self, now: Instant) -> Vec<Output> {
+        let half_step = self.half_step();
+        let mut outputs = Vec::new();
+        for repeating in &mut self.held {
+            // Skip triggers missed while we weren't running rather than bursting them out
+            if repeating.next_at + self.config.rate <= now {
+                repeating.next_at = now;
+            }
+            while repeating.next_at <= now {
+                for note_on in &repeating.note_ons {
+                    if repeating.sounding {
+                        let mut note_off = note_on.clone();
+                        note_off.message.status = 0x80 | (note_on.message.status & 0x0F);
+                        note_off.message.data2 = 0;
+                        outputs.push(note_off);
+                    } else {
+                        outputs.push(note_on.clone());
+                    }
+                }
+                repeating.sounding = !repeating.sounding;
+                repeating.next_at += half_step;
+            }
+        }
+        outputs
+    }
+
+    fn half_step(&self) -> Duration {
+        self.config.rate / 2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::MidiMessage;
+
+    fn note_on(note: u8) -> Output {
+        Output::all(MidiMessage { status: 0x90, data1: note, data2: 100 })
+    }
+
+    #[test]
+    fn test_only_listed_notes_repeat() {
+        let mut repeat = NoteRepeat::new(NoteRepeatConfig { rate: Duration::from_millis(100), notes: Some(vec![36, 38]) });
+        let now = Instant::now();
+        repeat.hold(0, 36, &[note_on(36)], now);
+        repeat.hold(0, 60, &[note_on(60)], now);
+
+        assert!(repeat.repeats(38));
+        assert!(!repeat.repeats(60));
+        assert_eq!(repeat.release(0, 60), None);
+        assert_eq!(repeat.release(0, 36), Some(true));
+        assert_eq!(repeat.next_event(), None);
+    }
+}
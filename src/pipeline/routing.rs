@@ -0,0 +1,114 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+use crate::midi::{MidiMessage, Route};
+
+// A processed message together with the sinks it should be delivered to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+    pub message: MidiMessage,
+    pub route: Route,
+}
+
+impl Output {
+    pub fn all(message: MidiMessage) -> Self {
+        Output { message, route: Route::All }
+    }
+}
+
+// Keyboard split: notes played in `range` are delivered only to the sink named `sink`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Split {
+    pub range: RangeInclusive<u8>,
+    pub sink: String,
+}
+
+// Groups of messages that can be sent to their own output port
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageCategory {
+    // Note On / Note Off
+    Notes,
+    ControlChange,
+    ProgramChange,
+    PitchBend,
+    // Polyphonic Key Pressure and Channel Pressure
+    Pressure,
+    // Everything from 0xF0 up
+    System,
+}
+
+impl MessageCategory {
+    pub fn of(message: &MidiMessage) -> Self {
+        match message.status & 0xF0 {
+            0x80 | 0x90 => MessageCategory::Notes,
+            0xA0 | 0xD0 => MessageCategory::Pressure,
+            0xB0 => MessageCategory::ControlChange,
+            0xC0 => MessageCategory::ProgramChange,
+            0xE0 => MessageCategory::PitchBend,
+            _ => MessageCategory::System,
+        }
+    }
+}
+
+// Route for a message by its category: the port configured for it, or every sink
+pub fn route_category(ports: &BTreeMap<MessageCategory, String>, message: &MidiMessage) -> Route {
+    match ports.get(&MessageCategory::of(message)) {
+        Some(port) => Route::Sink(port.clone()),
+        None => Route::All,
+    }
+}
+
+// Pick the route for a note from the key that was played (before any transposition),
+// so a Note Off always follows its Note On. With no splits configured every note goes
+// everywhere; otherwise the first matching split wins and a note outside all of them
+// is dropped (None).
+pub fn route_note(splits: &[Split], note: u8) -> Option<Route> {
+    if splits.is_empty() {
+        return Some(Route::All);
+    }
+    splits
+        .iter()
+        .find(|split| split.range.contains(&note))
+        .map(|split| Route::Sink(split.sink.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn splits() -> Vec<Split> {
+        vec![
+            Split { range: 0..=59, sink: "bass".to_string() },
+            Split { range: 60..=100, sink: "lead".to_string() },
+        ]
+    }
+
+    #[test]
+    fn test_message_categories() {
+        let category = |status| MessageCategory::of(&MidiMessage { status, data1: 0, data2: 0 });
+        assert_eq!(category(0x83), MessageCategory::Notes);
+        assert_eq!(category(0x9F), MessageCategory::Notes);
+        assert_eq!(category(0xA0), MessageCategory::Pressure);
+        assert_eq!(category(0xB1), MessageCategory::ControlChange);
+        assert_eq!(category(0xC0), MessageCategory::ProgramChange);
+        assert_eq!(category(0xD0), MessageCategory::Pressure);
+        assert_eq!(category(0xE5), MessageCategory::PitchBend);
+        assert_eq!(category(0xF8), MessageCategory::System);
+    }
+
+    #[test]
+    fn test_route_note_without_splits() {
+        assert_eq!(route_note(&[], 10), Some(Route::All));
+    }
+
+    #[test]
+    fn test_route_note_by_range() {
+        let splits = splits();
+        assert_eq!(route_note(&splits, 0), Some(Route::Sink("bass".to_string())));
+        assert_eq!(route_note(&splits, 59), Some(Route::Sink("bass".to_string())));
+        assert_eq!(route_note(&splits, 60), Some(Route::Sink("lead".to_string())));
+        assert_eq!(route_note(&splits, 101), None);
+    }
+}
@@ -0,0 +1,90 @@
+use serde::{Deserialize, Serialize};
+
+// Scales notes can be snapped to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scale {
+    Major,
+    // Natural minor
+    Minor,
+    HarmonicMinor,
+    PentatonicMajor,
+    PentatonicMinor,
+    Blues,
+}
+
+impl Scale {
+    // Semitones of each degree above the root
+    pub fn intervals(&self) -> &'static [u8] {
+        match self {
+            Scale::Major => &[0, 2, 4, 5, 7, 9, 11],
+            Scale::Minor => &[0, 2, 3, 5, 7, 8, 10],
+            Scale::HarmonicMinor => &[0, 2, 3, 5, 7, 8, 11],
+            Scale::PentatonicMajor => &[0, 2, 4, 7, 9],
+            Scale::PentatonicMinor => &[0, 3, 5, 7, 10],
+            Scale::Blues => &[0, 3, 5, 6, 7, 10],
+        }
+    }
+}
+
+// A key to snap notes to, e.g. A minor is `ScaleConfig { root: 9, scale: Scale::Minor }`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ScaleConfig {
+    // Pitch class of the root, 0 (C) to 11 (B)
+    pub root: u8,
+    pub scale: Scale,
+}
+
+impl ScaleConfig {
+    pub fn contains(&self, note: u8) -> bool {
+        let degree = (note + 12 - self.root % 12) % 12;
+        self.scale.intervals().contains(&degree)
+    }
+
+    // The nearest note of the scale; halfway between two, the lower one
+    pub fn snap(&self, note: u8) -> u8 {
+        (0..12)
+            .flat_map(|distance| [note.checked_sub(distance), note.checked_add(distance).filter(|&up| up <= 127)])
+            .flatten()
+            .find(|&candidate| self.contains(candidate))
+            .unwrap_or(note)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const C_MAJOR: ScaleConfig = ScaleConfig { root: 0, scale: Scale::Major };
+
+    #[test]
+    fn test_snap_to_c_major() {
+        // C# lies between C and D and goes down; D is in the scale
+        assert_eq!(C_MAJOR.snap(61), 60);
+        assert_eq!(C_MAJOR.snap(62), 62);
+        assert_eq!(C_MAJOR.snap(63), 62);
+        assert_eq!(C_MAJOR.snap(66), 65);
+        assert_eq!(C_MAJOR.snap(70), 69);
+        assert!((0..=127).map(|note| C_MAJOR.snap(note)).all(|note| C_MAJOR.contains(note)));
+    }
+
+    #[test]
+    fn test_snap_to_other_keys() {
+        // In D major, C# is the seventh degree and C lies between it and B
+        let d_major = ScaleConfig { root: 2, scale: Scale::Major };
+        assert_eq!(d_major.snap(61), 61);
+        assert_eq!(d_major.snap(60), 59);
+        // C pentatonic minor: C Eb F G Bb
+        let pentatonic = ScaleConfig { root: 0, scale: Scale::PentatonicMinor };
+        assert_eq!(pentatonic.snap(62), 63);
+        assert_eq!(pentatonic.snap(68), 67);
+        assert_eq!(pentatonic.snap(69), 70);
+    }
+
+    #[test]
+    fn test_snap_stays_in_range() {
+        let c_sharp_pentatonic = ScaleConfig { root: 1, scale: Scale::PentatonicMajor };
+        assert_eq!(c_sharp_pentatonic.snap(0), 1);
+        assert_eq!(C_MAJOR.snap(127), 127);
+    }
+}
@@ -0,0 +1,81 @@
+use log::{info, warn};
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::time::Instant;
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+// Safety valve against a glitching controller flooding the synth with Note Ons.
+//
+// Counts the Note Ons received over the last second, including suppressed ones, and
+// suppresses them while that count is above the limit. A flood therefore stays muted
+// for as long as it lasts, and Note Ons pass again once it has died down.
+#[derive(Debug)]
+pub struct NoteThrottle {
+    max_per_sec: usize,
+    recent: VecDeque<Instant>,
+    suppressing: bool,
+}
+
+impl NoteThrottle {
+    pub fn new(max_per_sec: u32) -> Self {
+        NoteThrottle {
+            max_per_sec: max_per_sec as usize,
+            recent: VecDeque::new(),
+            suppressing: false,
+        }
+    }
+
+    // Record a Note On received at `now` and decide whether it may be forwarded
+    pub fn allow(&mut self, now: Instant) -> bool {
+        while self.recent.front().is_some_and(|&t| now.duration_since(t) >= WINDOW) {
+            self.recent.pop_front();
+        }
+        // Only keep as many timestamps as needed to tell whether the limit is exceeded
+        if self.recent.len() > self.max_per_sec {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(now);
+
+        let flooding = self.recent.len() > self.max_per_sec;
+        if flooding && !self.suppressing {
+            warn!("More than {} Note Ons per second - suppressing Note Ons until the flood stops", self.max_per_sec);
+        } else if !flooding && self.suppressing {
+            info!("Note On rate back to normal, forwarding again");
+        }
+        self.suppressing = flooding;
+        !flooding
+    }
+
+    pub fn is_suppressing(&self) -> bool {
+        self.suppressing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fast_playing_passes() {
+        let mut throttle = NoteThrottle::new(10);
+        let start = Instant::now();
+        for i in 0..100 {
+            assert!(throttle.allow(start + Duration::from_millis(i * 100)));
+        }
+    }
+
+    #[test]
+    fn test_flood_is_suppressed_until_it_stops() {
+        let mut throttle = NoteThrottle::new(10);
+        let start = Instant::now();
+
+        let allowed = (0..50).filter(|&i| throttle.allow(start + Duration::from_millis(i))).count();
+        assert_eq!(allowed, 10);
+        assert!(throttle.is_suppressing());
+
+        // A second later the flood has passed
+        assert!(throttle.allow(start + Duration::from_millis(1100)));
+        assert!(!throttle.is_suppressing());
+    }
+}
@@ -0,0 +1,61 @@
+// Serde helpers writing a `Duration` as (fractional) seconds, as configuration files
+// hold them: `#[serde(with = "crate::util::duration_secs")]`, or `duration_secs::option`
+// for an `Option<Duration>`. Reading rounds to whole nanoseconds, so what was written
+// reads back exactly.
+use serde::{Deserialize, Deserializer, Serializer};
+use std::time::Duration;
+
+pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    from_secs(f64::deserialize(deserializer)?)
+}
+
+fn from_secs<E: serde::de::Error>(secs: f64) -> Result<Duration, E> {
+    if !secs.is_finite() || secs < 0.0 || secs > u64::MAX as f64 / 1e9 {
+        return Err(E::custom(format!("{} isn't a valid number of seconds", secs)));
+    }
+    Ok(Duration::from_nanos((secs * 1e9).round() as u64))
+}
+
+pub mod option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => serializer.serialize_some(&duration.as_secs_f64()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+        Option::<f64>::deserialize(deserializer)?.map(from_secs).transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Timing {
+        #[serde(with = "super")]
+        delay: Duration,
+        #[serde(default, with = "super::option")]
+        wait: Option<Duration>,
+    }
+
+    #[test]
+    fn test_sub_second_durations_round_trip() {
+        let timing = Timing { delay: Duration::from_micros(960), wait: Some(Duration::from_nanos(1_234_567_891)) };
+        let text = toml::to_string(&timing).unwrap();
+        assert_eq!(toml::from_str::<Timing>(&text).unwrap(), timing);
+
+        // Whole seconds may be written as integers, and a missing option is None
+        assert_eq!(toml::from_str::<Timing>("delay = 2").unwrap(), Timing { delay: Duration::from_secs(2), wait: None });
+        assert!(toml::from_str::<Timing>("delay = -1.0").is_err());
+    }
+}
@@ -0,0 +1,96 @@
+mod clock;
+pub mod duration_secs;
+mod random;
+
+pub use clock::{Clock, MockClock, SystemClock};
+pub use random::XorShift32;
+
+// Format bytes in the classic offset / hex / ASCII three-column layout, 16 bytes per row:
+//
+// 00000000  80 80 90 3c 7f                                    |...<.|
+//
+// Short final rows are padded so the ASCII column always lines up.
+pub fn hexdump(data: &[u8]) -> String {
+    let mut lines = Vec::new();
+
+    for (row, chunk) in data.chunks(16).enumerate() {
+        let mut line = format!("{:08x}  ", row * 16);
+
+        for i in 0..16 {
+            match chunk.get(i) {
+                Some(byte) => line.push_str(&format!("{:02x} ", byte)),
+                None => line.push_str("   "),
+            }
+            // Extra gap between the two groups of eight bytes
+            if i == 7 {
+                line.push(' ');
+            }
+        }
+
+        line.push_str(" |");
+        for &byte in chunk {
+            line.push(if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' });
+        }
+        line.push('|');
+
+        lines.push(line);
+    }
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hexdump_empty() {
+        assert_eq!(hexdump(&[]), "");
+    }
+
+    #[test]
+    fn test_hexdump_single_row() {
+        let dump = hexdump(&[0x80, 0x80, 0x90, 0x3C, 0x7F]);
+        assert_eq!(
+            dump,
+            "00000000  80 80 90 3c 7f                                    |...<.|"
+        );
+    }
+
+    #[test]
+    fn test_hexdump_full_row() {
+        let data: Vec<u8> = (0x41..0x51).collect(); // 'A'..='P'
+        assert_eq!(
+            hexdump(&data),
+            "00000000  41 42 43 44 45 46 47 48  49 4a 4b 4c 4d 4e 4f 50  |ABCDEFGHIJKLMNOP|"
+        );
+    }
+
+    #[test]
+    fn test_hexdump_alignment_on_partial_rows() {
+        // Lengths that are not a multiple of 16, including ones ending mid-group
+        for len in [1usize, 7, 8, 9, 15, 17, 31, 33] {
+            let data: Vec<u8> = (0..len as u8).collect();
+            let dump = hexdump(&data);
+            let lines: Vec<&str> = dump.lines().collect();
+
+            assert_eq!(lines.len(), len.div_ceil(16), "row count for {} bytes", len);
+            for line in &lines {
+                assert_eq!(line.find('|'), Some(60), "ASCII column misaligned for {} bytes: {}", len, line);
+            }
+        }
+    }
+
+    #[test]
+    fn test_hexdump_offsets_and_ascii() {
+        let mut data = b"BLE-MIDI packet!".to_vec();
+        data.extend_from_slice(&[0x00, 0xFF]);
+        let dump = hexdump(&data);
+        let lines: Vec<&str> = dump.lines().collect();
+
+        assert!(lines[0].starts_with("00000000  "));
+        assert!(lines[0].ends_with("|BLE-MIDI packet!|"));
+        assert!(lines[1].starts_with("00000010  00 ff "));
+        assert!(lines[1].ends_with("|..|"));
+    }
+}
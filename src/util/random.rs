@@ -0,0 +1,44 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+// xorshift32: small, fast and seedable, which is all musical randomness needs
+#[derive(Debug, Clone)]
+pub struct XorShift32 {
+    state: u32,
+}
+
+impl XorShift32 {
+    // The same seed gives the same sequence. Zero would only ever give zeros, so it is
+    // replaced by another constant.
+    pub fn new(seed: u32) -> Self {
+        XorShift32 { state: if seed == 0 { 0x2545_F491 } else { seed } }
+    }
+
+    // Seeded differently every run
+    pub fn from_entropy() -> Self {
+        Self::new(RandomState::new().build_hasher().finish() as u32)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seed_determines_the_sequence() {
+        let sequence = |seed| {
+            let mut random = XorShift32::new(seed);
+            (0..4).map(|_| random.next_u32()).collect::<Vec<_>>()
+        };
+        assert_eq!(sequence(7), sequence(7));
+        assert_ne!(sequence(7), sequence(8));
+        assert!(sequence(0).iter().all(|&value| value != 0));
+    }
+}